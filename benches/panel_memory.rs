@@ -0,0 +1,136 @@
+//Peak heap memory of parsing a large candidate panel, before (HaplotypeVariants of the whole panel, filtered
+//to the called variants afterwards) and after (PanelMatrix, expanded at the called variants only).
+//The panel is generated, its size can be set with the environment variables PANEL_HAPLOTYPES, PANEL_VARIANTS
+//and PANEL_CALLED_VARIANTS, e.g.
+//PANEL_HAPLOTYPES=20000 PANEL_VARIANTS=50000 cargo bench --bench panel_memory
+//Only allocations of Rust are counted, the ones of htslib are the same for both.
+use orthanq::calling::haplotypes::haplotypes::{
+    CandidateMatrix, HaplotypeVariants, PanelMatrix, VariantID,
+};
+use rust_htslib::bcf;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct PeakAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(allocated, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: PeakAllocator = PeakAllocator;
+
+//peak returns the result of 'f' and the peak heap memory (in bytes) above the memory allocated before.
+fn peak<T, F: FnOnce() -> T>(f: F) -> (T, usize) {
+    let before = ALLOCATED.load(Ordering::SeqCst);
+    PEAK.store(before, Ordering::SeqCst);
+    let result = f();
+    (result, PEAK.load(Ordering::SeqCst) - before)
+}
+
+fn env(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+//write_panel writes a candidate file, where haplotype h carries variant v if (h + v) % 7 == 0 and is not
+//characterized at the variant if (h + v) % 13 == 0.
+fn write_panel(path: &Path, haplotypes: usize, variants: usize) {
+    let mut vcf = std::io::BufWriter::new(std::fs::File::create(path).unwrap());
+    writeln!(vcf, "##fileformat=VCFv4.2").unwrap();
+    writeln!(vcf, "##contig=<ID=6,length=171115067>").unwrap();
+    writeln!(
+        vcf,
+        "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">"
+    )
+    .unwrap();
+    writeln!(
+        vcf,
+        "##FORMAT=<ID=C,Number=1,Type=Integer,Description=\"Coverage\">"
+    )
+    .unwrap();
+    write!(vcf, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT").unwrap();
+    for haplotype in 0..haplotypes {
+        write!(vcf, "\tH*{:06}", haplotype).unwrap();
+    }
+    writeln!(vcf).unwrap();
+    for variant in 0..variants {
+        write!(vcf, "6\t{}\t{}\tA\tG\t.\t.\t.\tGT:C", variant + 1, variant).unwrap();
+        for haplotype in 0..haplotypes {
+            let gt = if (haplotype + variant) % 7 == 0 {
+                "1|1"
+            } else {
+                "0|0"
+            };
+            let covered = ((haplotype + variant) % 13 != 0) as u8;
+            write!(vcf, "\t{}:{}", gt, covered).unwrap();
+        }
+        writeln!(vcf).unwrap();
+    }
+}
+
+fn main() {
+    let haplotypes = env("PANEL_HAPLOTYPES", 5000);
+    let variants = env("PANEL_VARIANTS", 10000);
+    let called_variants = env("PANEL_CALLED_VARIANTS", 1000).min(variants);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("candidates.vcf");
+    write_panel(&path, haplotypes, variants);
+    //every n-th variant is called
+    let called: Vec<VariantID> = (0..variants)
+        .step_by((variants / called_variants.max(1)).max(1))
+        .take(called_variants)
+        .map(|variant| VariantID(variant as i32))
+        .collect();
+
+    let (before, before_peak) = peak(|| {
+        let haplotype_variants =
+            HaplotypeVariants::new(&mut bcf::Reader::from_path(&path).unwrap()).unwrap();
+        haplotype_variants.filter_for_variants(&called).unwrap()
+    });
+    let (after, after_peak) = peak(|| {
+        let panel = PanelMatrix::new(&mut bcf::Reader::from_path(&path).unwrap()).unwrap();
+        panel.haplotype_variants_for(&called)
+    });
+    //the selection is based on the candidate matrix of the called variants, which has to be the same
+    assert_eq!(before, after);
+    assert_eq!(
+        CandidateMatrix::new(&before).unwrap(),
+        CandidateMatrix::new(&after).unwrap()
+    );
+
+    let mib = |bytes: usize| bytes as f64 / (1024.0 * 1024.0);
+    println!(
+        "panel of {} haplotypes and {} variants, {} called",
+        haplotypes,
+        variants,
+        called.len()
+    );
+    println!(
+        "peak heap memory before (HaplotypeVariants): {:.1} MiB",
+        mib(before_peak)
+    );
+    println!(
+        "peak heap memory after (PanelMatrix): {:.1} MiB",
+        mib(after_peak)
+    );
+}
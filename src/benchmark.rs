@@ -0,0 +1,63 @@
+use crate::calling::haplotypes::hla;
+use anyhow::{anyhow, Result};
+use rust_htslib::bcf;
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+//fixed input of the benchmark: a panel of three HLA-A haplotypes, four variant calls and an excerpt of the IMGT xml
+const CANDIDATES: &str = include_str!("../resources/benchmark/candidates.vcf");
+const CALLS: &str = include_str!("../resources/benchmark/calls.vcf");
+const XML: &str = include_str!("../resources/benchmark/hla.xml");
+
+//run calls the HLA haplotypes on the bundled fixture and returns the throughput metrics of the run as JSON:
+//parsed variants, haplotypes in the panel, time of the linear program, number of evaluated events and total time.
+pub fn run() -> Result<serde_json::Value> {
+    let dir = tempfile::tempdir()?;
+    let start = Instant::now();
+    let outdir = call_fixture(dir.path())?;
+    let total_time = start.elapsed().as_secs_f64();
+
+    let manifest: serde_json::Value =
+        serde_json::from_reader(fs::File::open(outdir.join("manifest.json"))?)?;
+    Ok(json!({
+        "variants": manifest["variants"],
+        "panel_haplotypes": manifest["haplotype_funnel"]["panel"],
+        "lp_time_s": manifest["lp_time_s"],
+        "evaluated_events": manifest["evaluated_events"],
+        "total_time_s": total_time,
+    }))
+}
+
+//call_fixture writes the bundled fixture to the given directory and calls the HLA haplotypes on it.
+//The results (benchmark.csv, manifest.json, ...) are written to the returned output directory.
+pub(crate) fn call_fixture(dir: &Path) -> Result<PathBuf> {
+    let (candidates, calls, xml) = (
+        dir.join("candidates.vcf"),
+        dir.join("calls.vcf"),
+        dir.join("hla.xml"),
+    );
+    fs::write(&candidates, CANDIDATES)?;
+    fs::write(&calls, CALLS)?;
+    fs::write(&xml, XML)?;
+    let outdir = dir.join("out");
+    fs::create_dir_all(&outdir)?;
+
+    hla::CallerBuilder::default()
+        .haplotype_variants(bcf::Reader::from_path(&candidates)?)
+        .variant_calls(bcf::Reader::from_path(&calls)?)
+        .xml(xml)
+        .common_variants(false)
+        .enable_equivalence_class_constraint(false)
+        .outcsv(outdir.join("benchmark.csv"))
+        .prior("diploid".to_string())
+        .lp_cutoff(0.01)
+        .threshold_equivalence_class(1)
+        .extend_haplotypes(Some(false))
+        .num_extend_haplotypes(0)
+        .build()
+        .map_err(|error| anyhow!(error))?
+        .call()?;
+    Ok(outdir)
+}
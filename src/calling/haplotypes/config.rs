@@ -0,0 +1,296 @@
+use crate::calling::haplotypes::haplotypes::{AfdScale, MissingAfdPolicy, VafDenominator};
+use crate::calling::haplotypes::{hla, virus};
+use crate::errors::OrthanqError;
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use yaml_rust::{Yaml, YamlLoader};
+
+//CallerConfig holds the tunable parameters of the haplotype callers. It can be loaded from a YAML file,
+//with the keys named as the fields of the callers (e.g. lp_cutoff: 0.05), and merged with explicitly
+//given CLI flags, which take precedence. Parameters that are given nowhere fall back to the defaults of the caller.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CallerConfig {
+    pub prior: Option<String>,
+    pub common_variants: Option<bool>,
+    pub lp_cutoff: Option<f64>,
+    pub enable_equivalence_class_constraint: Option<bool>,
+    pub extend_haplotypes: Option<bool>,
+    pub threshold_equivalence_class: Option<usize>,
+    pub threshold_considered_variants: Option<f64>,
+    pub num_extend_haplotypes: Option<i64>,
+    pub integer_selection: Option<bool>,
+    pub depth_weighting: Option<bool>,
+    pub relax_sum: Option<bool>,
+    pub max_haplotypes: Option<usize>,
+    pub extension_overlap: Option<f64>,
+    pub timeout: Option<u64>,
+    pub max_events: Option<usize>,
+    pub missing_afd: Option<String>,
+    pub afd_scale: Option<String>,
+    pub vaf_denominator: Option<String>,
+    pub lp_time_limit: Option<f64>,
+    pub lp_gap: Option<f64>,
+    pub sample: Option<String>,
+    pub af_field: Option<String>,
+    pub error_field: Option<String>,
+    pub variant_quality_percentile: Option<f64>,
+    pub min_odds: Option<f64>,
+    pub consistency_tolerance: Option<f64>,
+    pub tie_tolerance: Option<f64>,
+}
+
+impl CallerConfig {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let docs = YamlLoader::load_from_str(&fs::read_to_string(path)?)?;
+        let mut config = CallerConfig::default();
+        let entries = match docs.first() {
+            Some(Yaml::Hash(entries)) => entries,
+            None | Some(Yaml::Null) => return Ok(config),
+            Some(_) => {
+                return Err(anyhow!(
+                    "config file must be a mapping of parameter names to values"
+                ))
+            }
+        };
+        for (key, value) in entries.iter() {
+            let key = key
+                .as_str()
+                .ok_or_else(|| anyhow!("config keys must be strings, found {:?}", key))?;
+            match key {
+                "prior" => config.prior = Some(as_string(key, value)?),
+                "common_variants" => config.common_variants = Some(as_bool(key, value)?),
+                "lp_cutoff" => config.lp_cutoff = Some(as_f64(key, value)?),
+                "enable_equivalence_class_constraint" => {
+                    config.enable_equivalence_class_constraint = Some(as_bool(key, value)?)
+                }
+                "extend_haplotypes" => config.extend_haplotypes = Some(as_bool(key, value)?),
+                "threshold_equivalence_class" => {
+                    config.threshold_equivalence_class = Some(as_u64(key, value)? as usize)
+                }
+                "threshold_considered_variants" => {
+                    config.threshold_considered_variants = Some(as_f64(key, value)?)
+                }
+                "num_extend_haplotypes" => config.num_extend_haplotypes = Some(as_i64(key, value)?),
+                "integer_selection" => config.integer_selection = Some(as_bool(key, value)?),
+                "depth_weighting" => config.depth_weighting = Some(as_bool(key, value)?),
+                "relax_sum" => config.relax_sum = Some(as_bool(key, value)?),
+                "max_haplotypes" => {
+                    let max_haplotypes = as_i64(key, value)?;
+                    if max_haplotypes < 1 {
+                        return Err(OrthanqError::InvalidMaxHaplotypes {
+                            value: max_haplotypes,
+                        }
+                        .into());
+                    }
+                    config.max_haplotypes = Some(max_haplotypes as usize)
+                }
+                "extension_overlap" => config.extension_overlap = Some(as_f64(key, value)?),
+                "timeout" => config.timeout = Some(as_u64(key, value)?),
+                "max_events" => config.max_events = Some(as_u64(key, value)? as usize),
+                "missing_afd" => config.missing_afd = Some(as_string(key, value)?),
+                "afd_scale" => config.afd_scale = Some(as_string(key, value)?),
+                "vaf_denominator" => config.vaf_denominator = Some(as_string(key, value)?),
+                "lp_time_limit" => config.lp_time_limit = Some(as_f64(key, value)?),
+                "lp_gap" => config.lp_gap = Some(as_f64(key, value)?),
+                "sample" => config.sample = Some(as_string(key, value)?),
+                "af_field" => config.af_field = Some(as_string(key, value)?),
+                "error_field" => config.error_field = Some(as_string(key, value)?),
+                "variant_quality_percentile" => {
+                    config.variant_quality_percentile = Some(as_f64(key, value)?)
+                }
+                "min_odds" => config.min_odds = Some(as_f64(key, value)?),
+                "consistency_tolerance" => config.consistency_tolerance = Some(as_f64(key, value)?),
+                "tie_tolerance" => config.tie_tolerance = Some(as_f64(key, value)?),
+                _ => return Err(anyhow!("unknown parameter in config file: {}", key)),
+            }
+        }
+        Ok(config)
+    }
+
+    //merge returns the config with all parameters that are given in 'overrides' replaced.
+    pub fn merge(self, overrides: CallerConfig) -> Self {
+        CallerConfig {
+            prior: overrides.prior.or(self.prior),
+            common_variants: overrides.common_variants.or(self.common_variants),
+            lp_cutoff: overrides.lp_cutoff.or(self.lp_cutoff),
+            enable_equivalence_class_constraint: overrides
+                .enable_equivalence_class_constraint
+                .or(self.enable_equivalence_class_constraint),
+            extend_haplotypes: overrides.extend_haplotypes.or(self.extend_haplotypes),
+            threshold_equivalence_class: overrides
+                .threshold_equivalence_class
+                .or(self.threshold_equivalence_class),
+            threshold_considered_variants: overrides
+                .threshold_considered_variants
+                .or(self.threshold_considered_variants),
+            num_extend_haplotypes: overrides
+                .num_extend_haplotypes
+                .or(self.num_extend_haplotypes),
+            integer_selection: overrides.integer_selection.or(self.integer_selection),
+            depth_weighting: overrides.depth_weighting.or(self.depth_weighting),
+            relax_sum: overrides.relax_sum.or(self.relax_sum),
+            max_haplotypes: overrides.max_haplotypes.or(self.max_haplotypes),
+            extension_overlap: overrides.extension_overlap.or(self.extension_overlap),
+            timeout: overrides.timeout.or(self.timeout),
+            max_events: overrides.max_events.or(self.max_events),
+            missing_afd: overrides.missing_afd.or(self.missing_afd),
+            afd_scale: overrides.afd_scale.or(self.afd_scale),
+            vaf_denominator: overrides.vaf_denominator.or(self.vaf_denominator),
+            lp_time_limit: overrides.lp_time_limit.or(self.lp_time_limit),
+            lp_gap: overrides.lp_gap.or(self.lp_gap),
+            sample: overrides.sample.or(self.sample),
+            af_field: overrides.af_field.or(self.af_field),
+            error_field: overrides.error_field.or(self.error_field),
+            variant_quality_percentile: overrides
+                .variant_quality_percentile
+                .or(self.variant_quality_percentile),
+            min_odds: overrides.min_odds.or(self.min_odds),
+            consistency_tolerance: overrides
+                .consistency_tolerance
+                .or(self.consistency_tolerance),
+            tie_tolerance: overrides.tie_tolerance.or(self.tie_tolerance),
+        }
+    }
+
+    pub fn apply_hla(&self, builder: hla::CallerBuilder) -> Result<hla::CallerBuilder> {
+        Ok(builder
+            .prior(self.prior()?)
+            .common_variants(self.common_variants.unwrap_or(false))
+            .lp_cutoff(self.lp_cutoff.unwrap_or(0.01))
+            .enable_equivalence_class_constraint(
+                self.enable_equivalence_class_constraint.unwrap_or(false),
+            )
+            .extend_haplotypes(Some(self.extend_haplotypes.unwrap_or(false)))
+            .threshold_equivalence_class(self.threshold_equivalence_class.unwrap_or(1))
+            .num_extend_haplotypes(self.num_extend_haplotypes.unwrap_or(3))
+            .integer_selection(self.integer_selection.unwrap_or(false))
+            .depth_weighting(self.depth_weighting.unwrap_or(false))
+            .relax_sum(self.relax_sum.unwrap_or(false))
+            .max_haplotypes(self.max_haplotypes)
+            .extension_overlap(self.extension_overlap)
+            .timeout(self.timeout)
+            .max_events(self.max_events)
+            .missing_afd(self.missing_afd()?)
+            .afd_scale(self.afd_scale()?)
+            .vaf_denominator(self.vaf_denominator()?)
+            .lp_time_limit(self.lp_time_limit)
+            .lp_gap(self.lp_gap)
+            .sample(self.sample.clone())
+            .af_field(self.af_field.clone())
+            .error_field(self.error_field.clone())
+            .variant_quality_percentile(self.variant_quality_percentile)
+            .min_odds(self.min_odds)
+            .consistency_tolerance(self.consistency_tolerance.unwrap_or(0.25))
+            .tie_tolerance(self.tie_tolerance.unwrap_or(1e-6)))
+    }
+
+    pub fn apply_virus(&self, builder: virus::CallerBuilder) -> Result<virus::CallerBuilder> {
+        Ok(builder
+            .prior(self.prior()?)
+            .lp_cutoff(self.lp_cutoff.unwrap_or(0.01))
+            .enable_equivalence_class_constraint(
+                self.enable_equivalence_class_constraint.unwrap_or(false),
+            )
+            .extend_haplotypes(Some(self.extend_haplotypes.unwrap_or(true)))
+            .threshold_equivalence_class(self.threshold_equivalence_class.unwrap_or(2))
+            .threshold_considered_variants(self.threshold_considered_variants.unwrap_or(0.35))
+            .num_extend_haplotypes(self.num_extend_haplotypes.unwrap_or(0))
+            .integer_selection(self.integer_selection.unwrap_or(false))
+            .depth_weighting(self.depth_weighting.unwrap_or(false))
+            .relax_sum(self.relax_sum.unwrap_or(false))
+            .max_haplotypes(self.max_haplotypes)
+            .extension_overlap(self.extension_overlap)
+            .timeout(self.timeout)
+            .max_events(self.max_events)
+            .missing_afd(self.missing_afd()?)
+            .afd_scale(self.afd_scale()?)
+            .vaf_denominator(self.vaf_denominator()?)
+            .lp_time_limit(self.lp_time_limit)
+            .lp_gap(self.lp_gap)
+            .sample(self.sample.clone())
+            .af_field(self.af_field.clone())
+            .error_field(self.error_field.clone())
+            .variant_quality_percentile(self.variant_quality_percentile)
+            .min_odds(self.min_odds)
+            .consistency_tolerance(self.consistency_tolerance.unwrap_or(0.25))
+            .tie_tolerance(self.tie_tolerance.unwrap_or(1e-6)))
+    }
+
+    fn missing_afd(&self) -> Result<MissingAfdPolicy> {
+        match &self.missing_afd {
+            Some(policy) => MissingAfdPolicy::from_str(policy).map_err(|_| {
+                anyhow!(
+                    "missing_afd must be either uninformative or skip, found {}",
+                    policy
+                )
+            }),
+            None => Ok(MissingAfdPolicy::default()),
+        }
+    }
+
+    fn afd_scale(&self) -> Result<AfdScale> {
+        match &self.afd_scale {
+            Some(scale) => AfdScale::from_str(scale).map_err(|_| {
+                anyhow!(
+                    "afd_scale must be either phred, prob or logprob, found {}",
+                    scale
+                )
+            }),
+            None => Ok(AfdScale::default()),
+        }
+    }
+
+    fn vaf_denominator(&self) -> Result<VafDenominator> {
+        match &self.vaf_denominator {
+            Some(denominator) => VafDenominator::from_str(denominator).map_err(|_| {
+                anyhow!(
+                    "vaf_denominator must be either exclude-uncovered or include-all, found {}",
+                    denominator
+                )
+            }),
+            None => Ok(VafDenominator::default()),
+        }
+    }
+
+    fn prior(&self) -> Result<String> {
+        self.prior.clone().ok_or_else(|| {
+            anyhow!("a prior has to be given, either with --prior or in the config file")
+        })
+    }
+}
+
+fn as_string(key: &str, value: &Yaml) -> Result<String> {
+    value
+        .as_str()
+        .map(|value| value.to_string())
+        .ok_or_else(|| anyhow!("parameter {} must be a string", key))
+}
+
+fn as_bool(key: &str, value: &Yaml) -> Result<bool> {
+    value
+        .as_bool()
+        .ok_or_else(|| anyhow!("parameter {} must be true or false", key))
+}
+
+fn as_i64(key: &str, value: &Yaml) -> Result<i64> {
+    value
+        .as_i64()
+        .ok_or_else(|| anyhow!("parameter {} must be an integer", key))
+}
+
+fn as_u64(key: &str, value: &Yaml) -> Result<u64> {
+    let value = as_i64(key, value)?;
+    u64::try_from(value)
+        .map_err(|_| anyhow!("parameter {} must not be negative, found {}", key, value))
+}
+
+fn as_f64(key: &str, value: &Yaml) -> Result<f64> {
+    match value {
+        Yaml::Integer(value) => Ok(*value as f64),
+        _ => value
+            .as_f64()
+            .ok_or_else(|| anyhow!("parameter {} must be a number", key)),
+    }
+}
@@ -1,5 +1,7 @@
+use crate::errors::OrthanqError;
 use crate::model::{AlleleFreq, Data, HaplotypeFractions};
-use anyhow::Result;
+use crate::preprocess::hla::GeneRegions;
+use anyhow::{anyhow, Result};
 use bio::stats::{probs::LogProb, PHREDProb, Prob};
 use bv::BitVec;
 
@@ -7,6 +9,8 @@ use derefable::Derefable;
 
 use derive_deref::DerefMut;
 
+use log::{info, warn};
+
 use ordered_float::NotNan;
 
 use rust_htslib::bcf::{
@@ -15,6 +19,7 @@ use rust_htslib::bcf::{
     Read,
 };
 
+use coin_cbc::raw::Status;
 use good_lp::IntoAffineExpression;
 use good_lp::*;
 use good_lp::{variable, Expression};
@@ -22,25 +27,38 @@ use good_lp::{variable, Expression};
 use petgraph::dot::{Config, Dot};
 use petgraph::graph::{Graph, NodeIndex};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::collections::{BTreeMap, HashMap};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
+use std::fmt;
 use std::fs;
 use std::io::Write;
 use std::str::FromStr;
+use std::time::Instant;
 use std::{path::PathBuf, str};
 
+//VariantID is the (integer) ID column of the candidate and call records. Candidates and calls are matched by ID,
+//hence the same ID has to refer to the same variant in both files.
 #[derive(Derefable, Debug, Copy, Clone, PartialEq, Eq, Hash, Ord, PartialOrd, Serialize)]
 pub struct VariantID(#[deref] pub i32);
 
+//Haplotype is the name of a haplotype as given by the sample name of the candidate file, e.g. A*01:01:01.
 #[derive(Derefable, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
 pub struct Haplotype(#[deref] pub String);
 
+//AlleleFreqDist maps allele frequencies to their (log) posterior density, as given by the AFD field of the calls.
 #[derive(Debug, Clone, Derefable)]
 pub struct AlleleFreqDist(#[deref] BTreeMap<AlleleFreq, LogProb>);
 
 impl AlleleFreqDist {
+    pub fn new(densities: BTreeMap<AlleleFreq, LogProb>) -> Self {
+        AlleleFreqDist(densities)
+    }
+
+    //vaf_query returns the density at the given allele frequency, linearly interpolated between the neighbouring
+    //allele frequencies of the distribution. The densities are already converted to LogProb (see AfdScale).
     pub fn vaf_query(&self, vaf: &AlleleFreq) -> Option<LogProb> {
         if self.contains_key(&vaf) {
             Some(*self.get(&vaf).unwrap())
@@ -57,7 +75,11 @@ impl AlleleFreqDist {
     }
 }
 
-#[derive(Derefable, Debug, Clone)]
+//CandidateMatrix holds, per variant, the genotype of each haplotype of the panel and whether the haplotype
+//is characterized at the variant locus (panel coverage, the C field of the candidates), e.g. alleles with
+//unsequenced exons are not. Whether the *sample* has reads at the variant (sample coverage) is a separate dimension
+//given by the read depths of the VariantCalls. Only variants covered in both dimensions constrain the linear program.
+#[derive(Derefable, Debug, Clone, PartialEq)]
 pub struct CandidateMatrix(#[deref] BTreeMap<VariantID, (Vec<VariantStatus>, BitVec)>);
 
 impl CandidateMatrix {
@@ -77,13 +99,53 @@ impl CandidateMatrix {
         });
         Ok(CandidateMatrix(candidate_matrix))
     }
+
+    //from_panel equals CandidateMatrix::new of the expanded PanelMatrix, without expanding it.
+    pub fn from_panel(panel: &PanelMatrix) -> Self {
+        CandidateMatrix(
+            panel
+                .variants()
+                .into_iter()
+                .map(|variant| (variant, panel.row(&variant).unwrap()))
+                .collect(),
+        )
+    }
+
+    //select_haplotypes derives the candidate matrix for a subset of the haplotypes by selecting their columns,
+    //without iterating the HaplotypeVariants again. 'haplotypes' are the columns of this matrix (in order),
+    //the result equals CandidateMatrix::new of the HaplotypeVariants filtered for 'selected'.
+    pub fn select_haplotypes(&self, haplotypes: &[Haplotype], selected: &[Haplotype]) -> Self {
+        let columns: Vec<usize> = haplotypes
+            .iter()
+            .enumerate()
+            .filter(|(_, haplotype)| selected.contains(haplotype))
+            .map(|(i, _)| i)
+            .collect();
+        CandidateMatrix(
+            self.iter()
+                .map(|(variant_id, (genotypes, coverages))| {
+                    let mut selected_genotypes = Vec::with_capacity(columns.len());
+                    let mut selected_coverages = BitVec::new();
+                    for i in columns.iter() {
+                        selected_genotypes.push(genotypes[*i].clone());
+                        selected_coverages.push(coverages[*i as u64]);
+                    }
+                    (*variant_id, (selected_genotypes, selected_coverages))
+                })
+                .collect(),
+        )
+    }
 }
 
+//PriorTypes are the priors of the haplotype fractions: diploid (each fraction 0.0, 0.5 or 1.0), diploid-subclonal
+//(at most 4 haplotypes with a fraction above 0.0), uniform, and haploid, where exactly one haplotype has the fraction 1.0,
+//i.e. the model selects the single haplotype that explains the calls best (e.g. for viral or mitochondrial samples).
 #[derive(Debug, Clone, PartialEq)]
 pub enum PriorTypes {
     Diploid,
     DiploidSubclonal,
     Uniform,
+    Haploid,
 }
 
 impl FromStr for PriorTypes {
@@ -94,44 +156,386 @@ impl FromStr for PriorTypes {
             "uniform" => Ok(PriorTypes::Uniform),
             "diploid" => Ok(PriorTypes::Diploid),
             "diploid-subclonal" => Ok(PriorTypes::DiploidSubclonal),
+            "haploid" => Ok(PriorTypes::Haploid),
+            _ => Err(()),
+        }
+    }
+}
+
+//MissingAfdPolicy decides how variant calls with a missing ("." or empty) AFD are handled, independent of the PROB_ABSENT band.
+//Uninformative (default) keeps them with an empty allele frequency distribution, which does not contribute to the likelihood,
+//Skip drops them (they are reported as missing-AFD rejections).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingAfdPolicy {
+    Uninformative,
+    Skip,
+}
+
+impl Default for MissingAfdPolicy {
+    fn default() -> Self {
+        MissingAfdPolicy::Uninformative
+    }
+}
+
+//AfdScale is the interpretation of the densities in the AFD field of the calls: PHRED-scaled probabilities (default,
+//as written by varlociraptor), plain probabilities or natural log probabilities. They are converted to LogProb while parsing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AfdScale {
+    Phred,
+    Prob,
+    LogProb,
+}
+
+impl Default for AfdScale {
+    fn default() -> Self {
+        AfdScale::Phred
+    }
+}
+
+impl FromStr for AfdScale {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<AfdScale, Self::Err> {
+        match input {
+            "phred" => Ok(AfdScale::Phred),
+            "prob" => Ok(AfdScale::Prob),
+            "logprob" => Ok(AfdScale::LogProb),
+            _ => Err(()),
+        }
+    }
+}
+
+impl AfdScale {
+    pub fn to_logprob(&self, density: f64) -> LogProb {
+        match self {
+            AfdScale::Phred => LogProb::from(PHREDProb(density)),
+            AfdScale::Prob => LogProb::from(Prob(density)),
+            AfdScale::LogProb => LogProb(density),
+        }
+    }
+}
+
+//VafDenominator decides how the expected allele frequency of a variant (see expected_vaf) is normalized. The numerator
+//is always the sum of the fractions of the haplotypes that carry the variant and are covered at it. ExcludeUncovered
+//(default) divides it by one minus the fractions of the haplotypes that don't carry the variant and are not covered at it,
+//i.e. haplotypes without any information at the locus neither support nor contradict the variant. IncludeAll divides it
+//by the sum of all fractions (1.0), so that uncovered haplotypes count as evidence against the variant.
+//Haplotypes with unknown genotype are in neither the numerator nor the excluded part of the denominator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VafDenominator {
+    ExcludeUncovered,
+    IncludeAll,
+}
+
+impl Default for VafDenominator {
+    fn default() -> Self {
+        VafDenominator::ExcludeUncovered
+    }
+}
+
+impl FromStr for VafDenominator {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<VafDenominator, Self::Err> {
+        match input {
+            "exclude-uncovered" => Ok(VafDenominator::ExcludeUncovered),
+            "include-all" => Ok(VafDenominator::IncludeAll),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for MissingAfdPolicy {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<MissingAfdPolicy, Self::Err> {
+        match input {
+            "uninformative" => Ok(MissingAfdPolicy::Uninformative),
+            "skip" => Ok(MissingAfdPolicy::Skip),
             _ => Err(()),
         }
     }
 }
 
-#[derive(Derefable, DerefMut, Debug, Clone)]
-pub struct VariantCalls(#[deref] BTreeMap<VariantID, (f32, AlleleFreqDist)>); //The place of f32 is maximum a posteriori estimate of AF.
+//VariantCallsOptions configure how the variant calls are read: the policy for missing AFD values,
+//the sample (by name in the header) to read from a multi-sample BCF, the first sample if not given,
+//and the FORMAT field of the allele frequency that is used as target by the linear program (AF if not given),
+//as well as the scale of the AFD densities (see AfdScale). If error_field is given, the error rate of each call is
+//read from this FORMAT (or, if not defined as FORMAT, INFO) field, calls without the field are considered reliable.
+//If variant_quality_percentile (0-100) is given, only the calls with a quality score above this percentile of the
+//sample are kept: the score is the distance of PROB_ABSENT (as probability) from 0.5, i.e. how confident the caller is
+//about the presence or absence of the variant, with ties ranked by read depth (DP).
+#[derive(Debug, Clone, Default)]
+pub struct VariantCallsOptions {
+    pub missing_afd: MissingAfdPolicy,
+    pub sample: Option<String>,
+    pub af_field: Option<String>,
+    pub afd_scale: AfdScale,
+    pub error_field: Option<String>,
+    pub variant_quality_percentile: Option<f64>,
+}
+
+//VariantCalls holds, per variant, the maximum a posteriori allele frequency (AF) and the allele frequency distribution
+//of the sample, as well as its read depth (DP) and, if requested, its error rate. Variants without reads are not part
+//of the calls, and the distribution is empty for calls without AFD (see MissingAfdPolicy). The calls are sorted by VariantID.
+#[derive(Derefable, Debug, Clone)]
+pub struct VariantCalls(
+    #[deref(mutable)] BTreeMap<VariantID, (f32, AlleleFreqDist)>, //The place of f32 is maximum a posteriori estimate of AF.
+    BTreeMap<VariantID, i32>,                                     //read depths (DP) of the calls
+    BTreeMap<VariantID, f64>, //error rates of the calls (see VariantCallsOptions::error_field)
+);
+
+//RejectedVariants collects the ids of variant calls that are not considered, grouped by the reason of rejection.
+//Note: the PROB_ABSENT band filter is currently disabled, hence it does not contribute any rejections.
+#[derive(Debug, Clone, Default)]
+pub struct RejectedVariants {
+    pub zero_depth: Vec<VariantID>,
+    pub malformed_afd: Vec<VariantID>,
+    pub missing_afd: Vec<VariantID>,
+    pub low_quality: Vec<VariantID>,
+}
+
+impl RejectedVariants {
+    pub fn len(&self) -> usize {
+        self.zero_depth.len()
+            + self.malformed_afd.len()
+            + self.missing_afd.len()
+            + self.low_quality.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "dropped {}: {} DP0, {} bad-AFD, {} missing-AFD, {} low-quality",
+            self.len(),
+            self.zero_depth.len(),
+            self.malformed_afd.len(),
+            self.missing_afd.len(),
+            self.low_quality.len()
+        )
+    }
+
+    pub fn write(&self, path: &PathBuf) -> Result<()> {
+        let mut wtr = csv::Writer::from_path(path)?;
+        wtr.write_record(&["variant", "reason"])?;
+        for (variants, reason) in [
+            (&self.zero_depth, "DP0"),
+            (&self.malformed_afd, "bad-AFD"),
+            (&self.missing_afd, "missing-AFD"),
+            (&self.low_quality, "low-quality"),
+        ] {
+            for variant in variants.iter() {
+                wtr.write_record(&[variant.0.to_string(), reason.to_string()])?;
+            }
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
 
 impl VariantCalls {
+    /// Reads the calls of the first sample with the default options, e.g.
+    ///
+    /// ```
+    /// use orthanq::calling::haplotypes::haplotypes::{VariantCalls, VariantID};
+    /// use rust_htslib::bcf;
+    ///
+    /// let variant_calls =
+    ///     VariantCalls::new(&mut bcf::Reader::from_path("tests/small_calls.vcf").unwrap()).unwrap();
+    /// assert_eq!(variant_calls.len(), 4);
+    /// let (af, afd) = &variant_calls[&VariantID(1)];
+    /// assert_eq!(*af, 0.5);
+    /// assert!(!afd.is_empty());
+    /// assert_eq!(variant_calls.read_depth(&VariantID(1)), Some(30));
+    /// ```
     pub fn new(variant_calls: &mut bcf::Reader) -> Result<Self> {
+        let (calls, _) = Self::new_with_rejections(variant_calls, &VariantCallsOptions::default())?;
+        Ok(calls)
+    }
+
+    //from_calls builds the calls from already parsed allele frequencies and read depths, e.g. for testing.
+    pub fn from_calls(
+        calls: BTreeMap<VariantID, (f32, AlleleFreqDist)>,
+        read_depths: BTreeMap<VariantID, i32>,
+    ) -> Self {
+        VariantCalls(calls, read_depths, BTreeMap::new())
+    }
+
+    //with_error_rates sets the error rates of the calls, e.g. for testing.
+    pub fn with_error_rates(mut self, error_rates: BTreeMap<VariantID, f64>) -> Self {
+        self.2 = error_rates;
+        self
+    }
+
+    //new_with_rejections additionally returns the variants that were dropped while parsing, grouped by reason.
+    pub fn new_with_rejections(
+        variant_calls: &mut bcf::Reader,
+        options: &VariantCallsOptions,
+    ) -> Result<(Self, RejectedVariants)> {
+        let sample_index = match &options.sample {
+            Some(sample) => variant_calls
+                .header()
+                .sample_id(sample.as_bytes())
+                .ok_or_else(|| OrthanqError::UnknownSample {
+                    sample: sample.clone(),
+                })?,
+            None => 0,
+        };
+        let af_field = options.af_field.as_deref().unwrap_or("AF");
         let mut calls = BTreeMap::new();
+        let mut read_depths_of_calls = BTreeMap::new();
+        let mut error_rates = BTreeMap::new();
+        let mut quality_scores = Vec::new();
+        let mut rejected = RejectedVariants::default();
         for record_result in variant_calls.records() {
             let mut record = record_result?;
             record.unpack();
-            let prob_absent = record.info(b"PROB_ABSENT").float().unwrap().unwrap()[0];
-            let _prob_absent_prob = Prob::from(PHREDProb(prob_absent.into()));
-            let afd_utf = record.format(b"AFD").string()?;
-            let afd = std::str::from_utf8(afd_utf[0]).unwrap();
-            let read_depths = record.format(b"DP").integer().unwrap();
-            if read_depths[0] != &[0]
-            // && (&prob_absent_prob <= &Prob(0.05) || &prob_absent_prob >= &Prob(0.95))
+            let variant_id: i32 = String::from_utf8(record.id())?.parse().unwrap();
+            let missing = |field: &str| OrthanqError::MissingField {
+                variant: variant_id.to_string(),
+                field: field.to_string(),
+            };
+            let prob_absent = record
+                .info(b"PROB_ABSENT")
+                .float()
+                .ok()
+                .flatten()
+                .ok_or_else(|| missing("PROB_ABSENT"))?[0];
+            let prob_absent_prob = Prob::from(PHREDProb(prob_absent.into()));
+            let quality_score = (f64::from(prob_absent_prob) - 0.5).abs();
+            if let Some(error_field) = &options.error_field {
+                let error_rate = match record.format(error_field.as_bytes()).float() {
+                    Ok(values) => values[sample_index].first().cloned(),
+                    Err(_) => record
+                        .info(error_field.as_bytes())
+                        .float()
+                        .ok()
+                        .flatten()
+                        .map(|values| values[0]),
+                };
+                //missing values are NaN
+                if let Some(error_rate) = error_rate.filter(|error_rate| !error_rate.is_nan()) {
+                    error_rates
+                        .insert(VariantID(variant_id), (error_rate as f64).max(0.0).min(1.0));
+                }
+            }
+            let afd_utf = record.format(b"AFD").string().map_err(|_| missing("AFD"))?;
+            let afd = std::str::from_utf8(afd_utf[sample_index]).unwrap().trim();
+            let read_depths = record.format(b"DP").integer().map_err(|_| missing("DP"))?;
+            if read_depths[sample_index] == &[0]
+            // || (&prob_absent_prob > &Prob(0.05) && &prob_absent_prob < &Prob(0.95))
             {
-                //because some afd strings are just "." and that throws an error while splitting below.
-                let variant_id: i32 = String::from_utf8(record.id())?.parse().unwrap();
-                let af = (&*record.format(b"AF").float().unwrap()[0]).to_vec()[0];
+                rejected.zero_depth.push(VariantID(variant_id));
+            } else if afd.is_empty() || afd == "." {
+                read_depths_of_calls.insert(VariantID(variant_id), read_depths[sample_index][0]);
+                //some afd strings are just ".", even if the record passes the filters above.
+                match options.missing_afd {
+                    MissingAfdPolicy::Uninformative => {
+                        let af = (&*record
+                            .format(af_field.as_bytes())
+                            .float()
+                            .map_err(|_| missing(af_field))?[sample_index])
+                            .to_vec()[0];
+                        calls.insert(VariantID(variant_id), (af, AlleleFreqDist(BTreeMap::new())));
+                    }
+                    MissingAfdPolicy::Skip => rejected.missing_afd.push(VariantID(variant_id)),
+                }
+            } else {
+                read_depths_of_calls.insert(VariantID(variant_id), read_depths[sample_index][0]);
+                let af = (&*record
+                    .format(af_field.as_bytes())
+                    .float()
+                    .map_err(|_| missing(af_field))?[sample_index])
+                    .to_vec()[0];
                 let mut vaf_density = BTreeMap::new();
+                let mut malformed = false;
                 for pair in afd.split(',') {
                     if let Some((vaf, density)) = pair.split_once("=") {
-                        let (vaf, density): (AlleleFreq, f64) =
-                            (vaf.parse().unwrap(), density.parse().unwrap());
-                        vaf_density.insert(vaf, LogProb::from(PHREDProb(density)));
+                        match (vaf.parse::<AlleleFreq>(), density.parse::<f64>()) {
+                            (Ok(vaf), Ok(density)) => {
+                                vaf_density.insert(vaf, options.afd_scale.to_logprob(density));
+                            }
+                            _ => malformed = true,
+                        }
                     }
                 }
-                calls.insert(VariantID(variant_id), (af, AlleleFreqDist(vaf_density)));
+                if malformed {
+                    rejected.malformed_afd.push(VariantID(variant_id));
+                } else {
+                    calls.insert(VariantID(variant_id), (af, AlleleFreqDist(vaf_density)));
+                }
+            }
+            if calls.contains_key(&VariantID(variant_id)) {
+                quality_scores.push((
+                    VariantID(variant_id),
+                    quality_score,
+                    read_depths[sample_index][0],
+                ));
+            }
+        }
+        if let Some(percentile) = options.variant_quality_percentile {
+            //rank the calls by score (and depth) with the best first, the sort is stable such that ties keep their order
+            quality_scores.sort_by(|(_, a_score, a_depth), (_, b_score, b_depth)| {
+                b_score
+                    .partial_cmp(a_score)
+                    .unwrap_or(Ordering::Equal)
+                    .then(b_depth.cmp(a_depth))
+            });
+            let percentile = percentile.max(0.0).min(100.0);
+            let kept = quality_scores.len()
+                - (quality_scores.len() as f64 * percentile / 100.0).floor() as usize;
+            for (variant, _, _) in quality_scores.iter().skip(kept) {
+                calls.remove(variant);
+                read_depths_of_calls.remove(variant);
+                rejected.low_quality.push(*variant);
             }
+            rejected.low_quality.sort();
+        }
+        if !rejected.is_empty() {
+            info!("{}", rejected.summary());
         }
-        Ok(VariantCalls(calls))
+        error_rates.retain(|variant, _| calls.contains_key(variant));
+        Ok((
+            VariantCalls(calls, read_depths_of_calls, error_rates),
+            rejected,
+        ))
+    }
+    //informative_variants counts the calls that have an allele frequency distribution.
+    pub fn informative_variants(&self) -> usize {
+        self.values().filter(|(_, afd)| !afd.is_empty()).count()
+    }
+
+    //is_sample_covered returns whether the sample has at least 'min_depth' reads at the variant (sample coverage).
+    //Calls without a recorded read depth are considered covered.
+    pub fn is_sample_covered(&self, variant: &VariantID, min_depth: i32) -> bool {
+        self.1
+            .get(variant)
+            .map_or(true, |depth| *depth >= min_depth)
+    }
+
+    //read_depth returns the read depth (DP) of the sample at the variant, if recorded.
+    pub fn read_depth(&self, variant: &VariantID) -> Option<i32> {
+        self.1.get(variant).cloned()
+    }
+
+    //error_rate returns the error rate of the call at the variant, if read (see VariantCallsOptions::error_field).
+    pub fn error_rate(&self, variant: &VariantID) -> Option<f64> {
+        self.2.get(variant).cloned()
+    }
+
+    //min_read_depth is the minimum read depth over the (remaining) calls.
+    pub fn min_read_depth(&self) -> Option<i32> {
+        self.keys()
+            .filter_map(|variant| self.1.get(variant))
+            .min()
+            .cloned()
     }
+
     pub fn filter_variant_calls(&self, variants: &Vec<VariantID>) -> Result<Self> {
         let mut variant_calls_filtered = self.clone();
         for (v, _) in self.iter() {
@@ -143,18 +547,19 @@ impl VariantCalls {
     }
     pub fn check_variant_threshold(
         &self,
-        haplotype_variants: &HaplotypeVariants,
+        panel: &PanelSummary,
         threshold_considered_variants: f64,
     ) -> Result<bool> {
-        let variants_haplotype_variants: Vec<_> = haplotype_variants.keys().cloned().collect();
         let variants_haplotype_calls: Vec<_> = self.keys().cloned().collect();
 
         let rateof_evaluated_variants: f64 =
-            variants_haplotype_calls.len() as f64 / variants_haplotype_variants.len() as f64;
+            variants_haplotype_calls.len() as f64 / panel.variants as f64;
         Ok(rateof_evaluated_variants > threshold_considered_variants)
     }
 }
 
+//VariantStatus is the genotype of a haplotype at a variant (GT field of the candidates). Unknown is not produced
+//by the parsing of the candidates.
 #[derive(Clone, Debug, Eq, PartialEq, PartialOrd)]
 pub enum VariantStatus {
     Present,
@@ -162,127 +567,872 @@ pub enum VariantStatus {
     Unknown,
 }
 
+//InputCache stores the parsed variant calls and candidate panel of a run in a file, such that subsequent runs
+//on the same inputs (e.g. with other priors or thresholds) skip the parsing. The cache is only used if the inputs
+//are unchanged (same paths, sizes and modification times) and were parsed with the same options (see CacheOptions)
+//by the same version of the cache format, otherwise it is replaced by the result of the run.
+#[derive(Debug, Clone)]
+pub struct InputCache {
+    path: PathBuf,
+    inputs: String,
+}
+
+//CACHE_VERSION is the version of the cache format, it has to be increased whenever the format or the parsing of the
+//inputs changes, such that caches of older versions are replaced.
+const CACHE_VERSION: u32 = 3;
+
+//CacheOptions are the options that the parsing of the inputs depends on, a cache is only used with the same options.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct CacheOptions {
+    //the caller that parsed the inputs, i.e. hla or virus
+    pub caller: String,
+    pub missing_afd: MissingAfdPolicy,
+    pub afd_scale: AfdScale,
+    pub sample: Option<String>,
+    pub af_field: Option<String>,
+    pub error_field: Option<String>,
+    pub variant_quality_percentile: Option<f64>,
+    pub require_coverage: bool,
+    pub additional_panels: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedInputs {
+    version: u32,
+    inputs: String,
+    options: CacheOptions,
+    //variant, MAP allele frequency, allele frequency distribution and read depth. The densities are written as strings,
+    //such that non-finite values (e.g. -inf for a probability of 0) survive the round trip, which JSON numbers don't.
+    variant_calls: Vec<(i32, f32, Vec<(f64, String)>, Option<i32>)>,
+    //variant and error rate of the calls, if read
+    #[serde(default)]
+    error_rates: Vec<(i32, f64)>,
+    //variant and per haplotype its genotype (see VariantStatus) and coverage, for the called variants only
+    haplotype_variants: Vec<(i32, Vec<(String, u8, bool)>)>,
+    panel: PanelSummary,
+}
+
+impl InputCache {
+    pub fn new(path: PathBuf, inputs: &[PathBuf]) -> Result<Self> {
+        let mut keys = Vec::new();
+        for input in inputs {
+            let metadata = fs::metadata(input)?;
+            let modified = metadata
+                .modified()?
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|duration| duration.as_nanos())
+                .unwrap_or(0);
+            keys.push(format!(
+                "{}:{}:{}",
+                input.display(),
+                metadata.len(),
+                modified
+            ));
+        }
+        Ok(InputCache {
+            path,
+            inputs: keys.join(";"),
+        })
+    }
+
+    //load returns the cached inputs, if the cache exists and matches the version, inputs and options.
+    pub fn load(
+        &self,
+        options: &CacheOptions,
+    ) -> Result<Option<(VariantCalls, HaplotypeVariants, PanelSummary)>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let unreadable = |error: serde_json::Error| -> Result<Option<_>> {
+            warn!(
+                "ignoring unreadable cache {}: {}",
+                self.path.display(),
+                error
+            );
+            Ok(None)
+        };
+        let cached: serde_json::Value = match serde_json::from_reader(fs::File::open(&self.path)?) {
+            Ok(cached) => cached,
+            Err(error) => return unreadable(error),
+        };
+        if cached["version"].as_u64() != Some(CACHE_VERSION as u64) {
+            info!(
+                "the cache {} was written by another version, it is replaced",
+                self.path.display()
+            );
+            return Ok(None);
+        }
+        let cached: CachedInputs = match serde_json::from_value(cached) {
+            Ok(cached) => cached,
+            Err(error) => return unreadable(error),
+        };
+        if cached.inputs != self.inputs || &cached.options != options {
+            info!(
+                "inputs or options changed since the cache {} was written",
+                self.path.display()
+            );
+            return Ok(None);
+        }
+        let mut calls = BTreeMap::new();
+        let mut read_depths = BTreeMap::new();
+        for (variant, af, afd, read_depth) in cached.variant_calls {
+            let afd = afd
+                .into_iter()
+                .map(|(vaf, density)| -> Result<(AlleleFreq, LogProb)> {
+                    let density = density.parse::<f64>().map_err(|_| {
+                        anyhow!(
+                            "invalid density {} in the cache {}",
+                            density,
+                            self.path.display()
+                        )
+                    })?;
+                    Ok((NotNan::new(vaf).unwrap(), LogProb(density)))
+                })
+                .collect::<Result<_>>()?;
+            calls.insert(VariantID(variant), (af, AlleleFreqDist::new(afd)));
+            if let Some(read_depth) = read_depth {
+                read_depths.insert(VariantID(variant), read_depth);
+            }
+        }
+        let haplotype_variants = cached
+            .haplotype_variants
+            .into_iter()
+            .map(|(variant, matrix)| {
+                let matrix = matrix
+                    .into_iter()
+                    .map(|(haplotype, status, covered)| {
+                        let status = match status {
+                            1 => VariantStatus::Present,
+                            0 => VariantStatus::NotPresent,
+                            _ => VariantStatus::Unknown,
+                        };
+                        (Haplotype(haplotype), (status, covered))
+                    })
+                    .collect();
+                (VariantID(variant), matrix)
+            })
+            .collect();
+        info!(
+            "loaded the parsed inputs from the cache {}",
+            self.path.display()
+        );
+        Ok(Some((
+            VariantCalls::from_calls(calls, read_depths).with_error_rates(
+                cached
+                    .error_rates
+                    .into_iter()
+                    .map(|(variant, error_rate)| (VariantID(variant), error_rate))
+                    .collect(),
+            ),
+            HaplotypeVariants(haplotype_variants),
+            cached.panel,
+        )))
+    }
+
+    pub fn store(
+        &self,
+        options: &CacheOptions,
+        variant_calls: &VariantCalls,
+        haplotype_variants: &HaplotypeVariants,
+        panel: &PanelSummary,
+    ) -> Result<()> {
+        let cached = CachedInputs {
+            version: CACHE_VERSION,
+            inputs: self.inputs.clone(),
+            options: options.clone(),
+            variant_calls: variant_calls
+                .iter()
+                .map(|(variant, (af, afd))| {
+                    (
+                        variant.0,
+                        *af,
+                        afd.iter()
+                            .map(|(vaf, density)| (NotNan::into_inner(*vaf), density.0.to_string()))
+                            .collect(),
+                        variant_calls.read_depth(variant),
+                    )
+                })
+                .collect(),
+            error_rates: variant_calls
+                .2
+                .iter()
+                .map(|(variant, error_rate)| (variant.0, *error_rate))
+                .collect(),
+            haplotype_variants: haplotype_variants
+                .iter()
+                .map(|(variant, matrix)| {
+                    (
+                        variant.0,
+                        matrix
+                            .iter()
+                            .map(|(haplotype, (status, covered))| {
+                                let status = match status {
+                                    VariantStatus::Present => 1,
+                                    VariantStatus::NotPresent => 0,
+                                    VariantStatus::Unknown => 2,
+                                };
+                                (haplotype.to_string(), status, *covered)
+                            })
+                            .collect(),
+                    )
+                })
+                .collect(),
+            panel: panel.clone(),
+        };
+        serde_json::to_writer(fs::File::create(&self.path)?, &cached)?;
+        Ok(())
+    }
+
+    //load_or_parse returns the cached inputs if possible, otherwise it parses them and updates the cache.
+    //The inputs are the variant calls, the candidate panel at the called variants and the summary of the whole panel.
+    pub fn load_or_parse<F: FnOnce() -> Result<(VariantCalls, HaplotypeVariants, PanelSummary)>>(
+        cache: Option<&InputCache>,
+        options: &CacheOptions,
+        parse: F,
+    ) -> Result<(VariantCalls, HaplotypeVariants, PanelSummary)> {
+        if let Some(cache) = cache {
+            if let Some(inputs) = cache.load(options)? {
+                return Ok(inputs);
+            }
+        }
+        let (variant_calls, haplotype_variants, panel) = parse()?;
+        if let Some(cache) = cache {
+            cache.store(options, &variant_calls, &haplotype_variants, &panel)?;
+        }
+        Ok((variant_calls, haplotype_variants, panel))
+    }
+}
+
+//PanelIssue is a problem of a candidate file, see HaplotypeVariants::validate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PanelIssue {
+    //the ID of a record is not an integer, such that it can't be matched with the calls
+    InvalidId { id: String },
+    //the FORMAT field C (coverage of the haplotypes) is not defined
+    MissingCoverage,
+    //no haplotype carries the variant
+    NoPresentGenotype { variant: VariantID },
+    //several records share the ID, only the last one is kept
+    DuplicateId { variant: VariantID },
+    //the file has no samples, i.e. haplotypes
+    NoSamples,
+}
+
+impl fmt::Display for PanelIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PanelIssue::InvalidId { id } => write!(f, "record ID {:?} is not an integer", id),
+            PanelIssue::MissingCoverage => write!(f, "the FORMAT field C is not defined"),
+            PanelIssue::NoPresentGenotype { variant } => {
+                write!(f, "variant {} is not present in any haplotype", variant.0)
+            }
+            PanelIssue::DuplicateId { variant } => {
+                write!(f, "variant ID {} occurs in several records", variant.0)
+            }
+            PanelIssue::NoSamples => write!(f, "there are no haplotypes (samples)"),
+        }
+    }
+}
+
+//fail_on_issue fails on the issues of a panel that prevent calling and ignores the others, as done when reading
+//the candidates for calling. Duplicate IDs fail as well, as the genotypes of all but one of the records would be lost.
+fn fail_on_issue(issue: PanelIssue) -> Result<()> {
+    match issue {
+        PanelIssue::InvalidId { id } => Err(OrthanqError::MissingField {
+            variant: id,
+            field: "ID".to_string(),
+        }
+        .into()),
+        PanelIssue::DuplicateId { variant } => {
+            Err(OrthanqError::DuplicateVariantId { variant: variant.0 }.into())
+        }
+        PanelIssue::NoSamples => Err(OrthanqError::EmptyPanel.into()),
+        _ => Ok(()),
+    }
+}
+
+//HaplotypeVariants holds the panel of the candidate file: per variant, the genotype of every haplotype of the panel
+//and whether the haplotype is characterized at the variant locus (C field). Every variant lists the same haplotypes.
 #[derive(Derefable, Debug, Clone, PartialEq, Eq, PartialOrd, DerefMut)]
 pub struct HaplotypeVariants(
     #[deref] pub BTreeMap<VariantID, BTreeMap<Haplotype, (VariantStatus, bool)>>,
 );
 
-#[derive(Derefable, Debug, Clone)]
-pub struct HaplotypeGraph {
-    #[deref]
-    graph: Graph<(Haplotype, Haplotype), i32, petgraph::Undirected>,
-    node_indices: HashMap<(Haplotype, Haplotype), NodeIndex>,
+//PanelMatrix is a compact representation of the candidate panel for large panels (tens of thousands of haplotypes,
+//hundreds of thousands of variants), where the nested maps of HaplotypeVariants exhaust the memory. Genotypes (set if Present)
+//and coverages are bit matrices with one row per variant and one column per haplotype, the columns are sorted by name
+//as the haplotypes of HaplotypeVariants. The candidate records are parsed one at a time into the bit matrices.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PanelMatrix {
+    haplotypes: Vec<Haplotype>,
+    rows: BTreeMap<VariantID, u64>,
+    genotypes: BitVec,
+    coverages: BitVec,
 }
 
-impl HaplotypeGraph {
-    pub fn get_node_index(&self, query: &(Haplotype, Haplotype)) -> Option<NodeIndex> {
-        self.node_indices.get(query).cloned()
+impl PanelMatrix {
+    pub fn new(haplotype_variants: &mut bcf::Reader) -> Result<Self> {
+        Self::new_in_region(haplotype_variants, false, |_, _| true, &mut fail_on_issue)
     }
-}
 
-#[derive(Derefable, Debug, Clone)]
-pub struct DistanceMatrix(#[deref] pub BTreeMap<(Haplotype, Haplotype), usize>);
+    pub fn new_requiring_coverage(haplotype_variants: &mut bcf::Reader) -> Result<Self> {
+        Self::new_in_region(haplotype_variants, true, |_, _| true, &mut fail_on_issue)
+    }
 
-impl HaplotypeVariants {
-    pub fn new(haplotype_variants: &mut bcf::Reader) -> Result<Self> {
-        let mut variant_records = BTreeMap::new();
+    //see HaplotypeVariants::new_in_region.
+    fn new_in_region<F: Fn(&[u8], u64) -> bool>(
+        haplotype_variants: &mut bcf::Reader,
+        require_coverage: bool,
+        in_region: F,
+        report: &mut dyn FnMut(PanelIssue) -> Result<()>,
+    ) -> Result<Self> {
+        let has_coverage = haplotype_variants.header().format_type(b"C").is_ok();
+        if !has_coverage {
+            report(PanelIssue::MissingCoverage)?;
+            if require_coverage {
+                return Err(OrthanqError::MissingCoverage.into());
+            }
+            warn!("the candidate variants lack the FORMAT field C, all haplotypes are assumed to be characterized at all variant loci");
+        }
+        let samples: Vec<Haplotype> = haplotype_variants
+            .header()
+            .samples()
+            .iter()
+            .map(|sample| Haplotype(str::from_utf8(sample).unwrap().to_string()))
+            .collect();
+        let mut haplotypes = samples.clone();
+        haplotypes.sort();
+        haplotypes.dedup();
+        //column of each sample of the candidate file
+        let columns: Vec<u64> = samples
+            .iter()
+            .map(|sample| haplotypes.binary_search(sample).unwrap() as u64)
+            .collect();
+        let width = haplotypes.len() as u64;
+        let mut panel = PanelMatrix {
+            haplotypes,
+            rows: BTreeMap::new(),
+            genotypes: BitVec::new(),
+            coverages: BitVec::new(),
+        };
         for record_result in haplotype_variants.records() {
             let record = record_result?;
-            let variant_id: VariantID = VariantID(String::from_utf8(record.id())?.parse().unwrap());
-            let header = record.header();
+            let chrom = record.header().rid2name(record.rid().unwrap())?;
+            if !in_region(chrom, record.pos() as u64) {
+                continue;
+            }
+            let id = String::from_utf8(record.id())?;
+            let variant_id = match id.parse() {
+                Ok(id) => VariantID(id),
+                Err(_) => {
+                    report(PanelIssue::InvalidId { id })?;
+                    continue;
+                }
+            };
             let gts = record.genotypes()?;
-            let loci = record.format(b"C").integer().unwrap();
-            let mut matrices = BTreeMap::new();
-            for (index, haplotype) in header.samples().iter().enumerate() {
-                let haplotype = Haplotype(str::from_utf8(haplotype).unwrap().to_string());
-                //generate phased genotypes.
-                for gta in gts.get(index).iter().skip(1) {
-                    //maternal and paternal gts will be the same in the vcf i.e. 0|0 and 1|1
-                    if *gta == Unphased(1) || *gta == Phased(1) {
-                        matrices.insert(
-                            haplotype.clone(),
-                            (VariantStatus::Present, loci[index] == &[1]),
-                        );
-                    } else {
-                        matrices.insert(
-                            haplotype.clone(),
-                            (VariantStatus::NotPresent, loci[index] == &[1]),
-                        );
-                    }
+            let loci = if has_coverage {
+                Some(record.format(b"C").integer()?)
+            } else {
+                None
+            };
+            let mut present = vec![false; width as usize];
+            let mut covered = vec![true; width as usize];
+            for (index, column) in columns.iter().enumerate() {
+                //maternal and paternal gts will be the same in the vcf i.e. 0|0 and 1|1
+                present[*column as usize] = gts
+                    .get(index)
+                    .iter()
+                    .skip(1)
+                    .last()
+                    .map_or(false, |gta| *gta == Unphased(1) || *gta == Phased(1));
+                covered[*column as usize] = loci.as_ref().map_or(true, |loci| loci[index] == &[1]);
+            }
+            if !present.iter().any(|present| *present) {
+                report(PanelIssue::NoPresentGenotype {
+                    variant: variant_id,
+                })?;
+            }
+            //a duplicate ID replaces the row of the earlier record
+            let row = match panel.rows.get(&variant_id) {
+                Some(row) => {
+                    report(PanelIssue::DuplicateId {
+                        variant: variant_id,
+                    })?;
+                    *row
                 }
+                None => {
+                    let row = panel.rows.len() as u64;
+                    panel.rows.insert(variant_id, row);
+                    panel.genotypes.resize((row + 1) * width, false);
+                    panel.coverages.resize((row + 1) * width, false);
+                    row
+                }
+            };
+            for column in 0..width {
+                panel
+                    .genotypes
+                    .set(row * width + column, present[column as usize]);
+                panel
+                    .coverages
+                    .set(row * width + column, covered[column as usize]);
             }
-            variant_records.insert(variant_id, matrices);
         }
-        Ok(HaplotypeVariants(variant_records))
+        if haplotype_variants.header().sample_count() == 0 {
+            report(PanelIssue::NoSamples)?;
+        }
+        Ok(panel)
     }
 
-    pub fn filter_for_variants(&self, variant_ids: &Vec<VariantID>) -> Result<HaplotypeVariants> {
-        let mut filtered_haplotype_variants: BTreeMap<
-            VariantID,
-            BTreeMap<Haplotype, (VariantStatus, bool)>,
-        > = BTreeMap::new();
-        for (variant, haplotype_map) in self.iter() {
-            if variant_ids.contains(&variant) {
-                filtered_haplotype_variants.insert(variant.clone(), haplotype_map.clone());
-            }
-        }
-        Ok(HaplotypeVariants(filtered_haplotype_variants))
+    pub fn haplotypes(&self) -> &[Haplotype] {
+        &self.haplotypes
     }
 
-    pub fn filter_for_haplotypes(&self, haplotypes: &Vec<Haplotype>) -> Result<Self> {
-        let mut new_haplotype_variants: BTreeMap<
-            VariantID,
-            BTreeMap<Haplotype, (VariantStatus, bool)>,
-        > = BTreeMap::new();
-        for (variant, matrix_map) in self.iter() {
-            let mut new_matrix_map = BTreeMap::new();
-            for (haplotype_m, (variant_status, coverage_status)) in matrix_map {
-                if haplotypes.contains(&haplotype_m) {
-                    new_matrix_map.insert(
-                        haplotype_m.clone(),
-                        (variant_status.clone(), coverage_status.clone()),
-                    );
-                }
+    pub fn variants(&self) -> Vec<VariantID> {
+        self.rows.keys().cloned().collect()
+    }
+
+    //row returns the genotypes and coverages of the haplotypes (in the order of haplotypes()) at the given variant.
+    pub fn row(&self, variant: &VariantID) -> Option<(Vec<VariantStatus>, BitVec)> {
+        let width = self.haplotypes.len() as u64;
+        self.rows.get(variant).map(|row| {
+            let mut genotypes = Vec::with_capacity(self.haplotypes.len());
+            let mut coverages = BitVec::new();
+            for column in row * width..(row + 1) * width {
+                genotypes.push(if self.genotypes[column] {
+                    VariantStatus::Present
+                } else {
+                    VariantStatus::NotPresent
+                });
+                coverages.push(self.coverages[column]);
             }
-            new_haplotype_variants.insert(variant.clone(), new_matrix_map);
-        }
-        Ok(HaplotypeVariants(new_haplotype_variants))
+            (genotypes, coverages)
+        })
     }
 
-    pub fn find_common_variants(
-        &self,
-        variant_calls: &VariantCalls,
-        haplotypes: &Vec<Haplotype>,
-    ) -> Result<Vec<VariantID>> {
-        let candidate_matrix_values: Vec<(Vec<VariantStatus>, BitVec)> = CandidateMatrix::new(self)
-            .unwrap()
-            .values()
+    //to_haplotype_variants expands the panel into HaplotypeVariants, haplotype_variants_for does the same
+    //for the given variants only, e.g. the called ones, which keeps the nested maps small.
+    pub fn to_haplotype_variants(&self) -> HaplotypeVariants {
+        self.haplotype_variants_for(&self.variants())
+    }
+
+    pub fn haplotype_variants_for(&self, variant_ids: &[VariantID]) -> HaplotypeVariants {
+        HaplotypeVariants(
+            variant_ids
+                .iter()
+                .filter_map(|variant| {
+                    self.row(variant).map(|(genotypes, coverages)| {
+                        let matrix = self
+                            .haplotypes
+                            .iter()
+                            .cloned()
+                            .zip(genotypes.into_iter())
+                            .enumerate()
+                            .map(|(i, (haplotype, genotype))| {
+                                (haplotype, (genotype, coverages[i as u64]))
+                            })
+                            .collect();
+                        (*variant, matrix)
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    //merge equals HaplotypeVariants::merge of the expanded panels, without expanding them.
+    pub fn merge(&self, other: &PanelMatrix, namespace: &str) -> Self {
+        //as in HaplotypeVariants, a panel without variants has no haplotypes
+        let own_haplotypes: &[Haplotype] = if self.rows.is_empty() {
+            &[]
+        } else {
+            &self.haplotypes
+        };
+        let other_haplotypes: Vec<Haplotype> = if other.rows.is_empty() {
+            Vec::new()
+        } else {
+            other
+                .haplotypes
+                .iter()
+                .map(|haplotype| {
+                    if own_haplotypes.binary_search(haplotype).is_ok() {
+                        Haplotype(format!("{}/{}", namespace, haplotype.0))
+                    } else {
+                        haplotype.clone()
+                    }
+                })
+                .collect()
+        };
+        let mut haplotypes: Vec<Haplotype> = own_haplotypes
+            .iter()
+            .chain(other_haplotypes.iter())
             .cloned()
             .collect();
-        let mut common_variants = Vec::new();
-        for ((_genotype_matrix, coverage_matrix), (variant, (_af, _))) in
-            candidate_matrix_values.iter().zip(variant_calls.iter())
-        {
-            let mut counter = 0;
-            for (i, _haplotype) in haplotypes.iter().enumerate() {
-                if coverage_matrix[i as u64] {
-                    counter += 1;
+        haplotypes.sort();
+        haplotypes.dedup();
+        let columns = |names: &[Haplotype]| -> Vec<u64> {
+            names
+                .iter()
+                .map(|name| haplotypes.binary_search(name).unwrap() as u64)
+                .collect()
+        };
+        let own_columns = columns(own_haplotypes);
+        let other_columns = columns(&other_haplotypes);
+        let width = haplotypes.len() as u64;
+        let variants: BTreeSet<VariantID> =
+            self.rows.keys().chain(other.rows.keys()).cloned().collect();
+        let mut merged = PanelMatrix {
+            haplotypes,
+            rows: BTreeMap::new(),
+            genotypes: BitVec::new(),
+            coverages: BitVec::new(),
+        };
+        for (row, variant) in variants.into_iter().enumerate() {
+            let row = row as u64;
+            merged.rows.insert(variant, row);
+            //the haplotypes of a panel without the variant are covered and without it
+            merged.genotypes.resize((row + 1) * width, false);
+            merged.coverages.resize((row + 1) * width, true);
+            for (panel, columns) in &[(self, &own_columns), (other, &other_columns)] {
+                if let Some((genotypes, coverages)) = panel.row(&variant) {
+                    for (i, column) in columns.iter().enumerate() {
+                        merged
+                            .genotypes
+                            .set(row * width + column, genotypes[i] == VariantStatus::Present);
+                        merged
+                            .coverages
+                            .set(row * width + column, coverages[i as u64]);
+                    }
                 }
             }
-            if counter == haplotypes.len() {
-                common_variants.push(variant.clone());
-            }
         }
-        Ok(common_variants)
+        merged
     }
-    pub fn filter_haplotype_variants(&self, variants: &Vec<VariantID>) -> Result<Self> {
-        let mut haplotype_variants_filtered = self.clone();
-        for (v, _) in self.iter() {
-            if !variants.contains(&v) {
-                haplotype_variants_filtered.remove_entry(&v);
+
+    //see HaplotypeVariants::check_overlap.
+    pub fn check_overlap(&self, variant_ids: &[VariantID]) -> Result<()> {
+        check_overlap(variant_ids, self.rows.keys())
+    }
+
+    //fingerprint equals the fingerprint of the expanded HaplotypeVariants.
+    pub fn fingerprint(&self) -> String {
+        let mut fingerprint = Fingerprint::new();
+        for variant in self.rows.keys() {
+            fingerprint.variant(variant);
+            let (genotypes, coverages) = self.row(variant).unwrap();
+            for (i, (haplotype, genotype)) in
+                self.haplotypes.iter().zip(genotypes.iter()).enumerate()
+            {
+                fingerprint.haplotype(haplotype, genotype, coverages[i as u64]);
             }
         }
-        Ok(haplotype_variants_filtered)
+        fingerprint.to_string()
+    }
+}
+
+//PanelSummary describes the whole candidate panel, of which the callers only expand the called variants
+//(see PanelMatrix::haplotype_variants_for).
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct PanelSummary {
+    //see HaplotypeVariants::fingerprint
+    pub fingerprint: String,
+    //number of variants of the panel
+    pub variants: usize,
+}
+
+impl PanelSummary {
+    pub fn new(panel: &PanelMatrix) -> Self {
+        PanelSummary {
+            fingerprint: panel.fingerprint(),
+            variants: panel.rows.len(),
+        }
+    }
+}
+
+//Fingerprint is the 64 bit FNV-1a hash behind HaplotypeVariants::fingerprint and PanelMatrix::fingerprint.
+struct Fingerprint(u64);
+
+impl Fingerprint {
+    fn new() -> Self {
+        Fingerprint(0xcbf29ce484222325)
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn variant(&mut self, variant: &VariantID) {
+        self.update(&variant.0.to_le_bytes());
+    }
+
+    fn haplotype(&mut self, haplotype: &Haplotype, status: &VariantStatus, covered: bool) {
+        self.update(haplotype.as_bytes());
+        let status = match status {
+            VariantStatus::Present => 1,
+            VariantStatus::NotPresent => 0,
+            VariantStatus::Unknown => 2,
+        };
+        self.update(&[0, status, covered as u8]);
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+//check_overlap is behind HaplotypeVariants::check_overlap and PanelMatrix::check_overlap.
+fn check_overlap<'a>(
+    variant_ids: &[VariantID],
+    candidates: impl Iterator<Item = &'a VariantID>,
+) -> Result<()> {
+    let candidates: BTreeSet<VariantID> = candidates.cloned().collect();
+    if variant_ids
+        .iter()
+        .any(|variant| candidates.contains(variant))
+    {
+        Ok(())
+    } else {
+        let range = |mut ids: Vec<VariantID>| {
+            ids.sort();
+            match (ids.first(), ids.last()) {
+                (Some(first), Some(last)) => format!("{}-{}", first.0, last.0),
+                _ => "none".to_string(),
+            }
+        };
+        Err(OrthanqError::DisjointVariants {
+            calls: range(variant_ids.to_vec()),
+            candidates: range(candidates.into_iter().collect()),
+        }
+        .into())
+    }
+}
+
+#[derive(Derefable, Debug, Clone)]
+pub struct HaplotypeGraph {
+    #[deref]
+    graph: Graph<(Haplotype, Haplotype), i32, petgraph::Undirected>,
+    node_indices: HashMap<(Haplotype, Haplotype), NodeIndex>,
+}
+
+impl HaplotypeGraph {
+    pub fn get_node_index(&self, query: &(Haplotype, Haplotype)) -> Option<NodeIndex> {
+        self.node_indices.get(query).cloned()
+    }
+}
+
+#[derive(Derefable, Debug, Clone)]
+pub struct DistanceMatrix(#[deref] pub BTreeMap<(Haplotype, Haplotype), usize>);
+
+impl HaplotypeVariants {
+    pub fn new(haplotype_variants: &mut bcf::Reader) -> Result<Self> {
+        Self::new_in_region(haplotype_variants, false, |_, _| true, &mut fail_on_issue)
+    }
+
+    //validate checks the candidate file for common problems (see PanelIssue) and returns all issues that were found
+    //instead of failing at the first one.
+    pub fn validate(haplotype_variants: &mut bcf::Reader) -> Result<Vec<PanelIssue>> {
+        let mut issues = Vec::new();
+        Self::new_in_region(haplotype_variants, false, |_, _| true, &mut |issue| {
+            issues.push(issue);
+            Ok(())
+        })?;
+        Ok(issues)
+    }
+
+    //new_requiring_coverage fails if the candidate file lacks the FORMAT field C, whereas new assumes all haplotypes
+    //to be covered at all variants in that case (with a warning).
+    pub fn new_requiring_coverage(haplotype_variants: &mut bcf::Reader) -> Result<Self> {
+        Self::new_in_region(haplotype_variants, true, |_, _| true, &mut fail_on_issue)
+    }
+
+    //new_for_gene only reads the candidate records within the region of the given HLA gene (e.g. A or HLA-A,
+    //see preprocess::hla::GeneRegions) and keeps the haplotypes of that gene, such that one gene
+    //at a time can be called from a multi-gene panel. The chromosome may be named in Ensembl (6) or UCSC style (chr6).
+    pub fn new_for_gene(haplotype_variants: &mut bcf::Reader, gene: &str) -> Result<Self> {
+        let gene = gene.trim_start_matches("HLA-");
+        let gene_regions = GeneRegions::bundled();
+        let region = gene_regions
+            .get(gene)
+            .ok_or_else(|| OrthanqError::UnknownGenes {
+                genes: vec![gene.to_string()],
+                known: gene_regions.genes(),
+            })?;
+        let in_gene = Self::new_in_region(
+            haplotype_variants,
+            false,
+            |chrom, pos| {
+                chrom.strip_prefix(b"chr").unwrap_or(chrom) == region.chromosome.as_bytes()
+                    && pos >= region.start
+                    && pos < region.end
+            },
+            &mut fail_on_issue,
+        )?;
+        let mut haplotypes: Vec<Haplotype> = in_gene
+            .values()
+            .flat_map(|matrix| matrix.keys())
+            .filter(|haplotype| haplotype.starts_with(&format!("{}*", gene)))
+            .cloned()
+            .collect();
+        haplotypes.sort();
+        haplotypes.dedup();
+        if haplotypes.is_empty() {
+            return Err(OrthanqError::EmptyPanel.into());
+        }
+        in_gene.filter_for_haplotypes(&haplotypes)
+    }
+
+    //new_in_region reads the candidate records for which 'in_region' holds, given the chromosome and the (0-based) position.
+    //Candidate files from other sources often lack the coverage (FORMAT field C). This is checked once in the header:
+    //if 'require_coverage' is set, an error is returned, otherwise all haplotypes are considered covered.
+    //Problems of the panel are passed to 'report', which decides whether to fail (see fail_on_issue) or to go on.
+    fn new_in_region<F: Fn(&[u8], u64) -> bool>(
+        haplotype_variants: &mut bcf::Reader,
+        require_coverage: bool,
+        in_region: F,
+        report: &mut dyn FnMut(PanelIssue) -> Result<()>,
+    ) -> Result<Self> {
+        Ok(
+            PanelMatrix::new_in_region(haplotype_variants, require_coverage, in_region, report)?
+                .to_haplotype_variants(),
+        )
+    }
+
+    //fingerprint is a stable hash (64 bit FNV-1a, as hex) of the panel contents, i.e. the variant IDs and per variant
+    //the haplotype names with their genotype and coverage. As both are visited in sorted order, the fingerprint
+    //doesn't depend on the order of the records or samples in the candidate file, but changes with any change of the panel.
+    pub fn fingerprint(&self) -> String {
+        let mut fingerprint = Fingerprint::new();
+        for (variant, matrix) in self.iter() {
+            fingerprint.variant(variant);
+            for (haplotype, (status, covered)) in matrix {
+                fingerprint.haplotype(haplotype, status, *covered);
+            }
+        }
+        fingerprint.to_string()
+    }
+
+    //merge combines the panel with the panel of another candidate file (e.g. of another gene) into one, such that
+    //the haplotypes of both can be called jointly. The variants are the union of both panels. At a variant of only one
+    //of the panels, the haplotypes of the other panel are considered covered and without the variant.
+    //Variant IDs that occur in both panels are considered the same variant and the haplotypes of both records are combined.
+    //Haplotype names of the other panel that already exist in this panel are namespaced, i.e. prefixed with '<namespace>/'.
+    pub fn merge(&self, other: &HaplotypeVariants, namespace: &str) -> Self {
+        let own_haplotypes: BTreeSet<Haplotype> = self
+            .values()
+            .flat_map(|matrix| matrix.keys().cloned())
+            .collect();
+        let rename = |haplotype: &Haplotype| {
+            if own_haplotypes.contains(haplotype) {
+                Haplotype(format!("{}/{}", namespace, haplotype.0))
+            } else {
+                haplotype.clone()
+            }
+        };
+        let other_haplotypes: BTreeSet<Haplotype> = other
+            .values()
+            .flat_map(|matrix| matrix.keys().map(rename))
+            .collect();
+        let absent = |haplotypes: &BTreeSet<Haplotype>| {
+            haplotypes
+                .iter()
+                .map(|haplotype| (haplotype.clone(), (VariantStatus::NotPresent, true)))
+                .collect::<Vec<_>>()
+        };
+
+        let mut merged = BTreeMap::new();
+        for variant in self.keys().chain(other.keys()) {
+            let mut matrix = BTreeMap::new();
+            match self.get(variant) {
+                Some(own) => matrix.extend(own.clone()),
+                None => matrix.extend(absent(&own_haplotypes)),
+            }
+            match other.get(variant) {
+                Some(others) => matrix.extend(
+                    others
+                        .iter()
+                        .map(|(haplotype, status)| (rename(haplotype), status.clone())),
+                ),
+                None => matrix.extend(absent(&other_haplotypes)),
+            }
+            merged.insert(*variant, matrix);
+        }
+        HaplotypeVariants(merged)
+    }
+
+    //check_overlap makes sure that at least one of the called variants is part of the candidates.
+    //Otherwise, the calls were most likely made against a different candidate file and the run would be meaningless.
+    pub fn check_overlap(&self, variant_ids: &[VariantID]) -> Result<()> {
+        check_overlap(variant_ids, self.keys())
+    }
+
+    pub fn filter_for_variants(&self, variant_ids: &Vec<VariantID>) -> Result<HaplotypeVariants> {
+        let mut filtered_haplotype_variants: BTreeMap<
+            VariantID,
+            BTreeMap<Haplotype, (VariantStatus, bool)>,
+        > = BTreeMap::new();
+        for (variant, haplotype_map) in self.iter() {
+            if variant_ids.contains(&variant) {
+                filtered_haplotype_variants.insert(variant.clone(), haplotype_map.clone());
+            }
+        }
+        Ok(HaplotypeVariants(filtered_haplotype_variants))
+    }
+
+    pub fn filter_for_haplotypes(&self, haplotypes: &Vec<Haplotype>) -> Result<Self> {
+        let mut new_haplotype_variants: BTreeMap<
+            VariantID,
+            BTreeMap<Haplotype, (VariantStatus, bool)>,
+        > = BTreeMap::new();
+        for (variant, matrix_map) in self.iter() {
+            let mut new_matrix_map = BTreeMap::new();
+            for (haplotype_m, (variant_status, coverage_status)) in matrix_map {
+                if haplotypes.contains(&haplotype_m) {
+                    new_matrix_map.insert(
+                        haplotype_m.clone(),
+                        (variant_status.clone(), coverage_status.clone()),
+                    );
+                }
+            }
+            new_haplotype_variants.insert(variant.clone(), new_matrix_map);
+        }
+        Ok(HaplotypeVariants(new_haplotype_variants))
+    }
+
+    pub fn find_common_variants(
+        &self,
+        variant_calls: &VariantCalls,
+        haplotypes: &Vec<Haplotype>,
+    ) -> Result<Vec<VariantID>> {
+        let candidate_matrix = CandidateMatrix::new(self).unwrap();
+        let mut common_variants = Vec::new();
+        //join the candidate matrix and the calls by variant, variants that are missing in either of them are skipped
+        for (variant, (_genotype_matrix, coverage_matrix)) in candidate_matrix
+            .iter()
+            .filter(|(variant, _)| variant_calls.contains_key(variant))
+        {
+            let mut counter = 0;
+            for (i, _haplotype) in haplotypes.iter().enumerate() {
+                if coverage_matrix[i as u64] {
+                    counter += 1;
+                }
+            }
+            if counter == haplotypes.len() {
+                common_variants.push(variant.clone());
+            }
+        }
+        Ok(common_variants)
+    }
+    pub fn filter_haplotype_variants(&self, variants: &Vec<VariantID>) -> Result<Self> {
+        let mut haplotype_variants_filtered = self.clone();
+        for (v, _) in self.iter() {
+            if !variants.contains(&v) {
+                haplotype_variants_filtered.remove_entry(&v);
+            }
+        }
+        Ok(haplotype_variants_filtered)
     }
 
     pub fn find_equivalence_classes_with_graph(
@@ -406,7 +1556,7 @@ impl HaplotypeVariants {
         &self,
         application: &str,
     ) -> Result<DistanceMatrix> {
-        //initialize distances as a HashMap
+        //initialize distances as a BTreeMap
         let mut distances: BTreeMap<(Haplotype, Haplotype), usize> = BTreeMap::new();
 
         //compute hamming distances between each pair of haplotypes
@@ -429,7 +1579,7 @@ impl HaplotypeVariants {
                         distance += 1;
                     }
                 }
-                //insert the distance into the distances HashMap
+                //insert the distance into the distances BTreeMap
                 distances.insert((hap1.clone(), hap2.clone()), distance);
             }
         }
@@ -442,6 +1592,351 @@ impl HaplotypeVariants {
     }
 }
 
+//Manifest collects run-level summary values of a calling run; it is written as manifest.json
+//next to the output table. Only the values that were computed are written.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct Manifest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mixture_entropy: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timed_out: Option<bool>,
+    //true if the events were searched approximately (see max_events) and some of them were left out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capped: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub informative_variants: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lp_residual: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_coverage: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<QualityFlag>,
+    //natural log of the model evidence; if the computation timed out, it only covers the evaluated events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_evidence: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub haplotype_funnel: Option<HaplotypeFunnel>,
+    //runtime metrics: the number of parsed variant calls, the time of the linear program (in seconds)
+    //and the number of events evaluated by the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variants: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lp_time_s: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub evaluated_events: Option<usize>,
+    //true if several events share the best posterior density, see select_best_event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ambiguous: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tied_best_events: Option<usize>,
+    //fraction of the linear program that is not assigned to any haplotype, only with relax_sum
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lp_unassigned_fraction: Option<f64>,
+    //fingerprint of the candidate panel, see HaplotypeVariants::fingerprint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub panel_fingerprint: Option<String>,
+    //true if a homozygous best solution is challenged by a heterozygous one, see homozygous_ambiguity
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub homozygous_ambiguity: Option<bool>,
+}
+
+//QcReport consolidates the diagnostics of a calling run into a single document, qc.json next to the output, e.g. to be
+//ingested by a LIMS. The core metrics (taken from the manifest) are always present, the sections of the optional
+//diagnostics (fit divergence, presence concordance) only if they were computed. schema_version is increased with each
+//incompatible change of the layout.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct QcReport {
+    pub schema_version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<QualityFlag>,
+    pub coverage: QcCoverage,
+    pub solution: QcSolution,
+    //haplotypes whose fractions diverge between the linear program and the posterior, see check_lp_posterior_consistency
+    pub lp_posterior_divergent: Vec<Haplotype>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fit_divergence: Option<QcFitDivergence>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_concordance: Option<QcPresenceConcordance>,
+}
+
+//QcCoverage are the called variants, the informative ones among them and the minimum read depth of the calls.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct QcCoverage {
+    pub variants: Option<usize>,
+    pub informative_variants: Option<usize>,
+    pub min_coverage: Option<i32>,
+}
+
+//QcSolution describes the best solution and how reliably it was found.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct QcSolution {
+    pub lp_residual: Option<f64>,
+    pub log_evidence: Option<f64>,
+    pub mixture_entropy: Option<f64>,
+    pub ambiguous: Option<bool>,
+    pub timed_out: Option<bool>,
+    pub capped: Option<bool>,
+}
+
+//QcFitDivergence summarizes the fit divergences (see FitDivergence) of the variants, poorly fit variants are the ones
+//with a divergence above POORLY_FIT_DIVERGENCE.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct QcFitDivergence {
+    pub variants: usize,
+    pub max_divergence: Option<f64>,
+    pub poorly_fit_variants: Vec<VariantID>,
+}
+
+//POORLY_FIT_DIVERGENCE is the divergence (in nats) above which a variant counts as poorly fit, i.e. the call supports
+//another allele frequency more than 10 times better than the expected one.
+pub const POORLY_FIT_DIVERGENCE: f64 = std::f64::consts::LN_10;
+
+//QcPresenceConcordance summarizes the presence concordances (see PresenceConcordance): unexplained variants are called
+//present but not implied by the best solution, missing variants are implied but called absent.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct QcPresenceConcordance {
+    pub variants: usize,
+    pub ambiguous: usize,
+    pub unexplained_variants: Vec<VariantID>,
+    pub missing_variants: Vec<VariantID>,
+}
+
+impl QcReport {
+    pub fn new(manifest: &Manifest) -> Self {
+        QcReport {
+            schema_version: 1,
+            quality: manifest.quality,
+            coverage: QcCoverage {
+                variants: manifest.variants,
+                informative_variants: manifest.informative_variants,
+                min_coverage: manifest.min_coverage,
+            },
+            solution: QcSolution {
+                lp_residual: manifest.lp_residual,
+                log_evidence: manifest.log_evidence,
+                mixture_entropy: manifest.mixture_entropy,
+                ambiguous: manifest.ambiguous,
+                timed_out: manifest.timed_out,
+                capped: manifest.capped,
+            },
+            lp_posterior_divergent: Vec::new(),
+            fit_divergence: None,
+            presence_concordance: None,
+        }
+    }
+
+    pub fn with_lp_posterior_divergent(mut self, divergent: &[(Haplotype, f64, f64)]) -> Self {
+        self.lp_posterior_divergent = divergent
+            .iter()
+            .map(|(haplotype, _, _)| haplotype.clone())
+            .collect();
+        self
+    }
+
+    pub fn with_fit_divergences(mut self, fits: &[FitDivergence]) -> Self {
+        self.fit_divergence = Some(QcFitDivergence {
+            variants: fits.len(),
+            max_divergence: fits.iter().map(|fit| fit.divergence).fold(
+                None,
+                |max: Option<f64>, divergence| {
+                    Some(max.map_or(divergence, |max| max.max(divergence)))
+                },
+            ),
+            poorly_fit_variants: fits
+                .iter()
+                .filter(|fit| fit.divergence > POORLY_FIT_DIVERGENCE)
+                .map(|fit| fit.variant)
+                .collect(),
+        });
+        self
+    }
+
+    pub fn with_presence_concordance(mut self, concordances: &[PresenceConcordance]) -> Self {
+        let variants = |observed: bool| {
+            concordances
+                .iter()
+                .filter(|concordance| {
+                    concordance.is_discordant() && concordance.observed == Some(observed)
+                })
+                .map(|concordance| concordance.variant)
+                .collect::<Vec<VariantID>>()
+        };
+        self.presence_concordance = Some(QcPresenceConcordance {
+            variants: concordances.len(),
+            ambiguous: concordances
+                .iter()
+                .filter(|concordance| concordance.observed.is_none())
+                .count(),
+            unexplained_variants: variants(true),
+            missing_variants: variants(false),
+        });
+        self
+    }
+
+    pub fn write(&self, outdir: &PathBuf) -> Result<()> {
+        let mut parent = outdir.clone();
+        parent.pop();
+        fs::create_dir_all(&parent)?;
+        let file = fs::File::create(parent.join("qc.json"))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+//HaplotypeFunnel counts the haplotypes that survive each stage of a run: all haplotypes of the panel,
+//the ones selected by the linear program (above lp_cutoff), after the extension by similar haplotypes
+//and the ones finally passed to the model.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct HaplotypeFunnel {
+    pub panel: usize,
+    pub lp_selected: usize,
+    pub extended: usize,
+    pub modeled: usize,
+}
+
+impl HaplotypeFunnel {
+    pub fn log(&self) {
+        info!(
+            "haplotypes per stage: {} in panel, {} selected by linear program, {} after extension, {} modeled",
+            self.panel, self.lp_selected, self.extended, self.modeled
+        );
+    }
+}
+
+//QualityFlag is a per-locus triage flag: PASS typings can be trusted, WARN typings should be reviewed
+//and FAIL typings are unreliable.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum QualityFlag {
+    Pass,
+    Warn,
+    Fail,
+}
+
+//QualityThresholds define the quality flag of a locus. The flag is the worst over all criteria, with each criterion
+//yielding FAIL below (or, for the residual, above) its fail threshold and WARN below (above) its warn threshold.
+//Defaults: at least 10 (warn) and 3 (fail) informative variants, an LP residual of at most 0.05 (warn) and 0.15 (fail)
+//and a minimum coverage of at least 20 (warn) and 5 (fail) reads.
+#[derive(Debug, Clone)]
+pub struct QualityThresholds {
+    pub warn_informative_variants: usize,
+    pub fail_informative_variants: usize,
+    pub warn_residual: f64,
+    pub fail_residual: f64,
+    pub warn_coverage: i32,
+    pub fail_coverage: i32,
+}
+
+impl Default for QualityThresholds {
+    fn default() -> Self {
+        QualityThresholds {
+            warn_informative_variants: 10,
+            fail_informative_variants: 3,
+            warn_residual: 0.05,
+            fail_residual: 0.15,
+            warn_coverage: 20,
+            fail_coverage: 5,
+        }
+    }
+}
+
+impl QualityThresholds {
+    pub fn flag(
+        &self,
+        informative_variants: usize,
+        residual: f64,
+        min_coverage: i32,
+    ) -> QualityFlag {
+        if informative_variants < self.fail_informative_variants
+            || residual > self.fail_residual
+            || min_coverage < self.fail_coverage
+        {
+            QualityFlag::Fail
+        } else if informative_variants < self.warn_informative_variants
+            || residual > self.warn_residual
+            || min_coverage < self.warn_coverage
+        {
+            QualityFlag::Warn
+        } else {
+            QualityFlag::Pass
+        }
+    }
+}
+
+impl Manifest {
+    //with_quality adds the quality diagnostics of the locus and the resulting quality flag.
+    pub fn with_quality(
+        mut self,
+        variant_calls: &VariantCalls,
+        lp_residual: f64,
+        thresholds: &QualityThresholds,
+    ) -> Self {
+        let informative_variants = variant_calls.informative_variants();
+        let min_coverage = variant_calls.min_read_depth().unwrap_or(0);
+        self.informative_variants = Some(informative_variants);
+        self.lp_residual = Some(lp_residual);
+        self.min_coverage = Some(min_coverage);
+        self.quality = Some(thresholds.flag(informative_variants, lp_residual, min_coverage));
+        self
+    }
+
+    pub fn write(&self, outdir: &PathBuf) -> Result<()> {
+        let mut parent = outdir.clone();
+        parent.pop();
+        fs::create_dir_all(&parent)?;
+        let file = fs::File::create(parent.join("manifest.json"))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+//mixture_entropy computes the Shannon entropy (in bits) of a fraction vector over its nonzero entries.
+//A pure sample yields 0.0, an even mixture of n haplotypes yields log2(n).
+pub fn mixture_entropy(fractions: &[f64]) -> f64 {
+    -fractions
+        .iter()
+        .filter(|fraction| **fraction > 0.0)
+        .map(|fraction| fraction * fraction.log2())
+        .sum::<f64>()
+}
+
+//summarize_panel tallies, for each haplotype (sample) in the candidate bcf, the number of variants
+//that the haplotype covers (C:1). No calling is performed.
+pub fn summarize_panel(haplotype_variants: &mut bcf::Reader) -> Result<BTreeMap<Haplotype, usize>> {
+    let haplotypes: Vec<Haplotype> = haplotype_variants
+        .header()
+        .samples()
+        .iter()
+        .map(|sample| Haplotype(str::from_utf8(sample).unwrap().to_string()))
+        .collect();
+    //initialize with zero, so that haplotypes without any covered variant are listed as well
+    let mut summary: BTreeMap<Haplotype, usize> =
+        haplotypes.iter().map(|h| (h.clone(), 0)).collect();
+    for record_result in haplotype_variants.records() {
+        let record = record_result?;
+        let loci = record.format(b"C").integer()?;
+        for (index, haplotype) in haplotypes.iter().enumerate() {
+            if loci[index] == &[1] {
+                *summary.get_mut(haplotype).unwrap() += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+pub fn write_panel_summary<W: Write>(
+    summary: &BTreeMap<Haplotype, usize>,
+    writer: W,
+) -> Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record(&["haplotype", "covered_variants"])?;
+    for (haplotype, covered) in summary.iter() {
+        wtr.write_record(&[haplotype.to_string(), covered.to_string()])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
 #[derive(Serialize, Debug)]
 pub(crate) struct DatasetVariants {
     variant: VariantID,
@@ -468,10 +1963,11 @@ pub(crate) struct DatasetAfd {
 pub fn plot_prediction(
     outdir: &PathBuf,
     solution: &str,
-    candidate_matrix_values: &Vec<(Vec<VariantStatus>, BitVec)>,
+    candidate_matrix: &CandidateMatrix,
     haplotypes: &Vec<Haplotype>,
     variant_calls: &VariantCalls,
     best_variables: &Vec<f64>,
+    plot_csv: bool,
 ) -> Result<()> {
     let mut file_name = "".to_string();
     let json = include_str!("../../../templates/prediction.json");
@@ -481,11 +1977,17 @@ pub fn plot_prediction(
     let mut plot_data_haplotype_fractions = Vec::new();
     let mut plot_data_covered_variants = Vec::new();
     let mut plot_data_dataset_afd = Vec::new();
+    //join the candidate matrix and the calls by variant, variants that are missing in either of them are skipped
+    let joined = candidate_matrix
+        .iter()
+        .filter_map(|(variant_id, candidates)| {
+            variant_calls
+                .get(variant_id)
+                .map(|call| (variant_id, candidates, call))
+        });
 
     if &solution == &"lp" {
-        for ((genotype_matrix, coverage_matrix), (variant_id, (af, _))) in
-            candidate_matrix_values.iter().zip(variant_calls.iter())
-        {
+        for (variant_id, (genotype_matrix, coverage_matrix), (af, _)) in joined {
             let mut counter = 0;
             for (i, _variable) in best_variables.iter().enumerate() {
                 if coverage_matrix[i as u64] {
@@ -515,52 +2017,69 @@ pub fn plot_prediction(
         }
         file_name.push_str("lp_solution.json");
     } else if &solution == &"final" {
-        candidate_matrix_values
-            .iter()
-            .zip(variant_calls.iter())
-            .for_each(|((genotypes, covered), (variant_id, (af, afd)))| {
-                best_variables
-                    .iter()
-                    .zip(haplotypes.iter())
-                    .enumerate()
-                    .for_each(|(i, (fraction, haplotype))| {
-                        if genotypes[i] == VariantStatus::Present && covered[i as u64] {
-                            plot_data_haplotype_fractions.push(DatasetHaplotypeFractions {
-                                haplotype: haplotype.to_string(),
-                                fraction: NotNan::new(*fraction).unwrap(),
-                            });
-                            plot_data_haplotype_variants.push(DatasetHaplotypeVariants {
-                                variant: *variant_id,
-                                haplotype: haplotype.to_string(),
-                            });
-                            plot_data_variants.push(DatasetVariants {
-                                variant: *variant_id,
-                                vaf: *af,
-                            });
-                            //addition of one more rect plot for coverage matrix in addition to genotype matrix
-                            //create the plot_data_covered_variants using only the variants that have GT:1 for at least one haplotype.
-                            for (j, haplotype) in haplotypes.iter().enumerate() {
-                                if covered[j as u64] {
-                                    plot_data_covered_variants.push(DatasetHaplotypeVariants {
-                                        variant: *variant_id,
-                                        haplotype: haplotype.to_string(),
-                                    });
-                                }
-                            }
-
-                            //also add the heatmap for afd below the covered panels
-                            for (allele_freq, prob) in afd.iter() {
-                                plot_data_dataset_afd.push(DatasetAfd {
+        joined.for_each(|(variant_id, (genotypes, covered), (af, afd))| {
+            best_variables
+                .iter()
+                .zip(haplotypes.iter())
+                .enumerate()
+                .for_each(|(i, (fraction, haplotype))| {
+                    if genotypes[i] == VariantStatus::Present && covered[i as u64] {
+                        plot_data_haplotype_fractions.push(DatasetHaplotypeFractions {
+                            haplotype: haplotype.to_string(),
+                            fraction: NotNan::new(*fraction).unwrap(),
+                        });
+                        plot_data_haplotype_variants.push(DatasetHaplotypeVariants {
+                            variant: *variant_id,
+                            haplotype: haplotype.to_string(),
+                        });
+                        plot_data_variants.push(DatasetVariants {
+                            variant: *variant_id,
+                            vaf: *af,
+                        });
+                        //addition of one more rect plot for coverage matrix in addition to genotype matrix
+                        //create the plot_data_covered_variants using only the variants that have GT:1 for at least one haplotype.
+                        for (j, haplotype) in haplotypes.iter().enumerate() {
+                            if covered[j as u64] {
+                                plot_data_covered_variants.push(DatasetHaplotypeVariants {
                                     variant: *variant_id,
-                                    allele_freq: *allele_freq,
-                                    probability: f64::from(*prob),
-                                })
+                                    haplotype: haplotype.to_string(),
+                                });
                             }
                         }
-                    });
-            });
+
+                        //also add the heatmap for afd below the covered panels
+                        for (allele_freq, prob) in afd.iter() {
+                            plot_data_dataset_afd.push(DatasetAfd {
+                                variant: *variant_id,
+                                allele_freq: *allele_freq,
+                                probability: f64::from(*prob),
+                            })
+                        }
+                    }
+                });
+        });
         file_name.push_str("final_solution.json");
     }
+    let mut parent = outdir.clone();
+    parent.pop();
+    fs::create_dir_all(&parent)?;
+
+    //optionally export the datasets of the plot as csv for external tools, e.g. final_plot_variants.csv
+    if plot_csv {
+        write_plot_csv(
+            &parent.join(format!("{}_plot_variants.csv", solution)),
+            &plot_data_variants,
+        )?;
+        write_plot_csv(
+            &parent.join(format!("{}_plot_haplotype_variants.csv", solution)),
+            &plot_data_haplotype_variants,
+        )?;
+        write_plot_csv(
+            &parent.join(format!("{}_plot_haplotype_fractions.csv", solution)),
+            &plot_data_haplotype_fractions,
+        )?;
+    }
+
     let plot_data_variants = json!(plot_data_variants);
     let plot_data_haplotype_variants = json!(plot_data_haplotype_variants);
     let plot_data_haplotype_fractions = json!(plot_data_haplotype_fractions);
@@ -573,58 +2092,321 @@ pub fn plot_prediction(
     blueprint["datasets"]["covered_variants"] = plot_data_covered_variants;
     blueprint["datasets"]["allele_frequency_distribution"] = plot_data_dataset_afd;
 
-    let mut parent = outdir.clone();
-    parent.pop();
-    fs::create_dir_all(&parent)?;
     let file = fs::File::create(parent.join(file_name)).unwrap();
     serde_json::to_writer(file, &blueprint)?;
     Ok(())
 }
 
+fn write_plot_csv<T: Serialize>(path: &PathBuf, rows: &[T]) -> Result<()> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    for row in rows {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+//write_combined_plot merges the already written lp_solution.json and final_solution.json next to the output into
+//combined_solution.json, a single plot faceted by stage. Each row of the combined dataset carries its stage and
+//either the observed vaf of a variant or the fraction of a haplotype having the variant.
+pub fn write_combined_plot(outdir: &PathBuf) -> Result<()> {
+    let mut parent = outdir.clone();
+    parent.pop();
+    let mut solutions = Vec::new();
+    for stage in &["lp", "final"] {
+        let path = parent.join(format!("{}_solution.json", stage));
+        let file = fs::File::open(&path).map_err(|error| {
+            anyhow!(
+                "cannot read {} for the combined plot: {}",
+                path.display(),
+                error
+            )
+        })?;
+        let plot: serde_json::Value = serde_json::from_reader(file)?;
+        let datasets = &plot["datasets"];
+        let fractions: HashMap<&str, &serde_json::Value> = datasets["haplotype_fractions"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|row| Some((row["haplotype"].as_str()?, &row["fraction"])))
+            .collect();
+        for row in datasets["variants"].as_array().into_iter().flatten() {
+            solutions.push(json!({
+                "stage": stage,
+                "variant": row["variant"],
+                "vaf": row["vaf"],
+            }));
+        }
+        for row in datasets["haplotype_variants"]
+            .as_array()
+            .into_iter()
+            .flatten()
+        {
+            let haplotype = row["haplotype"].as_str().unwrap_or_default();
+            solutions.push(json!({
+                "stage": stage,
+                "variant": row["variant"],
+                "haplotype": haplotype,
+                "fraction": fractions.get(haplotype).copied().unwrap_or(&serde_json::Value::Null),
+            }));
+        }
+    }
+    let json = include_str!("../../../templates/combined_prediction.json");
+    let mut blueprint: serde_json::Value = serde_json::from_str(json).unwrap();
+    blueprint["datasets"]["solutions"] = json!(solutions);
+    let file = fs::File::create(parent.join("combined_solution.json"))?;
+    serde_json::to_writer(file, &blueprint)?;
+    Ok(())
+}
+
+//catch_solver_panic runs the solver and turns a panic of it (i.e. of the Rust bindings of CBC) into an error that
+//tells how to install the solver. An abort within CBC or a CBC library that can't be loaded is no panic and can't be
+//caught within orthanq, see selftest::probe_solver for detecting them up front.
+pub fn catch_solver_panic<R, F: FnOnce() -> R>(solve: F) -> Result<R> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(solve)).map_err(|payload| {
+        let reason = payload
+            .downcast_ref::<&str>()
+            .map(|reason| reason.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "the solver panicked".to_string());
+        OrthanqError::SolverUnavailable { reason }.into()
+    })
+}
+
+//solve_probe solves a linear program of a single variable (minimise x subject to x >= 0.5), such that a broken
+//solver installation shows up without any input, see selftest::probe_solver.
+pub fn solve_probe() -> Result<()> {
+    let mut problem = ProblemVariables::new();
+    let x = problem.add(variable().min(0.0).max(1.0));
+    let solution = catch_solver_panic(|| {
+        problem
+            .minimise(x)
+            .using(default_solver)
+            .with(constraint!(x >= 0.5))
+            .solve()
+    })?
+    .map_err(|error| anyhow!(error))?;
+    if (solution.value(x) - 0.5).abs() > 1e-6 {
+        return Err(OrthanqError::SolverUnavailable {
+            reason: format!(
+                "the probe was solved with x = {}, expected 0.5",
+                solution.value(x)
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+//LinearProgramSettings bundles the user defined options of the linear program.
+#[derive(Debug, Clone)]
+pub struct LinearProgramSettings {
+    pub lp_cutoff: f64,
+    pub extend_haplotypes: bool,
+    pub num_variant_distance: i64,
+    //if true, a binary selection variable is introduced per haplotype in addition to its continuous fraction
+    //(mixed integer program). This turns the linear program into a branch and bound search of the solver,
+    //hence runtime can grow exponentially with the number of haplotypes in the panel.
+    pub integer_selection: bool,
+    //upper bound for the number of selected haplotypes, only used with integer_selection.
+    pub max_haplotypes: Option<usize>,
+    //minimum fraction of shared variants for a haplotype to be added during extension.
+    pub extension_overlap: Option<f64>,
+    //time limit of the solver in seconds. If the solver stops at the limit, the best solution found so far (incumbent)
+    //is used with a warning. If the mixed integer program (integer_selection) has none yet, its continuous relaxation
+    //is solved within the remaining time instead. Only if there is no solution at all, an error is returned.
+    pub lp_time_limit: Option<f64>,
+    //relative gap at which the solver stops the branch and bound search, only used with integer_selection.
+    pub lp_gap: Option<f64>,
+    //minimum read depth of the sample at a variant for the variant to be used as a constraint (sample coverage).
+    pub min_sample_depth: i32,
+    //if true, the datasets of the lp solution plot are additionally written as csv.
+    pub plot_csv: bool,
+    //if true, the deviation at each variant is weighted by the square root of its read depth in the objective,
+    //such that allele frequencies estimated from few reads influence the solution less.
+    pub depth_weighting: bool,
+    //if true, the formulation of the linear program is written in CPLEX LP format (lp.lp) next to the output,
+    //such that it can be solved with an external solver.
+    pub export_lp: bool,
+    //if true, the haplotype fractions only have to sum up to at most 1.0 instead of exactly 1.0, such that signal
+    //that can't be explained by the panel (e.g. contamination) is left unassigned.
+    pub relax_sum: bool,
+    //fractions of the haplotypes (in the order of the haplotypes passed to linear_program) to seed the solver with, e.g.
+    //the solution of a previous run with other parameters. Only CBC (the default solver) honors it, as initial solution
+    //of the branch and bound search, i.e. it only saves effort with integer_selection; a pure linear program is solved
+    //from scratch anyways. A start that doesn't match the haplotypes is ignored. The solution is the same as without it.
+    pub warm_start: Option<Vec<f64>>,
+}
+
+impl Default for LinearProgramSettings {
+    fn default() -> Self {
+        LinearProgramSettings {
+            lp_cutoff: 0.01,
+            extend_haplotypes: false,
+            num_variant_distance: 0,
+            integer_selection: false,
+            max_haplotypes: None,
+            extension_overlap: None,
+            lp_time_limit: None,
+            lp_gap: None,
+            min_sample_depth: 1,
+            plot_csv: false,
+            depth_weighting: false,
+            export_lp: false,
+            relax_sum: false,
+            warm_start: None,
+        }
+    }
+}
+
+//check_max_haplotypes validates max_haplotypes against the number of haplotypes in the panel: it fails for 0 and
+//is clamped to the panel size (with a warning) if larger, as there can't be more haplotypes selected than available.
+pub fn check_max_haplotypes(
+    max_haplotypes: Option<usize>,
+    panel_size: usize,
+) -> Result<Option<usize>> {
+    match max_haplotypes {
+        Some(0) => Err(OrthanqError::InvalidMaxHaplotypes { value: 0 }.into()),
+        Some(max_haplotypes) if max_haplotypes > panel_size => {
+            warn!(
+                "max_haplotypes ({}) exceeds the number of haplotypes in the panel ({}), using the latter",
+                max_haplotypes, panel_size
+            );
+            Ok(Some(panel_size))
+        }
+        max_haplotypes => Ok(max_haplotypes),
+    }
+}
+
+//LinearProgramSolution holds the haplotypes selected by the linear program (lp_haplotypes) with their fractions,
+//the selection extended by similar haplotypes and the residual, i.e. the mean absolute deviation between
+//the observed and the explained allele frequencies over the considered variants.
+#[derive(Debug, Clone)]
+pub struct LinearProgramSolution {
+    pub extended_haplotypes: Vec<Haplotype>,
+    pub lp_haplotypes: Vec<Haplotype>,
+    pub fractions: BTreeMap<Haplotype, f64>,
+    pub residual: f64,
+    //the variants that were used as constraints, i.e. covered by the panel and the sample
+    pub considered_variants: Vec<VariantID>,
+    //the fraction that is not assigned to any haplotype (1 - sum of the fractions), only with relax_sum
+    pub unassigned_fraction: Option<f64>,
+    //true if the mixed integer program stopped at the time limit without a solution and the solution is the one of
+    //its continuous relaxation
+    pub relaxed: bool,
+    //true if the solver stopped at the time limit, i.e. the solution is the best one found so far, not necessarily the optimum
+    pub stopped_at_limit: bool,
+}
+
+//variant_weight is the weight of the deviation at a variant in the objective of the linear program: the square root of
+//the read depth with depth_weighting, times the reliability (1 - error rate) of the call if its error rate was read,
+//such that unreliable calls influence the solution less.
+fn variant_weight(
+    settings: &LinearProgramSettings,
+    variant_calls: &VariantCalls,
+    variant: &VariantID,
+) -> f64 {
+    let depth_weight = match (settings.depth_weighting, variant_calls.read_depth(variant)) {
+        (true, Some(depth)) => (depth as f64).sqrt(),
+        _ => 1.0,
+    };
+    depth_weight * (1.0 - variant_calls.error_rate(variant).unwrap_or(0.0))
+}
+
 pub fn linear_program(
     outdir: &PathBuf,
     candidate_matrix: &CandidateMatrix,
     haplotypes: &Vec<Haplotype>,
     variant_calls: &VariantCalls,
-    lp_cutoff: f64,
-    extend_haplotypes: bool,
-    num_variant_distance: i64,
-) -> Result<(Vec<Haplotype>, Vec<Haplotype>)> {
+    settings: &LinearProgramSettings,
+) -> Result<LinearProgramSolution> {
+    let lp_cutoff = settings.lp_cutoff;
+    let extend_haplotypes = settings.extend_haplotypes;
+    let num_variant_distance = settings.num_variant_distance;
+
     //first init the problem
     let mut problem = ProblemVariables::new();
     //introduce variables
     let variables: Vec<Variable> =
         problem.add_vector(variable().min(0.0).max(1.0), haplotypes.len());
 
+    //in case of integer selection, introduce a binary indicator for each haplotype
+    let selections: Vec<Variable> = if settings.integer_selection {
+        problem.add_vector(variable().binary(), haplotypes.len())
+    } else {
+        Vec::new()
+    };
+
     //init the constraints
     let mut constraints: Vec<Expression> = Vec::new();
+    let mut considered_variants: Vec<VariantID> = Vec::new();
 
     //execute the following function to fill up the constraints and create a haplotype_dict
     let haplotype_dict = collect_constraints_and_variants(
         candidate_matrix,
         haplotypes,
         variant_calls,
+        settings.min_sample_depth,
         &variables,
         &mut constraints,
+        &mut considered_variants,
     )
     .unwrap();
+    //without any variant, the linear program is unconstrained and any fractions would be meaningless
+    if considered_variants.is_empty() {
+        return Err(OrthanqError::NoUsableVariants {
+            variants: variant_calls.len(),
+            haplotypes: haplotypes.len(),
+        }
+        .into());
+    }
+
+    if settings.export_lp {
+        let mut parent = outdir.clone();
+        parent.pop();
+        fs::create_dir_all(&parent)?;
+        write_lp_formulation(
+            &parent.join("lp.lp"),
+            haplotypes,
+            &haplotype_dict,
+            &considered_variants,
+            variant_calls,
+            settings,
+        )?;
+    }
 
     //define temporary variables
     let t_vars: Vec<Variable> = problem.add_vector(variable().min(0.0).max(1.0), constraints.len());
 
-    //create the model to minimise the sum of temporary variables
+    //create the model to minimise the (weighted) sum of temporary variables
     let mut sum_tvars = Expression::from_other_affine(0.);
-    for t_var in t_vars.iter() {
-        sum_tvars += t_var.into_expression();
+    for (t_var, variant) in t_vars.iter().zip(considered_variants.iter()) {
+        sum_tvars += variant_weight(settings, variant_calls, variant) * t_var.into_expression();
     }
     let mut model = problem.minimise(sum_tvars.clone()).using(default_solver); // multiple solvers available
 
-    //add a constraint to make sure variables sum up to 1.0.
+    //add a constraint to make sure variables sum up to 1.0 (or at most 1.0 in the relaxed mode).
     let mut sum = Expression::from_other_affine(0.);
     for var in variables.iter() {
         sum += Expression::from_other_affine(var);
     }
-    model = model.with(constraint!(sum == 1.0));
+    if settings.relax_sum {
+        model = model.with(constraint!(sum.clone() <= 1.0));
+    } else {
+        model = model.with(constraint!(sum.clone() == 1.0));
+    }
+
+    //link fractions to the binary indicators (big-M formulation, M = 1.0 as fractions are bounded by 1.0)
+    //and limit the number of selected haplotypes
+    if settings.integer_selection {
+        let mut sum_selections = Expression::from_other_affine(0.);
+        for (var, selection) in variables.iter().zip(selections.iter()) {
+            model = model.with(constraint!(*var <= *selection));
+            sum_selections += Expression::from_other_affine(selection);
+        }
+        let max_haplotypes = settings.max_haplotypes.unwrap_or(haplotypes.len()) as f64;
+        model = model.with(constraint!(sum_selections <= max_haplotypes));
+    }
 
     //add the constraints to the model
     for (c, t_var) in constraints.iter().zip(t_vars.iter()) {
@@ -633,7 +2415,113 @@ pub fn linear_program(
     }
 
     //solve the problem with the default solver, i.e. coin_cbc
-    let solution = model.solve().unwrap();
+    //configure the solver
+    if let Some(lp_time_limit) = settings.lp_time_limit {
+        model.set_parameter("sec", &lp_time_limit.to_string());
+    }
+    if let (Some(lp_gap), true) = (settings.lp_gap, settings.integer_selection) {
+        model.set_parameter("ratioGap", &lp_gap.to_string());
+    }
+    match &settings.warm_start {
+        Some(start) if start.len() == haplotypes.len() => {
+            //the selection indicators follow from the fractions
+            let selection_start = selections
+                .iter()
+                .zip(start.iter())
+                .map(|(selection, fraction)| (*selection, if *fraction > 0.0 { 1.0 } else { 0.0 }));
+            model = model.with_initial_solution(
+                variables
+                    .iter()
+                    .cloned()
+                    .zip(start.iter().cloned())
+                    .chain(selection_start)
+                    .collect::<Vec<(Variable, f64)>>(),
+            );
+        }
+        Some(start) => warn!(
+            "ignoring the warm start of the linear program, it has {} fractions for {} haplotypes",
+            start.len(),
+            haplotypes.len()
+        ),
+        None => (),
+    }
+
+    //solve with CBC directly instead of good_lp's solve, as the latter drops the incumbent if CBC stops at the time limit.
+    //The columns of CBC are the variables in the order of their creation: fractions, selections and deviations.
+    let started = Instant::now();
+    let cbc_solution = catch_solver_panic(|| model.as_inner_mut().solve())?;
+    let raw = cbc_solution.raw();
+    let stopped_at_limit = match raw.status() {
+        Status::Finished | Status::Unlaunched if raw.is_continuous_unbounded() => {
+            return Err(OrthanqError::InfeasibleLinearProgram {
+                reason: ResolutionError::Unbounded.to_string(),
+            }
+            .into())
+        }
+        Status::Finished | Status::Unlaunched if raw.is_proven_infeasible() => {
+            return Err(OrthanqError::InfeasibleLinearProgram {
+                reason: ResolutionError::Infeasible.to_string(),
+            }
+            .into())
+        }
+        Status::Finished | Status::Unlaunched => false,
+        Status::Stopped => true,
+        status => {
+            return Err(OrthanqError::InfeasibleLinearProgram {
+                reason: format!("the solver ended with status {:?}", status),
+            }
+            .into())
+        }
+    };
+    let mut solution: HashMap<Variable, f64> = variables
+        .iter()
+        .chain(selections.iter())
+        .cloned()
+        .zip(raw.col_solution().iter().cloned())
+        .collect();
+    //the deviations follow from the fractions, such that the residual is also valid for an incumbent
+    for (c, t_var) in constraints.iter().zip(t_vars.iter()) {
+        let deviation = solution.eval(c.clone()).abs();
+        solution.insert(*t_var, deviation);
+    }
+
+    if stopped_at_limit {
+        let lp_time_limit = settings.lp_time_limit.unwrap_or_default();
+        if is_feasible_incumbent(&solution, &variables, &selections, settings) {
+            warn!(
+                "linear program did not finish within the time limit of {} seconds, using the best solution found so far",
+                lp_time_limit
+            );
+        } else if settings.integer_selection {
+            let remaining = (lp_time_limit - started.elapsed().as_secs_f64()).max(0.0);
+            warn!(
+                "mixed integer linear program found no solution within the time limit of {} seconds, using the continuous relaxation instead (within the remaining {} seconds)",
+                lp_time_limit, remaining
+            );
+            let solution = linear_program(
+                outdir,
+                candidate_matrix,
+                haplotypes,
+                variant_calls,
+                &LinearProgramSettings {
+                    integer_selection: false,
+                    lp_gap: None,
+                    lp_time_limit: Some(remaining),
+                    ..settings.clone()
+                },
+            )?;
+            return Ok(LinearProgramSolution {
+                relaxed: true,
+                stopped_at_limit: true,
+                ..solution
+            });
+        } else {
+            return Err(OrthanqError::LinearProgramTimeLimit {
+                seconds: lp_time_limit,
+            }
+            .into());
+        }
+    }
 
     let mut best_variables = Vec::new();
     //finally, print the variables and the sum
@@ -646,248 +2534,2001 @@ pub fn linear_program(
             lp_haplotypes.insert(haplotype.clone(), solution.value(*var).clone());
         }
     }
-    // println!("sum = {}", solution.eval(sum_tvars));
-    // dbg!(&lp_haplotypes);
-    //plot the best result
-    let candidate_matrix_values: Vec<(Vec<VariantStatus>, BitVec)> =
-        candidate_matrix.values().cloned().collect();
-    plot_prediction(
-        outdir,
-        &"lp",
-        &candidate_matrix_values,
-        &haplotypes,
-        &variant_calls,
-        &best_variables,
-    )?;
+    // println!("sum = {}", solution.eval(sum_tvars));
+    // dbg!(&lp_haplotypes);
+    //plot the best result
+    plot_prediction(
+        outdir,
+        &"lp",
+        candidate_matrix,
+        &haplotypes,
+        &variant_calls,
+        &best_variables,
+        settings.plot_csv,
+    )?;
+
+    //extend haplotypes found by linear program, add haplotypes that have the same variants to the final list.
+    //then sort by hamming distance, take the closest x additional alleles according to 'num_variant_distance'.
+    //this is done by storing only the variants that have GT:1 and C:1 for all haplotypes in haplotype_dict and remaining variants are not included.
+    let lp_keys: Vec<_> = lp_haplotypes.keys().cloned().collect();
+    let extended_haplotypes = if extend_haplotypes {
+        extend_lp_haplotypes(
+            &lp_keys,
+            &haplotype_dict,
+            num_variant_distance,
+            settings.extension_overlap,
+        )
+    } else {
+        lp_keys.clone()
+    };
+    dbg!(&lp_keys, &extended_haplotypes);
+
+    //mean absolute deviation between the observed and the explained allele frequencies
+    let residual = if constraints.is_empty() {
+        0.0
+    } else {
+        solution.eval(sum_tvars) / constraints.len() as f64
+    };
+    let unassigned_fraction = if settings.relax_sum {
+        let unassigned = (1.0 - solution.eval(sum)).max(0.0);
+        info!("fraction not assigned to any haplotype: {}", unassigned);
+        Some(unassigned)
+    } else {
+        None
+    };
+    Ok(LinearProgramSolution {
+        extended_haplotypes,
+        lp_haplotypes: lp_keys,
+        fractions: lp_haplotypes,
+        residual,
+        considered_variants,
+        unassigned_fraction,
+        relaxed: false,
+        stopped_at_limit,
+    })
+}
+
+//is_feasible_incumbent checks whether the values of a solver that stopped at the time limit satisfy the constraints
+//on the fractions (and the selections with integer_selection), i.e. whether the solver found a solution before it stopped.
+fn is_feasible_incumbent(
+    solution: &HashMap<Variable, f64>,
+    variables: &[Variable],
+    selections: &[Variable],
+    settings: &LinearProgramSettings,
+) -> bool {
+    let tolerance = 1e-6;
+    let fractions: Vec<f64> = variables
+        .iter()
+        .map(|variable| solution[variable])
+        .collect();
+    let selected: Vec<f64> = selections
+        .iter()
+        .map(|selection| solution[selection])
+        .collect();
+    let total: f64 = fractions.iter().sum();
+    let max_haplotypes = settings.max_haplotypes.unwrap_or(variables.len()) as f64;
+    let bounded = fractions
+        .iter()
+        .all(|fraction| (-tolerance..=1.0 + tolerance).contains(fraction));
+    let summed_up = if settings.relax_sum {
+        total <= 1.0 + tolerance
+    } else {
+        (total - 1.0).abs() <= tolerance
+    };
+    let binary = selected
+        .iter()
+        .all(|selection| selection.abs() <= tolerance || (selection - 1.0).abs() <= tolerance);
+    let linked = fractions
+        .iter()
+        .zip(selected.iter())
+        .all(|(fraction, selection)| *fraction <= selection + tolerance);
+    let limited = selected.iter().sum::<f64>() <= max_haplotypes + tolerance;
+    bounded && summed_up && binary && linked && limited
+}
+
+//write_lp_formulation writes the linear program in CPLEX LP format. The fraction of the i-th haplotype is h<i>
+//(the names are listed in the comments at the top, as haplotype names can't be used as LP variables) and the
+//absolute deviation at a considered variant <id> is t<id>, bounded by the constraints v<id>_over and v<id>_under.
+pub fn write_lp_formulation(
+    path: &PathBuf,
+    haplotypes: &[Haplotype],
+    haplotype_dict: &BTreeMap<Haplotype, Vec<VariantID>>,
+    considered_variants: &[VariantID],
+    variant_calls: &VariantCalls,
+    settings: &LinearProgramSettings,
+) -> Result<()> {
+    let mut file = fs::File::create(path)?;
+    writeln!(
+        file,
+        "\\ linear program of orthanq {}",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    for (i, haplotype) in haplotypes.iter().enumerate() {
+        writeln!(file, "\\ h{} = {}", i, haplotype.as_str())?;
+    }
+
+    //objective: the (weighted) sum of the deviations
+    let objective = considered_variants
+        .iter()
+        .map(|variant| {
+            format!(
+                "{} t{}",
+                variant_weight(settings, variant_calls, variant),
+                variant.0
+            )
+        })
+        .collect::<Vec<String>>();
+    writeln!(file, "Minimize")?;
+    if objective.is_empty() {
+        writeln!(file, " obj: 0 h0")?;
+    } else {
+        writeln!(file, " obj: {}", objective.join(" + "))?;
+    }
+
+    writeln!(file, "Subject To")?;
+    let fractions = (0..haplotypes.len())
+        .map(|i| format!("h{}", i))
+        .collect::<Vec<String>>();
+    let relation = if settings.relax_sum { "<=" } else { "=" };
+    writeln!(file, " sum: {} {} 1", fractions.join(" + "), relation)?;
+    for variant in considered_variants {
+        //the haplotypes that carry the variant
+        let present = haplotypes
+            .iter()
+            .enumerate()
+            .filter(|(_, haplotype)| {
+                haplotype_dict
+                    .get(haplotype)
+                    .map_or(false, |variants| variants.contains(variant))
+            })
+            .map(|(i, _)| i)
+            .collect::<Vec<usize>>();
+        let af = variant_calls.get(variant).map_or(0.0, |(af, _)| *af as f64);
+        //t >= sum(h) - af and t >= af - sum(h)
+        let over = present
+            .iter()
+            .map(|i| format!(" - h{}", i))
+            .collect::<String>();
+        let under = present
+            .iter()
+            .map(|i| format!(" + h{}", i))
+            .collect::<String>();
+        writeln!(
+            file,
+            " v{}_over: t{}{} >= {}",
+            variant.0, variant.0, over, -af
+        )?;
+        writeln!(
+            file,
+            " v{}_under: t{}{} >= {}",
+            variant.0, variant.0, under, af
+        )?;
+    }
+    if settings.integer_selection {
+        for i in 0..haplotypes.len() {
+            writeln!(file, " select{}: h{} - s{} <= 0", i, i, i)?;
+        }
+        let selections = (0..haplotypes.len())
+            .map(|i| format!("s{}", i))
+            .collect::<Vec<String>>();
+        let max_haplotypes = settings.max_haplotypes.unwrap_or(haplotypes.len());
+        writeln!(
+            file,
+            " max_haplotypes: {} <= {}",
+            selections.join(" + "),
+            max_haplotypes
+        )?;
+    }
+
+    writeln!(file, "Bounds")?;
+    for fraction in &fractions {
+        writeln!(file, " 0 <= {} <= 1", fraction)?;
+    }
+    for variant in considered_variants {
+        writeln!(file, " 0 <= t{} <= 1", variant.0)?;
+    }
+    if settings.integer_selection {
+        writeln!(file, "Binary")?;
+        for i in 0..haplotypes.len() {
+            writeln!(file, " s{}", i)?;
+        }
+    }
+    writeln!(file, "End")?;
+    Ok(())
+}
+
+//prune_uninformative_haplotypes removes the haplotypes that are not covered at any informative variant call,
+//i.e. a call with an allele frequency distribution. Such haplotypes don't contribute to the likelihood, but enlarge
+//the space of events of the model. If no haplotype would remain, all are kept. The number of removed haplotypes is logged.
+pub fn prune_uninformative_haplotypes(
+    haplotype_variants: &HaplotypeVariants,
+    variant_calls: &VariantCalls,
+    haplotypes: &[Haplotype],
+) -> Vec<Haplotype> {
+    let informative: Vec<Haplotype> = haplotypes
+        .iter()
+        .filter(|haplotype| {
+            haplotype_variants.iter().any(|(variant, matrix)| {
+                let covered = matrix.get(haplotype).map_or(false, |(_, covered)| *covered);
+                let informative_call = variant_calls
+                    .get(variant)
+                    .map_or(false, |(_, afd)| !afd.is_empty());
+                covered && informative_call
+            })
+        })
+        .cloned()
+        .collect();
+    if informative.is_empty() {
+        return haplotypes.to_vec();
+    }
+    if informative.len() < haplotypes.len() {
+        info!(
+            "removed {} haplotypes without any covered, informative variant",
+            haplotypes.len() - informative.len()
+        );
+    }
+    informative
+}
+
+//write_lp_vs_posterior writes the fractions estimated by the linear program next to the fractions of the best posterior event.
+//Haplotypes that were not selected by the linear program (e.g. added by extension) have an LP fraction of zero.
+pub fn write_lp_vs_posterior(
+    outdir: &PathBuf,
+    lp_fractions: &BTreeMap<Haplotype, f64>,
+    final_haplotypes: &[Haplotype],
+    best_fractions: &[f64],
+) -> Result<()> {
+    let mut parent = outdir.clone();
+    parent.pop();
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(parent.join("lp_vs_posterior.tsv"))?;
+    wtr.write_record(&["haplotype", "lp_fraction", "posterior_fraction"])?;
+    for (haplotype, posterior_fraction) in final_haplotypes.iter().zip(best_fractions.iter()) {
+        let lp_fraction = lp_fractions.get(haplotype).cloned().unwrap_or(0.0);
+        wtr.write_record(&[
+            haplotype.to_string(),
+            format!("{:.2}", lp_fraction),
+            format!("{:.2}", posterior_fraction),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+//relative_fractions divides the fraction of each haplotype by the fraction of the reference haplotype,
+//e.g. for allele-specific expression. If the reference has a fraction of zero, ratios are infinite (or NaN
+//for haplotypes that have a fraction of zero as well).
+pub fn relative_fractions(
+    final_haplotypes: &[Haplotype],
+    best_fractions: &[f64],
+    reference: &Haplotype,
+) -> Result<Vec<(Haplotype, f64)>> {
+    let reference_fraction = final_haplotypes
+        .iter()
+        .zip(best_fractions.iter())
+        .find(|(haplotype, _)| *haplotype == reference)
+        .map(|(_, fraction)| *fraction)
+        .ok_or_else(|| OrthanqError::UnknownHaplotype {
+            haplotype: reference.to_string(),
+        })?;
+    Ok(final_haplotypes
+        .iter()
+        .zip(best_fractions.iter())
+        .map(|(haplotype, fraction)| (haplotype.clone(), fraction / reference_fraction))
+        .collect())
+}
+
+//write_relative_fractions writes the fractions of the best posterior event relative to the reference haplotype
+//to relative_fractions.tsv. Undefined ratios (0/0) are written as NA, division by a zero reference as inf.
+pub fn write_relative_fractions(
+    outdir: &PathBuf,
+    final_haplotypes: &[Haplotype],
+    best_fractions: &[f64],
+    reference: &Haplotype,
+) -> Result<()> {
+    let ratios = relative_fractions(final_haplotypes, best_fractions, reference)?;
+    let mut parent = outdir.clone();
+    parent.pop();
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(parent.join("relative_fractions.tsv"))?;
+    wtr.write_record(&["haplotype", "fraction", "ratio"])?;
+    for ((haplotype, ratio), fraction) in ratios.iter().zip(best_fractions.iter()) {
+        let ratio = if ratio.is_nan() {
+            "NA".to_string()
+        } else if ratio.is_infinite() {
+            "inf".to_string()
+        } else {
+            format!("{:.2}", ratio)
+        };
+        wtr.write_record(&[haplotype.to_string(), format!("{:.2}", fraction), ratio])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+//presence_probabilities computes, for each haplotype, the posterior probability that it is present in the sample,
+//i.e. the sum of the (normalized) posterior probabilities of the events in which it has a nonzero fraction.
+pub fn presence_probabilities(
+    event_posteriors: &[(HaplotypeFractions, LogProb)],
+    final_haplotypes: &[Haplotype],
+) -> Vec<(Haplotype, f64)> {
+    let total = LogProb::ln_sum_exp(
+        &event_posteriors
+            .iter()
+            .map(|(_, density)| *density)
+            .collect::<Vec<LogProb>>(),
+    );
+    final_haplotypes
+        .iter()
+        .enumerate()
+        .map(|(index, haplotype)| {
+            let probability = event_posteriors
+                .iter()
+                .filter(|(fractions, _)| {
+                    fractions
+                        .get(index)
+                        .map_or(false, |fraction| *fraction > NotNan::new(0.0).unwrap())
+                })
+                .map(|(_, density)| (*density - total).exp())
+                .sum::<f64>();
+            (haplotype.clone(), probability.min(1.0))
+        })
+        .collect()
+}
+
+//candidate_alleles returns all haplotypes with a presence probability (see presence_probabilities) of at least 'band',
+//sorted by decreasing probability. In contrast to the best event, this reports every allele that is supported by
+//the posterior, e.g. when the probability mass is spread over several events.
+pub fn candidate_alleles(
+    event_posteriors: &[(HaplotypeFractions, LogProb)],
+    final_haplotypes: &[Haplotype],
+    band: f64,
+) -> Vec<(Haplotype, f64)> {
+    let mut candidates = presence_probabilities(event_posteriors, final_haplotypes)
+        .into_iter()
+        .filter(|(_, probability)| *probability >= band)
+        .collect::<Vec<(Haplotype, f64)>>();
+    candidates.sort_by(|(a_haplotype, a), (b_haplotype, b)| {
+        b.partial_cmp(a)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a_haplotype.cmp(b_haplotype))
+    });
+    candidates
+}
+
+//write_candidate_alleles writes the candidate alleles (see candidate_alleles) to candidate_alleles.tsv.
+pub fn write_candidate_alleles(
+    outdir: &PathBuf,
+    event_posteriors: &[(HaplotypeFractions, LogProb)],
+    final_haplotypes: &[Haplotype],
+    band: f64,
+) -> Result<()> {
+    let mut parent = outdir.clone();
+    parent.pop();
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(parent.join("candidate_alleles.tsv"))?;
+    wtr.write_record(&["haplotype", "presence_probability"])?;
+    for (haplotype, probability) in candidate_alleles(event_posteriors, final_haplotypes, band) {
+        wtr.write_record(&[haplotype.to_string(), format!("{:.3}", probability)])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+//BestEvent holds the index of the event reported as best solution and the indices of all events that are tied with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BestEvent {
+    pub index: usize,
+    pub tied: Vec<usize>,
+}
+
+impl BestEvent {
+    pub fn is_ambiguous(&self) -> bool {
+        self.tied.len() > 1
+    }
+}
+
+//EventTies decides which events are tied for the best posterior density: the ones whose log density is within
+//'tolerance' of the highest one. The tolerance is hence relative to the highest density (1e-6 ties densities that are
+//at least 1 - 1e-6 times the highest one), such that it doesn't depend on the scale of the densities.
+#[derive(Debug, Clone, Copy)]
+pub struct EventTies {
+    best_density: LogProb,
+    tolerance: f64,
+}
+
+impl EventTies {
+    pub fn new(event_posteriors: &[(HaplotypeFractions, LogProb)], tolerance: f64) -> Self {
+        let best_density = event_posteriors.iter().map(|(_, density)| *density).fold(
+            LogProb::ln_zero(),
+            |max, density| {
+                if density > max {
+                    density
+                } else {
+                    max
+                }
+            },
+        );
+        EventTies {
+            best_density,
+            tolerance,
+        }
+    }
+
+    pub fn best_density(&self) -> LogProb {
+        self.best_density
+    }
+
+    pub fn is_tied(&self, density: LogProb) -> bool {
+        density == self.best_density || *self.best_density - *density <= self.tolerance
+    }
+}
+
+//compare_events ranks two events by density, best first. The events that are tied for the best density (see EventTies)
+//come first and are ordered by the present (non-zero) haplotypes in lexicographic order and finally by the fractions,
+//as are the remaining events of equal density. The ranking is a total order that doesn't depend on the order in which
+//the model emits the events. All outputs ranking the events use it, such that the best event (see select_best_event)
+//is always ranked first.
+pub fn compare_events(
+    a: &(HaplotypeFractions, LogProb),
+    b: &(HaplotypeFractions, LogProb),
+    haplotypes: &[Haplotype],
+    ties: &EventTies,
+) -> Ordering {
+    match (ties.is_tied(a.1), ties.is_tied(b.1)) {
+        (true, true) => compare_tied_events(a, b, haplotypes),
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        (false, false) => {
+            b.1.partial_cmp(&a.1)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| compare_tied_events(a, b, haplotypes))
+        }
+    }
+}
+
+//compare_tied_events is the tiebreak of compare_events, it ignores the densities.
+fn compare_tied_events(
+    a: &(HaplotypeFractions, LogProb),
+    b: &(HaplotypeFractions, LogProb),
+    haplotypes: &[Haplotype],
+) -> Ordering {
+    let present = |fractions: &HaplotypeFractions| -> Vec<&Haplotype> {
+        haplotypes
+            .iter()
+            .zip(fractions.iter())
+            .filter(|(_, fraction)| **fraction > NotNan::new(0.0).unwrap())
+            .map(|(haplotype, _)| haplotype)
+            .collect()
+    };
+    present(&a.0)
+        .cmp(&present(&b.0))
+        .then_with(|| a.0.iter().cmp(b.0.iter()))
+}
+
+//rank_events sorts the events with compare_events, best first.
+pub fn rank_events(
+    event_posteriors: &mut [(HaplotypeFractions, LogProb)],
+    haplotypes: &[Haplotype],
+    tie_tolerance: f64,
+) {
+    let ties = EventTies::new(event_posteriors, tie_tolerance);
+    event_posteriors.sort_by(|a, b| compare_events(a, b, haplotypes, &ties));
+}
+
+//select_best_event finds all events that are tied for the best posterior density (see EventTies).
+//Among tied events, the one whose haplotypes with nonzero fraction come first in lexicographic order is chosen,
+//so that the reported solution doesn't depend on the order of the events. It is the first event of rank_events.
+//Ties are reported with a warning.
+pub fn select_best_event(
+    event_posteriors: &[(HaplotypeFractions, LogProb)],
+    haplotypes: &[Haplotype],
+    tie_tolerance: f64,
+) -> BestEvent {
+    let ties = EventTies::new(event_posteriors, tie_tolerance);
+    let tied: Vec<usize> = event_posteriors
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, logprob))| ties.is_tied(*logprob))
+        .map(|(i, _)| i)
+        .collect();
+    let selected_haplotypes = |i: usize| -> Vec<&Haplotype> {
+        haplotypes
+            .iter()
+            .zip(event_posteriors[i].0.iter())
+            .filter(|(_, fraction)| **fraction > NotNan::new(0.0).unwrap())
+            .map(|(haplotype, _)| haplotype)
+            .collect()
+    };
+    let index = tied
+        .iter()
+        .cloned()
+        .min_by(|a, b| {
+            compare_tied_events(&event_posteriors[*a], &event_posteriors[*b], haplotypes)
+        })
+        .unwrap_or(0);
+    if tied.len() > 1 {
+        warn!(
+            "{} events are tied for the best posterior density ({:.4}), reporting the lexicographically first: {}",
+            tied.len(),
+            ties.best_density().exp(),
+            tied.iter()
+                .map(|i| {
+                    selected_haplotypes(*i)
+                        .iter()
+                        .map(|haplotype| haplotype.to_string())
+                        .collect::<Vec<_>>()
+                        .join("/")
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    BestEvent { index, tied }
+}
+
+//check_lp_posterior_consistency compares the fractions of the haplotypes selected by the linear program to their fractions
+//in the best posterior event and warns about each haplotype where both differ by more than 'tolerance'.
+//Large differences point to a modeling problem, e.g. variants that are considered by only one of the two stages.
+//The divergent haplotypes are returned with their LP and posterior fractions.
+pub fn check_lp_posterior_consistency(
+    lp_fractions: &BTreeMap<Haplotype, f64>,
+    final_haplotypes: &[Haplotype],
+    best_fractions: &[f64],
+    tolerance: f64,
+) -> Vec<(Haplotype, f64, f64)> {
+    let mut divergent = Vec::new();
+    for (haplotype, posterior_fraction) in final_haplotypes.iter().zip(best_fractions.iter()) {
+        if let Some(lp_fraction) = lp_fractions.get(haplotype) {
+            if (lp_fraction - posterior_fraction).abs() > tolerance {
+                warn!(
+                    "fractions of {} diverge between linear program ({:.2}) and posterior ({:.2})",
+                    haplotype.as_str(),
+                    lp_fraction,
+                    posterior_fraction
+                );
+                divergent.push((haplotype.clone(), *lp_fraction, *posterior_fraction));
+            }
+        }
+    }
+    divergent
+}
+
+//PanelCoverage describes how well a candidate panel explains the variant calls of a sample:
+//the called variants that are part of the panel, the haplotypes that only this panel contains (compared to the other panel)
+//and the residual of the linear program on the covered variants (None if the panel covers none of the calls).
+#[derive(Debug, Clone, PartialEq)]
+pub struct PanelCoverage {
+    pub covered_variants: Vec<VariantID>,
+    pub unique_haplotypes: Vec<Haplotype>,
+    pub lp_residual: Option<f64>,
+}
+
+//PanelComparison compares two candidate panels (e.g. of two IMGT releases) on the same variant calls.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PanelComparison {
+    pub first: PanelCoverage,
+    pub second: PanelCoverage,
+}
+
+pub fn compare_panels(
+    first: &HaplotypeVariants,
+    second: &HaplotypeVariants,
+    variant_calls: &VariantCalls,
+    settings: &LinearProgramSettings,
+) -> Result<PanelComparison> {
+    let haplotypes = |panel: &HaplotypeVariants| -> Vec<Haplotype> {
+        panel
+            .values()
+            .next()
+            .map(|haplotype_map| haplotype_map.keys().cloned().collect())
+            .unwrap_or_default()
+    };
+    let (first_haplotypes, second_haplotypes) = (haplotypes(first), haplotypes(second));
+    //the linear program writes its plots next to the given output, hence use a scratch directory
+    let scratch = tempfile::tempdir()?;
+    let coverage = |panel: &HaplotypeVariants,
+                    panel_haplotypes: &Vec<Haplotype>,
+                    other_haplotypes: &Vec<Haplotype>|
+     -> Result<PanelCoverage> {
+        let covered_variants: Vec<VariantID> = variant_calls
+            .keys()
+            .filter(|variant| panel.contains_key(variant))
+            .cloned()
+            .collect();
+        let lp_residual = if covered_variants.is_empty() {
+            None
+        } else {
+            let candidate_matrix =
+                CandidateMatrix::new(&panel.filter_for_variants(&covered_variants)?)?;
+            let solution = linear_program(
+                &scratch.path().join("panel.csv"),
+                &candidate_matrix,
+                panel_haplotypes,
+                &variant_calls.filter_variant_calls(&covered_variants)?,
+                settings,
+            )?;
+            Some(solution.residual)
+        };
+        Ok(PanelCoverage {
+            covered_variants,
+            unique_haplotypes: panel_haplotypes
+                .iter()
+                .filter(|haplotype| !other_haplotypes.contains(haplotype))
+                .cloned()
+                .collect(),
+            lp_residual,
+        })
+    };
+    Ok(PanelComparison {
+        first: coverage(first, &first_haplotypes, &second_haplotypes)?,
+        second: coverage(second, &second_haplotypes, &first_haplotypes)?,
+    })
+}
+
+//extend_lp_haplotypes adds haplotypes that are close to the ones selected by the linear program.
+//A haplotype is added if it has the same variants, if it is within 'num_variant_distance' variants,
+//or, if 'extension_overlap' is given, if it shares at least that fraction of the variants of the selected haplotype.
+pub fn extend_lp_haplotypes(
+    lp_haplotypes: &[Haplotype],
+    haplotype_dict: &BTreeMap<Haplotype, Vec<VariantID>>,
+    num_variant_distance: i64,
+    extension_overlap: Option<f64>,
+) -> Vec<Haplotype> {
+    let mut extended_haplotypes = Vec::new();
+    lp_haplotypes.iter().for_each(|f_haplotype| {
+        let variants = haplotype_dict.get(&f_haplotype).unwrap().clone();
+        haplotype_dict
+            .iter()
+            .for_each(|(haplotype, haplotype_variants)| {
+                if &variants == haplotype_variants && !extended_haplotypes.contains(haplotype) {
+                    //fix: the last operand '&&' is required to avoid duplicate additions
+                    extended_haplotypes.push(haplotype.clone());
+                } else {
+                    let mut difference = vec![];
+                    for i in haplotype_variants.iter() {
+                        if !variants.contains(&i) {
+                            difference.push(i);
+                        }
+                    }
+                    let within_distance = (difference.len() as i64 <= num_variant_distance)
+                        && ((variants.len() as i64 - haplotype_variants.len() as i64).abs()
+                            <= num_variant_distance);
+                    //shared fraction of the variants of the selected haplotype
+                    let within_overlap = match extension_overlap {
+                        Some(overlap) if !variants.is_empty() => {
+                            let shared = variants
+                                .iter()
+                                .filter(|v| haplotype_variants.contains(v))
+                                .count();
+                            shared as f64 / variants.len() as f64 >= overlap
+                        }
+                        _ => false,
+                    };
+                    if (within_distance || within_overlap)
+                        && !extended_haplotypes.contains(&haplotype)
+                    //fix: the last operand '&&' is required to avoid duplicate additions
+                    {
+                        extended_haplotypes.push(haplotype.clone());
+                    }
+                }
+            });
+    });
+    extended_haplotypes
+}
+
+//expected_vaf is the allele frequency that the fractions (in the order of the columns of the candidate matrix) expect at
+//a variant: the sum of the fractions of the haplotypes carrying the variant, normalized according to the given
+//VafDenominator. Haplotypes with unknown genotype don't contribute. It is None if none of the haplotypes carries the variant.
+pub(crate) fn expected_vaf(
+    fractions: &[AlleleFreq],
+    genotypes: &[VariantStatus],
+    covered: &BitVec,
+    denominator: VafDenominator,
+) -> Option<AlleleFreq> {
+    let mut denom = NotNan::new(1.0).unwrap();
+    let mut vaf_sum = NotNan::new(0.0).unwrap();
+    let mut counter = 0;
+    fractions.iter().enumerate().for_each(|(i, fraction)| {
+        if genotypes[i] == VariantStatus::Present && covered[i as u64] {
+            vaf_sum += *fraction;
+            counter += 1;
+        } else if denominator == VafDenominator::ExcludeUncovered
+            && genotypes[i] == VariantStatus::NotPresent
+            && !covered[i as u64]
+        {
+            denom -= *fraction;
+        }
+    });
+    if counter == 0 {
+        return None;
+    }
+    if denom > NotNan::new(0.0).unwrap() {
+        vaf_sum /= denom;
+    }
+    Some(vaf_sum)
+}
+
+/// Computes the allele frequency that a hypothetical mixture of the haplotypes (with the fractions in the order of the
+/// columns of the candidate matrix) expects at each variant of the candidate matrix, independent of any calls.
+/// Variants that none of the haplotypes carries are expected at 0.0. See `VafDenominator` for the normalization, e.g.
+///
+/// ```
+/// use orthanq::calling::haplotypes::haplotypes::{
+///     expected_vafs, CandidateMatrix, HaplotypeVariants, VafDenominator, VariantID,
+/// };
+/// use orthanq::model::HaplotypeFractions;
+/// use ordered_float::NotNan;
+/// use rust_htslib::bcf;
+///
+/// //the columns are A*01:01:01, A*02:01:01 and A*03:01:01
+/// let haplotype_variants =
+///     HaplotypeVariants::new(&mut bcf::Reader::from_path("tests/small_candidates.vcf").unwrap())
+///         .unwrap();
+/// let candidate_matrix = CandidateMatrix::new(&haplotype_variants).unwrap();
+/// let fractions = HaplotypeFractions(
+///     [0.6, 0.4, 0.0].iter().map(|f| NotNan::new(*f).unwrap()).collect(),
+/// );
+/// let vafs = expected_vafs(&fractions, &candidate_matrix, VafDenominator::default());
+/// assert!((vafs[&VariantID(1)] - 0.6).abs() < 1e-9);
+/// assert!((vafs[&VariantID(2)] - 0.4).abs() < 1e-9);
+/// assert_eq!(vafs[&VariantID(3)], 0.0);
+/// assert!((vafs[&VariantID(4)] - 1.0).abs() < 1e-9);
+/// ```
+pub fn expected_vafs(
+    fractions: &HaplotypeFractions,
+    candidate_matrix: &CandidateMatrix,
+    denominator: VafDenominator,
+) -> BTreeMap<VariantID, f64> {
+    candidate_matrix
+        .iter()
+        .map(|(variant_id, (genotypes, covered))| {
+            (
+                *variant_id,
+                expected_vaf(fractions, genotypes, covered, denominator).map_or(0.0, |vaf| *vaf),
+            )
+        })
+        .collect()
+}
+
+pub fn write_results(
+    outdir: &PathBuf,
+    data: &Data,
+    event_posteriors: &Vec<(HaplotypeFractions, LogProb)>,
+    final_haplotypes: &Vec<Haplotype>,
+    prior: String,
+    variant_info: bool,
+    min_odds: Option<f64>,
+    fraction_snap: Option<f64>,
+    vaf_denominator: VafDenominator,
+    tie_tolerance: f64,
+) -> Result<()> {
+    //sort the events by density, best first (see compare_events), such that the odds of the remaining events don't
+    //exceed 1 (apart from events tied with the best one, see EventTies).
+    let mut event_posteriors = event_posteriors.clone();
+    rank_events(&mut event_posteriors, final_haplotypes, tie_tolerance);
+    let event_posteriors = &event_posteriors;
+
+    if let (Ok(prior), Some((best_fractions, _))) =
+        (PriorTypes::from_str(&prior), event_posteriors.first())
+    {
+        for (haplotype, fraction) in boundary_fractions(best_fractions, final_haplotypes, &prior) {
+            warn!(
+                "the fraction {:.2} of {} is within one grid step of the boundary, the estimate may be clipped",
+                fraction, haplotype
+            );
+        }
+    }
+
+    //firstly add variant query and probabilities to the outout table for each event
+    let mut event_queries: Vec<BTreeMap<VariantID, (AlleleFreq, LogProb)>> = Vec::new();
+    // let event_posteriors = computed_model.event_posteriors();
+    if variant_info {
+        event_posteriors.iter().for_each(|(fractions, _)| {
+            let mut vaf_queries: BTreeMap<VariantID, (AlleleFreq, LogProb)> = BTreeMap::new();
+            //join the candidate matrix and the calls by variant, variants that are missing in either of them are skipped
+            data.candidate_matrix
+                .iter()
+                .filter_map(|(variant_id, candidates)| {
+                    data.variant_calls
+                        .get(variant_id)
+                        .map(|(_, afd)| (variant_id, candidates, afd))
+                })
+                .for_each(|(variant_id, (genotypes, covered), afd)| {
+                    if let Some(vaf_sum) =
+                        expected_vaf(fractions, genotypes, covered, vaf_denominator)
+                    {
+                        let vaf_sum = NotNan::new((vaf_sum * NotNan::new(100.0).unwrap()).round())
+                            .unwrap()
+                            / NotNan::new(100.0).unwrap();
+                        if !afd.is_empty() {
+                            let answer = afd.vaf_query(&vaf_sum);
+                            vaf_queries.insert(*variant_id, (vaf_sum, answer.unwrap()));
+                        }
+                    }
+                });
+            event_queries.push(vaf_queries);
+        });
+    }
+    // Then,print TSV table with results
+    // Columns: posterior_prob, haplotype_a, haplotype_b, haplotype_c, ...
+    // with each column after the first showing the fraction of the respective haplotype
+    let mut wtr = csv::Writer::from_path(outdir)?;
+    let mut headers: Vec<_> = vec!["density".to_string(), "odds".to_string()];
+    let haplotypes_str: Vec<String> = final_haplotypes
+        .clone()
+        .iter()
+        .map(|h| h.to_string())
+        .collect();
+    headers.extend(haplotypes_str);
+    if variant_info {
+        let variant_names = event_queries[0]
+            .keys()
+            .map(|key| format!("{:?}", key))
+            .collect::<Vec<String>>();
+        headers.extend(variant_names); //add variant names as separate columns
+    }
+    wtr.write_record(&headers)?;
+
+    //write best record on top
+    let mut records = Vec::new();
+    // let mut event_posteriors = computed_model.event_posteriors(); //compute a second time because event_posteriors can't be cloned from above.
+    let (haplotype_frequencies, best_density) = event_posteriors.iter().next().unwrap();
+    let best_odds = 1;
+    let format_f64 = |number: f64, records: &mut Vec<String>| {
+        if number <= 0.01 {
+            records.push(format!("{:+.2e}", number))
+        } else {
+            records.push(format!("{:.2}", number))
+        }
+    };
+    format_f64(best_density.exp(), &mut records);
+    records.push(best_odds.to_string());
+    //optionally snap the fractions to the closest multiple of 'fraction_snap' (e.g. 0.05), such that near-equal mixtures
+    //are reported as clean values
+    let format_freqs = |frequency: NotNan<f64>, records: &mut Vec<String>| {
+        let frequency = snap_fraction(frequency, fraction_snap);
+        if frequency <= NotNan::new(0.01).unwrap() {
+            records.push(format!("{:+.2e}", NotNan::into_inner(frequency)))
+        } else {
+            records.push(format!("{:.2}", frequency))
+        }
+    };
+    haplotype_frequencies
+        .iter()
+        .for_each(|frequency| format_freqs(*frequency, &mut records));
+
+    if variant_info {
+        //add vaf queries and probabilities for the first event to the output table
+        let queries: Vec<(AlleleFreq, LogProb)> = event_queries
+            .iter()
+            .next()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        queries.iter().for_each(|(query, answer)| {
+            let prob = f64::from(Prob::from(*answer));
+            if prob <= 0.01 {
+                records.push(format!("{}{}{:+.2e}", query, ":", prob));
+            } else {
+                records.push(format!("{}{}{:.2}", query, ":", prob));
+            }
+        });
+    }
+
+    wtr.write_record(records)?;
+
+    //write the rest of the records, stopping at the first one with odds (relative to the best record) below min_odds.
+    //As the records are sorted by density, all further records are below as well.
+    // dbg!(&event_posteriors);
+    let above_min_odds = |density: &LogProb| {
+        min_odds.map_or(true, |min_odds| (density - best_density).exp() >= min_odds)
+    };
+
+    if variant_info {
+        event_posteriors
+            .iter()
+            .skip(1)
+            .zip(event_queries.iter().skip(1))
+            .take_while(|((_, density), _)| above_min_odds(density))
+            .for_each(|((haplotype_frequencies, density), queries)| {
+                let mut records = Vec::new();
+                let odds = (density - best_density).exp();
+                format_f64(density.exp(), &mut records);
+                format_f64(odds, &mut records);
+                haplotype_frequencies
+                    .iter()
+                    .for_each(|frequency| format_freqs(*frequency, &mut records));
+
+                queries.iter().for_each(|(_, (query, answer))| {
+                    let prob = f64::from(Prob::from(*answer));
+                    if prob <= 0.01 {
+                        records.push(format!("{}{}{:+.2e}", query, ":", prob));
+                    } else {
+                        records.push(format!("{}{}{:.2}", query, ":", prob));
+                    }
+                });
+                wtr.write_record(records).unwrap();
+            });
+    } else {
+        event_posteriors
+            .iter()
+            .skip(1)
+            .take_while(|(_, density)| above_min_odds(density))
+            .for_each(|(haplotype_frequencies, density)| {
+                let mut records = Vec::new();
+                let odds = (density - best_density).exp();
+                format_f64(density.exp(), &mut records);
+                format_f64(odds, &mut records);
+                haplotype_frequencies
+                    .iter()
+                    .for_each(|frequency| format_freqs(*frequency, &mut records));
+
+                wtr.write_record(records).unwrap();
+            });
+    }
+
+    //write the observed allele frequencies next to the ones expected by the events, for the events of the table
+    if variant_info {
+        let shown_events = 1 + event_posteriors
+            .iter()
+            .skip(1)
+            .take_while(|(_, density)| above_min_odds(density))
+            .count();
+        let mut parent = outdir.clone();
+        parent.pop();
+        write_vaf_fit(
+            &parent.join("vaf_fit.tsv"),
+            &data.variant_calls,
+            &event_queries[..shown_events],
+        )?;
+    }
+
+    Ok(())
+}
+
+//VariantAttribution explains the allele frequency that the best solution expects for an observed variant: the
+//contribution of each haplotype carrying the variant (its fraction, normalized according to the VafDenominator as in
+//expected_vaf). The contributions sum up to the expected allele frequency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantAttribution {
+    pub variant: VariantID,
+    pub observed_af: f64,
+    pub expected_vaf: f64,
+    pub contributions: Vec<(Haplotype, f64)>,
+}
+
+//variant_attribution computes the attribution of each variant that is both called and part of the candidate matrix,
+//whose columns are the given haplotypes with the given (best) fractions.
+pub fn variant_attribution(
+    candidate_matrix: &CandidateMatrix,
+    variant_calls: &VariantCalls,
+    haplotypes: &[Haplotype],
+    fractions: &[f64],
+    denominator: VafDenominator,
+) -> Vec<VariantAttribution> {
+    candidate_matrix
+        .iter()
+        .filter_map(|(variant, (genotypes, covered))| {
+            let (af, _) = variant_calls.get(variant)?;
+            //unless all haplotypes are included, the ones that are not characterized at the variant can't explain it
+            let denom = match denominator {
+                VafDenominator::ExcludeUncovered => {
+                    1.0 - fractions
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| {
+                            genotypes[*i] == VariantStatus::NotPresent && !covered[*i as u64]
+                        })
+                        .map(|(_, fraction)| *fraction)
+                        .sum::<f64>()
+                }
+                VafDenominator::IncludeAll => 1.0,
+            };
+            let contributions = haplotypes
+                .iter()
+                .zip(fractions.iter())
+                .enumerate()
+                .filter(|(i, (_, fraction))| {
+                    genotypes[*i] == VariantStatus::Present
+                        && covered[*i as u64]
+                        && **fraction > 0.0
+                })
+                .map(|(_, (haplotype, fraction))| {
+                    let contribution = if denom > 0.0 {
+                        fraction / denom
+                    } else {
+                        *fraction
+                    };
+                    (haplotype.clone(), contribution)
+                })
+                .collect::<Vec<(Haplotype, f64)>>();
+            Some(VariantAttribution {
+                variant: *variant,
+                observed_af: *af as f64,
+                expected_vaf: contributions
+                    .iter()
+                    .map(|(_, contribution)| contribution)
+                    .sum(),
+                contributions,
+            })
+        })
+        .collect()
+}
+
+//write_variant_attribution writes the attribution of the observed variants to variant_attribution.tsv, with one row per
+//variant and contributing haplotype. Variants that are not carried by any haplotype of the solution have a single row
+//without haplotype.
+pub fn write_variant_attribution(
+    outdir: &PathBuf,
+    candidate_matrix: &CandidateMatrix,
+    variant_calls: &VariantCalls,
+    haplotypes: &[Haplotype],
+    fractions: &[f64],
+    denominator: VafDenominator,
+) -> Result<()> {
+    let mut parent = outdir.clone();
+    parent.pop();
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(parent.join("variant_attribution.tsv"))?;
+    wtr.write_record(&[
+        "variant",
+        "observed_af",
+        "expected_vaf",
+        "haplotype",
+        "contribution",
+    ])?;
+    for attribution in variant_attribution(
+        candidate_matrix,
+        variant_calls,
+        haplotypes,
+        fractions,
+        denominator,
+    ) {
+        let variant = attribution.variant.0.to_string();
+        let observed_af = format!("{:.4}", attribution.observed_af);
+        let expected_vaf = format!("{:.4}", attribution.expected_vaf);
+        if attribution.contributions.is_empty() {
+            wtr.write_record(&[&variant, &observed_af, &expected_vaf, "", "0.0000"])?;
+        }
+        for (haplotype, contribution) in attribution.contributions.iter() {
+            wtr.write_record(&[
+                &variant,
+                &observed_af,
+                &expected_vaf,
+                haplotype.as_str(),
+                &format!("{:.4}", contribution),
+            ])?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+//read_fragment_ids reads, per variant, the IDs of the fragments supporting the call from the given FORMAT field of the
+//variant calls (e.g. as reported with varlociraptor's --report-fragment-ids), for the given sample (the first one if
+//not given). The IDs are expected to be comma-separated, variants without IDs are left out.
+pub fn read_fragment_ids(
+    variant_calls: &mut bcf::Reader,
+    field: &str,
+    sample: Option<&str>,
+) -> Result<BTreeMap<VariantID, Vec<String>>> {
+    let sample_index = match sample {
+        Some(sample) => variant_calls
+            .header()
+            .sample_id(sample.as_bytes())
+            .ok_or_else(|| OrthanqError::UnknownSample {
+                sample: sample.to_string(),
+            })?,
+        None => 0,
+    };
+    let mut fragment_ids = BTreeMap::new();
+    for record_result in variant_calls.records() {
+        let record = record_result?;
+        let variant_id: i32 = String::from_utf8(record.id())?.parse()?;
+        let values =
+            record
+                .format(field.as_bytes())
+                .string()
+                .map_err(|_| OrthanqError::MissingField {
+                    variant: variant_id.to_string(),
+                    field: field.to_string(),
+                })?;
+        let ids: Vec<String> = std::str::from_utf8(values[sample_index])?
+            .split(',')
+            .map(|id| id.trim())
+            .filter(|id| !id.is_empty() && *id != ".")
+            .map(|id| id.to_string())
+            .collect();
+        if !ids.is_empty() {
+            fragment_ids.insert(VariantID(variant_id), ids);
+        }
+    }
+    Ok(fragment_ids)
+}
+
+//write_read_support writes, for the best solution, the fragments supporting each observed variant together with the
+//haplotypes that carry it (see variant_attribution) to read_support.tsv, with one row per variant and contributing
+//haplotype. A fragment of a variant carried by several haplotypes can't be assigned to one of them, hence it is
+//listed for each of them.
+pub fn write_read_support(
+    outdir: &PathBuf,
+    candidate_matrix: &CandidateMatrix,
+    variant_calls: &VariantCalls,
+    haplotypes: &[Haplotype],
+    fractions: &[f64],
+    fragment_ids: &BTreeMap<VariantID, Vec<String>>,
+    denominator: VafDenominator,
+) -> Result<()> {
+    let mut parent = outdir.clone();
+    parent.pop();
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(parent.join("read_support.tsv"))?;
+    wtr.write_record(&["variant", "haplotype", "contribution", "fragment_ids"])?;
+    for attribution in variant_attribution(
+        candidate_matrix,
+        variant_calls,
+        haplotypes,
+        fractions,
+        denominator,
+    ) {
+        if let Some(ids) = fragment_ids.get(&attribution.variant) {
+            for (haplotype, contribution) in attribution.contributions.iter() {
+                wtr.write_record(&[
+                    &attribution.variant.0.to_string(),
+                    haplotype.as_str(),
+                    &format!("{:.4}", contribution),
+                    &ids.join(","),
+                ])?;
+            }
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+//read_prob_absent reads, per called variant, the posterior probability (not PHRED-scaled) that the variant is absent
+//from the PROB_ABSENT field of the variant calls.
+pub fn read_prob_absent(variant_calls: &mut bcf::Reader) -> Result<BTreeMap<VariantID, f64>> {
+    let mut prob_absent = BTreeMap::new();
+    for record_result in variant_calls.records() {
+        let record = record_result?;
+        let variant_id: i32 = String::from_utf8(record.id())?.parse()?;
+        let phred = record
+            .info(b"PROB_ABSENT")
+            .float()
+            .ok()
+            .flatten()
+            .ok_or_else(|| OrthanqError::MissingField {
+                variant: variant_id.to_string(),
+                field: "PROB_ABSENT".to_string(),
+            })?[0];
+        prob_absent.insert(
+            VariantID(variant_id),
+            f64::from(Prob::from(PHREDProb(phred as f64))),
+        );
+    }
+    Ok(prob_absent)
+}
+
+//PresenceConcordance compares, for an observed variant, the presence according to the call with the presence implied by
+//the best solution. The call states presence if PROB_ABSENT <= 0.05 and absence if PROB_ABSENT >= 0.95, in between it
+//is ambiguous (None). The solution implies presence if any haplotype with a fraction above 0.0 carries the variant
+//(see variant_attribution). A discordance hints at a problem of the model, e.g. a missing haplotype in the panel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PresenceConcordance {
+    pub variant: VariantID,
+    pub prob_absent: f64,
+    pub observed: Option<bool>,
+    pub implied: bool,
+}
+
+impl PresenceConcordance {
+    pub fn is_discordant(&self) -> bool {
+        self.observed
+            .map_or(false, |observed| observed != self.implied)
+    }
+}
+
+pub fn presence_concordance(
+    candidate_matrix: &CandidateMatrix,
+    variant_calls: &VariantCalls,
+    haplotypes: &[Haplotype],
+    fractions: &[f64],
+    prob_absent: &BTreeMap<VariantID, f64>,
+    denominator: VafDenominator,
+) -> Vec<PresenceConcordance> {
+    variant_attribution(
+        candidate_matrix,
+        variant_calls,
+        haplotypes,
+        fractions,
+        denominator,
+    )
+    .into_iter()
+    .filter_map(|attribution| {
+        let prob_absent = *prob_absent.get(&attribution.variant)?;
+        let observed = if prob_absent <= 0.05 {
+            Some(true)
+        } else if prob_absent >= 0.95 {
+            Some(false)
+        } else {
+            None
+        };
+        Some(PresenceConcordance {
+            variant: attribution.variant,
+            prob_absent,
+            observed,
+            implied: !attribution.contributions.is_empty(),
+        })
+    })
+    .collect()
+}
+
+//write_presence_concordance writes the concordances (see PresenceConcordance) to presence_concordance.tsv next to the
+//output. The observed presence is present, absent or ambiguous, concordant is NA for ambiguous calls.
+pub fn write_presence_concordance(
+    outdir: &PathBuf,
+    concordances: &[PresenceConcordance],
+) -> Result<()> {
+    let mut parent = outdir.clone();
+    parent.pop();
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(parent.join("presence_concordance.tsv"))?;
+    wtr.write_record(&[
+        "variant",
+        "prob_absent",
+        "observed",
+        "implied",
+        "concordant",
+    ])?;
+    let presence = |present: bool| if present { "present" } else { "absent" };
+    for concordance in concordances {
+        wtr.write_record(&[
+            concordance.variant.0.to_string(),
+            format!("{:.4}", concordance.prob_absent),
+            concordance
+                .observed
+                .map_or("ambiguous", presence)
+                .to_string(),
+            presence(concordance.implied).to_string(),
+            concordance.observed.map_or("NA".to_string(), |_| {
+                (!concordance.is_discordant()).to_string()
+            }),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+//FitDivergence measures how well the allele frequency distribution (AFD) of a call supports the allele frequency that
+//the best solution expects at the variant. A point estimate has no finite KL divergence to the AFD, hence the divergence
+//is the log ratio (in nats) of the highest density of the AFD to the density at the expected allele frequency:
+//0 if the expected allele frequency is the best supported one, growing the less the call supports it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FitDivergence {
+    pub variant: VariantID,
+    pub expected_vaf: f64,
+    pub divergence: f64,
+}
+
+//fit_divergences computes the divergence of each variant with an AFD that is both called and part of the candidate matrix,
+//for the given haplotypes (the columns of the candidate matrix) and (best) fractions. As in the model, the expected allele
+//frequency is normalized with the given VafDenominator and rounded to two decimals before it is queried.
+pub fn fit_divergences(
+    candidate_matrix: &CandidateMatrix,
+    variant_calls: &VariantCalls,
+    haplotypes: &[Haplotype],
+    fractions: &[f64],
+    denominator: VafDenominator,
+) -> Vec<FitDivergence> {
+    variant_attribution(
+        candidate_matrix,
+        variant_calls,
+        haplotypes,
+        fractions,
+        denominator,
+    )
+    .into_iter()
+    .filter_map(|attribution| {
+        let (_, afd) = variant_calls.get(&attribution.variant)?;
+        let (first, _) = afd.iter().next()?;
+        let (last, _) = afd.iter().next_back()?;
+        let expected_vaf = ((attribution.expected_vaf * 100.0).round() / 100.0)
+            .max(**first)
+            .min(**last);
+        let mode = afd
+            .values()
+            .cloned()
+            .fold(LogProb::ln_zero(), |max, density| {
+                if density > max {
+                    density
+                } else {
+                    max
+                }
+            });
+        let at_expected = afd.vaf_query(&NotNan::new(expected_vaf).unwrap())?;
+        Some(FitDivergence {
+            variant: attribution.variant,
+            expected_vaf,
+            divergence: (*mode - *at_expected).max(0.0),
+        })
+    })
+    .collect()
+}
+
+//write_fit_divergences writes the divergences (see fit_divergences) to fit_divergence.tsv next to the output.
+pub fn write_fit_divergences(
+    outdir: &PathBuf,
+    candidate_matrix: &CandidateMatrix,
+    variant_calls: &VariantCalls,
+    haplotypes: &[Haplotype],
+    fractions: &[f64],
+    denominator: VafDenominator,
+) -> Result<()> {
+    let mut parent = outdir.clone();
+    parent.pop();
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(parent.join("fit_divergence.tsv"))?;
+    wtr.write_record(&["variant", "expected_vaf", "divergence"])?;
+    for fit in fit_divergences(
+        candidate_matrix,
+        variant_calls,
+        haplotypes,
+        fractions,
+        denominator,
+    ) {
+        wtr.write_record(&[
+            fit.variant.0.to_string(),
+            format!("{:.2}", fit.expected_vaf),
+            format!("{:.4}", fit.divergence),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+//boundary_fractions are the haplotypes whose fraction lies within one grid step (0.1, as the fractions are integrated
+//over) of 0.0 or the upper bound (the sum of the fractions), such that the true value may lie beyond the grid. This is
+//only checked for the uniform and diploid-subclonal priors, the fractions of the diploid and haploid priors are exact
+//grid points. Fractions of exactly 0.0 denote absent haplotypes and are not reported.
+pub fn boundary_fractions(
+    fractions: &HaplotypeFractions,
+    haplotypes: &[Haplotype],
+    prior: &PriorTypes,
+) -> Vec<(Haplotype, f64)> {
+    let grid_step = match prior {
+        PriorTypes::Uniform | PriorTypes::DiploidSubclonal => 0.1,
+        PriorTypes::Diploid | PriorTypes::Haploid => return Vec::new(),
+    };
+    let upper_bound: f64 = fractions.iter().map(|fraction| **fraction).sum();
+    haplotypes
+        .iter()
+        .zip(fractions.iter())
+        .map(|(haplotype, fraction)| (haplotype.clone(), **fraction))
+        .filter(|(_, fraction)| {
+            *fraction > 0.0
+                && (*fraction <= grid_step + 1e-9 || *fraction >= upper_bound - grid_step - 1e-9)
+        })
+        .collect()
+}
+
+//snap_fraction rounds the fraction to the closest multiple of 'grid', it is returned unchanged without a grid.
+pub fn snap_fraction(fraction: AlleleFreq, grid: Option<f64>) -> AlleleFreq {
+    match grid {
+        Some(grid) if grid > 0.0 => {
+            NotNan::new(((*fraction / grid).round() * grid).min(1.0)).unwrap()
+        }
+        _ => fraction,
+    }
+}
+
+//write_vaf_fit writes the observed (maximum a posteriori) allele frequency of each variant next to the allele
+//frequency expected by each event (see write_results) and the probability of the latter given the allele frequency
+//distribution, with one row per event and variant. Large deviations point to loci that are poorly explained.
+fn write_vaf_fit(
+    path: &PathBuf,
+    variant_calls: &VariantCalls,
+    event_queries: &[BTreeMap<VariantID, (AlleleFreq, LogProb)>],
+) -> Result<()> {
+    let mut wtr = csv::WriterBuilder::new().delimiter(b'\t').from_path(path)?;
+    wtr.write_record(&[
+        "event",
+        "variant",
+        "observed_af",
+        "expected_vaf",
+        "deviation",
+        "probability",
+    ])?;
+    for (event, queries) in event_queries.iter().enumerate() {
+        for (variant, (expected, answer)) in queries {
+            let observed = variant_calls.get(variant).map_or(0.0, |(af, _)| *af as f64);
+            let expected = NotNan::into_inner(*expected);
+            wtr.write_record(&[
+                event.to_string(),
+                variant.0.to_string(),
+                format!("{:.2}", observed),
+                format!("{:.2}", expected),
+                format!("{:.2}", (observed - expected).abs()),
+                format!("{:.2e}", f64::from(Prob::from(*answer))),
+            ])?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+//with_output_prefix runs 'write' with the output redirected into a scratch directory next to 'outcsv' and moves all files
+//that it generates (including the output itself) into the directory of 'outcsv', with 'prefix' prepended to their names.
+//Like this, the runs of several samples can share an output directory without their generated files colliding.
+pub fn with_output_prefix<F>(outcsv: &PathBuf, prefix: &str, write: F) -> Result<()>
+where
+    F: FnOnce(&PathBuf) -> Result<()>,
+{
+    let mut parent = outcsv.clone();
+    parent.pop();
+    if parent.as_os_str().is_empty() {
+        parent = PathBuf::from(".");
+    }
+    fs::create_dir_all(&parent)?;
+    let file_name = outcsv
+        .file_name()
+        .ok_or_else(|| anyhow!("the output {} has no file name", outcsv.display()))?;
+    let scratch = tempfile::Builder::new()
+        .prefix(".orthanq")
+        .tempdir_in(&parent)?;
+    write(&scratch.path().join(file_name))?;
+    for entry in fs::read_dir(scratch.path())? {
+        let entry = entry?;
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        fs::rename(entry.path(), parent.join(name))?;
+    }
+    Ok(())
+}
+
+//sample_outputs assigns each of the variant calls of several related samples (e.g. a trio or tumor and normal)
+//its sample name, given by the file name up to the first '.', and its output, i.e. the output file within a
+//subdirectory of the output directory that is named after the sample.
+pub fn sample_outputs(
+    variant_calls: &[PathBuf],
+    output: &PathBuf,
+) -> Result<Vec<(String, PathBuf)>> {
+    let mut parent = output.clone();
+    parent.pop();
+    let file_name = output
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_default();
+    let mut outputs: Vec<(String, PathBuf)> = Vec::new();
+    for calls in variant_calls {
+        let sample = calls
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.split('.').next().unwrap().to_string())
+            .unwrap_or_default();
+        if outputs.iter().any(|(other, _)| *other == sample) {
+            return Err(anyhow!(
+                "the variant calls of several samples are named {}, please rename them such that the samples can be told apart",
+                sample
+            ));
+        }
+        outputs.push((sample.clone(), parent.join(&sample).join(&file_name)));
+    }
+    Ok(outputs)
+}
 
-    //extend haplotypes found by linear program, add haplotypes that have the same variants to the final list.
-    //then sort by hamming distance, take the closest x additional alleles according to 'num_variant_distance'.
-    //this is done by storing only the variants that have GT:1 and C:1 for all haplotypes in haplotype_dict and remaining variants are not included.
-    let mut extended_haplotypes = Vec::new();
-    if extend_haplotypes {
-        lp_haplotypes.iter().for_each(|(f_haplotype, _)| {
-            let variants = haplotype_dict.get(&f_haplotype).unwrap().clone();
-            haplotype_dict
-                .iter()
-                .for_each(|(haplotype, haplotype_variants)| {
-                    if &variants == haplotype_variants && !extended_haplotypes.contains(haplotype) {
-                        //fix: the last operand '&&' is required to avoid duplicate additions
-                        extended_haplotypes.push(haplotype.clone());
-                    } else {
-                        let mut difference = vec![];
-                        for i in haplotype_variants.iter() {
-                            if !variants.contains(&i) {
-                                difference.push(i);
-                            }
-                        }
-                        if (difference.len() as i64 <= num_variant_distance)
-                            && ((variants.len() as i64 - haplotype_variants.len() as i64).abs()
-                                <= num_variant_distance)
-                            && !extended_haplotypes.contains(&haplotype)
-                        //fix: the last operand '&&' is required to avoid duplicate additions
-                        {
-                            extended_haplotypes.push(haplotype.clone());
-                        }
-                    }
-                });
-        });
-        let lp_keys: Vec<_> = lp_haplotypes.keys().cloned().collect();
-        dbg!(&lp_keys, &extended_haplotypes);
-        Ok((extended_haplotypes, lp_keys))
-    } else {
-        let lp_keys: Vec<_> = lp_haplotypes.keys().cloned().collect();
-        let extended_haplotypes = lp_keys.clone();
-        dbg!(&lp_keys, &extended_haplotypes);
-        Ok((extended_haplotypes, lp_keys))
+//combine_sample_results combines the results tables (see write_results) of several samples into one table with
+//a leading sample column. The haplotype columns are the union of the haplotypes of all samples, with a fraction of 0
+//for the haplotypes that are not part of the results of a sample.
+pub fn combine_sample_results(
+    sample_results: &[(String, PathBuf)],
+    output: &PathBuf,
+) -> Result<()> {
+    let mut tables = Vec::new();
+    let mut haplotypes: BTreeSet<String> = BTreeSet::new();
+    for (sample, results) in sample_results {
+        let mut rdr = csv::Reader::from_path(results)?;
+        let headers = rdr.headers()?.clone();
+        haplotypes.extend(headers.iter().skip(2).map(|header| header.to_string()));
+        let records = rdr
+            .records()
+            .collect::<Result<Vec<csv::StringRecord>, _>>()?;
+        tables.push((sample, headers, records));
+    }
+    let mut wtr = csv::Writer::from_path(output)?;
+    let mut headers = vec![
+        "sample".to_string(),
+        "density".to_string(),
+        "odds".to_string(),
+    ];
+    headers.extend(haplotypes.iter().cloned());
+    wtr.write_record(&headers)?;
+    for (sample, sample_headers, records) in tables {
+        for record in records {
+            let mut row = vec![
+                sample.clone(),
+                record.get(0).unwrap_or_default().to_string(),
+                record.get(1).unwrap_or_default().to_string(),
+            ];
+            for haplotype in &haplotypes {
+                let fraction = sample_headers
+                    .iter()
+                    .position(|header| header == haplotype)
+                    .and_then(|index| record.get(index))
+                    .unwrap_or("0");
+                row.push(fraction.to_string());
+            }
+            wtr.write_record(&row)?;
+        }
     }
+    wtr.flush()?;
+    Ok(())
 }
 
-pub fn write_results(
+//write_log_densities writes the densities of all events in natural log space at full precision to log_densities.tsv.
+pub fn write_log_densities(
     outdir: &PathBuf,
-    data: &Data,
     event_posteriors: &Vec<(HaplotypeFractions, LogProb)>,
     final_haplotypes: &Vec<Haplotype>,
-    _prior: String,
-    variant_info: bool,
+    tie_tolerance: f64,
 ) -> Result<()> {
-    //firstly add variant query and probabilities to the outout table for each event
-    let variant_calls: Vec<AlleleFreqDist> = data
-        .variant_calls
-        .iter()
-        .map(|(_, (_, afd))| afd.clone())
-        .collect();
-    let mut event_queries: Vec<BTreeMap<VariantID, (AlleleFreq, LogProb)>> = Vec::new();
-    // let event_posteriors = computed_model.event_posteriors();
-    if variant_info {
-        event_posteriors.iter().for_each(|(fractions, _)| {
-            let mut vaf_queries: BTreeMap<VariantID, (AlleleFreq, LogProb)> = BTreeMap::new();
-            data.candidate_matrix
-                .iter()
-                .zip(variant_calls.iter())
-                .for_each(|((variant_id, (genotypes, covered)), afd)| {
-                    let mut denom = NotNan::new(1.0).unwrap();
-                    let mut vaf_sum = NotNan::new(0.0).unwrap();
-                    let mut counter = 0;
-                    fractions.iter().enumerate().for_each(|(i, fraction)| {
-                        if genotypes[i] == VariantStatus::Present && covered[i as u64] {
-                            vaf_sum += *fraction;
-                            counter += 1;
-                        } else if genotypes[i] == VariantStatus::Unknown && covered[i as u64] {
-                            todo!();
-                        } else if genotypes[i] == VariantStatus::Unknown
-                            && covered[i as u64] == false
-                        {
-                            todo!();
-                        } else if genotypes[i] == VariantStatus::NotPresent
-                            && covered[i as u64] == false
-                        {
-                            denom -= *fraction;
-                        }
-                    });
-                    if denom > NotNan::new(0.0).unwrap() {
-                        vaf_sum /= denom;
-                    }
-                    vaf_sum = NotNan::new((vaf_sum * NotNan::new(100.0).unwrap()).round()).unwrap()
-                        / NotNan::new(100.0).unwrap();
-                    if !afd.is_empty() && counter > 0 {
-                        let answer = afd.vaf_query(&vaf_sum);
-                        vaf_queries.insert(*variant_id, (vaf_sum, answer.unwrap()));
-                    } else {
-                        ()
-                    }
-                });
-            event_queries.push(vaf_queries);
-        });
-    }
-    // Then,print TSV table with results
-    // Columns: posterior_prob, haplotype_a, haplotype_b, haplotype_c, ...
-    // with each column after the first showing the fraction of the respective haplotype
-    let mut wtr = csv::Writer::from_path(outdir)?;
-    let mut headers: Vec<_> = vec!["density".to_string(), "odds".to_string()];
-    let haplotypes_str: Vec<String> = final_haplotypes
-        .clone()
-        .iter()
-        .map(|h| h.to_string())
-        .collect();
-    headers.extend(haplotypes_str);
-    if variant_info {
-        let variant_names = event_queries[0]
-            .keys()
-            .map(|key| format!("{:?}", key))
-            .collect::<Vec<String>>();
-        headers.extend(variant_names); //add variant names as separate columns
-    }
-    wtr.write_record(&headers)?;
+    let mut event_posteriors = event_posteriors.clone();
+    rank_events(&mut event_posteriors, final_haplotypes, tie_tolerance);
 
-    //write best record on top
-    let mut records = Vec::new();
-    // let mut event_posteriors = computed_model.event_posteriors(); //compute a second time because event_posteriors can't be cloned from above.
-    let (haplotype_frequencies, best_density) = event_posteriors.iter().next().unwrap();
-    let best_odds = 1;
-    let format_f64 = |number: f64, records: &mut Vec<String>| {
-        if number <= 0.01 {
-            records.push(format!("{:+.2e}", number))
-        } else {
-            records.push(format!("{:.2}", number))
+    let mut parent = outdir.clone();
+    parent.pop();
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(parent.join("log_densities.tsv"))?;
+    let mut headers = vec!["log_density".to_string(), "log_odds".to_string()];
+    headers.extend(
+        final_haplotypes
+            .iter()
+            .map(|haplotype| haplotype.to_string()),
+    );
+    wtr.write_record(&headers)?;
+    if let Some((_, best_density)) = event_posteriors.first() {
+        let best_density = *best_density;
+        for (fractions, density) in event_posteriors.iter() {
+            let mut record = vec![
+                density.0.to_string(),
+                (density.0 - best_density.0).to_string(),
+            ];
+            record.extend(fractions.iter().map(|fraction| fraction.to_string()));
+            wtr.write_record(&record)?;
         }
-    };
-    format_f64(best_density.exp(), &mut records);
-    records.push(best_odds.to_string());
-    let format_freqs = |frequency: NotNan<f64>, records: &mut Vec<String>| {
-        if frequency <= NotNan::new(0.01).unwrap() {
-            records.push(format!("{:+.2e}", NotNan::into_inner(frequency)))
-        } else {
-            records.push(format!("{:.2}", frequency))
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+//write_results_long writes the haplotype fractions of all events in long format to fractions_long.tsv,
+//with one row per event and haplotype. Events are numbered in the order of the rows of write_results (0 is the best event).
+pub fn write_results_long(
+    outdir: &PathBuf,
+    event_posteriors: &Vec<(HaplotypeFractions, LogProb)>,
+    final_haplotypes: &Vec<Haplotype>,
+    tie_tolerance: f64,
+) -> Result<()> {
+    let mut event_posteriors = event_posteriors.clone();
+    rank_events(&mut event_posteriors, final_haplotypes, tie_tolerance);
+
+    let mut parent = outdir.clone();
+    parent.pop();
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(parent.join("fractions_long.tsv"))?;
+    wtr.write_record(&["event", "haplotype", "fraction", "density"])?;
+    for (event, (fractions, density)) in event_posteriors.iter().enumerate() {
+        for (haplotype, fraction) in final_haplotypes.iter().zip(fractions.iter()) {
+            wtr.write_record(&[
+                event.to_string(),
+                haplotype.to_string(),
+                fraction.to_string(),
+                density.exp().to_string(),
+            ])?;
         }
-    };
-    haplotype_frequencies
-        .iter()
-        .for_each(|frequency| format_freqs(*frequency, &mut records));
+    }
+    wtr.flush()?;
+    Ok(())
+}
 
-    if variant_info {
-        //add vaf queries and probabilities for the first event to the output table
-        let queries: Vec<(AlleleFreq, LogProb)> = event_queries
+//distinct_haplotype_sets collapses the events by their set of present (non-zero) haplotypes: per set, only the event
+//with the highest density is kept, as many events only differ in the fractions of the same haplotypes.
+//The best events of at most 'k' sets are returned, sorted by density (see compare_events).
+pub fn distinct_haplotype_sets(
+    event_posteriors: &[(HaplotypeFractions, LogProb)],
+    haplotypes: &[Haplotype],
+    k: usize,
+    tie_tolerance: f64,
+) -> Vec<(HaplotypeFractions, LogProb)> {
+    let mut event_posteriors = event_posteriors.to_vec();
+    rank_events(&mut event_posteriors, haplotypes, tie_tolerance);
+    let mut seen: BTreeSet<Vec<usize>> = BTreeSet::new();
+    event_posteriors
+        .into_iter()
+        .filter(|(fractions, _)| {
+            let present: Vec<usize> = fractions
+                .iter()
+                .enumerate()
+                .filter(|(_, fraction)| **fraction > NotNan::new(0.0).unwrap())
+                .map(|(i, _)| i)
+                .collect();
+            seen.insert(present)
+        })
+        .take(k)
+        .collect()
+}
+
+//write_distinct_solutions writes the best events of at most 'k' distinct haplotype sets (see distinct_haplotype_sets)
+//to distinct_solutions.tsv next to the output, with the present haplotypes and the fractions of all haplotypes.
+pub fn write_distinct_solutions(
+    outdir: &PathBuf,
+    event_posteriors: &[(HaplotypeFractions, LogProb)],
+    final_haplotypes: &[Haplotype],
+    k: usize,
+    tie_tolerance: f64,
+) -> Result<()> {
+    let mut parent = outdir.clone();
+    parent.pop();
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(parent.join("distinct_solutions.tsv"))?;
+    let mut headers = vec!["density".to_string(), "haplotypes".to_string()];
+    headers.extend(
+        final_haplotypes
             .iter()
-            .next()
-            .unwrap()
-            .values()
-            .cloned()
+            .map(|haplotype| haplotype.to_string()),
+    );
+    wtr.write_record(&headers)?;
+    for (fractions, density) in
+        distinct_haplotype_sets(event_posteriors, final_haplotypes, k, tie_tolerance)
+    {
+        let present: Vec<String> = final_haplotypes
+            .iter()
+            .zip(fractions.iter())
+            .filter(|(_, fraction)| **fraction > NotNan::new(0.0).unwrap())
+            .map(|(haplotype, _)| haplotype.to_string())
             .collect();
-        queries.iter().for_each(|(query, answer)| {
-            let prob = f64::from(Prob::from(*answer));
-            if prob <= 0.01 {
-                records.push(format!("{}{}{:+.2e}", query, ":", prob));
-            } else {
-                records.push(format!("{}{}{:.2}", query, ":", prob));
-            }
-        });
+        let mut record = vec![format!("{:.4}", density.exp()), present.join(",")];
+        record.extend(fractions.iter().map(|fraction| format!("{:.2}", fraction)));
+        wtr.write_record(&record)?;
     }
+    wtr.flush()?;
+    Ok(())
+}
 
-    wtr.write_record(records)?;
-
-    //write the rest of the records
-    // dbg!(&event_posteriors);
+//HomozygousAmbiguity is a homozygous best solution (all mass on a single haplotype) that is challenged by a heterozygous
+//solution (two haplotypes) whose odds relative to the best one are within the configured band, such that the second,
+//weakly supported allele may be real. Both solutions are given as the index of their event (sorted by density).
+#[derive(Debug, Clone, PartialEq)]
+pub struct HomozygousAmbiguity {
+    pub homozygous: usize,
+    pub heterozygous: usize,
+    pub odds: f64,
+}
 
-    if variant_info {
-        event_posteriors
+//homozygous_ambiguity checks whether the best event assigns all mass to a single haplotype while the best heterozygous
+//event has odds of at least 'band' (e.g. 0.1) relative to it. The events are ranked with compare_events.
+pub fn homozygous_ambiguity(
+    event_posteriors: &[(HaplotypeFractions, LogProb)],
+    haplotypes: &[Haplotype],
+    band: f64,
+    tie_tolerance: f64,
+) -> Option<HomozygousAmbiguity> {
+    let ties = EventTies::new(event_posteriors, tie_tolerance);
+    let mut ranked: Vec<usize> = (0..event_posteriors.len()).collect();
+    ranked.sort_by(|a, b| {
+        compare_events(
+            &event_posteriors[*a],
+            &event_posteriors[*b],
+            haplotypes,
+            &ties,
+        )
+    });
+    let present = |index: &usize| {
+        event_posteriors[*index]
+            .0
             .iter()
-            .skip(1)
-            .zip(event_queries.iter().skip(1))
-            .for_each(|((haplotype_frequencies, density), queries)| {
-                let mut records = Vec::new();
-                let odds = (density - best_density).exp();
-                format_f64(density.exp(), &mut records);
-                format_f64(odds, &mut records);
-                haplotype_frequencies
-                    .iter()
-                    .for_each(|frequency| format_freqs(*frequency, &mut records));
-
-                queries.iter().for_each(|(_, (query, answer))| {
-                    let prob = f64::from(Prob::from(*answer));
-                    if prob <= 0.01 {
-                        records.push(format!("{}{}{:+.2e}", query, ":", prob));
-                    } else {
-                        records.push(format!("{}{}{:.2}", query, ":", prob));
-                    }
-                });
-                wtr.write_record(records).unwrap();
-            });
+            .filter(|fraction| **fraction > NotNan::new(0.0).unwrap())
+            .count()
+    };
+    let homozygous = *ranked.first()?;
+    if present(&homozygous) != 1 {
+        return None;
+    }
+    let heterozygous = ranked
+        .into_iter()
+        .skip(1)
+        .find(|index| present(index) == 2)?;
+    let odds = (event_posteriors[heterozygous].1 - event_posteriors[homozygous].1).exp();
+    if odds >= band {
+        Some(HomozygousAmbiguity {
+            homozygous,
+            heterozygous,
+            odds,
+        })
     } else {
-        event_posteriors
+        None
+    }
+}
+
+//write_homozygous_ambiguity writes both solutions of the ambiguity (see homozygous_ambiguity) to
+//homozygous_ambiguity.tsv next to the output, with the present haplotypes, the fractions of all haplotypes and the odds
+//relative to the homozygous solution.
+pub fn write_homozygous_ambiguity(
+    outdir: &PathBuf,
+    event_posteriors: &[(HaplotypeFractions, LogProb)],
+    final_haplotypes: &[Haplotype],
+    ambiguity: &HomozygousAmbiguity,
+) -> Result<()> {
+    let mut parent = outdir.clone();
+    parent.pop();
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(parent.join("homozygous_ambiguity.tsv"))?;
+    let mut headers = vec![
+        "solution".to_string(),
+        "density".to_string(),
+        "odds".to_string(),
+        "haplotypes".to_string(),
+    ];
+    headers.extend(
+        final_haplotypes
             .iter()
-            .skip(1)
-            .for_each(|(haplotype_frequencies, density)| {
-                let mut records = Vec::new();
-                let odds = (density - best_density).exp();
-                format_f64(density.exp(), &mut records);
-                format_f64(odds, &mut records);
-                haplotype_frequencies
-                    .iter()
-                    .for_each(|frequency| format_freqs(*frequency, &mut records));
+            .map(|haplotype| haplotype.to_string()),
+    );
+    wtr.write_record(&headers)?;
+    for (solution, index, odds) in [
+        ("homozygous", ambiguity.homozygous, 1.0),
+        ("heterozygous", ambiguity.heterozygous, ambiguity.odds),
+    ]
+    .iter()
+    {
+        let (fractions, density) = &event_posteriors[*index];
+        let present: Vec<String> = final_haplotypes
+            .iter()
+            .zip(fractions.iter())
+            .filter(|(_, fraction)| **fraction > NotNan::new(0.0).unwrap())
+            .map(|(haplotype, _)| haplotype.to_string())
+            .collect();
+        let mut record = vec![
+            solution.to_string(),
+            format!("{:.4}", density.exp()),
+            format!("{:.4}", odds),
+            present.join(","),
+        ];
+        record.extend(fractions.iter().map(|fraction| format!("{:.2}", fraction)));
+        wtr.write_record(&record)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
 
-                wtr.write_record(records).unwrap();
-            });
+//write_report writes a self-contained HTML report (report.html) with the final solution plot (final_solution.json,
+//see plot_prediction) and a table of the 'top' best solutions with their fractions. The report is tagged with the
+//orthanq version and the command line it was generated with. It doesn't load any external resource, such that it can
+//be opened offline: the plot is rendered as static SVG (see render_prediction_svg) and its vega-lite spec is embedded
+//as JSON for rendering it elsewhere.
+pub fn write_report(
+    outdir: &PathBuf,
+    event_posteriors: &[(HaplotypeFractions, LogProb)],
+    final_haplotypes: &[Haplotype],
+    top: usize,
+    tie_tolerance: f64,
+) -> Result<()> {
+    let mut parent = outdir.clone();
+    parent.pop();
+    let spec = fs::read_to_string(parent.join("final_solution.json"))?;
+    let plot = render_prediction_svg(&serde_json::from_str(&spec)?);
+    //make sure that the embedded spec can't close the script tag
+    let spec = spec.replace("</", "<\\/");
+
+    let mut event_posteriors = event_posteriors.to_vec();
+    rank_events(&mut event_posteriors, final_haplotypes, tie_tolerance);
+
+    let mut table = String::from("<tr><th>density</th>");
+    for haplotype in final_haplotypes {
+        table.push_str(&format!("<th>{}</th>", escape_html(&haplotype.to_string())));
     }
+    table.push_str("</tr>\n");
+    for (fractions, density) in event_posteriors.iter().take(top) {
+        table.push_str(&format!("<tr><td>{:.2}</td>", density.exp()));
+        for fraction in fractions.iter() {
+            table.push_str(&format!("<td>{:.2}</td>", fraction));
+        }
+        table.push_str("</tr>\n");
+    }
+    let command_line = std::env::args().collect::<Vec<String>>().join(" ");
 
+    let html = format!(
+        r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>orthanq report</title>
+<style>
+body {{ font-family: sans-serif; }}
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid #ccc; padding: 4px 8px; text-align: right; }}
+</style>
+</head>
+<body>
+<h1>orthanq report</h1>
+<p class="provenance">orthanq {version}, command: <code>{command_line}</code></p>
+<h2>Top {top} solutions</h2>
+<table>
+{table}</table>
+<h2>Final solution</h2>
+<div id="plot">{plot}</div>
+<script id="spec" type="application/json">{spec}</script>
+</body>
+</html>
+"##,
+        version = env!("CARGO_PKG_VERSION"),
+        command_line = escape_html(&command_line),
+        top = top,
+        table = table,
+        plot = plot,
+        spec = spec,
+    );
+    fs::write(parent.join("report.html"), html)?;
     Ok(())
 }
 
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+//render_prediction_svg renders the main panel of a prediction plot (see plot_prediction) as static SVG: per variant,
+//the fractions of the haplotypes carrying it are stacked to the expected allele frequency and the observed allele
+//frequency is marked by a point. Hovering a mark shows its value.
+fn render_prediction_svg(spec: &serde_json::Value) -> String {
+    const PALETTE: [&str; 10] = [
+        "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+        "#bcbd22", "#17becf",
+    ];
+    let dataset = |name: &str| {
+        spec["datasets"][name]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+    };
+    let fractions: BTreeMap<String, f64> = dataset("haplotype_fractions")
+        .iter()
+        .filter_map(|entry| {
+            Some((
+                entry["haplotype"].as_str()?.to_string(),
+                entry["fraction"].as_f64()?,
+            ))
+        })
+        .collect();
+    let mut stacks: BTreeMap<i64, BTreeSet<String>> = BTreeMap::new();
+    for entry in dataset("haplotype_variants") {
+        if let (Some(variant), Some(haplotype)) =
+            (entry["variant"].as_i64(), entry["haplotype"].as_str())
+        {
+            stacks
+                .entry(variant)
+                .or_default()
+                .insert(haplotype.to_string());
+        }
+    }
+    let observed: BTreeMap<i64, f64> = dataset("variants")
+        .iter()
+        .filter_map(|entry| Some((entry["variant"].as_i64()?, entry["vaf"].as_f64()?)))
+        .collect();
+    let variants: BTreeSet<i64> = stacks.keys().chain(observed.keys()).cloned().collect();
+    let color = |haplotype: &str| {
+        PALETTE[fractions.keys().position(|h| h == haplotype).unwrap_or(0) % PALETTE.len()]
+    };
+
+    let (left, top, height, step) = (40.0, 10.0, 200.0, 24.0);
+    let y = |value: f64| top + height * (1.0 - value.clamp(0.0, 1.0));
+    let legend_x = left + step * variants.len() as f64 + 20.0;
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="11">"#,
+        legend_x + 160.0,
+        (top + height + 60.0).max(top + 16.0 * fractions.len() as f64),
+    );
+    svg.push_str(&format!(
+        r#"<line x1="{x}" y1="{}" x2="{x}" y2="{}" stroke="black"/>"#,
+        y(1.0),
+        y(0.0),
+        x = left,
+    ));
+    for tick in [0.0, 0.5, 1.0].iter() {
+        svg.push_str(&format!(
+            r#"<text x="{}" y="{}" text-anchor="end">{:.1}</text>"#,
+            left - 4.0,
+            y(*tick) + 4.0,
+            tick
+        ));
+    }
+    for (i, variant) in variants.iter().enumerate() {
+        let x = left + step * i as f64 + 2.0;
+        let mut base = 0.0;
+        for haplotype in stacks.get(variant).into_iter().flatten() {
+            let fraction = fractions.get(haplotype).cloned().unwrap_or(0.0);
+            svg.push_str(&format!(
+                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"><title>{}: {:.2}</title></rect>"#,
+                x,
+                y(base + fraction),
+                step - 4.0,
+                y(base) - y(base + fraction),
+                color(haplotype),
+                escape_html(haplotype),
+                fraction
+            ));
+            base += fraction;
+        }
+        if let Some(vaf) = observed.get(variant) {
+            svg.push_str(&format!(
+                r#"<circle cx="{}" cy="{}" r="3" fill="black"><title>observed: {:.2}</title></circle>"#,
+                x + (step - 4.0) / 2.0,
+                y(*vaf),
+                vaf
+            ));
+        }
+        svg.push_str(&format!(
+            r#"<text transform="translate({},{}) rotate(-90)" text-anchor="end">{}</text>"#,
+            x + (step - 4.0) / 2.0 + 4.0,
+            y(0.0) + 6.0,
+            variant
+        ));
+    }
+    for (i, haplotype) in fractions.keys().enumerate() {
+        let legend_y = top + 16.0 * i as f64;
+        svg.push_str(&format!(
+            r#"<rect x="{}" y="{}" width="10" height="10" fill="{}"/><text x="{}" y="{}">{}</text>"#,
+            legend_x,
+            legend_y,
+            color(haplotype),
+            legend_x + 14.0,
+            legend_y + 9.0,
+            escape_html(haplotype)
+        ));
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+//collect_constraints_and_variants adds a constraint per variant that is covered in both dimensions (see CandidateMatrix):
+//all haplotypes of the panel have to be characterized at the variant (panel coverage) and the sample needs
+//at least 'min_sample_depth' reads (sample coverage). Otherwise, the allele frequency of the variant can't be explained
+//by the fractions of the haplotypes and the variant is left out.
 pub fn collect_constraints_and_variants(
     candidate_matrix: &CandidateMatrix,
     haplotypes: &Vec<Haplotype>,
     variant_calls: &VariantCalls,
+    min_sample_depth: i32,
     variables: &Vec<Variable>,
     constraints: &mut Vec<Expression>,
-) -> Result<HashMap<Haplotype, Vec<VariantID>>> {
-    let candidate_matrix_values: Vec<(Vec<VariantStatus>, BitVec)> =
-        candidate_matrix.values().cloned().collect();
-    //collect haplotype-to-variants information
-    let mut haplotype_dict: HashMap<Haplotype, Vec<VariantID>> =
+    considered_variants: &mut Vec<VariantID>,
+) -> Result<BTreeMap<Haplotype, Vec<VariantID>>> {
+    //collect haplotype-to-variants information, sorted by haplotype such that the extension (and all outputs based on it)
+    //is reproducible across runs
+    let mut haplotype_dict: BTreeMap<Haplotype, Vec<VariantID>> =
         haplotypes.iter().map(|h| (h.clone(), vec![])).collect();
-    //variant-wise iteration
+    //variant-wise iteration, joining the candidate matrix and the calls by variant (variants that are missing in
+    //either of them are skipped)
     let mut expr = Expression::from_other_affine(0.); // A constant expression
-    for ((genotype_matrix, coverage_matrix), (variant, (af, _))) in
-        candidate_matrix_values.iter().zip(variant_calls.iter())
+    for (variant, (genotype_matrix, coverage_matrix), (af, _)) in
+        candidate_matrix.iter().filter_map(|(variant, candidates)| {
+            variant_calls
+                .get(variant)
+                .map(|call| (variant, candidates, call))
+        })
     {
         let mut fraction_cont = Expression::from_other_affine(0.);
         let _prime_fraction_cont = Expression::from_other_affine(0.);
@@ -898,7 +4539,10 @@ pub fn collect_constraints_and_variants(
                 counter += 1;
             }
         }
-        if counter == variables.len() {
+        let panel_covered = counter == variables.len();
+        let sample_covered = variant_calls.is_sample_covered(variant, min_sample_depth);
+        if panel_covered && sample_covered {
+            considered_variants.push(variant.clone());
             for (i, (variable, haplotype)) in variables.iter().zip(haplotypes.iter()).enumerate() {
                 if genotype_matrix[i] == VariantStatus::Present {
                     fraction_cont += *variable;
@@ -976,3 +4620,166 @@ pub fn plot_densities(
     serde_json::to_writer(file, &blueprint)?;
     Ok(())
 }
+
+//read_prior_weights reads the population frequencies of the haplotypes from a tsv with the columns haplotype and prior_weight.
+//The weights have to be greater than 0.0 and at most 1.0, they don't need to sum up to 1.0.
+pub fn read_prior_weights(path: &PathBuf) -> Result<BTreeMap<Haplotype, f64>> {
+    let mut rdr = csv::ReaderBuilder::new().delimiter(b'\t').from_path(path)?;
+    let mut weights = BTreeMap::new();
+    for record in rdr.records() {
+        let record = record?;
+        let haplotype = record
+            .get(0)
+            .ok_or_else(|| anyhow!("missing haplotype in {}", path.display()))?;
+        let weight: f64 = record
+            .get(1)
+            .ok_or_else(|| {
+                anyhow!(
+                    "missing prior_weight of {} in {}",
+                    haplotype,
+                    path.display()
+                )
+            })?
+            .parse()?;
+        if !(weight > 0.0 && weight <= 1.0) {
+            return Err(anyhow!(
+                "prior_weight of {} has to be in (0, 1], found {}",
+                haplotype,
+                weight
+            ));
+        }
+        weights.insert(Haplotype(haplotype.to_string()), weight);
+    }
+    Ok(weights)
+}
+
+//prior_weights_for orders the weights by the given haplotypes, as expected by the model.
+//Haplotypes without a weight are treated as the rarest haplotype of the table, i.e. they get its weight.
+pub fn prior_weights_for(weights: &BTreeMap<Haplotype, f64>, haplotypes: &[Haplotype]) -> Vec<f64> {
+    let rarest = weights.values().cloned().fold(1.0, f64::min);
+    haplotypes
+        .iter()
+        .map(|haplotype| *weights.get(haplotype).unwrap_or(&rarest))
+        .collect()
+}
+
+//best_solution reads the fractions of the present haplotypes of the best solution (the first row) from a results table.
+//Columns of the variants (written with --variant-info) are skipped.
+pub fn best_solution(results: &PathBuf) -> Result<BTreeMap<Haplotype, f64>> {
+    let mut rdr = csv::Reader::from_path(results)?;
+    let headers = rdr.headers()?.clone();
+    let record = rdr
+        .records()
+        .next()
+        .ok_or_else(|| anyhow!("the results table {} is empty", results.display()))??;
+    let mut solution = BTreeMap::new();
+    for (column, value) in headers.iter().zip(record.iter()).skip(2) {
+        if column.starts_with("VariantID(") {
+            continue;
+        }
+        let fraction: f64 = value.parse()?;
+        if fraction > 0.0 {
+            solution.insert(Haplotype(column.to_string()), fraction);
+        }
+    }
+    Ok(solution)
+}
+
+//CallDiff compares the best solutions of a sample in two results tables, e.g. of two orthanq versions or panels.
+//The call changed if the set of present haplotypes differs or a fraction differs by more than the tolerance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallDiff {
+    pub sample: String,
+    pub before: Option<BTreeMap<Haplotype, f64>>,
+    pub after: Option<BTreeMap<Haplotype, f64>>,
+    pub max_fraction_change: f64,
+    pub changed: bool,
+}
+
+//diff_results compares the results tables of the same samples, matched by file name. Samples that are only present on
+//one side are reported as changed, their fraction change is the largest fraction of the present side.
+pub fn diff_results(
+    before: &[PathBuf],
+    after: &[PathBuf],
+    tolerance: f64,
+) -> Result<Vec<CallDiff>> {
+    let by_sample = |results: &[PathBuf]| -> Result<BTreeMap<String, BTreeMap<Haplotype, f64>>> {
+        results
+            .iter()
+            .map(|path| {
+                let sample = path.file_stem().unwrap().to_string_lossy().to_string();
+                Ok((sample, best_solution(path)?))
+            })
+            .collect()
+    };
+    let mut before = by_sample(before)?;
+    let mut after = by_sample(after)?;
+    let samples: BTreeSet<String> = before.keys().chain(after.keys()).cloned().collect();
+    Ok(samples
+        .into_iter()
+        .map(|sample| {
+            let before = before.remove(&sample);
+            let after = after.remove(&sample);
+            //a missing sample is compared as an empty solution
+            let empty = BTreeMap::new();
+            let (solution_before, solution_after) = (
+                before.as_ref().unwrap_or(&empty),
+                after.as_ref().unwrap_or(&empty),
+            );
+            let max_fraction_change = solution_before
+                .keys()
+                .chain(solution_after.keys())
+                .map(|haplotype| {
+                    (solution_before.get(haplotype).unwrap_or(&0.0)
+                        - solution_after.get(haplotype).unwrap_or(&0.0))
+                    .abs()
+                })
+                .fold(0.0, f64::max);
+            let changed = before.is_none()
+                || after.is_none()
+                || !solution_before.keys().eq(solution_after.keys())
+                || max_fraction_change > tolerance;
+            CallDiff {
+                sample,
+                before,
+                after,
+                max_fraction_change,
+                changed,
+            }
+        })
+        .collect())
+}
+
+//write_results_diff writes the comparison of the calls as a tsv with one row per sample. The solutions are written as
+//comma-separated haplotype=fraction pairs, NA if the sample is missing on that side.
+pub fn write_results_diff(output: &PathBuf, diffs: &[CallDiff]) -> Result<()> {
+    let format_solution = |solution: &Option<BTreeMap<Haplotype, f64>>| match solution {
+        Some(solution) => solution
+            .iter()
+            .map(|(haplotype, fraction)| format!("{}={:.2}", haplotype.to_string(), fraction))
+            .collect::<Vec<String>>()
+            .join(","),
+        None => "NA".to_string(),
+    };
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(output)?;
+    wtr.write_record(&[
+        "sample",
+        "changed",
+        "before",
+        "after",
+        "max_fraction_change",
+    ])?;
+    for diff in diffs {
+        wtr.write_record(&[
+            diff.sample.clone(),
+            diff.changed.to_string(),
+            format_solution(&diff.before),
+            format_solution(&diff.after),
+            format!("{:.2}", diff.max_fraction_change),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
@@ -1,36 +1,50 @@
 use crate::calling::haplotypes::haplotypes;
 use crate::calling::haplotypes::haplotypes::{
-    CandidateMatrix, Haplotype, HaplotypeVariants, PriorTypes, VariantCalls, VariantID,
-    VariantStatus,
+    AfdScale, CacheOptions, CandidateMatrix, Haplotype, HaplotypeFunnel, HaplotypeVariants,
+    InputCache, LinearProgramSettings, Manifest, MissingAfdPolicy, PanelMatrix, PanelSummary,
+    PriorTypes, QcReport, QualityThresholds, VafDenominator, VariantCalls, VariantCallsOptions,
+    VariantID,
 };
-use crate::model::{Data, HaplotypeFractions, Likelihood, Marginal, Posterior, Prior};
-use anyhow::Result;
+use crate::model::{
+    Data, HaplotypeFractions, Likelihood, Marginal, ObservationModel, Posterior, Prior,
+    VarlociraptorLikelihood,
+};
+use crate::utils::{open_bcf, open_xml};
+use anyhow::{anyhow, Result};
 use bio::stats::{bayesian::model::Model, probs::LogProb};
-use bv::BitVec;
 use core::cmp::Ordering;
 
 use derive_builder::Builder;
+use log::{info, warn};
 
 use ordered_float::NotNan;
 
 use quick_xml::events::Event;
-use quick_xml::reader::Reader as xml_reader;
 
 use rust_htslib::bcf::{self};
 
-use std::collections::{BTreeMap, HashMap};
+use std::collections::BTreeMap;
 
+use std::fmt;
 use std::fs;
 use std::str::FromStr;
-use std::{path::PathBuf, str};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{
+    path::{Path, PathBuf},
+    str,
+};
 
 #[derive(Builder)]
 #[builder(pattern = "owned")]
 pub struct Caller {
     haplotype_variants: bcf::Reader,
+    //candidate files of further panels (e.g. other genes) that are merged into the one of haplotype_variants,
+    //see HaplotypeVariants::merge.
+    #[builder(default)]
+    additional_haplotype_variants: Vec<PathBuf>,
     variant_calls: bcf::Reader,
     xml: PathBuf,
-    // max_haplotypes: i64,
     // min_norm_counts: f64,
     outcsv: PathBuf,
     prior: String,
@@ -40,13 +54,143 @@ pub struct Caller {
     extend_haplotypes: Option<bool>,
     threshold_equivalence_class: usize,
     num_extend_haplotypes: i64,
+    #[builder(default)]
+    integer_selection: bool,
+    #[builder(default)]
+    depth_weighting: bool,
+    #[builder(default)]
+    relax_sum: bool,
+    #[builder(default)]
+    max_haplotypes: Option<usize>,
+    #[builder(default)]
+    extension_overlap: Option<f64>,
+    #[builder(default)]
+    timeout: Option<u64>,
+    #[builder(default)]
+    max_events: Option<usize>,
+    #[builder(default)]
+    missing_afd: MissingAfdPolicy,
+    #[builder(default)]
+    afd_scale: AfdScale,
+    #[builder(default)]
+    vaf_denominator: VafDenominator,
+    #[builder(default)]
+    quality_thresholds: QualityThresholds,
+    #[builder(default)]
+    lp_time_limit: Option<f64>,
+    #[builder(default)]
+    lp_gap: Option<f64>,
+    #[builder(default)]
+    sample: Option<String>,
+    #[builder(default)]
+    af_field: Option<String>,
+    #[builder(default)]
+    error_field: Option<String>,
+    #[builder(default)]
+    variant_quality_percentile: Option<f64>,
+    //prepended to the names of the output and of all files generated next to it, see with_output_prefix
+    #[builder(default)]
+    output_prefix: Option<String>,
+    #[builder(default)]
+    long_format: bool,
+    #[builder(default)]
+    log_densities: bool,
+    #[builder(default)]
+    plot_csv: bool,
+    #[builder(default)]
+    combined_plot: bool,
+    #[builder(default)]
+    export_lp: bool,
+    #[builder(default)]
+    require_coverage: bool,
+    //if true, the haplotypes that are unconfirmed according to the IMGT xml are removed from the candidates before
+    //calling, see unconfirmed_haplotypes
+    #[builder(default)]
+    confirmed_only: bool,
+    #[builder(default)]
+    report: Option<usize>,
+    #[builder(default)]
+    reference_haplotype: Option<String>,
+    #[builder(default)]
+    candidate_band: Option<f64>,
+    #[builder(default)]
+    fraction_snap: Option<f64>,
+    #[builder(default)]
+    input_cache: Option<InputCache>,
+    //if set, the variant calls that are not considered are written to this file together with the reason
+    #[builder(default)]
+    rejected_variants: Option<PathBuf>,
+    #[builder(default)]
+    variant_attribution: bool,
+    //the variant calls and their FORMAT field with the IDs of the supporting fragments, to write read_support.tsv
+    #[builder(default)]
+    read_support: Option<(PathBuf, String)>,
+    #[builder(default)]
+    fit_divergence: bool,
+    //the variant calls (for their PROB_ABSENT), to write presence_concordance.tsv
+    #[builder(default)]
+    presence_concordance: Option<PathBuf>,
+    #[builder(default)]
+    typing_report: Option<ReportResolution>,
+    #[builder(default)]
+    distinct_sets: Option<usize>,
+    //odds band (relative to a homozygous best solution) within which the best heterozygous solution is reported as well,
+    //see homozygous_ambiguity
+    #[builder(default)]
+    het_odds_band: Option<f64>,
+    //the population frequencies of the haplotypes to weight the prior with, see read_prior_weights
+    #[builder(default)]
+    prior_weights: Option<PathBuf>,
+    //the likelihood of the model, see ObservationModel. VarlociraptorLikelihood with the vaf_denominator if None.
+    #[builder(default)]
+    observation_model: Option<Arc<dyn ObservationModel>>,
+    #[builder(default)]
+    min_odds: Option<f64>,
+    #[builder(default = "0.25")]
+    consistency_tolerance: f64,
+    #[builder(default = "1e-6")]
+    tie_tolerance: f64,
 }
 
 impl Caller {
     pub fn call(&mut self) -> Result<()> {
+        match self.output_prefix.clone() {
+            Some(prefix) => {
+                let outcsv = self.outcsv.clone();
+                let result = haplotypes::with_output_prefix(&outcsv, &prefix, |staged| {
+                    self.outcsv = staged.clone();
+                    self.compute_and_write()
+                });
+                self.outcsv = outcsv;
+                result
+            }
+            None => self.compute_and_write(),
+        }
+    }
+
+    fn compute_and_write(&mut self) -> Result<()> {
         //Step 1: Prepare data and compute the model
-        //initially prepare haplotype_variants and variant_calls
-        let variant_calls = VariantCalls::new(&mut self.variant_calls)?;
+        //initially prepare haplotype_variants and variant_calls, from the cache if possible
+        let options = CacheOptions {
+            caller: "hla".to_string(),
+            missing_afd: self.missing_afd,
+            afd_scale: self.afd_scale,
+            sample: self.sample.clone(),
+            af_field: self.af_field.clone(),
+            error_field: self.error_field.clone(),
+            variant_quality_percentile: self.variant_quality_percentile,
+            require_coverage: self.require_coverage,
+            additional_panels: self.additional_haplotype_variants.clone(),
+        };
+        //the rejections are only known when the calls are parsed, hence the cache is bypassed if they are requested
+        let input_cache = self.input_cache.clone().filter(|_| {
+            if self.rejected_variants.is_some() {
+                info!("the input cache is not used since the rejected variants are written");
+            }
+            self.rejected_variants.is_none()
+        });
+        let (variant_calls, haplotype_variants, panel) =
+            InputCache::load_or_parse(input_cache.as_ref(), &options, || self.read_inputs())?;
 
         //write blank plots and tsv table if no variants are available.
         if variant_calls.len() == 0 {
@@ -72,15 +216,40 @@ impl Caller {
             wtr.write_record(&headers)?;
             Ok(())
         } else {
-            let variant_ids: Vec<VariantID> = variant_calls.keys().cloned().collect();
-            let haplotype_variants = HaplotypeVariants::new(&mut self.haplotype_variants)?;
-            //filter variants
-            let filtered_haplotype_variants =
-                haplotype_variants.filter_for_variants(&variant_ids)?;
+            let panel_fingerprint = panel.fingerprint.clone();
+            info!("fingerprint of the candidate panel: {}", panel_fingerprint);
+            //the panel is restricted to the called variants already
+            let filtered_haplotype_variants = haplotype_variants;
 
             let (_, haplotype_matrix) = filtered_haplotype_variants.iter().next().unwrap();
             let haplotypes: Vec<Haplotype> = haplotype_matrix.keys().cloned().collect();
 
+            //the xml is large, hence it is parsed once for the allele status and the G groups
+            let imgt_alleles = read_imgt_alleles(&self.xml)?;
+            let allele_status = imgt_alleles.allele_status();
+
+            //optionally restrict the candidates to the confirmed alleles, e.g. for clinical reporting
+            let (filtered_haplotype_variants, haplotypes) = if self.confirmed_only {
+                let unconfirmed = unconfirmed_haplotypes(&haplotypes, &allele_status);
+                info!(
+                    "excluding {} unconfirmed haplotypes from the candidates: {:?}",
+                    unconfirmed.len(),
+                    unconfirmed
+                );
+                let confirmed: Vec<Haplotype> = haplotypes
+                    .into_iter()
+                    .filter(|haplotype| !unconfirmed.contains(haplotype))
+                    .collect();
+                (
+                    filtered_haplotype_variants.filter_for_haplotypes(&confirmed)?,
+                    confirmed,
+                )
+            } else {
+                (filtered_haplotype_variants, haplotypes)
+            };
+            let max_haplotypes =
+                haplotypes::check_max_haplotypes(self.max_haplotypes, haplotypes.len())?;
+
             //check if common_variants is true, if yes use only common variants both for lp and model
             if self.common_variants {
                 //include only common variants if common_variants is true
@@ -96,15 +265,34 @@ impl Caller {
             let candidate_matrix = CandidateMatrix::new(&filtered_haplotype_variants).unwrap();
 
             //employ the linear program
-            let (lp_haplotypes, _) = haplotypes::linear_program(
+            let lp_start = Instant::now();
+            let lp_solution = haplotypes::linear_program(
                 &self.outcsv,
                 &candidate_matrix,
                 &haplotypes,
                 &variant_calls,
-                self.lp_cutoff,
-                self.extend_haplotypes.unwrap(),
-                self.num_extend_haplotypes,
+                &LinearProgramSettings {
+                    lp_cutoff: self.lp_cutoff,
+                    extend_haplotypes: self.extend_haplotypes.unwrap_or(false),
+                    num_variant_distance: self.num_extend_haplotypes,
+                    integer_selection: self.integer_selection,
+                    depth_weighting: self.depth_weighting,
+                    relax_sum: self.relax_sum,
+                    max_haplotypes,
+                    extension_overlap: self.extension_overlap,
+                    lp_time_limit: self.lp_time_limit,
+                    lp_gap: self.lp_gap,
+                    plot_csv: self.plot_csv,
+                    export_lp: self.export_lp,
+                    ..Default::default()
+                },
             )?;
+            let lp_time = lp_start.elapsed().as_secs_f64();
+            let lp_haplotypes = haplotypes::prune_uninformative_haplotypes(
+                &filtered_haplotype_variants,
+                &variant_calls,
+                &lp_solution.extended_haplotypes,
+            );
             dbg!(&lp_haplotypes);
 
             //take only haplotypes that are found by lp
@@ -115,8 +303,8 @@ impl Caller {
             let (_, haplotype_matrix) = filtered_haplotype_variants.iter().next().unwrap();
             let final_haplotypes: Vec<Haplotype> = haplotype_matrix.keys().cloned().collect();
 
-            //construct candidate matrix
-            let candidate_matrix = CandidateMatrix::new(&filtered_haplotype_variants).unwrap();
+            //derive the candidate matrix of the final haplotypes from the one of the linear program
+            let candidate_matrix = candidate_matrix.select_haplotypes(&haplotypes, &lp_haplotypes);
 
             //
             let eq_graph = filtered_haplotype_variants
@@ -130,31 +318,55 @@ impl Caller {
             //1-) model computation for chosen prior
             let prior = PriorTypes::from_str(&self.prior).unwrap();
             let upper_bond = NotNan::new(1.0).unwrap();
+            let prior_weights = match &self.prior_weights {
+                Some(path) => haplotypes::prior_weights_for(
+                    &haplotypes::read_prior_weights(path)?,
+                    &final_haplotypes,
+                ),
+                None => Vec::new(),
+            };
             let model = Model::new(
-                Likelihood::new(),
-                Prior::new(prior.clone()),
+                Likelihood::new(self.observation_model.clone().unwrap_or_else(|| {
+                    Arc::new(VarlociraptorLikelihood::new(self.vaf_denominator))
+                })),
+                Prior::new(prior.clone()).with_weights(prior_weights),
                 Posterior::new(),
             );
             let data = Data::new(candidate_matrix.clone(), variant_calls.clone());
-            let computed_model = model.compute_from_marginal(
-                &Marginal::new(
-                    final_haplotypes.len(),
-                    final_haplotypes.clone(),
-                    upper_bond,
-                    prior,
-                    Some(eq_graph),
-                    self.enable_equivalence_class_constraint,
-                    "hla".to_string(),
-                ),
-                &data,
+            //stop the enumeration of events after the timeout (in seconds) and keep the best-so-far posteriors
+            let marginal = Marginal::new(
+                final_haplotypes.len(),
+                final_haplotypes.clone(),
+                upper_bond,
+                prior,
+                Some(eq_graph),
+                self.enable_equivalence_class_constraint,
+                "hla".to_string(),
+            )
+            .with_deadline(
+                self.timeout
+                    .map(|timeout| Instant::now() + Duration::from_secs(timeout)),
+            )
+            .with_max_events(self.max_events);
+            let computed_model = model.compute_from_marginal(&marginal, &data);
+            if marginal.timed_out() {
+                warn!("model computation timed out, writing best-so-far posteriors");
+            }
+            if marginal.capped() {
+                warn!("the events were searched approximately (max_events), the posteriors are approximate");
+            }
+            let all_event_posteriors: Vec<(HaplotypeFractions, LogProb)> = computed_model
+                .event_posteriors()
+                .map(|(fractions, logprob)| (fractions.clone(), logprob.clone()))
+                .collect();
+            let best_event = haplotypes::select_best_event(
+                &all_event_posteriors,
+                &final_haplotypes,
+                self.tie_tolerance,
             );
-            let mut event_posteriors = computed_model.event_posteriors();
-
-            let (best_fractions, _) = event_posteriors.next().unwrap();
+            let (best_fractions, _) = &all_event_posteriors[best_event.index];
 
             //Step 2: plot the final solution
-            let candidate_matrix_values: Vec<(Vec<VariantStatus>, BitVec)> =
-                data.candidate_matrix.values().cloned().collect();
             let best_fractions = best_fractions
                 .iter()
                 .map(|f| NotNan::into_inner(*f))
@@ -162,11 +374,137 @@ impl Caller {
             haplotypes::plot_prediction(
                 &self.outcsv,
                 &"final",
-                &candidate_matrix_values,
+                &data.candidate_matrix,
                 &final_haplotypes,
                 &data.variant_calls,
                 &best_fractions,
+                self.plot_csv,
             )?;
+            if self.combined_plot {
+                haplotypes::write_combined_plot(&self.outcsv)?;
+            }
+            haplotypes::write_lp_vs_posterior(
+                &self.outcsv,
+                &lp_solution.fractions,
+                &final_haplotypes,
+                &best_fractions,
+            )?;
+            if self.variant_attribution {
+                haplotypes::write_variant_attribution(
+                    &self.outcsv,
+                    &data.candidate_matrix,
+                    &data.variant_calls,
+                    &final_haplotypes,
+                    &best_fractions,
+                    self.vaf_denominator,
+                )?;
+            }
+            if let Some((calls, field)) = &self.read_support {
+                let fragment_ids = haplotypes::read_fragment_ids(
+                    &mut open_bcf(calls)?,
+                    field,
+                    self.sample.as_deref(),
+                )?;
+                haplotypes::write_read_support(
+                    &self.outcsv,
+                    &data.candidate_matrix,
+                    &data.variant_calls,
+                    &final_haplotypes,
+                    &best_fractions,
+                    &fragment_ids,
+                    self.vaf_denominator,
+                )?;
+            }
+            let mut qc_concordances = None;
+            if let Some(calls) = &self.presence_concordance {
+                let concordances = haplotypes::presence_concordance(
+                    &data.candidate_matrix,
+                    &data.variant_calls,
+                    &final_haplotypes,
+                    &best_fractions,
+                    &haplotypes::read_prob_absent(&mut open_bcf(calls)?)?,
+                    self.vaf_denominator,
+                );
+                let discordant = concordances
+                    .iter()
+                    .filter(|concordance| concordance.is_discordant())
+                    .count();
+                if discordant > 0 {
+                    warn!(
+                        "the presence of {} variants implied by the best solution contradicts their calls, see presence_concordance.tsv",
+                        discordant
+                    );
+                }
+                haplotypes::write_presence_concordance(&self.outcsv, &concordances)?;
+                qc_concordances = Some(concordances);
+            }
+            let mut qc_fits = None;
+            if self.fit_divergence {
+                haplotypes::write_fit_divergences(
+                    &self.outcsv,
+                    &data.candidate_matrix,
+                    &data.variant_calls,
+                    &final_haplotypes,
+                    &best_fractions,
+                    self.vaf_denominator,
+                )?;
+                qc_fits = Some(haplotypes::fit_divergences(
+                    &data.candidate_matrix,
+                    &data.variant_calls,
+                    &final_haplotypes,
+                    &best_fractions,
+                    self.vaf_denominator,
+                ));
+            }
+            if let Some(band) = self.candidate_band {
+                haplotypes::write_candidate_alleles(
+                    &self.outcsv,
+                    &all_event_posteriors,
+                    &final_haplotypes,
+                    band,
+                )?;
+            }
+            if let Some(reference) = &self.reference_haplotype {
+                haplotypes::write_relative_fractions(
+                    &self.outcsv,
+                    &final_haplotypes,
+                    &best_fractions,
+                    &Haplotype(reference.clone()),
+                )?;
+            }
+            let lp_posterior_divergent = haplotypes::check_lp_posterior_consistency(
+                &lp_solution.fractions,
+                &final_haplotypes,
+                &best_fractions,
+                self.consistency_tolerance,
+            );
+            let haplotype_funnel = HaplotypeFunnel {
+                panel: haplotypes.len(),
+                lp_selected: lp_solution.lp_haplotypes.len(),
+                extended: lp_solution.extended_haplotypes.len(),
+                modeled: final_haplotypes.len(),
+            };
+            haplotype_funnel.log();
+            let mut manifest = Manifest {
+                mixture_entropy: Some(haplotypes::mixture_entropy(&best_fractions)),
+                timed_out: Some(marginal.timed_out()),
+                capped: Some(marginal.capped()),
+                log_evidence: marginal.log_evidence().map(|log_evidence| log_evidence.0),
+                haplotype_funnel: Some(haplotype_funnel),
+                variants: Some(variant_calls.len()),
+                lp_time_s: Some(lp_time),
+                evaluated_events: Some(marginal.evaluated_events()),
+                ambiguous: Some(best_event.is_ambiguous()),
+                tied_best_events: Some(best_event.tied.len()),
+                lp_unassigned_fraction: lp_solution.unassigned_fraction,
+                panel_fingerprint: Some(panel_fingerprint),
+                ..Default::default()
+            }
+            .with_quality(
+                &data.variant_calls,
+                lp_solution.residual,
+                &self.quality_thresholds,
+            );
 
             //write to tsv for nonzero densities
             let mut event_posteriors = Vec::new();
@@ -186,7 +524,66 @@ impl Caller {
                 &final_haplotypes,
                 self.prior.clone(),
                 false,
+                self.min_odds,
+                self.fraction_snap,
+                self.vaf_denominator,
+                self.tie_tolerance,
             )?;
+            if self.log_densities {
+                haplotypes::write_log_densities(
+                    &self.outcsv,
+                    &event_posteriors,
+                    &final_haplotypes,
+                    self.tie_tolerance,
+                )?;
+            }
+            if self.long_format {
+                haplotypes::write_results_long(
+                    &self.outcsv,
+                    &event_posteriors,
+                    &final_haplotypes,
+                    self.tie_tolerance,
+                )?;
+            }
+            if let Some(top) = self.report {
+                haplotypes::write_report(
+                    &self.outcsv,
+                    &event_posteriors,
+                    &final_haplotypes,
+                    top,
+                    self.tie_tolerance,
+                )?;
+            }
+            if let Some(band) = self.het_odds_band {
+                let ambiguity = haplotypes::homozygous_ambiguity(
+                    &event_posteriors,
+                    &final_haplotypes,
+                    band,
+                    self.tie_tolerance,
+                );
+                if let Some(ambiguity) = &ambiguity {
+                    warn!(
+                        "the homozygous best solution is challenged by a heterozygous one (odds {:.2}), see homozygous_ambiguity.tsv",
+                        ambiguity.odds
+                    );
+                    haplotypes::write_homozygous_ambiguity(
+                        &self.outcsv,
+                        &event_posteriors,
+                        &final_haplotypes,
+                        ambiguity,
+                    )?;
+                }
+                manifest.homozygous_ambiguity = Some(ambiguity.is_some());
+            }
+            if let Some(k) = self.distinct_sets {
+                haplotypes::write_distinct_solutions(
+                    &self.outcsv,
+                    &event_posteriors,
+                    &final_haplotypes,
+                    k,
+                    self.tie_tolerance,
+                )?;
+            }
             //second: 2-field
             let (two_field_haplotypes, two_field_event_posteriors) =
                 convert_to_two_field(&event_posteriors, &final_haplotypes)?;
@@ -199,6 +596,10 @@ impl Caller {
                 &two_field_haplotypes,
                 self.prior.clone(),
                 false,
+                self.min_odds,
+                self.fraction_snap,
+                self.vaf_denominator,
+                self.tie_tolerance,
             )?;
 
             //plot first 10 posteriors of orthanq output
@@ -218,21 +619,11 @@ impl Caller {
             //second: convert to G groups
             let mut converted_name = PathBuf::from(&self.outcsv.parent().unwrap());
             converted_name.push("G_groups.csv");
-            let allele_to_g_groups = self.convert_to_g().unwrap();
-            let mut final_haplotypes_converted: Vec<Haplotype> = Vec::new();
-            final_haplotypes.iter().for_each(|haplotype| {
-                let mut conv_haplotype = Vec::new();
-                allele_to_g_groups.iter().for_each(|(allele, g_group)| {
-                    if allele.starts_with(&haplotype.to_string()) {
-                        conv_haplotype.push(g_group.to_string());
-                    }
-                });
-                if conv_haplotype.is_empty() {
-                    conv_haplotype.push(haplotype.to_string());
-                }
-                let conv_haplotype = Haplotype(conv_haplotype[0].clone());
-                final_haplotypes_converted.push(conv_haplotype);
-            });
+            let allele_to_g_groups = imgt_alleles.allele_to_g_groups();
+            let final_haplotypes_converted: Vec<Haplotype> = final_haplotypes
+                .iter()
+                .map(|haplotype| to_g_group(haplotype, &allele_to_g_groups))
+                .collect();
 
             haplotypes::write_results(
                 &converted_name,
@@ -241,87 +632,174 @@ impl Caller {
                 &final_haplotypes_converted,
                 self.prior.clone(),
                 true,
+                self.min_odds,
+                self.fraction_snap,
+                self.vaf_denominator,
+                self.tie_tolerance,
             )?;
+            write_allele_status(
+                &self.outcsv,
+                &final_haplotypes,
+                &best_fractions,
+                &allele_status,
+            )?;
+            if let Some(resolution) = self.typing_report {
+                let (_, best_density) = &all_event_posteriors[best_event.index];
+                write_typing_report(
+                    &self.outcsv,
+                    &typing_report(
+                        &final_haplotypes,
+                        &best_fractions,
+                        resolution,
+                        best_density.exp(),
+                        &allele_to_g_groups,
+                    ),
+                )?;
+            }
+            manifest.write(&self.outcsv)?;
+            let mut qc =
+                QcReport::new(&manifest).with_lp_posterior_divergent(&lp_posterior_divergent);
+            if let Some(fits) = &qc_fits {
+                qc = qc.with_fit_divergences(fits);
+            }
+            if let Some(concordances) = &qc_concordances {
+                qc = qc.with_presence_concordance(concordances);
+            }
+            qc.write(&self.outcsv)?;
             Ok(())
         }
     }
 
-    pub fn convert_to_g(&self) -> Result<BTreeMap<String, String>> {
-        let mut reader = xml_reader::from_file(&self.xml)?;
-        reader.trim_text(true);
-        let mut buf = Vec::new();
-        let mut alleles: Vec<String> = Vec::new();
-        let mut confirmed: Vec<String> = Vec::new();
-        let mut hla_g_groups: HashMap<i32, String> = HashMap::new(); //some hla alleles dont have g groups information in the xml file.
-        let mut names_indices: Vec<i32> = Vec::new();
-        let mut groups_indices: Vec<i32> = Vec::new();
-        let mut counter = 0;
-        loop {
-            match reader.read_event_into(&mut buf) {
-                Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-                Ok(Event::Eof) => break,
-                Ok(Event::Start(e)) => match e.name().as_ref() {
-                    b"allele" => {
-                        let allele_name = e
-                            .attributes()
-                            .map(|a| String::from_utf8(a.unwrap().value.to_vec()))
-                            .collect::<Vec<_>>()[1]
-                            .clone()
-                            .unwrap();
-                        let mut allele_name_push = "".to_string();
-                        //allele names starting with HLA contain '-' however, some aleles e.g. MICA do not contain it.
-                        if allele_name.contains(&"-") {
-                            allele_name_push =
-                                allele_name.split("-").collect::<Vec<&str>>()[1].to_string()
-                        } else {
-                            allele_name_push = allele_name.clone()
-                        }
-                        alleles.push(allele_name_push); //allele_name is held in index 1, note: don't use expanded_name.
-                        names_indices.push(counter.clone());
-                        counter += 1;
-                    }
-                    _ => (),
-                },
-                Ok(Event::Empty(e)) => match e.name().as_ref() {
-                    b"releaseversions" => confirmed.push(
-                        e.attributes()
-                            .map(|a| String::from_utf8(a.unwrap().value.to_vec()))
-                            .collect::<Vec<_>>()[4]
-                            .as_ref()
-                            .unwrap()
-                            .to_string(), //index 4 holds the Confirmed info
-                    ),
-                    b"hla_g_group" => {
-                        groups_indices.push(counter.clone());
-                        hla_g_groups.insert(
-                            counter.clone(),
-                            e.attributes()
-                                .map(|a| String::from_utf8(a.unwrap().value.to_vec()))
-                                .collect::<Vec<_>>()[0]
-                                .as_ref()
-                                .unwrap()
-                                .to_string(), //index 0 holds the status info
-                        );
-                    }
-                    _ => (),
-                },
-                _ => (),
+    //read_inputs parses the variant calls and the candidate panel, merged with the additional panels. The panel is
+    //only parsed if there are variant calls, and only the called variants are expanded into HaplotypeVariants,
+    //such that large panels fit into memory (see PanelMatrix).
+    fn read_inputs(&mut self) -> Result<(VariantCalls, HaplotypeVariants, PanelSummary)> {
+        let (variant_calls, rejected) = VariantCalls::new_with_rejections(
+            &mut self.variant_calls,
+            &VariantCallsOptions {
+                missing_afd: self.missing_afd,
+                afd_scale: self.afd_scale,
+                sample: self.sample.clone(),
+                af_field: self.af_field.clone(),
+                error_field: self.error_field.clone(),
+                variant_quality_percentile: self.variant_quality_percentile,
+            },
+        )?;
+        if let Some(path) = &self.rejected_variants {
+            rejected.write(path)?;
+        }
+        if variant_calls.len() == 0 {
+            return Ok((
+                variant_calls,
+                HaplotypeVariants(BTreeMap::new()),
+                PanelSummary::default(),
+            ));
+        }
+        let require_coverage = self.require_coverage;
+        let read_panel = |reader: &mut bcf::Reader| {
+            if require_coverage {
+                PanelMatrix::new_requiring_coverage(reader)
+            } else {
+                PanelMatrix::new(reader)
             }
-            // if we don't keep a borrow elsewhere, we can clear the buffer to keep memory usage low
-            buf.clear();
+        };
+        let mut panel = read_panel(&mut self.haplotype_variants)?;
+        for path in self.additional_haplotype_variants.iter() {
+            let additional = read_panel(&mut open_bcf(path)?)?;
+            let namespace = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.split('.').next().unwrap().to_string())
+                .unwrap_or_default();
+            panel = panel.merge(&additional, &namespace);
         }
-        assert_eq!(alleles.len(), confirmed.len());
+        let variant_ids: Vec<VariantID> = variant_calls.keys().cloned().collect();
+        panel.check_overlap(&variant_ids)?;
+        Ok((
+            variant_calls,
+            panel.haplotype_variants_for(&variant_ids),
+            PanelSummary::new(&panel),
+        ))
+    }
+
+    //convert_to_g maps the confirmed alleles of the xml to their G groups, see allele_to_g_groups.
+    pub fn convert_to_g(&self) -> Result<BTreeMap<String, String>> {
+        allele_to_g_groups(&self.xml)
+    }
+
+    //allele_status returns the IMGT status ("Confirmed" or "Unconfirmed") of every allele in the xml.
+    pub fn allele_status(&self) -> Result<BTreeMap<String, String>> {
+        Ok(read_imgt_alleles(&self.xml)?.allele_status())
+    }
+}
+
+//allele_to_g_groups maps the confirmed alleles of the IMGT xml to their G groups.
+pub fn allele_to_g_groups(xml: &PathBuf) -> Result<BTreeMap<String, String>> {
+    Ok(read_imgt_alleles(xml)?.allele_to_g_groups())
+}
+
+//to_g_group returns the G group of the first allele that starts with the haplotype (e.g. A*01:01:01G for A*01:01:01),
+//the haplotype itself if there is none.
+pub fn to_g_group(
+    haplotype: &Haplotype,
+    allele_to_g_groups: &BTreeMap<String, String>,
+) -> Haplotype {
+    allele_to_g_groups
+        .iter()
+        .find(|(allele, _)| allele.starts_with(haplotype.as_str()))
+        .map_or_else(
+            || haplotype.clone(),
+            |(_, g_group)| Haplotype(g_group.clone()),
+        )
+}
+
+//convert_results_to_g converts the haplotype columns (those with '*', e.g. A*01:01:01) of an existing results table
+//to their G groups and writes the table to 'output', e.g. to update the G groups with a newer xml without calling again.
+//All other columns are kept as they are.
+pub fn convert_results_to_g(results: &PathBuf, xml: &PathBuf, output: &PathBuf) -> Result<()> {
+    let allele_to_g_groups = allele_to_g_groups(xml)?;
+    let mut rdr = csv::Reader::from_path(results)?;
+    let mut wtr = csv::Writer::from_path(output)?;
+    let headers: Vec<String> = rdr
+        .headers()?
+        .iter()
+        .map(|column| {
+            if column.contains('*') {
+                to_g_group(&Haplotype(column.to_string()), &allele_to_g_groups).to_string()
+            } else {
+                column.to_string()
+            }
+        })
+        .collect();
+    wtr.write_record(&headers)?;
+    for record in rdr.records() {
+        wtr.write_record(&record?)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+//ImgtAlleles are the allele names of the IMGT xml, their confirmed status and the g groups (by allele number, starting at 1).
+struct ImgtAlleles {
+    alleles: Vec<String>,
+    confirmed: Vec<String>,
+    hla_g_groups: BTreeMap<i32, String>,
+}
+
+impl ImgtAlleles {
+    //allele_to_g_groups maps the confirmed alleles to their G groups.
+    fn allele_to_g_groups(&self) -> BTreeMap<String, String> {
         let mut filtered_alleles = Vec::new();
         let mut filtered_confirmed = Vec::new();
-        hla_g_groups.iter().for_each(|(index, _)| {
-            filtered_alleles.push(alleles[*index as usize - 1].clone());
-            filtered_confirmed.push(confirmed[*index as usize - 1].clone());
+        self.hla_g_groups.iter().for_each(|(index, _)| {
+            filtered_alleles.push(self.alleles[*index as usize - 1].clone());
+            filtered_confirmed.push(self.confirmed[*index as usize - 1].clone());
         });
         assert_eq!(filtered_alleles.len(), filtered_confirmed.len());
-        assert_eq!(filtered_alleles.len(), hla_g_groups.len());
+        assert_eq!(filtered_alleles.len(), self.hla_g_groups.len());
 
         let mut g_to_alleles: BTreeMap<String, String> = BTreeMap::new();
-        let g_names: Vec<String> = hla_g_groups.values().cloned().collect();
+        let g_names: Vec<String> = self.hla_g_groups.values().cloned().collect();
         let _unconfirmed_alleles = filtered_alleles
             .iter()
             .zip(filtered_confirmed.iter())
@@ -330,8 +808,276 @@ impl Caller {
             .for_each(|((allele, _c), g_group)| {
                 g_to_alleles.insert(allele.clone(), g_group.to_string());
             });
-        Ok(g_to_alleles)
+        g_to_alleles
+    }
+
+    //allele_status maps every allele to its IMGT status ("Confirmed" or "Unconfirmed").
+    fn allele_status(&self) -> BTreeMap<String, String> {
+        self.alleles
+            .iter()
+            .cloned()
+            .zip(self.confirmed.iter().cloned())
+            .collect()
+    }
+}
+
+//read_imgt_alleles parses the allele names, their confirmed status and the g groups from the xml.
+fn read_imgt_alleles(xml: &PathBuf) -> Result<ImgtAlleles> {
+    let mut reader = open_xml(xml)?;
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut alleles: Vec<String> = Vec::new();
+    let mut confirmed: Vec<String> = Vec::new();
+    let mut hla_g_groups: BTreeMap<i32, String> = BTreeMap::new(); //some hla alleles dont have g groups information in the xml file.
+    let mut names_indices: Vec<i32> = Vec::new();
+    let mut groups_indices: Vec<i32> = Vec::new();
+    let mut counter = 0;
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
+            Ok(Event::Eof) => break,
+            Ok(Event::Start(e)) => match e.name().as_ref() {
+                b"allele" => {
+                    let allele_name = e
+                        .attributes()
+                        .map(|a| String::from_utf8(a.unwrap().value.to_vec()))
+                        .collect::<Vec<_>>()[1]
+                        .clone()
+                        .unwrap();
+                    let mut allele_name_push = "".to_string();
+                    //allele names starting with HLA contain '-' however, some aleles e.g. MICA do not contain it.
+                    if allele_name.contains(&"-") {
+                        allele_name_push =
+                            allele_name.split("-").collect::<Vec<&str>>()[1].to_string()
+                    } else {
+                        allele_name_push = allele_name.clone()
+                    }
+                    alleles.push(allele_name_push); //allele_name is held in index 1, note: don't use expanded_name.
+                    names_indices.push(counter.clone());
+                    counter += 1;
+                }
+                _ => (),
+            },
+            Ok(Event::Empty(e)) => match e.name().as_ref() {
+                b"releaseversions" => confirmed.push(
+                    e.attributes()
+                        .map(|a| String::from_utf8(a.unwrap().value.to_vec()))
+                        .collect::<Vec<_>>()[4]
+                        .as_ref()
+                        .unwrap()
+                        .to_string(), //index 4 holds the Confirmed info
+                ),
+                b"hla_g_group" => {
+                    groups_indices.push(counter.clone());
+                    hla_g_groups.insert(
+                        counter.clone(),
+                        e.attributes()
+                            .map(|a| String::from_utf8(a.unwrap().value.to_vec()))
+                            .collect::<Vec<_>>()[0]
+                            .as_ref()
+                            .unwrap()
+                            .to_string(), //index 0 holds the status info
+                    );
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+        // if we don't keep a borrow elsewhere, we can clear the buffer to keep memory usage low
+        buf.clear();
+    }
+    assert_eq!(alleles.len(), confirmed.len());
+    Ok(ImgtAlleles {
+        alleles,
+        confirmed,
+        hla_g_groups,
+    })
+}
+
+//imgt_status annotates a haplotype with the IMGT status of its alleles, i.e. the alleles that are equal to the haplotype
+//or have it as prefix (e.g. A*01:01:01:01 for A*01:01:01). The haplotype is "Confirmed" if any of them is confirmed,
+//"Unconfirmed" otherwise and "unknown-status" if the xml doesn't contain any of them. As the alleles are sorted, they
+//follow the haplotype itself in the map.
+pub fn imgt_status(haplotype: &Haplotype, allele_status: &BTreeMap<String, String>) -> String {
+    let statuses = allele_status
+        .range(haplotype.to_string()..)
+        .take_while(|(allele, _)| allele.starts_with(haplotype.as_str()))
+        .filter(|(allele, _)| {
+            allele.as_str() == haplotype.as_str()
+                || allele.starts_with(&format!("{}:", haplotype.as_str()))
+        })
+        .map(|(_, status)| status.as_str())
+        .collect::<Vec<&str>>();
+    if statuses.is_empty() {
+        "unknown-status".to_string()
+    } else if statuses.contains(&"Confirmed") {
+        "Confirmed".to_string()
+    } else {
+        "Unconfirmed".to_string()
+    }
+}
+
+//unconfirmed_haplotypes are the haplotypes whose IMGT status (see imgt_status) is "Unconfirmed". Haplotypes that are
+//not part of the xml (unknown-status) are kept.
+pub fn unconfirmed_haplotypes(
+    haplotypes: &[Haplotype],
+    allele_status: &BTreeMap<String, String>,
+) -> Vec<Haplotype> {
+    haplotypes
+        .iter()
+        .filter(|haplotype| imgt_status(haplotype, allele_status) == "Unconfirmed")
+        .cloned()
+        .collect()
+}
+
+//ReportResolution is the resolution of the alleles in the typing report: the first two or three fields of the
+//allele names, or their G groups (as given by the IMGT xml).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportResolution {
+    TwoField,
+    ThreeField,
+    GGroup,
+}
+
+impl FromStr for ReportResolution {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<ReportResolution, Self::Err> {
+        match input {
+            "2-field" => Ok(ReportResolution::TwoField),
+            "3-field" => Ok(ReportResolution::ThreeField),
+            "G" => Ok(ReportResolution::GGroup),
+            _ => Err(format!(
+                "resolution must be either 2-field, 3-field or G, found {}",
+                input
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ReportResolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReportResolution::TwoField => write!(f, "2-field"),
+            ReportResolution::ThreeField => write!(f, "3-field"),
+            ReportResolution::GGroup => write!(f, "G"),
+        }
+    }
+}
+
+//TypingReportRow is the genotype of one locus (gene) in the typing report: the two alleles of the best (diploid)
+//solution, equal for homozygous loci, and the posterior density of the best solution as confidence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypingReportRow {
+    pub locus: String,
+    pub allele1: String,
+    pub allele2: String,
+    pub resolution: ReportResolution,
+    pub confidence: f64,
+}
+
+//typing_report derives one row per locus from the haplotypes of the best solution with non-zero fraction. The alleles
+//of a locus are ordered by fraction, the first two are reported, a single allele is reported as homozygous.
+//For the G group resolution, haplotypes without G group (see Caller::convert_to_g) are reported as they are.
+pub fn typing_report(
+    haplotypes: &[Haplotype],
+    fractions: &[f64],
+    resolution: ReportResolution,
+    confidence: f64,
+    allele_to_g_groups: &BTreeMap<String, String>,
+) -> Vec<TypingReportRow> {
+    let at_resolution = |haplotype: &Haplotype| match resolution {
+        ReportResolution::TwoField => haplotype.split(':').take(2).collect::<Vec<_>>().join(":"),
+        ReportResolution::ThreeField => haplotype.split(':').take(3).collect::<Vec<_>>().join(":"),
+        ReportResolution::GGroup => to_g_group(haplotype, allele_to_g_groups).to_string(),
+    };
+    let mut loci: BTreeMap<String, Vec<(&Haplotype, f64)>> = BTreeMap::new();
+    for (haplotype, fraction) in haplotypes.iter().zip(fractions.iter()) {
+        if *fraction > 0.0 {
+            let locus = haplotype.split('*').next().unwrap().to_string();
+            loci.entry(locus).or_default().push((haplotype, *fraction));
+        }
+    }
+    loci.into_iter()
+        .map(|(locus, mut alleles)| {
+            alleles.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+            let allele1 = at_resolution(alleles[0].0);
+            let allele2 = alleles.get(1).map_or_else(
+                || allele1.clone(),
+                |(haplotype, _)| at_resolution(haplotype),
+            );
+            TypingReportRow {
+                locus,
+                allele1,
+                allele2,
+                resolution,
+                confidence,
+            }
+        })
+        .collect()
+}
+
+//write_typing_report writes the rows of the typing report to typing_report.tsv next to the output.
+pub fn write_typing_report(outdir: &PathBuf, rows: &[TypingReportRow]) -> Result<()> {
+    let mut parent = outdir.clone();
+    parent.pop();
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(parent.join("typing_report.tsv"))?;
+    wtr.write_record(&["locus", "allele1", "allele2", "resolution", "confidence"])?;
+    for row in rows {
+        wtr.write_record(&[
+            row.locus.clone(),
+            row.allele1.clone(),
+            row.allele2.clone(),
+            row.resolution.to_string(),
+            format!("{:.4}", row.confidence),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+//read_typing_report reads the rows of a typing report written by write_typing_report.
+pub fn read_typing_report(path: &Path) -> Result<Vec<TypingReportRow>> {
+    let mut reader = csv::ReaderBuilder::new().delimiter(b'\t').from_path(path)?;
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        rows.push(TypingReportRow {
+            locus: record[0].to_string(),
+            allele1: record[1].to_string(),
+            allele2: record[2].to_string(),
+            resolution: record[3].parse().map_err(|error: String| anyhow!(error))?,
+            confidence: record[4].parse()?,
+        });
+    }
+    Ok(rows)
+}
+
+//write_allele_status writes the fraction of each final haplotype in the best solution together with its IMGT status
+//to allele_status.tsv.
+pub fn write_allele_status(
+    outdir: &PathBuf,
+    final_haplotypes: &[Haplotype],
+    best_fractions: &[f64],
+    allele_status: &BTreeMap<String, String>,
+) -> Result<()> {
+    let mut parent = outdir.clone();
+    parent.pop();
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(parent.join("allele_status.tsv"))?;
+    wtr.write_record(&["haplotype", "fraction", "imgt_status"])?;
+    for (haplotype, fraction) in final_haplotypes.iter().zip(best_fractions.iter()) {
+        wtr.write_record(&[
+            haplotype.to_string(),
+            format!("{:.2}", fraction),
+            imgt_status(haplotype, allele_status),
+        ])?;
     }
+    wtr.flush()?;
+    Ok(())
 }
 
 //convert_to_two_field function converts the event posteriors that contain three-field info by default, to two-field information
@@ -1,3 +1,4 @@
+pub mod config;
 pub mod haplotypes;
 pub mod hla;
 pub mod virus;
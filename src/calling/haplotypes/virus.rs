@@ -3,15 +3,22 @@ use crate::calling::haplotypes::haplotypes;
 // use crate::calling::haplotypes::haplotypes::HaplotypeGraphVirus;
 // use crate::calling::haplotypes::haplotypes::SimilarL;
 use crate::calling::haplotypes::haplotypes::{
-    CandidateMatrix, Haplotype, HaplotypeVariants, PriorTypes, VariantCalls, VariantID,
+    AfdScale, CacheOptions, CandidateMatrix, Haplotype, HaplotypeFunnel, HaplotypeVariants,
+    InputCache, LinearProgramSettings, Manifest, MissingAfdPolicy, PanelMatrix, PanelSummary,
+    PriorTypes, QcReport, QualityThresholds, VafDenominator, VariantCalls, VariantCallsOptions,
+    VariantID,
 };
 
-use crate::model::{AlleleFreq, Data, HaplotypeFractions, Likelihood, Marginal, Posterior, Prior};
+use crate::model::{
+    AlleleFreq, Data, HaplotypeFractions, Likelihood, Marginal, ObservationModel, Posterior, Prior,
+    VarlociraptorLikelihood,
+};
+use crate::utils::open_bcf;
 
 use anyhow::Result;
 use bio::stats::bayesian::model::Model;
 use derive_builder::Builder;
-use log::warn;
+use log::{info, warn};
 
 use ordered_float::NotNan;
 
@@ -22,6 +29,8 @@ use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::fs;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{path::PathBuf, str};
 
 use super::haplotypes::DistanceMatrix;
@@ -40,17 +49,133 @@ pub struct Caller {
     threshold_considered_variants: f64,
     threshold_equivalence_class: usize,
     num_extend_haplotypes: i64,
+    #[builder(default)]
+    integer_selection: bool,
+    #[builder(default)]
+    depth_weighting: bool,
+    #[builder(default)]
+    relax_sum: bool,
+    #[builder(default)]
+    max_haplotypes: Option<usize>,
+    #[builder(default)]
+    extension_overlap: Option<f64>,
+    #[builder(default)]
+    timeout: Option<u64>,
+    #[builder(default)]
+    max_events: Option<usize>,
+    #[builder(default)]
+    missing_afd: MissingAfdPolicy,
+    #[builder(default)]
+    afd_scale: AfdScale,
+    #[builder(default)]
+    vaf_denominator: VafDenominator,
+    #[builder(default)]
+    quality_thresholds: QualityThresholds,
+    #[builder(default)]
+    lp_time_limit: Option<f64>,
+    #[builder(default)]
+    lp_gap: Option<f64>,
+    #[builder(default)]
+    sample: Option<String>,
+    #[builder(default)]
+    af_field: Option<String>,
+    #[builder(default)]
+    error_field: Option<String>,
+    #[builder(default)]
+    variant_quality_percentile: Option<f64>,
+    //prepended to the names of the output and of all files generated next to it, see with_output_prefix
+    #[builder(default)]
+    output_prefix: Option<String>,
+    #[builder(default)]
+    long_format: bool,
+    #[builder(default)]
+    log_densities: bool,
+    #[builder(default)]
+    plot_csv: bool,
+    #[builder(default)]
+    combined_plot: bool,
+    #[builder(default)]
+    export_lp: bool,
+    #[builder(default)]
+    require_coverage: bool,
+    #[builder(default)]
+    report: Option<usize>,
+    #[builder(default)]
+    reference_haplotype: Option<String>,
+    #[builder(default)]
+    candidate_band: Option<f64>,
+    #[builder(default)]
+    fraction_snap: Option<f64>,
+    #[builder(default)]
+    input_cache: Option<InputCache>,
+    //if set, the variant calls that are not considered are written to this file together with the reason
+    #[builder(default)]
+    rejected_variants: Option<PathBuf>,
+    #[builder(default)]
+    variant_attribution: bool,
+    //the variant calls and their FORMAT field with the IDs of the supporting fragments, to write read_support.tsv
+    #[builder(default)]
+    read_support: Option<(PathBuf, String)>,
+    #[builder(default)]
+    fit_divergence: bool,
+    //the variant calls (for their PROB_ABSENT), to write presence_concordance.tsv
+    #[builder(default)]
+    presence_concordance: Option<PathBuf>,
+    #[builder(default)]
+    distinct_sets: Option<usize>,
+    //the population frequencies of the haplotypes to weight the prior with, see read_prior_weights
+    #[builder(default)]
+    prior_weights: Option<PathBuf>,
+    //the likelihood of the model, see ObservationModel. VarlociraptorLikelihood with the vaf_denominator if None.
+    #[builder(default)]
+    observation_model: Option<Arc<dyn ObservationModel>>,
+    #[builder(default)]
+    min_odds: Option<f64>,
+    #[builder(default = "0.25")]
+    consistency_tolerance: f64,
+    #[builder(default = "1e-6")]
+    tie_tolerance: f64,
 }
 
 impl Caller {
     pub fn call(&mut self) -> Result<()> {
-        //Step 1: Prepare data and compute the model
-        //initially prepare haplotype_variants and variant_calls
-        let variant_calls = VariantCalls::new(&mut self.variant_calls)?;
+        match self.output_prefix.clone() {
+            Some(prefix) => {
+                let outcsv = self.outcsv.clone();
+                let result = haplotypes::with_output_prefix(&outcsv, &prefix, |staged| {
+                    self.outcsv = staged.clone();
+                    self.compute_and_write()
+                });
+                self.outcsv = outcsv;
+                result
+            }
+            None => self.compute_and_write(),
+        }
+    }
 
-        //read candidates vcf
-        let haplotype_variants_dir = self.candidates_folder.join("candidates.vcf");
-        let mut haplotype_variants_rdr = bcf::Reader::from_path(haplotype_variants_dir)?;
+    fn compute_and_write(&mut self) -> Result<()> {
+        //Step 1: Prepare data and compute the model
+        //initially prepare haplotype_variants and variant_calls, from the cache if possible
+        let options = CacheOptions {
+            caller: "virus".to_string(),
+            missing_afd: self.missing_afd,
+            afd_scale: self.afd_scale,
+            sample: self.sample.clone(),
+            af_field: self.af_field.clone(),
+            error_field: self.error_field.clone(),
+            variant_quality_percentile: self.variant_quality_percentile,
+            require_coverage: self.require_coverage,
+            additional_panels: Vec::new(),
+        };
+        //the rejections are only known when the calls are parsed, hence the cache is bypassed if they are requested
+        let input_cache = self.input_cache.clone().filter(|_| {
+            if self.rejected_variants.is_some() {
+                info!("the input cache is not used since the rejected variants are written");
+            }
+            self.rejected_variants.is_none()
+        });
+        let (variant_calls, haplotype_variants, panel) =
+            InputCache::load_or_parse(input_cache.as_ref(), &options, || self.read_inputs())?;
 
         //write blank plots and tsv table if no variants are available.
         if variant_calls.len() == 0 {
@@ -58,65 +183,100 @@ impl Caller {
             self.output_empty_files()?;
             Ok(())
         } else {
-            let variant_ids: Vec<VariantID> = variant_calls.keys().cloned().collect();
-            let haplotype_variants = HaplotypeVariants::new(&mut haplotype_variants_rdr)?;
+            let panel_fingerprint = panel.fingerprint.clone();
+            info!("fingerprint of the candidate panel: {}", panel_fingerprint);
 
             // check if there is enough observations in the data, and do that by checking rate of evaluated variants
             // if this does not pass, print insufficient data
-            if variant_calls
-                .check_variant_threshold(&haplotype_variants, self.threshold_considered_variants)?
-            {
-                // filter haplotype variants to contain only the evaluated variants in the model.
-                let filtered_haplotype_variants =
-                    haplotype_variants.filter_for_variants(&variant_ids)?;
+            if variant_calls.check_variant_threshold(&panel, self.threshold_considered_variants)? {
+                //the panel is restricted to the evaluated variants in the model already
+                let filtered_haplotype_variants = haplotype_variants;
 
                 // output haplotype list and candidate matrix to be used in lp
                 let (_, haplotype_matrix) = filtered_haplotype_variants.iter().next().unwrap();
                 let haplotypes: Vec<Haplotype> = haplotype_matrix.keys().cloned().collect();
+                let max_haplotypes =
+                    haplotypes::check_max_haplotypes(self.max_haplotypes, haplotypes.len())?;
                 let candidate_matrix = CandidateMatrix::new(&filtered_haplotype_variants).unwrap();
 
                 // employ the linear program and find the resulting haplotypes that are found and *extended* depending on --extend-haplotypes and --num-extend-haplotypes (0 default)
-                let (extended_lp_haplotypes, lp_haplotypes) = haplotypes::linear_program(
+                let lp_start = Instant::now();
+                let lp_solution = haplotypes::linear_program(
                     &self.outcsv,
                     &candidate_matrix,
                     &haplotypes,
                     &variant_calls,
-                    self.lp_cutoff,
-                    self.extend_haplotypes.unwrap_or(true),
-                    self.num_extend_haplotypes, //for now it has to be 0 only
+                    &LinearProgramSettings {
+                        lp_cutoff: self.lp_cutoff,
+                        extend_haplotypes: self.extend_haplotypes.unwrap_or(true),
+                        num_variant_distance: self.num_extend_haplotypes, //for now it has to be 0 only
+                        integer_selection: self.integer_selection,
+                        depth_weighting: self.depth_weighting,
+                        relax_sum: self.relax_sum,
+                        max_haplotypes,
+                        extension_overlap: self.extension_overlap,
+                        lp_time_limit: self.lp_time_limit,
+                        lp_gap: self.lp_gap,
+                        plot_csv: self.plot_csv,
+                        export_lp: self.export_lp,
+                        ..Default::default()
+                    },
                 )?;
+                let lp_time = lp_start.elapsed().as_secs_f64();
+                let extended_lp_haplotypes = lp_solution.extended_haplotypes.clone();
+                let lp_haplotypes = haplotypes::prune_uninformative_haplotypes(
+                    &filtered_haplotype_variants,
+                    &variant_calls,
+                    &lp_solution.lp_haplotypes,
+                );
 
-                //take only haplotypes that are found by lp
-                let lp_haplotype_variants =
-                    filtered_haplotype_variants.filter_for_haplotypes(&lp_haplotypes)?;
-
-                //construct candidate matrix
-                let lp_candidate_matrix = CandidateMatrix::new(&lp_haplotype_variants).unwrap();
+                //take only haplotypes that are found by lp, by selecting their columns in the candidate matrix
+                let lp_candidate_matrix =
+                    candidate_matrix.select_haplotypes(&haplotypes, &lp_haplotypes);
 
                 //compute model
                 let prior = PriorTypes::from_str(&self.prior).unwrap();
                 let upper_bond = NotNan::new(1.0).unwrap();
+                let prior_weights = match &self.prior_weights {
+                    Some(path) => haplotypes::prior_weights_for(
+                        &haplotypes::read_prior_weights(path)?,
+                        &lp_haplotypes,
+                    ),
+                    None => Vec::new(),
+                };
                 let model = Model::new(
-                    Likelihood::new(),
-                    Prior::new(prior.clone()),
+                    Likelihood::new(self.observation_model.clone().unwrap_or_else(|| {
+                        Arc::new(VarlociraptorLikelihood::new(self.vaf_denominator))
+                    })),
+                    Prior::new(prior.clone()).with_weights(prior_weights),
                     Posterior::new(),
                 );
 
                 let data = Data::new(lp_candidate_matrix.clone(), variant_calls.clone());
 
                 //marginal computation
-                let computed_model = model.compute_from_marginal(
-                    &Marginal::new(
-                        lp_haplotypes.len(),
-                        lp_haplotypes.clone(),
-                        upper_bond,
-                        prior,
-                        None,
-                        self.enable_equivalence_class_constraint,
-                        "virus".to_string(),
-                    ),
-                    &data,
-                );
+                //stop the enumeration of events after the timeout (in seconds) and keep the best-so-far posteriors
+                let marginal = Marginal::new(
+                    lp_haplotypes.len(),
+                    lp_haplotypes.clone(),
+                    upper_bond,
+                    prior,
+                    None,
+                    self.enable_equivalence_class_constraint,
+                    "virus".to_string(),
+                )
+                .with_deadline(
+                    self.timeout
+                        .map(|timeout| Instant::now() + Duration::from_secs(timeout)),
+                )
+                .with_max_events(self.max_events);
+                let computed_model = model.compute_from_marginal(&marginal, &data);
+                if marginal.timed_out() {
+                    warn!("model computation timed out, writing best-so-far posteriors");
+                }
+                if marginal.capped() {
+                    warn!("the events were searched approximately (max_events), the posteriors are approximate");
+                }
 
                 //find event posteriors
                 let event_posteriors = computed_model.event_posteriors();
@@ -144,16 +304,18 @@ impl Caller {
                         .unwrap();
 
                 //plot the best solution as final solution plot
-                let (best_fractions, _) = new_event_posteriors.iter().next().unwrap();
-                let candidate_matrix_all = CandidateMatrix::new(
+                let best_event = haplotypes::select_best_event(
+                    &new_event_posteriors,
+                    &all_haplotypes,
+                    self.tie_tolerance,
+                );
+                let (best_fractions, _) = &new_event_posteriors[best_event.index];
+                let candidate_matrix_of_all_haplotypes = CandidateMatrix::new(
                     &filtered_haplotype_variants
                         .filter_for_haplotypes(&all_haplotypes)
                         .unwrap(),
                 )
-                .unwrap()
-                .values()
-                .cloned()
-                .collect();
+                .unwrap();
 
                 let best_fractions = best_fractions
                     .iter()
@@ -163,11 +325,137 @@ impl Caller {
                 haplotypes::plot_prediction(
                     &self.outcsv,
                     &"final",
-                    &candidate_matrix_all,
+                    &candidate_matrix_of_all_haplotypes,
                     &all_haplotypes,
                     &data.variant_calls,
                     &best_fractions,
+                    self.plot_csv,
+                )?;
+                if self.combined_plot {
+                    haplotypes::write_combined_plot(&self.outcsv)?;
+                }
+                haplotypes::write_lp_vs_posterior(
+                    &self.outcsv,
+                    &lp_solution.fractions,
+                    &all_haplotypes,
+                    &best_fractions,
                 )?;
+                if self.variant_attribution {
+                    haplotypes::write_variant_attribution(
+                        &self.outcsv,
+                        &candidate_matrix_of_all_haplotypes,
+                        &data.variant_calls,
+                        &all_haplotypes,
+                        &best_fractions,
+                        self.vaf_denominator,
+                    )?;
+                }
+                if let Some((calls, field)) = &self.read_support {
+                    let fragment_ids = haplotypes::read_fragment_ids(
+                        &mut open_bcf(calls)?,
+                        field,
+                        self.sample.as_deref(),
+                    )?;
+                    haplotypes::write_read_support(
+                        &self.outcsv,
+                        &candidate_matrix_of_all_haplotypes,
+                        &data.variant_calls,
+                        &all_haplotypes,
+                        &best_fractions,
+                        &fragment_ids,
+                        self.vaf_denominator,
+                    )?;
+                }
+                let mut qc_concordances = None;
+                if let Some(calls) = &self.presence_concordance {
+                    let concordances = haplotypes::presence_concordance(
+                        &candidate_matrix_of_all_haplotypes,
+                        &data.variant_calls,
+                        &all_haplotypes,
+                        &best_fractions,
+                        &haplotypes::read_prob_absent(&mut open_bcf(calls)?)?,
+                        self.vaf_denominator,
+                    );
+                    let discordant = concordances
+                        .iter()
+                        .filter(|concordance| concordance.is_discordant())
+                        .count();
+                    if discordant > 0 {
+                        warn!(
+                            "the presence of {} variants implied by the best solution contradicts their calls, see presence_concordance.tsv",
+                            discordant
+                        );
+                    }
+                    haplotypes::write_presence_concordance(&self.outcsv, &concordances)?;
+                    qc_concordances = Some(concordances);
+                }
+                let mut qc_fits = None;
+                if self.fit_divergence {
+                    haplotypes::write_fit_divergences(
+                        &self.outcsv,
+                        &candidate_matrix_of_all_haplotypes,
+                        &data.variant_calls,
+                        &all_haplotypes,
+                        &best_fractions,
+                        self.vaf_denominator,
+                    )?;
+                    qc_fits = Some(haplotypes::fit_divergences(
+                        &candidate_matrix_of_all_haplotypes,
+                        &data.variant_calls,
+                        &all_haplotypes,
+                        &best_fractions,
+                        self.vaf_denominator,
+                    ));
+                }
+                if let Some(band) = self.candidate_band {
+                    haplotypes::write_candidate_alleles(
+                        &self.outcsv,
+                        &new_event_posteriors,
+                        &all_haplotypes,
+                        band,
+                    )?;
+                }
+                if let Some(reference) = &self.reference_haplotype {
+                    haplotypes::write_relative_fractions(
+                        &self.outcsv,
+                        &all_haplotypes,
+                        &best_fractions,
+                        &Haplotype(reference.clone()),
+                    )?;
+                }
+                let lp_posterior_divergent = haplotypes::check_lp_posterior_consistency(
+                    &lp_solution.fractions,
+                    &all_haplotypes,
+                    &best_fractions,
+                    self.consistency_tolerance,
+                );
+                let haplotype_funnel = HaplotypeFunnel {
+                    panel: haplotypes.len(),
+                    lp_selected: lp_solution.lp_haplotypes.len(),
+                    extended: lp_solution.extended_haplotypes.len(),
+                    modeled: lp_haplotypes.len(),
+                };
+                haplotype_funnel.log();
+                let manifest = Manifest {
+                    mixture_entropy: Some(haplotypes::mixture_entropy(&best_fractions)),
+                    timed_out: Some(marginal.timed_out()),
+                    capped: Some(marginal.capped()),
+                    log_evidence: marginal.log_evidence().map(|log_evidence| log_evidence.0),
+                    haplotype_funnel: Some(haplotype_funnel),
+                    variants: Some(variant_calls.len()),
+                    lp_time_s: Some(lp_time),
+                    evaluated_events: Some(marginal.evaluated_events()),
+                    ambiguous: Some(best_event.is_ambiguous()),
+                    tied_best_events: Some(best_event.tied.len()),
+                    lp_unassigned_fraction: lp_solution.unassigned_fraction,
+                    panel_fingerprint: Some(panel_fingerprint),
+                    ..Default::default()
+                }
+                .with_quality(
+                    &data.variant_calls,
+                    lp_solution.residual,
+                    &self.quality_thresholds,
+                );
 
                 //write results to tsv
                 haplotypes::write_results(
@@ -177,7 +465,45 @@ impl Caller {
                     &all_haplotypes,
                     self.prior.clone(),
                     false,
+                    self.min_odds,
+                    self.fraction_snap,
+                    self.vaf_denominator,
+                    self.tie_tolerance,
                 )?;
+                if self.log_densities {
+                    haplotypes::write_log_densities(
+                        &self.outcsv,
+                        &new_event_posteriors,
+                        &all_haplotypes,
+                        self.tie_tolerance,
+                    )?;
+                }
+                if self.long_format {
+                    haplotypes::write_results_long(
+                        &self.outcsv,
+                        &new_event_posteriors,
+                        &all_haplotypes,
+                        self.tie_tolerance,
+                    )?;
+                }
+                if let Some(top) = self.report {
+                    haplotypes::write_report(
+                        &self.outcsv,
+                        &new_event_posteriors,
+                        &all_haplotypes,
+                        top,
+                        self.tie_tolerance,
+                    )?;
+                }
+                if let Some(k) = self.distinct_sets {
+                    haplotypes::write_distinct_solutions(
+                        &self.outcsv,
+                        &new_event_posteriors,
+                        &all_haplotypes,
+                        k,
+                        self.tie_tolerance,
+                    )?;
+                }
 
                 //plot first 10 posteriors of orthanq output
                 haplotypes::plot_densities(
@@ -186,6 +512,16 @@ impl Caller {
                     &all_haplotypes,
                     "viral",
                 )?;
+                manifest.write(&self.outcsv)?;
+                let mut qc =
+                    QcReport::new(&manifest).with_lp_posterior_divergent(&lp_posterior_divergent);
+                if let Some(fits) = &qc_fits {
+                    qc = qc.with_fit_divergences(fits);
+                }
+                if let Some(concordances) = &qc_concordances {
+                    qc = qc.with_presence_concordance(concordances);
+                }
+                qc.write(&self.outcsv)?;
             } else {
                 self.output_empty_files()?;
                 warn!("Insufficient observations from data!");
@@ -193,6 +529,46 @@ impl Caller {
             Ok(())
         }
     }
+    //read_inputs parses the variant calls and the candidates (candidates.vcf of the candidates folder). The candidates
+    //are only parsed if there are variant calls, and only the called variants are expanded into HaplotypeVariants,
+    //such that large panels fit into memory (see PanelMatrix).
+    fn read_inputs(&mut self) -> Result<(VariantCalls, HaplotypeVariants, PanelSummary)> {
+        let (variant_calls, rejected) = VariantCalls::new_with_rejections(
+            &mut self.variant_calls,
+            &VariantCallsOptions {
+                missing_afd: self.missing_afd,
+                afd_scale: self.afd_scale,
+                sample: self.sample.clone(),
+                af_field: self.af_field.clone(),
+                error_field: self.error_field.clone(),
+                variant_quality_percentile: self.variant_quality_percentile,
+            },
+        )?;
+        if let Some(path) = &self.rejected_variants {
+            rejected.write(path)?;
+        }
+        if variant_calls.len() == 0 {
+            return Ok((
+                variant_calls,
+                HaplotypeVariants(BTreeMap::new()),
+                PanelSummary::default(),
+            ));
+        }
+        let mut haplotype_variants_rdr = open_bcf(&self.candidates_folder.join("candidates.vcf"))?;
+        let panel = if self.require_coverage {
+            PanelMatrix::new_requiring_coverage(&mut haplotype_variants_rdr)?
+        } else {
+            PanelMatrix::new(&mut haplotype_variants_rdr)?
+        };
+        let variant_ids: Vec<VariantID> = variant_calls.keys().cloned().collect();
+        panel.check_overlap(&variant_ids)?;
+        Ok((
+            variant_calls,
+            panel.haplotype_variants_for(&variant_ids),
+            PanelSummary::new(&panel),
+        ))
+    }
+
     pub fn output_empty_files(&self) -> Result<()> {
         //write blank plots, required for the workflow!
         let mut parent = self.outcsv.clone();
@@ -1,8 +1,12 @@
+use crate::benchmark;
 use crate::calling;
+use crate::calling::haplotypes::config::CallerConfig;
+use crate::calling::haplotypes::hla::ReportResolution;
 use crate::candidates;
 use crate::preprocess;
-use anyhow::Result;
-use rust_htslib::bcf;
+use crate::selftest;
+use crate::utils::open_bcf;
+use anyhow::{anyhow, Result};
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -43,6 +47,119 @@ pub enum Orthanq {
         #[structopt(subcommand)]
         kind: PreprocessKind,
     },
+    #[structopt(
+        name = "list-haplotypes",
+        about = "List haplotypes of a candidate BCF together with the number of variants they cover.",
+        setting = structopt::clap::AppSettings::ColoredHelp,
+    )]
+    ListHaplotypes {
+        #[structopt(
+            parse(from_os_str),
+            long = "haplotype-variants",
+            required = true,
+            help = "Haplotype variants compared to a common reference."
+        )]
+        haplotype_variants: PathBuf,
+        #[structopt(
+            long,
+            help = "CSV file to write the summary to. If not given, the summary is printed to stdout."
+        )]
+        output: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "validate-panel",
+        about = "Check a candidate BCF for common problems (e.g. non-integer or duplicate IDs, missing FORMAT field C) and report all of them.",
+        setting = structopt::clap::AppSettings::ColoredHelp,
+    )]
+    ValidatePanel {
+        #[structopt(
+            parse(from_os_str),
+            long = "haplotype-variants",
+            required = true,
+            help = "Haplotype variants compared to a common reference."
+        )]
+        haplotype_variants: PathBuf,
+    },
+    #[structopt(
+        name = "convert-to-g",
+        about = "Convert the haplotype columns of an existing (3-field) results table to their G groups, without calling again.",
+        setting = structopt::clap::AppSettings::ColoredHelp,
+    )]
+    ConvertToG {
+        #[structopt(
+            parse(from_os_str),
+            long = "results",
+            required = true,
+            help = "Results table (CSV) of the HLA caller with 3-field haplotype columns."
+        )]
+        results: PathBuf,
+        #[structopt(
+            parse(from_os_str),
+            long = "xml",
+            required = true,
+            help = "HLA allele list (XML) of IMGT/HLA with the G groups of the alleles."
+        )]
+        xml: PathBuf,
+        #[structopt(
+            parse(from_os_str),
+            long = "output",
+            required = true,
+            help = "Output CSV file for the converted results table."
+        )]
+        output: PathBuf,
+    },
+    #[structopt(
+        name = "diff-results",
+        about = "Compare the best solutions of the same samples in two sets of results tables, e.g. before and after an upgrade of orthanq or the panel.",
+        setting = structopt::clap::AppSettings::ColoredHelp,
+    )]
+    DiffResults {
+        #[structopt(
+            parse(from_os_str),
+            long = "before",
+            required = true,
+            help = "Results tables (CSV) of the previous version, one per sample, named by the sample."
+        )]
+        before: Vec<PathBuf>,
+        #[structopt(
+            parse(from_os_str),
+            long = "after",
+            required = true,
+            help = "Results tables (CSV) of the new version, matched to the previous ones by file name."
+        )]
+        after: Vec<PathBuf>,
+        #[structopt(
+            long = "tolerance",
+            default_value = "0.05",
+            help = "Maximum difference of the fractions of the same haplotypes that is not reported as a change."
+        )]
+        tolerance: f64,
+        #[structopt(
+            parse(from_os_str),
+            long = "output",
+            required = true,
+            help = "Output TSV file with one row per sample, stating whether the call changed."
+        )]
+        output: PathBuf,
+    },
+    #[structopt(
+        name = "benchmark",
+        about = "Call haplotypes on a bundled fixture and print throughput metrics as JSON (e.g. to track performance in CI).",
+        setting = structopt::clap::AppSettings::ColoredHelp,
+    )]
+    Benchmark,
+    #[structopt(
+        name = "selftest",
+        about = "Check the installation: the external tools of the preprocessing, htslib, the solver and calling on a bundled fixture.",
+        setting = structopt::clap::AppSettings::ColoredHelp,
+    )]
+    Selftest,
+    #[structopt(
+        name = "solver-probe",
+        about = "Solve a minimal linear program, used by the selftest to check the solver in a separate process.",
+        setting = structopt::clap::AppSettings::Hidden,
+    )]
+    SolverProbe,
 }
 
 #[derive(Debug, StructOpt, Clone)]
@@ -54,14 +171,18 @@ pub enum PreprocessKind {
             help = "Reference genome that is used during candidate generation."
         )]
         genome: PathBuf,
-        #[structopt(long = "vg-index", required = true, help = "VG pangenome graph")]
-        vg_index: PathBuf,
+        #[structopt(
+            long = "vg-index",
+            required_unless = "no-pangenome",
+            help = "VG pangenome graph"
+        )]
+        vg_index: Option<PathBuf>,
         #[structopt(long = "bwa-index", help = "bwa index")]
         bwa_index: Option<PathBuf>,
         #[structopt(
             long = "reads",
             required = true,
-            help = "Input FASTQ reads belonging to the sample."
+            help = "Input FASTQ reads belonging to the sample, or its alignment to the genome as a single coordinate sorted and indexed BAM or CRAM file, in which case the alignment step is skipped."
         )]
         reads: Vec<PathBuf>,
         #[structopt(
@@ -81,6 +202,70 @@ pub enum PreprocessKind {
             help = "Threads to use for tools used in preprocessing."
         )]
         threads: String,
+        #[structopt(
+            long = "genes",
+            help = "HLA genes to extract reads for (e.g. A B C or HLA-A HLA-B). By default, all ten classical and nonclassical genes are used."
+        )]
+        genes: Vec<String>,
+        #[structopt(
+            long = "region-flank",
+            default_value = "0",
+            help = "Number of bases to pad the HLA gene regions with on both sides when extracting reads."
+        )]
+        region_flank: u64,
+        #[structopt(
+            long = "gene-regions",
+            parse(from_os_str),
+            help = "TSV table of the HLA gene regions (columns build, gene, chromosome, start and end) to use instead of the bundled one, e.g. to add genes or to use another genome build."
+        )]
+        gene_regions: Option<PathBuf>,
+        #[structopt(
+            long = "genome-build",
+            help = "Genome build whose gene regions are used (e.g. GRCh38) [default: GRCh38]."
+        )]
+        genome_build: Option<String>,
+        #[structopt(
+            long = "no-pangenome",
+            help = "Skip the realignment of the extracted reads to the pangenome (vg giraffe) and use the linear alignment instead. This doesn't require a vg index, but can reduce the accuracy of the typing."
+        )]
+        no_pangenome: bool,
+        #[structopt(
+            long = "chromosomes",
+            help = "Chromosomes (e.g. 6) to keep after the realignment to the pangenome. By default, the standard chromosomes (1-22, X, Y, M) are kept."
+        )]
+        chromosomes: Vec<String>,
+        #[structopt(
+            long = "extra-contigs",
+            help = "Additional contigs to keep after the realignment to the pangenome, named as in the reference (e.g. alternative contigs carrying HLA reads)."
+        )]
+        extra_contigs: Vec<String>,
+        #[structopt(
+            long = "coverage-bed",
+            help = "Additionally write the intervals covered by the reads of the final alignment ({sample}_coverage.bed) next to the output, e.g. for inspection in IGV."
+        )]
+        coverage_bed: bool,
+        #[structopt(
+            long = "min-mapq",
+            help = "Minimum mapping quality of the reads extracted from the linear alignment (samtools view -q), e.g. to filter multi-mapping reads of the repetitive HLA region before the realignment. By default, no reads are filtered."
+        )]
+        min_mapq: Option<u8>,
+        #[structopt(
+            long = "read-fates",
+            help = "Write the fate of each extracted read ({sample}_read_fates.tsv) next to the output: whether it is part of the final bam after the realignment and where it is aligned, e.g. to trace a suspicious read that drives a variant call."
+        )]
+        read_fates: bool,
+        #[structopt(
+            long = "scenario",
+            parse(from_os_str),
+            help = "Varlociraptor scenario to use instead of the bundled one. It has to declare a sample named 'sample', under which the observations are passed to varlociraptor."
+        )]
+        scenario: Option<PathBuf>,
+        #[structopt(
+            long = "tempdir",
+            parse(from_os_str),
+            help = "Directory under which the intermediate files are written, e.g. a large scratch filesystem for deep samples. By default, the system temp directory is used."
+        )]
+        tempdir: Option<PathBuf>,
     },
     Virus {
         #[structopt(
@@ -194,20 +379,20 @@ pub enum CallKind {
             parse(from_os_str),
             long = "haplotype-variants",
             required = true,
-            help = "Haplotype variants compared to a common reference.", // TODO later, we will add a subcommand to generate this file with Varlociraptor as well
+            help = "Haplotype variants compared to a common reference. Several files (e.g. one per gene) are merged into one panel.", // TODO later, we will add a subcommand to generate this file with Varlociraptor as well
         )]
-        haplotype_variants: PathBuf,
+        haplotype_variants: Vec<PathBuf>,
         #[structopt(
             parse(from_os_str),
             long = "haplotype-calls",
             required = true,
-            help = "Haplotype calls"
+            help = "Haplotype calls. For several related samples (e.g. a trio), each sample is called separately in a subdirectory named after the calls file and the results are combined into the output, with a sample column."
         )]
-        variant_calls: PathBuf,
+        variant_calls: Vec<PathBuf>,
         #[structopt(
             long = "xml",
             required = true,
-            help = "xml file that is acquired from IMGT/HLA for the corresponding version"
+            help = "xml file that is acquired from IMGT/HLA for the corresponding version, optionally gzipped (.xml.gz)"
         )]
         xml: PathBuf,
         #[structopt(
@@ -215,15 +400,18 @@ pub enum CallKind {
             help = "Folder to store quality control plots for the inference of a CDF from Kallisto bootstraps for each haplotype of interest."
         )]
         output: PathBuf,
-        #[structopt(long, help = "Choose uniform, diploid or diploid-subclonal")]
-        prior: String,
+        #[structopt(
+            long,
+            help = "Choose uniform, diploid, diploid-subclonal or haploid. Required, if not given in the config file."
+        )]
+        prior: Option<String>,
         #[structopt(
             long,
             help = "If true, only common variants of considered haplotypes will be used in the model."
         )]
         common_variants: bool,
-        #[structopt(default_value = "0.01", help = "Cutoff for linear program solutions.")]
-        lp_cutoff: f64,
+        #[structopt(help = "Cutoff for linear program solutions [default: 0.01].")]
+        lp_cutoff: Option<f64>,
         #[structopt(
             long,
             help = "Enable equivalence based constrain during model exploration."
@@ -231,21 +419,239 @@ pub enum CallKind {
         enable_equivalence_class_constraint: bool,
         #[structopt(
             long,
-            help = "Enable extension of haplotypes that are computed with linear program."
+            help = "Enable extension of haplotypes that are computed with linear program [default: false]."
         )]
         extend_haplotypes: Option<bool>,
         #[structopt(
             long,
-            default_value = "1",
-            help = "Threshold for assigning equivalence classes."
+            help = "Threshold for assigning equivalence classes [default: 1]."
+        )]
+        threshold_equivalence_class: Option<usize>,
+        #[structopt(
+            long,
+            help = "Number of variant distances to extend haplotype list coming from the linear program [default: 3]."
+        )]
+        num_extend_haplotypes: Option<i64>,
+        #[structopt(
+            long,
+            help = "Use a mixed integer linear program with a binary selection variable per haplotype. Can be considerably slower than the default linear program on large panels."
+        )]
+        integer_selection: bool,
+        #[structopt(
+            long = "depth-weighting",
+            help = "Weight the deviation at each variant in the linear program by the square root of its read depth, such that variants with few reads influence the solution less."
+        )]
+        depth_weighting: bool,
+        #[structopt(
+            long = "relax-sum",
+            help = "Only require the haplotype fractions of the linear program to sum up to at most 1.0 instead of exactly 1.0, leaving signal that can't be explained by the panel (e.g. contamination) unassigned."
+        )]
+        relax_sum: bool,
+        #[structopt(
+            long,
+            help = "Maximum number of haplotypes to be selected by the mixed integer linear program (see --integer-selection)."
+        )]
+        max_haplotypes: Option<usize>,
+        #[structopt(
+            long,
+            help = "Additionally extend the haplotypes of the linear program by haplotypes sharing at least this fraction of their variants (between 0.0 and 1.0)."
+        )]
+        extension_overlap: Option<f64>,
+        #[structopt(
+            long,
+            help = "Stop the model computation after this many seconds and write the best-so-far posteriors (flagged as timed out in manifest.json)."
+        )]
+        timeout: Option<u64>,
+        #[structopt(
+            long = "max-events",
+            help = "Evaluate at most this many events (fraction combinations) in the model computation, using a beam search over the most promising partial assignments instead of the full enumeration. This keeps hard cases with many haplotypes tractable, but the posteriors are approximate (flagged as capped in manifest.json)."
+        )]
+        max_events: Option<usize>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "YAML file with caller parameters (keys named as the options with underscores, e.g. lp_cutoff). Options given on the command line take precedence."
+        )]
+        config: Option<PathBuf>,
+        #[structopt(
+            long,
+            help = "How to handle variant calls with a missing (\".\") AFD: uninformative (keep them without contributing to the likelihood) or skip [default: uninformative]."
+        )]
+        missing_afd: Option<String>,
+        #[structopt(
+            long,
+            help = "Scale of the densities in the AFD field of the calls: phred (as written by varlociraptor), prob or logprob [default: phred]."
+        )]
+        afd_scale: Option<String>,
+        #[structopt(
+            long,
+            help = "Denominator of the expected VAFs of the model and of the variant columns of the results: exclude-uncovered (haplotypes that are not covered at a variant and don't carry it are left out) or include-all (all haplotypes) [default: exclude-uncovered]."
+        )]
+        vaf_denominator: Option<String>,
+        #[structopt(
+            long,
+            help = "Time limit of the linear program solver in seconds. If it is reached, the best solution found so far is used with a warning. With --integer-selection and no solution yet, the continuous relaxation is solved within the remaining time instead."
+        )]
+        lp_time_limit: Option<f64>,
+        #[structopt(
+            long,
+            help = "Relative gap at which the solver stops the search of the mixed integer linear program (see --integer-selection)."
+        )]
+        lp_gap: Option<f64>,
+        #[structopt(
+            long,
+            help = "Name of the sample to use from a multi-sample calls BCF. By default, the first sample is used."
+        )]
+        sample: Option<String>,
+        #[structopt(
+            long = "af-field",
+            help = "FORMAT field of the variant calls that holds the allele frequency used as target by the linear program [default: AF]."
+        )]
+        af_field: Option<String>,
+        #[structopt(
+            long = "error-field",
+            help = "FORMAT (or INFO) field of the variant calls that holds the error rate of the call. The deviation at a variant is weighted by 1 - error rate in the linear program, such that unreliable calls influence the solution less. By default, all calls are weighted equally."
+        )]
+        error_field: Option<String>,
+        #[structopt(
+            long,
+            help = "Keep only the variant calls whose quality is above this percentile (0-100) of the sample. The quality of a call is the distance of its PROB_ABSENT from 0.5, ties are ranked by read depth (DP). By default, all calls are kept."
+        )]
+        variant_quality_percentile: Option<f64>,
+        #[structopt(
+            long,
+            help = "Only write result rows whose odds relative to the best row are at least the given value. The best row is always written."
+        )]
+        min_odds: Option<f64>,
+        #[structopt(
+            long,
+            help = "Warn if the fraction of a haplotype differs by more than this between the linear program and the best posterior event [default: 0.25]."
+        )]
+        consistency_tolerance: Option<f64>,
+        #[structopt(
+            long,
+            help = "Events whose log posterior density differs from the best one by at most this value (i.e. relative to the best density) are considered tied. Ties are reported in the manifest and resolved by the lexicographic order of the haplotypes in all outputs [default: 1e-6]."
+        )]
+        tie_tolerance: Option<f64>,
+        #[structopt(
+            long,
+            help = "Prefix prepended to the names of the output and of all files generated next to it (e.g. manifest.json, lp_solution.json), such that several samples can share an output directory."
+        )]
+        output_prefix: Option<String>,
+        #[structopt(
+            long,
+            help = "Additionally write the haplotype fractions of all events in long format (fractions_long.tsv) next to the output."
         )]
-        threshold_equivalence_class: usize,
+        long_format: bool,
         #[structopt(
             long,
-            default_value = "3",
-            help = "Number of variant distances to extend haplotype list coming from the linear program."
+            help = "Additionally write the densities of all events in natural log space at full precision (log_densities.tsv) next to the output."
         )]
-        num_extend_haplotypes: i64,
+        log_densities: bool,
+        #[structopt(
+            long,
+            help = "Additionally write the datasets of the solution plots as CSV (e.g. final_plot_variants.csv) next to the output."
+        )]
+        plot_csv: bool,
+        #[structopt(
+            long,
+            help = "Additionally write a single plot of the linear program and the final solution faceted by stage (combined_solution.json) next to the output."
+        )]
+        combined_plot: bool,
+        #[structopt(
+            long,
+            help = "Additionally write the linear program in CPLEX LP format (lp.lp) next to the output, e.g. to solve it with an external solver."
+        )]
+        export_lp: bool,
+        #[structopt(
+            long = "require-coverage",
+            help = "Fail if the candidate variants lack the FORMAT field C instead of assuming all haplotypes to be characterized at all variant loci."
+        )]
+        require_coverage: bool,
+        #[structopt(
+            long = "confirmed-only",
+            help = "Exclude the alleles that are unconfirmed according to the IMGT xml from the candidates before calling, such that only confirmed alleles are reported."
+        )]
+        confirmed_only: bool,
+        #[structopt(
+            long,
+            value_name = "N",
+            help = "Additionally write a self-contained HTML report (report.html) with the final solution plot and the N best solutions."
+        )]
+        report: Option<usize>,
+        #[structopt(
+            long = "reference-haplotype",
+            help = "Additionally write the fractions of the best solution relative to the given haplotype (relative_fractions.tsv), e.g. for allele-specific expression."
+        )]
+        reference_haplotype: Option<String>,
+        #[structopt(
+            long = "candidate-band",
+            value_name = "PROBABILITY",
+            help = "Additionally write all haplotypes that are present with at least the given posterior probability (summed over all events) to candidate_alleles.tsv next to the output."
+        )]
+        candidate_band: Option<f64>,
+        #[structopt(
+            long = "fraction-snap",
+            value_name = "GRID",
+            help = "Snap the fractions in the results table to the closest multiple of the given value (e.g. 0.05), such that near-equal mixtures are reported as clean values."
+        )]
+        fraction_snap: Option<f64>,
+        #[structopt(
+            long = "cache",
+            parse(from_os_str),
+            help = "File to cache the parsed variant calls and candidates in. Subsequent runs on unchanged inputs (e.g. with other parameters) load them from the cache instead of parsing them again."
+        )]
+        cache: Option<PathBuf>,
+        #[structopt(
+            long = "rejected-variants",
+            parse(from_os_str),
+            help = "File to write the variant calls that are not considered to, each with the reason (DP0, bad-AFD, missing-AFD or low-quality), e.g. to check why a variant doesn't contribute. With several samples, the file name is prefixed with the sample. The cache is not used if given."
+        )]
+        rejected_variants: Option<PathBuf>,
+        #[structopt(
+            long = "variant-attribution",
+            help = "Additionally write the contribution of each haplotype of the best solution to the expected allele frequency of each observed variant (variant_attribution.tsv) next to the output."
+        )]
+        variant_attribution: bool,
+        #[structopt(
+            long = "read-support",
+            value_name = "FIELD",
+            help = "Additionally write the IDs of the fragments supporting each observed variant, per haplotype of the best solution carrying it (read_support.tsv), next to the output. The IDs are read from the given FORMAT field of the variant calls (comma-separated, e.g. as reported with varlociraptor's --report-fragment-ids)."
+        )]
+        read_support: Option<String>,
+        #[structopt(
+            long = "fit-divergence",
+            help = "Additionally write, per observed variant, how poorly its allele frequency distribution supports the allele frequency expected by the best solution (fit_divergence.tsv) next to the output."
+        )]
+        fit_divergence: bool,
+        #[structopt(
+            long = "presence-concordance",
+            help = "Additionally write, per observed variant, whether its presence according to the call (PROB_ABSENT <= 0.05 present, >= 0.95 absent) agrees with the presence implied by the best solution (presence_concordance.tsv) next to the output."
+        )]
+        presence_concordance: bool,
+        #[structopt(
+            long = "distinct-sets",
+            value_name = "K",
+            help = "Additionally write the best solution of each of the K best distinct sets of present haplotypes (distinct_solutions.tsv) next to the output, collapsing solutions that only differ in the fractions."
+        )]
+        distinct_sets: Option<usize>,
+        #[structopt(
+            long = "het-odds-band",
+            help = "If the best solution is homozygous (a single haplotype), additionally report the best heterozygous solution (homozygous_ambiguity.tsv) and flag the ambiguity in the manifest if its odds relative to the best solution are at least the given value (e.g. 0.1), such that a weakly supported second allele isn't missed."
+        )]
+        het_odds_band: Option<f64>,
+        #[structopt(
+            long = "prior-weights",
+            parse(from_os_str),
+            help = "TSV with the population frequencies of the haplotypes (columns haplotype and prior_weight, in (0, 1]) to weight the prior with, favoring common haplotypes when the evidence is ambiguous. Haplotypes without a weight get the weight of the rarest haplotype in the table."
+        )]
+        prior_weights: Option<PathBuf>,
+        #[structopt(
+            long = "typing-report",
+            value_name = "RESOLUTION",
+            help = "Additionally write a typing report with the two alleles of the best solution per locus (typing_report.tsv) next to the output, at the given resolution (2-field, 3-field or G)."
+        )]
+        typing_report: Option<ReportResolution>,
     },
     Virus {
         #[structopt(
@@ -266,10 +672,13 @@ pub enum CallKind {
             help = "Folder to store quality control plots for the inference of a CDF from Kallisto bootstraps for each haplotype of interest."
         )]
         output: PathBuf,
-        #[structopt(long, help = "Choose uniform, diploid or diploid-subclonal")]
-        prior: String,
-        #[structopt(default_value = "0.01", help = "Cutoff for linear program solutions.")]
-        lp_cutoff: f64,
+        #[structopt(
+            long,
+            help = "Choose uniform, diploid, diploid-subclonal or haploid. Required, if not given in the config file."
+        )]
+        prior: Option<String>,
+        #[structopt(help = "Cutoff for linear program solutions [default: 0.01].")]
+        lp_cutoff: Option<f64>,
         #[structopt(
             long,
             help = "Enable equivalence based constrain during model exploration."
@@ -277,27 +686,228 @@ pub enum CallKind {
         enable_equivalence_class_constraint: bool,
         #[structopt(
             long,
-            help = "Enable extension of haplotypes that are computed with linear program."
+            help = "Enable extension of haplotypes that are computed with linear program [default: true]."
         )]
         extend_haplotypes: Option<bool>,
         #[structopt(
             long,
-            default_value = "0.35",
-            help = "Percent threshold for evaluated variants."
+            help = "Percent threshold for evaluated variants [default: 0.35]."
         )]
-        threshold_considered_variants: f64,
+        threshold_considered_variants: Option<f64>,
         #[structopt(
             long,
-            default_value = "2",
-            help = "Threshold for assigning equivalence classes."
+            help = "Threshold for assigning equivalence classes [default: 2]."
         )]
-        threshold_equivalence_class: usize,
+        threshold_equivalence_class: Option<usize>,
         #[structopt(
             long,
-            default_value = "0",
-            help = "Number of variant distances to extend haplotype list coming from the linear program."
+            help = "Number of variant distances to extend haplotype list coming from the linear program [default: 0]."
+        )]
+        num_extend_haplotypes: Option<i64>, //larger than 0 is not yet supported.
+        #[structopt(
+            long,
+            help = "Use a mixed integer linear program with a binary selection variable per haplotype. Can be considerably slower than the default linear program on large panels."
+        )]
+        integer_selection: bool,
+        #[structopt(
+            long = "depth-weighting",
+            help = "Weight the deviation at each variant in the linear program by the square root of its read depth, such that variants with few reads influence the solution less."
+        )]
+        depth_weighting: bool,
+        #[structopt(
+            long = "relax-sum",
+            help = "Only require the haplotype fractions of the linear program to sum up to at most 1.0 instead of exactly 1.0, leaving signal that can't be explained by the panel (e.g. contamination) unassigned."
+        )]
+        relax_sum: bool,
+        #[structopt(
+            long,
+            help = "Maximum number of haplotypes to be selected by the mixed integer linear program (see --integer-selection)."
+        )]
+        max_haplotypes: Option<usize>,
+        #[structopt(
+            long,
+            help = "Additionally extend the haplotypes of the linear program by haplotypes sharing at least this fraction of their variants (between 0.0 and 1.0)."
         )]
-        num_extend_haplotypes: i64, //larger than 0 is not yet supported.
+        extension_overlap: Option<f64>,
+        #[structopt(
+            long,
+            help = "Stop the model computation after this many seconds and write the best-so-far posteriors (flagged as timed out in manifest.json)."
+        )]
+        timeout: Option<u64>,
+        #[structopt(
+            long = "max-events",
+            help = "Evaluate at most this many events (fraction combinations) in the model computation, using a beam search over the most promising partial assignments instead of the full enumeration. This keeps hard cases with many haplotypes tractable, but the posteriors are approximate (flagged as capped in manifest.json)."
+        )]
+        max_events: Option<usize>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "YAML file with caller parameters (keys named as the options with underscores, e.g. lp_cutoff). Options given on the command line take precedence."
+        )]
+        config: Option<PathBuf>,
+        #[structopt(
+            long,
+            help = "How to handle variant calls with a missing (\".\") AFD: uninformative (keep them without contributing to the likelihood) or skip [default: uninformative]."
+        )]
+        missing_afd: Option<String>,
+        #[structopt(
+            long,
+            help = "Scale of the densities in the AFD field of the calls: phred (as written by varlociraptor), prob or logprob [default: phred]."
+        )]
+        afd_scale: Option<String>,
+        #[structopt(
+            long,
+            help = "Denominator of the expected VAFs of the model and of the variant columns of the results: exclude-uncovered (haplotypes that are not covered at a variant and don't carry it are left out) or include-all (all haplotypes) [default: exclude-uncovered]."
+        )]
+        vaf_denominator: Option<String>,
+        #[structopt(
+            long,
+            help = "Time limit of the linear program solver in seconds. If it is reached, the best solution found so far is used with a warning. With --integer-selection and no solution yet, the continuous relaxation is solved within the remaining time instead."
+        )]
+        lp_time_limit: Option<f64>,
+        #[structopt(
+            long,
+            help = "Relative gap at which the solver stops the search of the mixed integer linear program (see --integer-selection)."
+        )]
+        lp_gap: Option<f64>,
+        #[structopt(
+            long,
+            help = "Name of the sample to use from a multi-sample calls BCF. By default, the first sample is used."
+        )]
+        sample: Option<String>,
+        #[structopt(
+            long = "af-field",
+            help = "FORMAT field of the variant calls that holds the allele frequency used as target by the linear program [default: AF]."
+        )]
+        af_field: Option<String>,
+        #[structopt(
+            long = "error-field",
+            help = "FORMAT (or INFO) field of the variant calls that holds the error rate of the call. The deviation at a variant is weighted by 1 - error rate in the linear program, such that unreliable calls influence the solution less. By default, all calls are weighted equally."
+        )]
+        error_field: Option<String>,
+        #[structopt(
+            long,
+            help = "Keep only the variant calls whose quality is above this percentile (0-100) of the sample. The quality of a call is the distance of its PROB_ABSENT from 0.5, ties are ranked by read depth (DP). By default, all calls are kept."
+        )]
+        variant_quality_percentile: Option<f64>,
+        #[structopt(
+            long,
+            help = "Only write result rows whose odds relative to the best row are at least the given value. The best row is always written."
+        )]
+        min_odds: Option<f64>,
+        #[structopt(
+            long,
+            help = "Warn if the fraction of a haplotype differs by more than this between the linear program and the best posterior event [default: 0.25]."
+        )]
+        consistency_tolerance: Option<f64>,
+        #[structopt(
+            long,
+            help = "Events whose log posterior density differs from the best one by at most this value (i.e. relative to the best density) are considered tied. Ties are reported in the manifest and resolved by the lexicographic order of the haplotypes in all outputs [default: 1e-6]."
+        )]
+        tie_tolerance: Option<f64>,
+        #[structopt(
+            long,
+            help = "Prefix prepended to the names of the output and of all files generated next to it (e.g. manifest.json, lp_solution.json), such that several samples can share an output directory."
+        )]
+        output_prefix: Option<String>,
+        #[structopt(
+            long,
+            help = "Additionally write the haplotype fractions of all events in long format (fractions_long.tsv) next to the output."
+        )]
+        long_format: bool,
+        #[structopt(
+            long,
+            help = "Additionally write the densities of all events in natural log space at full precision (log_densities.tsv) next to the output."
+        )]
+        log_densities: bool,
+        #[structopt(
+            long,
+            help = "Additionally write the datasets of the solution plots as CSV (e.g. final_plot_variants.csv) next to the output."
+        )]
+        plot_csv: bool,
+        #[structopt(
+            long,
+            help = "Additionally write a single plot of the linear program and the final solution faceted by stage (combined_solution.json) next to the output."
+        )]
+        combined_plot: bool,
+        #[structopt(
+            long,
+            help = "Additionally write the linear program in CPLEX LP format (lp.lp) next to the output, e.g. to solve it with an external solver."
+        )]
+        export_lp: bool,
+        #[structopt(
+            long = "require-coverage",
+            help = "Fail if the candidate variants lack the FORMAT field C instead of assuming all haplotypes to be characterized at all variant loci."
+        )]
+        require_coverage: bool,
+        #[structopt(
+            long,
+            value_name = "N",
+            help = "Additionally write a self-contained HTML report (report.html) with the final solution plot and the N best solutions."
+        )]
+        report: Option<usize>,
+        #[structopt(
+            long = "reference-haplotype",
+            help = "Additionally write the fractions of the best solution relative to the given haplotype (relative_fractions.tsv), e.g. for allele-specific expression."
+        )]
+        reference_haplotype: Option<String>,
+        #[structopt(
+            long = "candidate-band",
+            value_name = "PROBABILITY",
+            help = "Additionally write all haplotypes that are present with at least the given posterior probability (summed over all events) to candidate_alleles.tsv next to the output."
+        )]
+        candidate_band: Option<f64>,
+        #[structopt(
+            long = "fraction-snap",
+            value_name = "GRID",
+            help = "Snap the fractions in the results table to the closest multiple of the given value (e.g. 0.05), such that near-equal mixtures are reported as clean values."
+        )]
+        fraction_snap: Option<f64>,
+        #[structopt(
+            long = "cache",
+            parse(from_os_str),
+            help = "File to cache the parsed variant calls and candidates in. Subsequent runs on unchanged inputs (e.g. with other parameters) load them from the cache instead of parsing them again."
+        )]
+        cache: Option<PathBuf>,
+        #[structopt(
+            long = "rejected-variants",
+            parse(from_os_str),
+            help = "File to write the variant calls that are not considered to, each with the reason (DP0, bad-AFD, missing-AFD or low-quality), e.g. to check why a variant doesn't contribute. With several samples, the file name is prefixed with the sample. The cache is not used if given."
+        )]
+        rejected_variants: Option<PathBuf>,
+        #[structopt(
+            long = "variant-attribution",
+            help = "Additionally write the contribution of each haplotype of the best solution to the expected allele frequency of each observed variant (variant_attribution.tsv) next to the output."
+        )]
+        variant_attribution: bool,
+        #[structopt(
+            long = "read-support",
+            value_name = "FIELD",
+            help = "Additionally write the IDs of the fragments supporting each observed variant, per haplotype of the best solution carrying it (read_support.tsv), next to the output. The IDs are read from the given FORMAT field of the variant calls (comma-separated, e.g. as reported with varlociraptor's --report-fragment-ids)."
+        )]
+        read_support: Option<String>,
+        #[structopt(
+            long = "fit-divergence",
+            help = "Additionally write, per observed variant, how poorly its allele frequency distribution supports the allele frequency expected by the best solution (fit_divergence.tsv) next to the output."
+        )]
+        fit_divergence: bool,
+        #[structopt(
+            long = "presence-concordance",
+            help = "Additionally write, per observed variant, whether its presence according to the call (PROB_ABSENT <= 0.05 present, >= 0.95 absent) agrees with the presence implied by the best solution (presence_concordance.tsv) next to the output."
+        )]
+        presence_concordance: bool,
+        #[structopt(
+            long = "distinct-sets",
+            value_name = "K",
+            help = "Additionally write the best solution of each of the K best distinct sets of present haplotypes (distinct_solutions.tsv) next to the output, collapsing solutions that only differ in the fractions."
+        )]
+        distinct_sets: Option<usize>,
+        #[structopt(
+            long = "prior-weights",
+            parse(from_os_str),
+            help = "TSV with the population frequencies of the haplotypes (columns haplotype and prior_weight, in (0, 1]) to weight the prior with, favoring common haplotypes when the evidence is ambiguous. Haplotypes without a weight get the weight of the rarest haplotype in the table."
+        )]
+        prior_weights: Option<PathBuf>,
     },
 }
 
@@ -322,7 +932,7 @@ pub enum VirusKind {
             help = "Folder to store quality control plots for the inference of a CDF from Kallisto bootstraps for each haplotype of interest."
         )]
         output: PathBuf,
-        #[structopt(long, help = "Choose uniform, diploid or diploid-subclonal")]
+        #[structopt(long, help = "Choose uniform, diploid, diploid-subclonal or haploid")]
         prior: String,
         #[structopt(default_value = "0.01", help = "Cutoff for linear program solutions.")]
         lp_cutoff: f64,
@@ -353,7 +963,7 @@ pub enum VirusKind {
         variant_calls: PathBuf,
         #[structopt(long, help = "File path to store TSV table output.")]
         output: PathBuf,
-        #[structopt(long, help = "Choose uniform, diploid or diploid-subclonal")]
+        #[structopt(long, help = "Choose uniform, diploid, diploid-subclonal or haploid")]
         prior: String,
         #[structopt(default_value = "0.01", help = "Cutoff for linear program solutions.")]
         lp_cutoff: f64,
@@ -370,6 +980,23 @@ pub enum VirusKind {
     },
 }
 
+//flag maps a CLI switch to a config value, so that a switch that is not given does not override the config file
+fn flag(enabled: bool) -> Option<bool> {
+    if enabled {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+//load_config merges the parameters given on the command line into the config file, if any
+fn load_config(path: Option<PathBuf>, cli: CallerConfig) -> Result<CallerConfig> {
+    match path {
+        Some(path) => Ok(CallerConfig::from_path(&path)?.merge(cli)),
+        None => Ok(cli),
+    }
+}
+
 pub fn run(opt: Orthanq) -> Result<()> {
     let opt_clone = opt.clone();
     match opt_clone {
@@ -378,7 +1005,6 @@ pub fn run(opt: Orthanq) -> Result<()> {
                 haplotype_variants,
                 variant_calls,
                 xml,
-                // max_haplotypes,
                 // min_norm_counts,
                 output,
                 prior,
@@ -388,25 +1014,161 @@ pub fn run(opt: Orthanq) -> Result<()> {
                 extend_haplotypes,
                 threshold_equivalence_class,
                 num_extend_haplotypes,
+                integer_selection,
+                depth_weighting,
+                relax_sum,
+                max_haplotypes,
+                extension_overlap,
+                timeout,
+                max_events,
+                config,
+                missing_afd,
+                afd_scale,
+                vaf_denominator,
+                lp_time_limit,
+                lp_gap,
+                sample,
+                af_field,
+                error_field,
+                variant_quality_percentile,
+                min_odds,
+                consistency_tolerance,
+                tie_tolerance,
+                output_prefix,
+                long_format,
+                log_densities,
+                plot_csv,
+                combined_plot,
+                export_lp,
+                require_coverage,
+                confirmed_only,
+                report,
+                reference_haplotype,
+                candidate_band,
+                fraction_snap,
+                cache,
+                rejected_variants,
+                variant_attribution,
+                read_support,
+                fit_divergence,
+                presence_concordance,
+                distinct_sets,
+                het_odds_band,
+                prior_weights,
+                typing_report,
             } => {
-                let mut caller = calling::haplotypes::hla::CallerBuilder::default()
-                    .haplotype_variants(bcf::Reader::from_path(haplotype_variants)?)
-                    .variant_calls(bcf::Reader::from_path(variant_calls)?)
-                    .xml(xml)
-                    // .max_haplotypes(max_haplotypes)
-                    // .min_norm_counts(min_norm_counts)
-                    .outcsv(output)
-                    .prior(prior)
-                    .common_variants(common_variants)
-                    .lp_cutoff(lp_cutoff)
-                    .enable_equivalence_class_constraint(enable_equivalence_class_constraint)
-                    .extend_haplotypes(extend_haplotypes)
-                    .threshold_equivalence_class(threshold_equivalence_class)
-                    .num_extend_haplotypes(num_extend_haplotypes)
-                    .build()
-                    .unwrap();
-                caller.call()?;
-                Ok(())
+                let config = load_config(
+                    config,
+                    CallerConfig {
+                        prior,
+                        common_variants: flag(common_variants),
+                        lp_cutoff,
+                        enable_equivalence_class_constraint: flag(
+                            enable_equivalence_class_constraint,
+                        ),
+                        extend_haplotypes,
+                        threshold_equivalence_class,
+                        num_extend_haplotypes,
+                        integer_selection: flag(integer_selection),
+                        depth_weighting: flag(depth_weighting),
+                        relax_sum: flag(relax_sum),
+                        max_haplotypes,
+                        extension_overlap,
+                        timeout,
+                        max_events,
+                        missing_afd,
+                        afd_scale,
+                        vaf_denominator,
+                        lp_time_limit,
+                        lp_gap,
+                        sample,
+                        af_field,
+                        error_field,
+                        variant_quality_percentile,
+                        min_odds,
+                        consistency_tolerance,
+                        tie_tolerance,
+                        ..Default::default()
+                    },
+                )?;
+                let call_sample = |variant_calls: &PathBuf,
+                                   outcsv: PathBuf,
+                                   cache: Option<PathBuf>,
+                                   rejected_variants: Option<PathBuf>|
+                 -> Result<()> {
+                    let mut inputs = haplotype_variants.clone();
+                    inputs.push(variant_calls.clone());
+                    let input_cache = match cache {
+                        Some(cache) => Some(calling::haplotypes::haplotypes::InputCache::new(
+                            cache, &inputs,
+                        )?),
+                        None => None,
+                    };
+                    let mut caller = config
+                        .apply_hla(
+                            calling::haplotypes::hla::CallerBuilder::default()
+                                .haplotype_variants(open_bcf(&haplotype_variants[0])?)
+                                .additional_haplotype_variants(haplotype_variants[1..].to_vec())
+                                .variant_calls(open_bcf(variant_calls)?)
+                                .xml(xml.clone())
+                                // .min_norm_counts(min_norm_counts)
+                                .outcsv(outcsv),
+                        )?
+                        .output_prefix(output_prefix.clone())
+                        .long_format(long_format)
+                        .log_densities(log_densities)
+                        .plot_csv(plot_csv)
+                        .combined_plot(combined_plot)
+                        .export_lp(export_lp)
+                        .require_coverage(require_coverage)
+                        .confirmed_only(confirmed_only)
+                        .report(report)
+                        .reference_haplotype(reference_haplotype.clone())
+                        .candidate_band(candidate_band)
+                        .fraction_snap(fraction_snap)
+                        .variant_attribution(variant_attribution)
+                        .read_support(
+                            read_support
+                                .clone()
+                                .map(|field| (variant_calls.clone(), field)),
+                        )
+                        .fit_divergence(fit_divergence)
+                        .presence_concordance(presence_concordance.then(|| variant_calls.clone()))
+                        .distinct_sets(distinct_sets)
+                        .het_odds_band(het_odds_band)
+                        .prior_weights(prior_weights.clone())
+                        .typing_report(typing_report)
+                        .input_cache(input_cache)
+                        .rejected_variants(rejected_variants)
+                        .build()
+                        .unwrap();
+                    caller.call()
+                };
+                if variant_calls.len() == 1 {
+                    call_sample(&variant_calls[0], output, cache, rejected_variants)
+                } else {
+                    //several samples share the panel, but are called one after another
+                    let sample_outputs =
+                        calling::haplotypes::haplotypes::sample_outputs(&variant_calls, &output)?;
+                    for (calls, (sample, outcsv)) in variant_calls.iter().zip(sample_outputs.iter())
+                    {
+                        //each sample has its own cache, e.g. mother_cache.json
+                        let per_sample = |path: &PathBuf| {
+                            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                            path.with_file_name(format!("{}_{}", sample, file_name))
+                        };
+                        call_sample(
+                            calls,
+                            outcsv.clone(),
+                            cache.as_ref().map(per_sample),
+                            rejected_variants.as_ref().map(per_sample),
+                        )?;
+                    }
+                    calling::haplotypes::haplotypes::combine_sample_results(
+                        &sample_outputs,
+                        &output,
+                    )
+                }
             }
             CallKind::Virus {
                 candidates_folder,
@@ -419,18 +1181,116 @@ pub fn run(opt: Orthanq) -> Result<()> {
                 threshold_equivalence_class,
                 threshold_considered_variants,
                 num_extend_haplotypes,
+                integer_selection,
+                depth_weighting,
+                relax_sum,
+                max_haplotypes,
+                extension_overlap,
+                timeout,
+                max_events,
+                config,
+                missing_afd,
+                afd_scale,
+                vaf_denominator,
+                lp_time_limit,
+                lp_gap,
+                sample,
+                af_field,
+                error_field,
+                variant_quality_percentile,
+                min_odds,
+                consistency_tolerance,
+                tie_tolerance,
+                output_prefix,
+                long_format,
+                log_densities,
+                plot_csv,
+                combined_plot,
+                export_lp,
+                require_coverage,
+                report,
+                reference_haplotype,
+                candidate_band,
+                fraction_snap,
+                cache,
+                rejected_variants,
+                variant_attribution,
+                read_support,
+                fit_divergence,
+                presence_concordance,
+                distinct_sets,
+                prior_weights,
             } => {
-                let mut caller = calling::haplotypes::virus::CallerBuilder::default()
-                    .candidates_folder(candidates_folder)
-                    .variant_calls(bcf::Reader::from_path(variant_calls)?)
-                    .outcsv(output)
-                    .prior(prior)
-                    .lp_cutoff(lp_cutoff)
-                    .enable_equivalence_class_constraint(enable_equivalence_class_constraint)
-                    .extend_haplotypes(extend_haplotypes)
-                    .threshold_equivalence_class(threshold_equivalence_class)
-                    .threshold_considered_variants(threshold_considered_variants)
-                    .num_extend_haplotypes(num_extend_haplotypes)
+                let config = load_config(
+                    config,
+                    CallerConfig {
+                        prior,
+                        lp_cutoff,
+                        enable_equivalence_class_constraint: flag(
+                            enable_equivalence_class_constraint,
+                        ),
+                        extend_haplotypes,
+                        threshold_equivalence_class,
+                        threshold_considered_variants,
+                        num_extend_haplotypes,
+                        integer_selection: flag(integer_selection),
+                        depth_weighting: flag(depth_weighting),
+                        relax_sum: flag(relax_sum),
+                        max_haplotypes,
+                        extension_overlap,
+                        timeout,
+                        max_events,
+                        missing_afd,
+                        afd_scale,
+                        vaf_denominator,
+                        lp_time_limit,
+                        lp_gap,
+                        sample,
+                        af_field,
+                        error_field,
+                        variant_quality_percentile,
+                        min_odds,
+                        consistency_tolerance,
+                        tie_tolerance,
+                        ..Default::default()
+                    },
+                )?;
+                let input_cache = match cache {
+                    Some(cache) => Some(calling::haplotypes::haplotypes::InputCache::new(
+                        cache,
+                        &[
+                            candidates_folder.join("candidates.vcf"),
+                            variant_calls.clone(),
+                        ],
+                    )?),
+                    None => None,
+                };
+                let mut caller = config
+                    .apply_virus(
+                        calling::haplotypes::virus::CallerBuilder::default()
+                            .candidates_folder(candidates_folder)
+                            .variant_calls(open_bcf(&variant_calls)?)
+                            .outcsv(output),
+                    )?
+                    .output_prefix(output_prefix)
+                    .long_format(long_format)
+                    .log_densities(log_densities)
+                    .plot_csv(plot_csv)
+                    .combined_plot(combined_plot)
+                    .export_lp(export_lp)
+                    .require_coverage(require_coverage)
+                    .report(report)
+                    .reference_haplotype(reference_haplotype)
+                    .candidate_band(candidate_band)
+                    .fraction_snap(fraction_snap)
+                    .variant_attribution(variant_attribution)
+                    .read_support(read_support.map(|field| (variant_calls.clone(), field)))
+                    .fit_divergence(fit_divergence)
+                    .presence_concordance(presence_concordance.then(|| variant_calls.clone()))
+                    .distinct_sets(distinct_sets)
+                    .prior_weights(prior_weights)
+                    .input_cache(input_cache)
+                    .rejected_variants(rejected_variants)
                     .build()
                     .unwrap();
                 caller.call()?;
@@ -495,6 +1355,18 @@ pub fn run(opt: Orthanq) -> Result<()> {
                 bwa_index,
                 output,
                 threads,
+                genes,
+                region_flank,
+                gene_regions,
+                genome_build,
+                no_pangenome,
+                chromosomes,
+                extra_contigs,
+                coverage_bed,
+                min_mapq,
+                read_fates,
+                scenario,
+                tempdir,
             } => {
                 preprocess::hla::CallerBuilder::default()
                     .genome(genome)
@@ -504,6 +1376,18 @@ pub fn run(opt: Orthanq) -> Result<()> {
                     .haplotype_variants(haplotype_variants)
                     .output(output)
                     .threads(threads)
+                    .genes(genes)
+                    .region_flank(region_flank)
+                    .gene_regions(gene_regions)
+                    .genome_build(genome_build)
+                    .use_pangenome(!no_pangenome)
+                    .chromosomes(chromosomes)
+                    .extra_contigs(extra_contigs)
+                    .coverage_bed(coverage_bed)
+                    .min_mapq(min_mapq)
+                    .read_fates(read_fates)
+                    .scenario(scenario)
+                    .tempdir(tempdir)
                     .build()
                     .unwrap()
                     .call()?;
@@ -528,5 +1412,69 @@ pub fn run(opt: Orthanq) -> Result<()> {
                 Ok(())
             }
         },
+        Orthanq::ListHaplotypes {
+            haplotype_variants,
+            output,
+        } => {
+            let summary = calling::haplotypes::haplotypes::summarize_panel(&mut open_bcf(
+                &haplotype_variants,
+            )?)?;
+            if let Some(output) = output {
+                calling::haplotypes::haplotypes::write_panel_summary(
+                    &summary,
+                    std::fs::File::create(output)?,
+                )?;
+            } else {
+                calling::haplotypes::haplotypes::write_panel_summary(&summary, std::io::stdout())?;
+            }
+            Ok(())
+        }
+        Orthanq::ValidatePanel { haplotype_variants } => {
+            let issues = calling::haplotypes::haplotypes::HaplotypeVariants::validate(
+                &mut open_bcf(&haplotype_variants)?,
+            )?;
+            for issue in issues.iter() {
+                println!("{}", issue);
+            }
+            if issues.is_empty() {
+                println!("no issues found");
+                Ok(())
+            } else {
+                Err(anyhow!("found {} issues in the panel", issues.len()))
+            }
+        }
+        Orthanq::ConvertToG {
+            results,
+            xml,
+            output,
+        } => calling::haplotypes::hla::convert_results_to_g(&results, &xml, &output),
+        Orthanq::DiffResults {
+            before,
+            after,
+            tolerance,
+            output,
+        } => {
+            let diffs = calling::haplotypes::haplotypes::diff_results(&before, &after, tolerance)?;
+            calling::haplotypes::haplotypes::write_results_diff(&output, &diffs)
+        }
+        Orthanq::Benchmark => {
+            println!("{}", benchmark::run()?);
+            Ok(())
+        }
+        Orthanq::SolverProbe => calling::haplotypes::haplotypes::solve_probe(),
+        Orthanq::Selftest => {
+            let results = selftest::run();
+            println!("{}", selftest::summary(&results));
+            let failed = results.iter().filter(|result| !result.passed()).count();
+            if failed > 0 {
+                Err(anyhow!(
+                    "{} of {} self test stages failed",
+                    failed,
+                    results.len()
+                ))
+            } else {
+                Ok(())
+            }
+        }
     }
 }
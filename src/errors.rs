@@ -0,0 +1,73 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+//OrthanqError covers the failure modes that library users may want to handle. Functions still return
+//anyhow::Result, such that the variants can be matched via downcast_ref::<OrthanqError>().
+#[derive(Error, Debug)]
+pub enum OrthanqError {
+    #[error("variant {variant} has no (valid) {field} field")]
+    MissingField { variant: String, field: String },
+    #[error("sample {sample} is not present in the variant calls")]
+    UnknownSample { sample: String },
+    #[error("the candidate variants don't contain any haplotypes")]
+    EmptyPanel,
+    #[error(
+        "the candidate variants lack the FORMAT field C, i.e. whether the haplotypes are characterized at the variant loci"
+    )]
+    MissingCoverage,
+    #[error(
+        "variant ID {variant} occurs in several records of the candidate variants, only one of them would be used"
+    )]
+    DuplicateVariantId { variant: i32 },
+    #[error("max_haplotypes has to be at least 1, found {value}")]
+    InvalidMaxHaplotypes { value: i64 },
+    #[error("haplotype {haplotype} is not part of the final solution")]
+    UnknownHaplotype { haplotype: String },
+    #[error(
+        "none of the called variants (IDs {calls}) is part of the candidate variants (IDs {candidates}), \
+         please check that the calls were made against the given candidates"
+    )]
+    DisjointVariants { calls: String, candidates: String },
+    #[error(
+        "none of the {variants} called variants can be used to constrain the {haplotypes} haplotypes: a variant is only \
+         used if all haplotypes are characterized at it (C field of the candidates) and the sample is covered, \
+         please check the coverage of the candidate variants"
+    )]
+    NoUsableVariants { variants: usize, haplotypes: usize },
+    #[error("the linear program could not be solved: {reason}")]
+    InfeasibleLinearProgram { reason: String },
+    #[error(
+        "the linear program did not finish within the time limit of {seconds} seconds, please increase --lp-time-limit"
+    )]
+    LinearProgramTimeLimit { seconds: f64 },
+    #[error(
+        "the solver of the linear program (CBC) is not usable: {reason}. Please install the CBC library, \
+         e.g. with 'mamba install coin-or-cbc' or 'apt install coinor-libcbc-dev'"
+    )]
+    SolverUnavailable { reason: String },
+    #[error("unknown HLA genes requested: {genes:?}, known genes are: {known:?}")]
+    UnknownGenes {
+        genes: Vec<String>,
+        known: Vec<String>,
+    },
+    #[error("{program} was not found, please make sure that it is installed and in PATH")]
+    ToolNotFound { program: String },
+    #[error("the genome {} can't be used: {reason}", .path.display())]
+    InvalidGenome { path: PathBuf, reason: String },
+    #[error(
+        "the scenario declares the samples {declared:?}, but the observations are passed to varlociraptor as {sample}, \
+         please name the sample of the scenario {sample}"
+    )]
+    ScenarioSampleMismatch {
+        sample: String,
+        declared: Vec<String>,
+    },
+    #[error("failed to read {}", .path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    Htslib(#[from] rust_htslib::errors::Error),
+}
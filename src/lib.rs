@@ -1,5 +1,10 @@
+pub mod benchmark;
 pub mod calling;
 pub mod candidates;
 pub mod cli;
+pub mod errors;
 pub mod model;
+pub mod pipeline;
 pub mod preprocess;
+pub mod selftest;
+pub mod utils;
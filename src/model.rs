@@ -1,16 +1,20 @@
 use crate::calling::haplotypes::haplotypes::{
-    AlleleFreqDist, CandidateMatrix, Haplotype, HaplotypeGraph, PriorTypes, VariantCalls,
-    VariantStatus,
+    expected_vaf, CandidateMatrix, Haplotype, HaplotypeGraph, PriorTypes, VafDenominator,
+    VariantCalls,
 };
 
 use bio::stats::probs::adaptive_integration;
 use bio::stats::{bayesian::model, LogProb, Prob};
-use bv::BitVec;
 use derefable::Derefable;
 use derive_new::new;
 use ordered_float::NotNan;
 use petgraph::visit::Bfs;
+use std::cell::Cell;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
 pub type AlleleFreq = NotNan<f64>;
 
 #[derive(Hash, PartialEq, Eq, Clone, Debug, Derefable, PartialOrd)]
@@ -25,9 +29,219 @@ pub(crate) struct Marginal {
     haplotype_graph: Option<HaplotypeGraph>,
     enable_equivalence_class_constraint: bool,
     application: String,
+    #[new(default)]
+    deadline: Option<Instant>,
+    #[new(default)]
+    evaluated_events: Cell<usize>,
+    #[new(default)]
+    timed_out: Cell<bool>,
+    #[new(default)]
+    max_events: Option<usize>,
+    #[new(default)]
+    capped: Cell<bool>,
+    #[new(default)]
+    log_evidence: Cell<Option<LogProb>>,
 }
 
 impl Marginal {
+    //with_deadline stops the enumeration of events once the deadline has passed.
+    //At least one event is always evaluated, the remaining events are left out of the posteriors.
+    pub(crate) fn with_deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    pub(crate) fn timed_out(&self) -> bool {
+        self.timed_out.get()
+    }
+
+    //with_max_events replaces the full enumeration of the events by a beam search that evaluates at most
+    //max_events events, see calc_marginal_beam. The posteriors are approximate if the search had to be cut (see capped).
+    pub(crate) fn with_max_events(mut self, max_events: Option<usize>) -> Self {
+        self.max_events = max_events;
+        self
+    }
+
+    //capped returns whether the beam search left out events, i.e. whether the posteriors are approximate.
+    pub(crate) fn capped(&self) -> bool {
+        self.capped.get()
+    }
+
+    //evaluated_events is the number of events for which the joint probability has been computed.
+    pub(crate) fn evaluated_events(&self) -> usize {
+        self.evaluated_events.get()
+    }
+
+    //log_evidence is the model evidence of the last computation, see compute.
+    pub(crate) fn log_evidence(&self) -> Option<LogProb> {
+        self.log_evidence.get()
+    }
+
+    //violates_equivalence_class returns whether a fraction greater than 0.0 for the haplotype at the given index is
+    //excluded by the equivalence class constraint (hla only), because a haplotype of the same class (reachable in
+    //the haplotype graph) already has a fraction greater than 0.0 in the preceding fractions.
+    fn violates_equivalence_class(
+        &self,
+        haplotype_index: usize,
+        fractions: &[AlleleFreq],
+        fraction: AlleleFreq,
+    ) -> bool {
+        if self.enable_equivalence_class_constraint
+            && fraction > NotNan::new(0.0).unwrap()
+            && fractions.len() > 1
+        {
+            if self.application == "hla".to_string() {
+                // only if the fraction for the current has greater than 0.0
+                let current_haplotype = &self.haplotypes[haplotype_index];
+                let splitted = &self.haplotypes[haplotype_index]
+                    .split(':')
+                    .collect::<Vec<&str>>();
+                let haplotype_group = Haplotype(splitted[0].to_owned() + &":" + splitted[1]);
+
+                //find the index of the (haplotype, haplotype_group) in graph
+                if let Some(haplotype_graph) = &self.haplotype_graph {
+                    // query node index
+                    let index = haplotype_graph
+                        .get_node_index(&(current_haplotype.clone(), haplotype_group))
+                        .unwrap();
+                    // step through the graph and sum incoming edges into the node weight
+                    let mut bfs = Bfs::new(&**haplotype_graph, index);
+
+                    while let Some(nx) = bfs.next(&**haplotype_graph) {
+                        // we can access `graph` mutably here still
+                        let haplotype_query = &haplotype_graph[nx].0;
+                        for (h, f) in self.haplotypes[0..haplotype_index]
+                            .iter()
+                            .zip(fractions[0..haplotype_index].iter())
+                        {
+                            if (h == haplotype_query) && (f > &NotNan::new(0.0).unwrap()) {
+                                return true;
+                            }
+                        }
+                    }
+                }
+            }
+            // else if self.application == "virus".to_string() {
+            //TODO: explore other methods.
+            // }
+        }
+        false
+    }
+
+    fn deadline_exceeded(&self) -> bool {
+        if !self.timed_out.get() && self.evaluated_events.get() > 0 {
+            if let Some(deadline) = self.deadline {
+                if Instant::now() >= deadline {
+                    self.timed_out.set(true);
+                }
+            }
+        }
+        self.timed_out.get()
+    }
+
+    //grid_points are the fractions that a haplotype can take under the diploid (0.0, 0.5 and 1.0) and the haploid
+    //(0.0 and 1.0) prior, None for the priors that are integrated over (uniform and diploid-subclonal).
+    fn grid_points(&self) -> Option<Vec<AlleleFreq>> {
+        let points: &[f64] = match self.prior_info {
+            PriorTypes::Diploid => &[0.0, 0.5, 1.0],
+            PriorTypes::Haploid => &[0.0, 1.0],
+            _ => return None,
+        };
+        Some(
+            points
+                .iter()
+                .map(|point| NotNan::new(*point).unwrap())
+                .collect(),
+        )
+    }
+
+    //candidate_fractions are the fractions that the next haplotype can take after the given fractions in the beam search:
+    //the remainder for the last haplotype, otherwise the grid points of the diploid and haploid priors (see grid_points)
+    //or, for the uniform and diploid-subclonal priors, steps of 0.1 up to the remainder.
+    fn candidate_fractions(&self, fractions: &[AlleleFreq]) -> Vec<AlleleFreq> {
+        let remainder = self.upper_bond - fractions.iter().sum::<NotNan<f64>>();
+        if fractions.len() == self.n_haplotypes - 1 {
+            vec![remainder]
+        } else if let Some(points) = self.grid_points() {
+            points
+                .into_iter()
+                .filter(|point| *point <= remainder)
+                .collect()
+        } else {
+            (0..=(*remainder * 10.0 + 1e-9).floor() as usize)
+                .map(|step| NotNan::new(step as f64 / 10.0).unwrap())
+                .collect()
+        }
+    }
+
+    //calc_marginal_beam approximates the marginal by a beam search instead of enumerating all events: the haplotypes are
+    //assigned one after another and a partial assignment is scored by the joint probability of its completion (the
+    //remainder assigned to the last haplotype). Only the most promising partial assignments are extended further, such
+    //that at most max_events events are evaluated. The marginal is the sum of the joint probabilities of the evaluated
+    //events, hence the evidence and the posteriors are approximate if the search was cut.
+    fn calc_marginal_beam<
+        F: FnMut(&<Self as model::Marginal>::Event, &<Self as model::Marginal>::Data) -> LogProb,
+    >(
+        &self,
+        data: &Data,
+        max_events: usize,
+        joint_prob: &mut F,
+    ) -> LogProb {
+        //at least one event is always evaluated
+        let max_events = max_events.max(1);
+        let max_branching = self.grid_points().map_or(11, |points| points.len());
+        let beam_width = (max_events / (self.n_haplotypes.max(1) * max_branching)).max(1);
+        let mut evaluated: HashMap<Vec<AlleleFreq>, LogProb> = HashMap::new();
+        let mut beam: Vec<Vec<AlleleFreq>> = vec![Vec::new()];
+        for haplotype_index in 0..self.n_haplotypes {
+            let mut extended: Vec<(Vec<AlleleFreq>, LogProb)> = Vec::new();
+            for fractions in beam.iter() {
+                for fraction in self.candidate_fractions(fractions) {
+                    if self.violates_equivalence_class(haplotype_index, fractions, fraction) {
+                        continue;
+                    }
+                    let mut partial = fractions.clone();
+                    partial.push(fraction);
+                    //complete the partial assignment by assigning the remainder to the last haplotype
+                    let mut event = partial.clone();
+                    event.resize(self.n_haplotypes, NotNan::new(0.0).unwrap());
+                    let last = self.n_haplotypes - 1;
+                    if partial.len() < self.n_haplotypes {
+                        event[last] = self.upper_bond - partial.iter().sum::<NotNan<f64>>();
+                        if self.violates_equivalence_class(last, &event[..last], event[last]) {
+                            extended.push((partial, LogProb::ln_zero()));
+                            continue;
+                        }
+                    }
+                    let probability = match evaluated.get(&event) {
+                        Some(probability) => *probability,
+                        None => {
+                            if evaluated.len() >= max_events || self.deadline_exceeded() {
+                                self.capped.set(true);
+                                continue;
+                            }
+                            self.evaluated_events.set(self.evaluated_events.get() + 1);
+                            let probability = joint_prob(&HaplotypeFractions(event.clone()), data);
+                            evaluated.insert(event, probability);
+                            probability
+                        }
+                    };
+                    extended.push((partial, probability));
+                }
+            }
+            if extended.len() > beam_width {
+                self.capped.set(true);
+            }
+            extended.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+            beam = extended
+                .into_iter()
+                .take(beam_width)
+                .map(|(partial, _)| partial)
+                .collect();
+        }
+        LogProb::ln_sum_exp(&evaluated.values().cloned().collect::<Vec<LogProb>>())
+    }
+
     pub(crate) fn calc_marginal<
         F: FnMut(&<Self as model::Marginal>::Event, &<Self as model::Marginal>::Data) -> LogProb,
     >(
@@ -37,52 +251,18 @@ impl Marginal {
         fractions: &mut [AlleleFreq],
         joint_prob: &mut F,
     ) -> LogProb {
+        if self.deadline_exceeded() {
+            return LogProb::ln_zero();
+        }
         if haplotype_index == self.n_haplotypes {
             let event = HaplotypeFractions(fractions.to_vec());
+            self.evaluated_events.set(self.evaluated_events.get() + 1);
             joint_prob(&event, data)
         } else {
             let fraction_upper_bound = self.upper_bond - fractions.iter().sum::<NotNan<f64>>();
             let mut density = |fraction| {
-                if self.enable_equivalence_class_constraint
-                    && fraction > NotNan::new(0.0).unwrap()
-                    && fractions.len() > 1
-                {
-                    if self.application == "hla".to_string() {
-                        // only if the fraction for the current has greater than 0.0
-                        let current_haplotype = &self.haplotypes[haplotype_index];
-                        let splitted = &self.haplotypes[haplotype_index]
-                            .split(':')
-                            .collect::<Vec<&str>>();
-                        let haplotype_group =
-                            Haplotype(splitted[0].to_owned() + &":" + splitted[1]);
-
-                        //find the index of the (haplotype, haplotype_group) in graph
-                        if let Some(haplotype_graph) = &self.haplotype_graph {
-                            // query node index
-                            let index = haplotype_graph
-                                .get_node_index(&(current_haplotype.clone(), haplotype_group))
-                                .unwrap();
-                            // step through the graph and sum incoming edges into the node weight
-                            let mut bfs = Bfs::new(&**haplotype_graph, index);
-
-                            while let Some(nx) = bfs.next(&**haplotype_graph) {
-                                // we can access `graph` mutably here still
-                                let haplotype_query = &haplotype_graph[nx].0;
-                                for (h, f) in self.haplotypes[0..haplotype_index]
-                                    .to_vec()
-                                    .iter()
-                                    .zip(fractions[0..haplotype_index].to_vec().iter())
-                                {
-                                    if (h == haplotype_query) && (f > &NotNan::new(0.0).unwrap()) {
-                                        return LogProb::ln_zero();
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    // else if self.application == "virus".to_string() {
-                    //TODO: explore other methods.
-                    // }
+                if self.violates_equivalence_class(haplotype_index, fractions, fraction) {
+                    return LogProb::ln_zero();
                 }
                 // dbg!(&fractions);
                 let mut fractions = fractions.to_vec();
@@ -97,23 +277,17 @@ impl Marginal {
                     density(NotNan::new(0.0).unwrap())
                 } else {
                     //check prior info
-                    if self.prior_info == PriorTypes::Diploid {
-                        //sum 0.0, 0.5 and 1.0
+                    if let Some(points) = self.grid_points() {
+                        //sum over the grid points, i.e. 0.0, 0.5 and 1.0 (diploid) or 0.0 and 1.0 (haploid)
                         let mut probs = Vec::new();
-                        let mut diploid_points = |point, probs: &mut Vec<_>| {
-                            let fractions = fractions.to_vec();
+                        for point in points {
+                            // this check is necessary to avoid combinations that sum up to more than 1.0.
                             if fractions.iter().sum::<NotNan<f64>>() + point
                                 <= NotNan::new(1.0).unwrap()
                             {
-                                // this check is necessary to avoid combinations that sum up to more than 1.0.
                                 probs.push(density(point));
-                            } else {
-                                ()
                             }
-                        };
-                        diploid_points(NotNan::new(0.0).unwrap(), &mut probs);
-                        diploid_points(NotNan::new(0.5).unwrap(), &mut probs);
-                        diploid_points(NotNan::new(1.0).unwrap(), &mut probs);
+                        }
                         LogProb::ln_sum_exp(&probs)
                     } else if self.prior_info == PriorTypes::Uniform
                         || self.prior_info == PriorTypes::DiploidSubclonal
@@ -125,7 +299,9 @@ impl Marginal {
                             NotNan::new(0.1).unwrap(),
                         )
                     } else {
-                        panic!("uniform, prior or diploid-subclonal must be selected")
+                        panic!(
+                            "uniform, diploid, diploid-subclonal or haploid prior must be selected"
+                        )
                     }
                 }
             }
@@ -138,68 +314,177 @@ impl model::Marginal for Marginal {
     type Data = Data;
     type BaseEvent = HaplotypeFractions;
 
+    //compute returns the model evidence (marginal likelihood), i.e. the joint probability of likelihood and prior
+    //summed (diploid and haploid priors) or integrated (uniform and diploid-subclonal priors) over all events.
+    //This is the normalization constant of the posteriors, it is kept to allow comparing models on the same data.
     fn compute<F: FnMut(&Self::Event, &Self::Data) -> LogProb>(
         &self,
         data: &Self::Data,
         joint_prob: &mut F,
     ) -> LogProb {
-        let mut fractions: Vec<AlleleFreq> = Vec::new();
-        self.calc_marginal(data, 0, &mut fractions, joint_prob)
+        let log_evidence = match self.max_events {
+            Some(max_events) => self.calc_marginal_beam(data, max_events, joint_prob),
+            None => {
+                let mut fractions: Vec<AlleleFreq> = Vec::new();
+                self.calc_marginal(data, 0, &mut fractions, joint_prob)
+            }
+        };
+        self.log_evidence.set(Some(log_evidence));
+        log_evidence
     }
 }
 
+//log_evidence computes the model evidence of the given haplotypes for the data, without equivalence class constraint.
+//The evidence of two candidate panels can be compared for the same variant calls and prior (Bayes factor),
+//higher evidence means that the panel explains the calls better.
+pub fn log_evidence(data: &Data, haplotypes: &[Haplotype], prior: PriorTypes) -> LogProb {
+    let model = model::Model::new(
+        Likelihood::new(Arc::new(VarlociraptorLikelihood::default())),
+        Prior::new(prior.clone()),
+        Posterior::new(),
+    );
+    let marginal = Marginal::new(
+        haplotypes.len(),
+        haplotypes.to_vec(),
+        NotNan::new(1.0).unwrap(),
+        prior,
+        None,
+        false,
+        "hla".to_string(),
+    );
+    model.compute_from_marginal(&marginal, data);
+    marginal.log_evidence().unwrap()
+}
+
 #[derive(Debug, new)]
 pub struct Data {
     pub candidate_matrix: CandidateMatrix,
     pub variant_calls: VariantCalls,
 }
 
-#[derive(Debug, new)]
-pub(crate) struct Likelihood;
+//event_posteriors computes the posteriors of all events of the given haplotypes for the data with the given
+//observation model, without equivalence class constraint. The posteriors are normalized by the model evidence.
+pub fn event_posteriors(
+    data: &Data,
+    haplotypes: &[Haplotype],
+    prior: PriorTypes,
+    observation_model: Arc<dyn ObservationModel>,
+) -> Vec<(HaplotypeFractions, LogProb)> {
+    weighted_event_posteriors(data, haplotypes, prior, &[], observation_model)
+}
+
+//weighted_event_posteriors is event_posteriors with the prior weighted by the population frequencies of the
+//haplotypes (prior_weights, in the order of the haplotypes), see Prior::with_weights.
+pub fn weighted_event_posteriors(
+    data: &Data,
+    haplotypes: &[Haplotype],
+    prior: PriorTypes,
+    prior_weights: &[f64],
+    observation_model: Arc<dyn ObservationModel>,
+) -> Vec<(HaplotypeFractions, LogProb)> {
+    event_posteriors_with_options(
+        data,
+        haplotypes,
+        prior,
+        &ModelOptions {
+            prior_weights: prior_weights.to_vec(),
+            ..Default::default()
+        },
+        observation_model,
+    )
+}
+
+//ModelOptions are the optional settings of the model computation: the weights of the prior (see Prior::with_weights)
+//and the maximum number of evaluated events, above which the events are searched approximately (see
+//Marginal::with_max_events).
+#[derive(Debug, Clone, Default)]
+pub struct ModelOptions {
+    pub prior_weights: Vec<f64>,
+    pub max_events: Option<usize>,
+}
+
+//event_posteriors_with_options is event_posteriors with the given model options.
+pub fn event_posteriors_with_options(
+    data: &Data,
+    haplotypes: &[Haplotype],
+    prior: PriorTypes,
+    options: &ModelOptions,
+    observation_model: Arc<dyn ObservationModel>,
+) -> Vec<(HaplotypeFractions, LogProb)> {
+    let model = model::Model::new(
+        Likelihood::new(observation_model),
+        Prior::new(prior.clone()).with_weights(options.prior_weights.clone()),
+        Posterior::new(),
+    );
+    let marginal = Marginal::new(
+        haplotypes.len(),
+        haplotypes.to_vec(),
+        NotNan::new(1.0).unwrap(),
+        prior,
+        None,
+        false,
+        "hla".to_string(),
+    )
+    .with_max_events(options.max_events);
+    model
+        .compute_from_marginal(&marginal, data)
+        .event_posteriors()
+        .map(|(fractions, logprob)| (fractions.clone(), logprob.clone()))
+        .collect()
+}
+
+//ObservationModel is the likelihood of an event (the fractions of the haplotypes, in the order of the haplotypes
+//given to the model) for the data, i.e. the candidate matrix and the variant calls. It is evaluated once per event,
+//hence it has to be deterministic and must not depend on the order of evaluation. The returned log probability doesn't
+//need to be normalized over the events, as the posteriors are normalized by the model evidence. Returning ln_zero
+//excludes the event. The callers use VarlociraptorLikelihood unless another observation model is given, e.g. to
+//account for mapping bias or overdispersion.
+pub trait ObservationModel: fmt::Debug {
+    fn compute(&self, event: &HaplotypeFractions, data: &Data) -> LogProb;
+}
+
+#[derive(Debug)]
+pub(crate) struct Likelihood {
+    observation_model: Arc<dyn ObservationModel>,
+}
+
+impl Likelihood {
+    pub(crate) fn new(observation_model: Arc<dyn ObservationModel>) -> Self {
+        Likelihood { observation_model }
+    }
+}
 
 impl model::Likelihood<Cache> for Likelihood {
     type Event = HaplotypeFractions;
     type Data = Data;
 
-    fn compute(&self, event: &Self::Event, data: &Self::Data, payload: &mut Cache) -> LogProb {
-        // self.compute_kallisto(event, data, payload) + //comment it out for now
-        self.compute_varlociraptor(event, data, payload)
+    fn compute(&self, event: &Self::Event, data: &Self::Data, _payload: &mut Cache) -> LogProb {
+        self.observation_model.compute(event, data)
     }
 }
 
-impl Likelihood {
-    fn compute_varlociraptor(
-        &self,
-        event: &HaplotypeFractions,
-        data: &Data,
-        _cache: &mut Cache,
-    ) -> LogProb {
-        let candidate_matrix_values: Vec<(Vec<VariantStatus>, BitVec)> =
-            data.candidate_matrix.values().cloned().collect();
-        let variant_calls: Vec<AlleleFreqDist> = data
-            .variant_calls
-            .iter()
-            .map(|(_, (_, afd))| afd.clone())
-            .collect();
+//VarlociraptorLikelihood is the default observation model: per variant, the expected allele frequency of the event
+//(see expected_vaf, normalized with the given VafDenominator) is queried in the allele frequency distribution of the
+//call (AFD), the likelihood is the product over all variants.
+#[derive(Debug, Clone, Copy, Default, new)]
+pub struct VarlociraptorLikelihood {
+    denominator: VafDenominator,
+}
+
+impl ObservationModel for VarlociraptorLikelihood {
+    fn compute(&self, event: &HaplotypeFractions, data: &Data) -> LogProb {
         let mut final_prob = LogProb::ln_one();
-        candidate_matrix_values
+        //join the candidate matrix and the calls by variant, variants that are missing in either of them are skipped
+        data.candidate_matrix
             .iter()
-            .zip(variant_calls.iter())
+            .filter_map(|(variant_id, candidates)| {
+                data.variant_calls
+                    .get(variant_id)
+                    .map(|(_, afd)| (candidates, afd))
+            })
             .for_each(|((genotypes, covered), afd)| {
-                let mut denom = NotNan::new(1.0).unwrap();
-                let mut vaf_sum = NotNan::new(0.0).unwrap();
-                event.iter().enumerate().for_each(|(i, fraction)| {
-                    if genotypes[i] == VariantStatus::Present && covered[i as u64] {
-                        vaf_sum += *fraction;
-                    } else if genotypes[i] == VariantStatus::Unknown || covered[i as u64] {
-                        ()
-                    } else if genotypes[i] == VariantStatus::NotPresent && !covered[i as u64] {
-                        denom -= *fraction;
-                    }
-                });
-                if denom > NotNan::new(0.0).unwrap() {
-                    vaf_sum /= denom;
-                }
+                let mut vaf_sum = expected_vaf(event, genotypes, covered, self.denominator)
+                    .unwrap_or_else(|| NotNan::new(0.0).unwrap());
                 //to overcome a bug that results in larger than 1.0 VAF. After around 10 - 15th decimal place, the value becomes larger.
                 //In any case, for a direct query to the AFD VAFs (they contain 2 decimal places).
                 vaf_sum = NotNan::new((vaf_sum * NotNan::new(100.0).unwrap()).round()).unwrap()
@@ -218,12 +503,39 @@ impl Likelihood {
 #[derive(Debug, new)]
 pub(crate) struct Prior {
     prior: PriorTypes,
+    #[new(default)]
+    weights: Vec<f64>,
 }
 
 impl model::Prior for Prior {
     type Event = HaplotypeFractions;
 
     fn compute(&self, event: &Self::Event) -> LogProb {
+        self.compute_fractions(event) + self.compute_weights(event)
+    }
+}
+
+impl Prior {
+    //with_weights weights the prior of an event by the population frequencies of the haplotypes, given in the order
+    //of the haplotypes of the model. The weights multiply the fraction prior: each haplotype with a fraction greater
+    //than 0.0 contributes the factor of its weight, hence among events that explain the data equally well, the ones
+    //made of common haplotypes are favored. Without weights, the prior is the fraction prior alone.
+    pub(crate) fn with_weights(mut self, weights: Vec<f64>) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    fn compute_weights(&self, event: &HaplotypeFractions) -> LogProb {
+        let mut weight_prob = LogProb::ln_one();
+        event
+            .iter()
+            .zip(self.weights.iter())
+            .filter(|(fraction, _)| **fraction > NotNan::new(0.0).unwrap())
+            .for_each(|(_, weight)| weight_prob += LogProb::from(Prob(*weight)));
+        weight_prob
+    }
+
+    fn compute_fractions(&self, event: &HaplotypeFractions) -> LogProb {
         if self.prior == PriorTypes::Diploid {
             let mut prior_prob = LogProb::ln_one();
             event.iter().for_each(|fraction| {
@@ -237,6 +549,15 @@ impl model::Prior for Prior {
                 }
             });
             prior_prob
+        } else if self.prior == PriorTypes::Haploid {
+            //haploid prior: all events with a single haplotype at 1.0 are equally likely
+            if event.iter().all(|fraction| {
+                *fraction == NotNan::new(0.0).unwrap() || *fraction == NotNan::new(1.0).unwrap()
+            }) {
+                LogProb::ln_one()
+            } else {
+                LogProb::ln_zero()
+            }
         } else if self.prior == PriorTypes::DiploidSubclonal {
             //diploid subclonal prior: don't allow for more than 4 fractions bearing greater than 0.0
             if event
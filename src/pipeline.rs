@@ -0,0 +1,94 @@
+use crate::calling::haplotypes::config::CallerConfig;
+use crate::calling::haplotypes::hla::{self, ReportResolution, TypingReportRow};
+use crate::preprocess;
+use crate::utils::open_bcf;
+use anyhow::{anyhow, Result};
+use derive_builder::Builder;
+use std::fs;
+use std::path::PathBuf;
+
+//Pipeline types a single HLA gene of an aligned sample (BAM or CRAM, coordinate sorted and indexed) in one go:
+//the reads of the gene are extracted, optionally realigned to the pangenome (if a vg index is given), called with
+//varlociraptor (see preprocess::hla::Caller) and the haplotypes are called on the calls (see calling::haplotypes::hla::Caller)
+//with the default parameters of the config file. The results, their G group conversion and the typing report are
+//written to outdir.
+#[derive(Builder, Clone)]
+pub struct Pipeline {
+    alignment: PathBuf,
+    gene: String,
+    genome: PathBuf,
+    haplotype_variants: PathBuf,
+    xml: PathBuf,
+    outdir: PathBuf,
+    #[builder(default)]
+    vg_index: Option<PathBuf>,
+    #[builder(default = "\"diploid\".to_string()")]
+    prior: String,
+    #[builder(default = "\"1\".to_string()")]
+    threads: String,
+    #[builder(default = "ReportResolution::GGroup")]
+    resolution: ReportResolution,
+}
+
+//CallResult is the outcome of a pipeline run: the variant calls of the sample, the haplotype results and the rows of
+//the typing report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallResult {
+    pub sample: String,
+    pub calls: PathBuf,
+    pub results: PathBuf,
+    pub typing: Vec<TypingReportRow>,
+}
+
+impl Pipeline {
+    pub fn call(&self) -> Result<CallResult> {
+        if !preprocess::hla::is_alignment(&self.alignment) {
+            return Err(anyhow!(
+                "the pipeline needs an aligned sample (BAM or CRAM), found {}",
+                self.alignment.display()
+            ));
+        }
+        fs::create_dir_all(&self.outdir)?;
+        let sample = preprocess::hla::aligned_sample_name(&self.alignment);
+
+        let preprocessed = preprocess::hla::CallerBuilder::default()
+            .genome(self.genome.clone())
+            .reads(vec![self.alignment.clone()])
+            .haplotype_variants(self.haplotype_variants.clone())
+            .bwa_index(None)
+            .vg_index(self.vg_index.clone())
+            .use_pangenome(self.vg_index.is_some())
+            .output(self.outdir.join(format!("{}.bcf", sample)))
+            .threads(self.threads.clone())
+            .genes(vec![self.gene.clone()])
+            .build()
+            .map_err(|error| anyhow!(error))?
+            .call()?;
+
+        let results = self.outdir.join(format!("{}.csv", sample));
+        let config = CallerConfig {
+            prior: Some(self.prior.clone()),
+            extend_haplotypes: Some(false),
+            ..Default::default()
+        };
+        config
+            .apply_hla(
+                hla::CallerBuilder::default()
+                    .haplotype_variants(open_bcf(&self.haplotype_variants)?)
+                    .variant_calls(open_bcf(&preprocessed.calls)?)
+                    .xml(self.xml.clone())
+                    .outcsv(results.clone()),
+            )?
+            .typing_report(Some(self.resolution))
+            .build()
+            .map_err(|error| anyhow!(error))?
+            .call()?;
+
+        Ok(CallResult {
+            sample: preprocessed.sample,
+            calls: preprocessed.calls,
+            results,
+            typing: hla::read_typing_report(&self.outdir.join("typing_report.tsv"))?,
+        })
+    }
+}
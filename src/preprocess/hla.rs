@@ -1,11 +1,18 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use derive_builder::Builder;
 
+use crate::errors::OrthanqError;
+use crate::preprocess::telemetry::Telemetry;
+use crate::preprocess::{check_scenario, CoverageSummary, PreprocessOutput, OBS_SAMPLE};
+use crate::utils::{index_bcf, prepare_genome, require_tools};
 use csv::ReaderBuilder;
+use rust_htslib::bam::{self, Read};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use tempfile::tempdir;
 use tempfile::NamedTempFile;
@@ -16,13 +23,465 @@ pub struct Caller {
     reads: Vec<PathBuf>,
     haplotype_variants: PathBuf,
     bwa_index: Option<PathBuf>,
-    vg_index: PathBuf,
+    #[builder(default)]
+    vg_index: Option<PathBuf>,
     output: PathBuf,
     threads: String,
+    #[builder(default)]
+    genes: Vec<String>,
+    #[builder(default)]
+    region_flank: u64,
+    //if false, the realignment of the extracted reads to the pangenome (vg giraffe) is skipped and
+    //the reads extracted from the linear alignment are used for varlociraptor. This doesn't require a
+    //pangenome index, but reads of the HLA genes are more often misplaced or mapped with a low quality
+    //in the linear genome, which can reduce the accuracy of the typing.
+    #[builder(default = "true")]
+    use_pangenome: bool,
+    //chromosomes (without "chr" prefix, e.g. 6) that are kept after the realignment to the pangenome,
+    //the standard chromosomes if empty (see final_chromosomes)
+    #[builder(default)]
+    chromosomes: Vec<String>,
+    //additional contigs that are kept as named in the reference, e.g. alternative contigs carrying HLA reads
+    #[builder(default)]
+    extra_contigs: Vec<String>,
+    //if true, the intervals covered by the reads of the final bam are written to {sample}_coverage.bed next to the output
+    #[builder(default)]
+    coverage_bed: bool,
+    //minimum mapping quality of the reads extracted from the linear alignment (samtools view -q), no filter if None
+    #[builder(default)]
+    min_mapq: Option<u8>,
+    //if true, the fate of each extracted read (whether and where it ended up in the final bam) is written to
+    //{sample}_read_fates.tsv next to the output, see read_fates
+    #[builder(default)]
+    read_fates: bool,
+    //table of the gene regions to use instead of the bundled one (see GeneRegions), and the genome build to take from it
+    #[builder(default)]
+    gene_regions: Option<PathBuf>,
+    #[builder(default)]
+    genome_build: Option<String>,
+    //varlociraptor scenario to use instead of the bundled one (resources/scenarios/scenario.yaml), it has to declare
+    //the sample OBS_SAMPLE
+    #[builder(default)]
+    scenario: Option<PathBuf>,
+    //directory under which the working directory of the intermediate files is created, e.g. a large scratch
+    //filesystem for deep samples, the system temp directory if None
+    #[builder(default)]
+    tempdir: Option<PathBuf>,
+}
+
+//DEFAULT_GENOME_BUILD is the genome build of the bundled gene regions and the one used if none is requested.
+pub const DEFAULT_GENOME_BUILD: &str = "GRCh38";
+
+//GeneRegion is the region (start, end) of an HLA gene, with the chromosome named in Ensembl style (e.g. 6).
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneRegion {
+    pub gene: String,
+    pub chromosome: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+//GeneRegions is the table of the HLA gene regions of one genome build. The bundled table (resources/hla_gene_regions.tsv)
+//holds the classical and nonclassical genes on GRCh38. It can be replaced by a table of the same format, i.e. a TSV with
+//the columns build, gene, chromosome, start and end, such that genes can be added or other builds used without recompiling.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneRegions(Vec<GeneRegion>);
+
+impl GeneRegions {
+    pub fn bundled() -> Self {
+        Self::parse(
+            include_str!("../../resources/hla_gene_regions.tsv"),
+            DEFAULT_GENOME_BUILD,
+        )
+        .expect("the bundled gene regions are valid")
+    }
+
+    pub fn from_path(path: &Path, build: &str) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|source| OrthanqError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::parse(&content, build)
+    }
+
+    //parse reads the regions of the given build, the rows of other builds are skipped.
+    fn parse(content: &str, build: &str) -> Result<Self> {
+        let mut reader = ReaderBuilder::new()
+            .delimiter(b'\t')
+            .from_reader(content.as_bytes());
+        let mut regions = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            if record.len() < 5 {
+                return Err(anyhow!(
+                    "gene regions need the columns build, gene, chromosome, start and end, found {:?}",
+                    record
+                ));
+            }
+            if &record[0] != build {
+                continue;
+            }
+            regions.push(GeneRegion {
+                gene: record[1].trim_start_matches("HLA-").to_string(),
+                chromosome: record[2].trim_start_matches("chr").to_string(),
+                start: record[3].parse()?,
+                end: record[4].parse()?,
+            });
+        }
+        if regions.is_empty() {
+            return Err(anyhow!(
+                "the gene regions don't contain any gene of the genome build {}",
+                build
+            ));
+        }
+        Ok(GeneRegions(regions))
+    }
+
+    pub fn get(&self, gene: &str) -> Option<&GeneRegion> {
+        self.0.iter().find(|region| region.gene == gene)
+    }
+
+    pub fn genes(&self) -> Vec<String> {
+        self.0.iter().map(|region| region.gene.clone()).collect()
+    }
+
+    //bed generates the BED content for the requested genes (e.g. A or HLA-A), all genes if none are given.
+    //chr_naming is either "ucsc" (chr6) or "ensembl" (6). Each region is padded by 'flank' bases on both sides,
+    //such that reads at the gene boundaries (e.g. in UTRs or soft-clipped) are extracted as well. Starts are clamped at 0.
+    pub fn bed(&self, genes: &[String], chr_naming: &str, flank: u64) -> Result<String> {
+        let requested = genes
+            .iter()
+            .map(|gene| gene.trim_start_matches("HLA-").to_string())
+            .collect::<Vec<String>>();
+        let unknown = requested
+            .iter()
+            .filter(|gene| self.get(gene).is_none())
+            .collect::<Vec<&String>>();
+        if !unknown.is_empty() {
+            return Err(OrthanqError::UnknownGenes {
+                genes: unknown.into_iter().cloned().collect(),
+                known: self.genes(),
+            }
+            .into());
+        }
+        let mut bed = String::new();
+        for region in self.0.iter() {
+            if requested.is_empty() || requested.iter().any(|gene| *gene == region.gene) {
+                let chromosome = if chr_naming == "ucsc" {
+                    format!("chr{}", region.chromosome)
+                } else {
+                    region.chromosome.clone()
+                };
+                bed.push_str(&format!(
+                    "{}\t{}\t{}\n",
+                    chromosome,
+                    region.start.saturating_sub(flank),
+                    region.end + flank
+                ));
+            }
+        }
+        Ok(bed)
+    }
+}
+
+//gene_region returns the region (start, end) of the given gene (e.g. A) on chromosome 6 in the bundled gene regions.
+pub fn gene_region(gene: &str) -> Option<(u64, u64)> {
+    GeneRegions::bundled()
+        .get(gene)
+        .map(|region| (region.start, region.end))
+}
+
+//regions_bed generates the BED content for the requested genes from the bundled gene regions, see GeneRegions::bed.
+pub fn regions_bed(genes: &[String], chr_naming: &str, flank: u64) -> Result<String> {
+    GeneRegions::bundled().bed(genes, chr_naming, flank)
+}
+
+//STANDARD_CHROMOSOMES are kept after the realignment to the pangenome, unless other chromosomes are requested.
+pub const STANDARD_CHROMOSOMES: [&str; 25] = [
+    "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13", "14", "15", "16", "17",
+    "18", "19", "20", "21", "22", "X", "Y", "M",
+];
+
+//final_chromosomes returns the contigs that are extracted in the final step: the given chromosomes (the standard
+//chromosomes if none are given), prefixed with "chr" for the ucsc naming, followed by the extra contigs as they are.
+pub fn final_chromosomes(
+    chromosomes: &[String],
+    extra_contigs: &[String],
+    chr_naming: &str,
+) -> Vec<String> {
+    let chromosomes = if chromosomes.is_empty() {
+        STANDARD_CHROMOSOMES
+            .iter()
+            .map(|chromosome| chromosome.to_string())
+            .collect()
+    } else {
+        chromosomes.to_vec()
+    };
+    chromosomes
+        .iter()
+        .map(|chromosome| {
+            let chromosome = chromosome.trim_start_matches("chr");
+            if chr_naming == "ucsc" {
+                format!("chr{}", chromosome)
+            } else {
+                chromosome.to_string()
+            }
+        })
+        .chain(extra_contigs.iter().cloned())
+        .collect()
+}
+
+//is_alignment checks whether the reads are given as an alignment (BAM or CRAM) instead of FASTQ.
+pub fn is_alignment(reads: &Path) -> bool {
+    matches!(
+        reads.extension().and_then(OsStr::to_str),
+        Some("bam") | Some("cram")
+    )
+}
+
+//aligned_sample_name is the sample name of an aligned sample, the file stem up to the first underscore as for the
+//FASTQ files, e.g. NA12878 for NA12878_sorted.cram.
+pub fn aligned_sample_name(alignment: &Path) -> String {
+    alignment
+        .file_stem()
+        .and_then(OsStr::to_str)
+        .unwrap_or_default()
+        .split('_')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+//extraction_args are the arguments of samtools for the extraction of the reads in the regions from the sorted alignment.
+//Reads with a mapping quality below min_mapq (e.g. multi-mapping reads of the repetitive HLA region) are left out.
+pub fn extraction_args(
+    bam: &Path,
+    regions: &Path,
+    output: &Path,
+    min_mapq: Option<u8>,
+) -> Vec<OsString> {
+    let mut args: Vec<OsString> = vec!["view".into(), bam.into(), "-L".into(), regions.into()];
+    if let Some(min_mapq) = min_mapq {
+        args.push("-q".into());
+        args.push(min_mapq.to_string().into());
+    }
+    args.extend(vec![
+        "--write-index".into(), //??
+        "-o".into(),
+        output.into(),
+    ]);
+    args
+}
+
+//covered_intervals returns the intervals (contig, 0-based start, exclusive end) that are covered by at least one mapped read
+//of the given bam (or sam) file, merging overlapping and adjacent alignments. They are sorted as the contigs in the header.
+pub fn covered_intervals(bam: &Path) -> Result<Vec<(String, i64, i64)>> {
+    let mut reader = bam::Reader::from_path(bam)?;
+    let mut alignments: BTreeMap<i32, Vec<(i64, i64)>> = BTreeMap::new();
+    for record_result in reader.records() {
+        let record = record_result?;
+        if record.is_unmapped() || record.tid() < 0 {
+            continue;
+        }
+        alignments
+            .entry(record.tid())
+            .or_default()
+            .push((record.pos(), record.cigar().end_pos()));
+    }
+    let mut intervals = Vec::new();
+    for (tid, mut alignments) in alignments {
+        let contig = String::from_utf8(reader.header().tid2name(tid as u32).to_vec())?;
+        alignments.sort();
+        let mut current: Option<(i64, i64)> = None;
+        for (start, end) in alignments {
+            current = match current {
+                Some((current_start, current_end)) if start <= current_end => {
+                    Some((current_start, current_end.max(end)))
+                }
+                Some((current_start, current_end)) => {
+                    intervals.push((contig.clone(), current_start, current_end));
+                    Some((start, end))
+                }
+                None => Some((start, end)),
+            };
+        }
+        if let Some((start, end)) = current {
+            intervals.push((contig.clone(), start, end));
+        }
+    }
+    Ok(intervals)
+}
+
+//FastqRecord is a read as written to fastq: name, sequence and quality (PHRED+33) in the orientation of the sequencing.
+type FastqRecord = (String, Vec<u8>, Vec<u8>);
+
+fn fastq_record(record: &bam::Record) -> Result<FastqRecord> {
+    let name = String::from_utf8(record.qname().to_vec())?;
+    let mut seq = record.seq().as_bytes();
+    //missing qualities (0xff) are written as the default quality of samtools fastq (1, i.e. '"')
+    let mut qual: Vec<u8> = record
+        .qual()
+        .iter()
+        .map(|q| match q {
+            0xff => b'"',
+            q => q.saturating_add(33),
+        })
+        .collect();
+    if record.is_reverse() {
+        seq = bio::alphabets::dna::revcomp(seq);
+        qual.reverse();
+    }
+    Ok((name, seq, qual))
+}
+
+fn write_fastq_record(file: &mut File, (name, seq, qual): &FastqRecord) -> Result<()> {
+    file.write_all(b"@")?;
+    file.write_all(name.as_bytes())?;
+    file.write_all(b"\n")?;
+    file.write_all(seq)?;
+    file.write_all(b"\n+\n")?;
+    file.write_all(qual)?;
+    file.write_all(b"\n")?;
+    Ok(())
+}
+
+//write_paired_fastq converts the read pairs of the bam into two fastq files (first and second mates in the same order), like
+//'samtools fastq -n': the read names are kept exactly as in the bam, without /1 and /2 suffixes, such that they survive the
+//realignment to the pangenome. The mates are paired up by name, hence the bam doesn't need to be sorted by name.
+//Secondary and supplementary alignments and reads whose mate is not part of the bam are skipped. Returns the number of pairs.
+pub fn write_paired_fastq(bam: &Path, fastq_1: &Path, fastq_2: &Path) -> Result<usize> {
+    let mut reader = bam::Reader::from_path(bam)?;
+    let mut order: Vec<String> = Vec::new();
+    let mut mates: HashMap<String, (Option<FastqRecord>, Option<FastqRecord>)> = HashMap::new();
+    for record_result in reader.records() {
+        let record = record_result?;
+        if !record.is_paired() || record.is_secondary() || record.is_supplementary() {
+            continue;
+        }
+        let read = fastq_record(&record)?;
+        let entry = mates.entry(read.0.clone()).or_insert_with(|| {
+            order.push(read.0.clone());
+            (None, None)
+        });
+        if record.is_first_in_template() {
+            entry.0 = Some(read);
+        } else {
+            entry.1 = Some(read);
+        }
+    }
+    let mut file_1 = File::create(fastq_1)?;
+    let mut file_2 = File::create(fastq_2)?;
+    let mut pairs = 0;
+    for name in order {
+        if let Some((Some(first), Some(second))) = mates.remove(&name) {
+            write_fastq_record(&mut file_1, &first)?;
+            write_fastq_record(&mut file_2, &second)?;
+            pairs += 1;
+        }
+    }
+    file_1.flush()?;
+    file_2.flush()?;
+    Ok(pairs)
+}
+
+//read_fates traces the reads extracted from the linear alignment (by name, in the order of the extraction) to the final bam:
+//the position (contig and 1-based start) of their first primary alignment in the final bam, None if they got lost
+//(e.g. unmapped in the pangenome or on a contig that is not kept). Reads that were not extracted don't appear.
+pub fn read_fates(extracted: &Path, final_bam: &Path) -> Result<Vec<(String, Option<String>)>> {
+    let mut positions: HashMap<String, String> = HashMap::new();
+    let mut reader = bam::Reader::from_path(final_bam)?;
+    let header = reader.header().clone();
+    for record_result in reader.records() {
+        let record = record_result?;
+        if record.is_unmapped()
+            || record.is_secondary()
+            || record.is_supplementary()
+            || record.tid() < 0
+        {
+            continue;
+        }
+        let contig = String::from_utf8(header.tid2name(record.tid() as u32).to_vec())?;
+        positions
+            .entry(String::from_utf8(record.qname().to_vec())?)
+            .or_insert_with(|| format!("{}:{}", contig, record.pos() + 1));
+    }
+    let mut fates: Vec<(String, Option<String>)> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut reader = bam::Reader::from_path(extracted)?;
+    for record_result in reader.records() {
+        let name = String::from_utf8(record_result?.qname().to_vec())?;
+        if seen.insert(name.clone()) {
+            let position = positions.get(&name).cloned();
+            fates.push((name, position));
+        }
+    }
+    Ok(fates)
+}
+
+//write_read_fates writes the read fates (see read_fates) as TSV with the columns read, extracted, realigned (whether
+//the read is part of the final bam after the realignment to the pangenome) and final_position.
+pub fn write_read_fates(
+    fates: &[(String, Option<String>)],
+    realigned: bool,
+    output: &Path,
+) -> Result<()> {
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(output)?;
+    wtr.write_record(&["read", "extracted", "realigned", "final_position"])?;
+    for (name, position) in fates {
+        wtr.write_record(&[
+            name.as_str(),
+            "true",
+            if realigned && position.is_some() {
+                "true"
+            } else {
+                "false"
+            },
+            position.as_deref().unwrap_or("NA"),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+//write_coverage_bed writes the covered intervals of the bam (see covered_intervals) as BED, e.g. to be viewed in IGV
+//along with the regions of the HLA genes (regions.bed).
+pub fn write_coverage_bed(bam: &Path, bed: &Path) -> Result<()> {
+    let mut file = File::create(bed)?;
+    for (contig, start, end) in covered_intervals(bam)? {
+        writeln!(file, "{}\t{}\t{}", contig, start, end)?;
+    }
+    file.flush()?;
+    Ok(())
 }
 
 impl Caller {
-    pub fn call(&self) -> Result<()> {
+    //gene_regions loads the requested table of gene regions, the bundled one if none is given.
+    fn gene_regions(&self) -> Result<GeneRegions> {
+        let build = self.genome_build.as_deref().unwrap_or(DEFAULT_GENOME_BUILD);
+        match &self.gene_regions {
+            Some(path) => GeneRegions::from_path(path, build),
+            None if build == DEFAULT_GENOME_BUILD => Ok(GeneRegions::bundled()),
+            None => Err(anyhow!(
+                "the bundled gene regions are only available for {}, please provide a table of the gene regions of {}",
+                DEFAULT_GENOME_BUILD,
+                build
+            )),
+        }
+    }
+
+    //aligned_input is the alignment of the sample if a single BAM or CRAM file (coordinate sorted and indexed) is given
+    //as reads. The alignment to the linear genome is skipped then, the reads are directly extracted from it.
+    fn aligned_input(&self) -> Option<&PathBuf> {
+        match self.reads.as_slice() {
+            [alignment] if is_alignment(alignment) => Some(alignment),
+            _ => None,
+        }
+    }
+
+    //call runs the preprocessing and returns the produced variant calls, see PreprocessOutput.
+    pub fn call(&self) -> Result<PreprocessOutput> {
         let outdir = &self.output; //the bcf
 
         //find the output folder
@@ -34,100 +493,145 @@ impl Caller {
         //create the folder first if it doesn't exist
         fs::create_dir_all(&parent)?;
 
+        //validate the requested genes before starting any of the external steps
+        let gene_regions = self.gene_regions()?;
+        gene_regions.bed(&self.genes, "ensembl", self.region_flank)?;
+        let scenario = match &self.scenario {
+            Some(path) => fs::read_to_string(path).map_err(|source| OrthanqError::Io {
+                path: path.clone(),
+                source,
+            })?,
+            None => include_str!("../../resources/scenarios/scenario.yaml").to_string(),
+        };
+        check_scenario(&scenario, OBS_SAMPLE)?;
+        if self.use_pangenome && self.vg_index.is_none() {
+            return Err(anyhow!(
+                "a vg index is required for the realignment to the pangenome, provide it or disable the realignment"
+            ));
+        }
+        let mut tools = vec!["samtools", "varlociraptor"];
+        if self.aligned_input().is_none() {
+            tools.push("bwa");
+        }
+        if self.use_pangenome {
+            tools.push("vg");
+        }
+        require_tools(&tools)?;
+
         //todo: consider caching for indexing.
 
         //create a temporary file for bwa index and execute bwa index
 
-        // Create a directory inside of `std::env::temp_dir()`
-        let temp_dir = tempdir()?;
+        // Create a directory inside of `std::env::temp_dir()` or the configured one
+        let temp_dir = match &self.tempdir {
+            Some(parent) => {
+                fs::create_dir_all(parent)?;
+                tempfile::Builder::new().tempdir_in(parent)?
+            }
+            None => tempdir()?,
+        };
 
-        //linear genome index location by default is temporary
-        let mut linear_genome_index = parent.join("hs_genome");
+        //record wall time and peak memory of the external steps
+        let mut telemetry = Telemetry::new(temp_dir.path());
 
-        // if bwa index is provided, linear genome index has to change
-        if let Some(bwa_genome_index) = &self.bwa_index {
-            linear_genome_index = bwa_genome_index.clone();
-            println!(
-                "using input bwa index at: {}",
-                linear_genome_index.display()
-            );
+        //bwa, samtools and varlociraptor need an uncompressed and indexed genome
+        let genome = prepare_genome(&self.genome, temp_dir.path())?;
+
+        //an aligned sample (BAM or CRAM, see aligned_input) is used as it is, otherwise the reads are aligned to the
+        //linear genome first
+        let (sample_name, file_aligned_sorted) = if let Some(alignment) = self.aligned_input() {
+            println!("using the aligned reads of {}", alignment.display());
+            (aligned_sample_name(alignment), alignment.clone())
         } else {
-            println!("building bwa index at: {}", linear_genome_index.display());
-            let index = {
-                Command::new("bwa")
-                    .arg("index")
-                    .arg("-p")
-                    .arg(&linear_genome_index)
-                    .arg("-a")
-                    .arg("bwtsw") //-a bwtsw' does not work for short genomes, lineage quantification?
-                    .arg(self.genome.clone())
-                    .status()
-                    .expect("failed to execute indexing process")
-            };
-            println!("The index was created successfully: {}", index);
-            println!(
-                "using input bwa index at: {}",
-                linear_genome_index.display()
-            );
-        }
+            //linear genome index location by default is temporary
+            let mut linear_genome_index = parent.join("hs_genome");
+
+            // if bwa index is provided, linear genome index has to change
+            if let Some(bwa_genome_index) = &self.bwa_index {
+                linear_genome_index = bwa_genome_index.clone();
+                println!(
+                    "using input bwa index at: {}",
+                    linear_genome_index.display()
+                );
+            } else {
+                println!("building bwa index at: {}", linear_genome_index.display());
+                let index = telemetry.run("bwa_index", "bwa", |command| {
+                    command
+                        .arg("index")
+                        .arg("-p")
+                        .arg(&linear_genome_index)
+                        .arg("-a")
+                        .arg("bwtsw") //-a bwtsw' does not work for short genomes, lineage quantification?
+                        .arg(&genome)
+                        .status()
+                        .expect("failed to execute indexing process")
+                });
+                println!("The index was created successfully: {}", index);
+                println!(
+                    "using input bwa index at: {}",
+                    linear_genome_index.display()
+                );
+            }
 
-        //perform the alignment for paired end reads
-        let _temp_aligned = NamedTempFile::new()?;
-
-        //find sample name of one of the fastq files from the read pair
-        let stem_of_sample_dir = &self.reads[0].file_stem().unwrap().to_str().unwrap();
-        //get rid of any underscore (PE reads contain them)
-        let splitted = stem_of_sample_dir.split('_').collect::<Vec<&str>>();
-        let sample_name = splitted[0];
-
-        //create the output file name in temp directory
-        let file_aligned = temp_dir.path().join(format!("{}.bam", sample_name));
-        println!("{}", file_aligned.display());
-
-        //insert read_group info from the sample names
-        let read_group = format!("@RG\\tID:{}\\tSM:{}", sample_name, sample_name);
-
-        //Step-1: align reads to the bwa index
-        let align = {
-            Command::new("bwa")
-                .arg("mem")
-                .arg("-t")
-                .arg("10")
-                .arg("-R")
-                .arg(&read_group)
-                .arg(linear_genome_index)
-                .arg(&self.reads[0])
-                .arg(&self.reads[1])
-                .arg("-o")
-                .arg(&file_aligned)
-                // .arg("2>")
-                // .arg("log.txt")
-                .status()
-                .expect("failed to execute the alignment process")
-        };
-        println!("The alignment was exited with: {}", align);
-        println!("{}", file_aligned.display());
-        //sort the aligned reads by coordinate
-
-        //create the output file name in temp directory
-        let file_aligned_sorted: PathBuf =
-            temp_dir.path().join(format!("{}_sorted.bam", sample_name));
-        // let file_aligned_sorted: PathBuf = outdir.join(format!("{}_sorted.bam", sample_name));
-
-        let sort = {
-            Command::new("samtools")
-                .arg("sort")
-                .arg(file_aligned)
-                .arg("-o")
-                .arg(&file_aligned_sorted)
-                .arg("-@")
-                .arg(&self.threads)
-                .arg("--write-index")
-                .status()
-                .expect("failed to execute the sorting process")
+            //perform the alignment for paired end reads
+            let _temp_aligned = NamedTempFile::new()?;
+
+            //find sample name of one of the fastq files from the read pair
+            let stem_of_sample_dir = &self.reads[0].file_stem().unwrap().to_str().unwrap();
+            //get rid of any underscore (PE reads contain them)
+            let splitted = stem_of_sample_dir.split('_').collect::<Vec<&str>>();
+            let sample_name = splitted[0].to_string();
+
+            //create the output file name in temp directory
+            let file_aligned = temp_dir.path().join(format!("{}.bam", sample_name));
+            println!("{}", file_aligned.display());
+
+            //insert read_group info from the sample names
+            let read_group = format!("@RG\\tID:{}\\tSM:{}", sample_name, sample_name);
+
+            //Step-1: align reads to the bwa index
+            let align = telemetry.run("bwa_mem", "bwa", |command| {
+                command
+                    .arg("mem")
+                    .arg("-t")
+                    .arg("10")
+                    .arg("-R")
+                    .arg(&read_group)
+                    .arg(linear_genome_index)
+                    .arg(&self.reads[0])
+                    .arg(&self.reads[1])
+                    .arg("-o")
+                    .arg(&file_aligned)
+                    // .arg("2>")
+                    // .arg("log.txt")
+                    .status()
+                    .expect("failed to execute the alignment process")
+            });
+            println!("The alignment was exited with: {}", align);
+            println!("{}", file_aligned.display());
+            //sort the aligned reads by coordinate
+
+            //create the output file name in temp directory
+            let file_aligned_sorted: PathBuf =
+                temp_dir.path().join(format!("{}_sorted.bam", sample_name));
+            // let file_aligned_sorted: PathBuf = outdir.join(format!("{}_sorted.bam", sample_name));
+
+            let sort = telemetry.run("samtools_sort", "samtools", |command| {
+                command
+                    .arg("sort")
+                    .arg(file_aligned)
+                    .arg("-o")
+                    .arg(&file_aligned_sorted)
+                    .arg("-@")
+                    .arg(&self.threads)
+                    .arg("--write-index")
+                    .status()
+                    .expect("failed to execute the sorting process")
+            });
+            println!("The sorting was exited with: {}", sort);
+            println!("{}", file_aligned_sorted.display());
+            (sample_name, file_aligned_sorted)
         };
-        println!("The sorting was exited with: {}", sort);
-        println!("{}", file_aligned_sorted.display());
 
         //Step-2: extract reads that map to HLA genes (classical and nonclassical class of genes)
 
@@ -136,13 +640,13 @@ impl Caller {
         // let path_idxstats = outdir.join("stats.txt");
         let mut file_idxstats = std::fs::File::create(path_idxstats.clone())?;
 
-        let idxstats = {
-            Command::new("samtools")
+        let idxstats = telemetry.run("samtools_idxstats", "samtools", |command| {
+            command
                 .arg("idxstats")
                 .arg(&file_aligned_sorted)
                 .output()
                 .expect("failed to execute idxstat process")
-        };
+        });
         file_idxstats.write_all(&idxstats.stdout)?; //write with bam writer
         file_idxstats.flush()?;
 
@@ -175,255 +679,237 @@ impl Caller {
         println!("chr_naming format: {}", chr_naming);
         let path_to_regions = parent.join("regions.bed");
         let mut regions_file = std::fs::File::create(&path_to_regions)?;
-        if chr_naming == &"ucsc" {
-            let regions_ensembl = "\
-chr6\t32659467\t32668383
-chr6\t32577902\t32589848
-chr6\t32628179\t32647062
-chr6\t31268749\t31272130
-chr6\t30489509\t30494194
-chr6\t29826967\t29831125
-chr6\t29722775\t29738528
-chr6\t29887752\t29890482
-chr6\t29941260\t29949572
-chr6\t31353872\t31367067";
-            regions_file.write_all(regions_ensembl.as_bytes())?;
-        } else if chr_naming == &"ensembl" {
-            let regions_ucsc = "\
-6\t32659467\t32668383
-6\t32577902\t32589848
-6\t32628179\t32647062
-6\t31268749\t31272130
-6\t30489509\t30494194
-6\t29826967\t29831125
-6\t29722775\t29738528
-6\t29887752\t29890482
-6\t29941260\t29949572
-6\t31353872\t31367067
-            ";
-            regions_file.write_all(regions_ucsc.as_bytes())?;
-        }
+        regions_file.write_all(
+            gene_regions
+                .bed(&self.genes, chr_naming, self.region_flank)?
+                .as_bytes(),
+        )?;
         regions_file.flush()?;
 
-        //create the output file name in temp directory
-        let file_extracted = temp_dir
-            .path()
-            .join(format!("{}_extracted.bam", sample_name));
+        //the final bam is written next to the output
+        let final_bam = parent.join(format!("{}_processed.bam", sample_name));
+
+        //create the output file name in temp directory; without the pangenome, the extracted reads are final
+        let file_extracted = if self.use_pangenome {
+            temp_dir
+                .path()
+                .join(format!("{}_extracted.bam", sample_name))
+        } else {
+            final_bam.clone()
+        };
         // let file_extracted = outdir.join(format!("{}_extracted.bam", sample_name));
         // let regions = format!("{}/resources/regions.bed", cargo_dir);
 
-        let extract = {
-            Command::new("samtools")
-                .arg("view")
-                .arg(file_aligned_sorted)
-                .arg("-L")
-                .arg(path_to_regions)
-                .arg("--write-index") //??
-                .arg("-o")
-                .arg(&file_extracted)
+        let extract = telemetry.run("samtools_extract", "samtools", |command| {
+            let mut args = extraction_args(
+                &file_aligned_sorted,
+                &path_to_regions,
+                &file_extracted,
+                self.min_mapq,
+            );
+            //the reads of a CRAM are decoded with the genome
+            if file_aligned_sorted.extension() == Some(OsStr::new("cram")) {
+                args.push("--reference".into());
+                args.push(genome.clone().into());
+            }
+            command
+                .args(args)
                 .status()
                 .expect("failed to execute the extracting process")
-        };
+        });
         println!("The extraction was exited with: {}", extract);
 
-        //convert the alignment file to fq
-
-        //create the output file name in temp directory
-        let temp_extracted_fq_1 = temp_dir.path().join(format!("{}_1.fastq", sample_name));
-        let temp_extracted_fq_2 = temp_dir.path().join(format!("{}_2.fastq", sample_name));
-
-        let bam_to_fq = {
-            Command::new("samtools")
-                .arg("fastq")
-                .arg(file_extracted)
-                .arg("-n") //-n for fastq
-                .arg("-1")
-                .arg(&temp_extracted_fq_1)
-                .arg("-2")
-                .arg(&temp_extracted_fq_2)
-                .status()
-                .expect("failed to execute the extracting process")
-        };
-        println!("Conversion from BAM to fq was exited with: {}", bam_to_fq);
-
-        //Step-3: map extracted reads to the pangenome with vg giraffe
-
-        //path to the index directory
-        // let vg_index = "resources/hprc-v1.0-mc-grch38.xg";
-
-        //create the output file name in temp directory
-        let file_aligned_pangenome = temp_dir.path().join(format!("{}_vg.bam", sample_name));
-
-        let align_pangenome = {
-            Command::new("vg")
-                .arg("giraffe")
-                .arg("-x")
-                .arg(self.vg_index.clone())
-                .arg("-f")
-                .arg(temp_extracted_fq_1)
-                .arg("-f")
-                .arg(temp_extracted_fq_2)
-                .arg("--output-format")
-                .arg("BAM")
-                .arg("-t")
-                .arg(&self.threads)
-                .stdout(Stdio::piped())
-                .spawn()
-                .expect("failed to execute the vg giraffe process")
-        };
-        println!(
-            "Alignment to pangenome was exited with: {:?}",
-            align_pangenome
-        );
+        if self.use_pangenome {
+            //convert the alignment file to fq
+
+            //create the output file name in temp directory
+            let temp_extracted_fq_1 = temp_dir.path().join(format!("{}_1.fastq", sample_name));
+            let temp_extracted_fq_2 = temp_dir.path().join(format!("{}_2.fastq", sample_name));
+
+            //the read names are kept as they are (see write_paired_fastq)
+            let pairs =
+                write_paired_fastq(&file_extracted, &temp_extracted_fq_1, &temp_extracted_fq_2)?;
+            println!("Converted {} read pairs from BAM to fq", pairs);
+
+            //Step-3: map extracted reads to the pangenome with vg giraffe
+
+            //path to the index directory
+            // let vg_index = "resources/hprc-v1.0-mc-grch38.xg";
+
+            //create the output file name in temp directory
+            let file_aligned_pangenome = temp_dir.path().join(format!("{}_vg.bam", sample_name));
+
+            let align_pangenome = telemetry.run("vg_giraffe", "vg", |command| {
+                command
+                    .arg("giraffe")
+                    .arg("-x")
+                    .arg(self.vg_index.as_ref().unwrap())
+                    .arg("-f")
+                    .arg(temp_extracted_fq_1)
+                    .arg("-f")
+                    .arg(temp_extracted_fq_2)
+                    .arg("--output-format")
+                    .arg("BAM")
+                    .arg("-t")
+                    .arg(&self.threads)
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .expect("failed to execute the vg giraffe process")
+                    .wait_with_output()
+                    .expect("Failed to read stdout")
+            });
+            println!(
+                "Alignment to pangenome was exited with: {:?}",
+                align_pangenome.status
+            );
 
-        //write bam to file (buffered)
-        // let mut vg_bam = std::fs::File::create(&file_aligned_pangenome)?;
-        // let mut f = std::fs::File::open(&file_aligned_pangenome).unwrap();
-        // let mut f = std::io::BufWriter::new(f);
-        // {
-        //     let stdout = align_pangenome.stdout;
-        //     let stdout_reader = std::io::BufReader::new(stdout);
-        //     let stdout_lines = stdout_reader.bytes();
-
-        //     for line in stdout_lines {
-        //         f.write(&[line.unwrap()]);
-        //     }
-        // }
-        // align_pangenome.wait().unwrap();
-        // f.flush()?;
-
-        // let output_align = align_pangenome.stdout.expect("failed to wait on aligning to pangenome");
-
-        let output = align_pangenome
-            .wait_with_output()
-            .expect("Failed to read stdout");
-
-        let mut vg_bam = std::fs::File::create(file_aligned_pangenome.clone())?;
-        vg_bam.write_all(&output.stdout)?; //write with bam writer
-        vg_bam.flush()?;
-
-        //sort the resulting vg aligned file
-        let file_vg_aligned_sorted = temp_dir
-            .path()
-            .join(format!("{}_vg_sorted.bam", sample_name));
-
-        let vg_sort = {
-            Command::new("samtools")
-                .arg("sort")
-                .arg(&file_aligned_pangenome)
-                .arg("-o")
-                .arg(&file_vg_aligned_sorted)
-                .arg("-@")
-                .arg(&self.threads)
-                .arg("--write-index")
-                .status()
-                .expect("failed to execute the sorting process")
-        };
-        println!("The sorting was exited with: {}", vg_sort);
-        println!("{}", file_vg_aligned_sorted.display());
-
-        //modify the header for chromosome names to be compatible with the reference genome that we acquire from ensembl
-
-        //prepare the temporary file path for the reheadered bam output
-        let file_reheadered = temp_dir
-            .path()
-            .join(format!("{}_reheadered.bam", sample_name));
-
-        println!("{}", file_reheadered.display());
-
-        //in Rust, piping cannot be done via "|" but instead in the following way:
-
-        //get the header
-        let samtools_view_child = Command::new("samtools")
-            .arg("view") // `samtools view` command...
-            .arg("-H") // of which we will pipe the output.
-            .arg(&file_vg_aligned_sorted) //Once configured, we actually spawn the command...
-            .stdout(Stdio::piped())
-            .spawn()
-            .unwrap();
-
-        //replace the 'GRCh38.chr' with '' or "chr" prefices depending on the genome reference chr naming style
-        let mut regex = &"";
-        if chr_naming == &"ucsc" {
-            regex = &"s/GRCh38.//g";
-        } else if chr_naming == &"ensembl" {
-            regex = &"s/GRCh38.chr//g";
-        }
-        println!("regex for reheader: {}", regex);
-        let sed_child_one = Command::new("sed")
-            .arg(regex)
-            .stdin(Stdio::from(samtools_view_child.stdout.unwrap())) // Pipe through.
-            .stdout(Stdio::piped())
-            .spawn()
-            .unwrap();
-
-        //then, reheader the header of the input bam
-        let reheader_child_two = Command::new("samtools")
-            .arg("reheader")
-            .arg("-")
-            .stdin(sed_child_one.stdout.unwrap())
-            .arg(file_vg_aligned_sorted)
-            .stdout(Stdio::piped())
-            .spawn()
-            .unwrap();
-
-        //write the reheadered bam to file
-        let output = reheader_child_two
-            .wait_with_output()
-            .expect("failed to wait on child");
-        let mut f = std::fs::File::create(file_reheadered.clone())?;
-        f.write_all(&output.stdout)?;
-
-        //index the resulting bam file
-        let samtools_index = {
-            Command::new("samtools")
-                .arg("index")
-                .arg(&file_reheadered)
-                .status()
-                .unwrap()
-        };
+            //write bam to file (buffered)
+            // let mut vg_bam = std::fs::File::create(&file_aligned_pangenome)?;
+            // let mut f = std::fs::File::open(&file_aligned_pangenome).unwrap();
+            // let mut f = std::io::BufWriter::new(f);
+            // {
+            //     let stdout = align_pangenome.stdout;
+            //     let stdout_reader = std::io::BufReader::new(stdout);
+            //     let stdout_lines = stdout_reader.bytes();
+
+            //     for line in stdout_lines {
+            //         f.write(&[line.unwrap()]);
+            //     }
+            // }
+            // align_pangenome.wait().unwrap();
+            // f.flush()?;
+
+            // let output_align = align_pangenome.stdout.expect("failed to wait on aligning to pangenome");
+
+            let output = align_pangenome;
+
+            let mut vg_bam = std::fs::File::create(file_aligned_pangenome.clone())?;
+            vg_bam.write_all(&output.stdout)?; //write with bam writer
+            vg_bam.flush()?;
+
+            //sort the resulting vg aligned file
+            let file_vg_aligned_sorted = temp_dir
+                .path()
+                .join(format!("{}_vg_sorted.bam", sample_name));
+
+            let vg_sort = telemetry.run("samtools_sort_vg", "samtools", |command| {
+                command
+                    .arg("sort")
+                    .arg(&file_aligned_pangenome)
+                    .arg("-o")
+                    .arg(&file_vg_aligned_sorted)
+                    .arg("-@")
+                    .arg(&self.threads)
+                    .arg("--write-index")
+                    .status()
+                    .expect("failed to execute the sorting process")
+            });
+            println!("The sorting was exited with: {}", vg_sort);
+            println!("{}", file_vg_aligned_sorted.display());
 
-        println!("The indexing was exited with: {}", samtools_index);
+            //modify the header for chromosome names to be compatible with the reference genome that we acquire from ensembl
 
-        //finally, extract only strandard chromosomes
-        let final_bam = parent.join(format!("{}_processed.bam", sample_name));
-        println!("{}", final_bam.display());
-
-        //construct chromosome names according to the genome reference chr naming style
-        let mut chromosomes = vec![];
-        if chr_naming == &"ucsc" {
-            chromosomes = vec![
-                "chr1", "chr2", "chr3", "chr4", "chr5", "chr6", "chr7", "chr8", "chr9", "chr10",
-                "chr11", "chr12", "chr13", "chr14", "chr15", "chr16", "chr17", "chr18", "chr19",
-                "chr20", "chr21", "chr22", "chrX", "chrY", "chrM",
-            ]
-        } else if chr_naming == &"ensembl" {
-            chromosomes = vec![
-                "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13", "14", "15",
-                "16", "17", "18", "19", "20", "21", "22", "X", "Y", "M",
-            ]
+            //prepare the temporary file path for the reheadered bam output
+            let file_reheadered = temp_dir
+                .path()
+                .join(format!("{}_reheadered.bam", sample_name));
+
+            println!("{}", file_reheadered.display());
+
+            //in Rust, piping cannot be done via "|" but instead in the following way:
+
+            //get the header
+            let samtools_view_child = Command::new("samtools")
+                .arg("view") // `samtools view` command...
+                .arg("-H") // of which we will pipe the output.
+                .arg(&file_vg_aligned_sorted) //Once configured, we actually spawn the command...
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+
+            //replace the 'GRCh38.chr' with '' or "chr" prefices depending on the genome reference chr naming style
+            let mut regex = &"";
+            if chr_naming == &"ucsc" {
+                regex = &"s/GRCh38.//g";
+            } else if chr_naming == &"ensembl" {
+                regex = &"s/GRCh38.chr//g";
+            }
+            println!("regex for reheader: {}", regex);
+            let sed_child_one = Command::new("sed")
+                .arg(regex)
+                .stdin(Stdio::from(samtools_view_child.stdout.unwrap())) // Pipe through.
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+
+            //then, reheader the header of the input bam
+            let output = telemetry.run("samtools_reheader", "samtools", |command| {
+                command
+                    .arg("reheader")
+                    .arg("-")
+                    .stdin(sed_child_one.stdout.unwrap())
+                    .arg(file_vg_aligned_sorted)
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .unwrap()
+                    .wait_with_output()
+                    .expect("failed to wait on child")
+            });
+
+            //write the reheadered bam to file
+            let mut f = std::fs::File::create(file_reheadered.clone())?;
+            f.write_all(&output.stdout)?;
+
+            //index the resulting bam file
+            let samtools_index = telemetry.run("samtools_index", "samtools", |command| {
+                command.arg("index").arg(&file_reheadered).status().unwrap()
+            });
+
+            println!("The indexing was exited with: {}", samtools_index);
+
+            //finally, extract only the requested (by default the standard) chromosomes
+            println!("{}", final_bam.display());
+
+            //construct chromosome names according to the genome reference chr naming style
+            let chromosomes = final_chromosomes(&self.chromosomes, &self.extra_contigs, chr_naming);
+            println!("chromosomes to extract: {:?}", chromosomes);
+
+            let samtools_extract =
+                telemetry.run("samtools_extract_chromosomes", "samtools", |command| {
+                    command
+                        .arg("view")
+                        .arg(&file_reheadered)
+                        .args(chromosomes)
+                        .arg("-o")
+                        .arg(&final_bam)
+                        .arg("-@")
+                        .arg(&self.threads)
+                        .arg("--write-index")
+                        .status()
+                        .expect("failed to execute the sorting process")
+                });
+
+            //write the final bam to file
+            println!(
+                "The extractiong of standard chromosomes was exited with: {}",
+                samtools_extract
+            );
         }
-        println!("chromosomes to extract: {:?}", chromosomes);
-
-        let samtools_extract = {
-            Command::new("samtools")
-                .arg("view")
-                .arg(&file_reheadered)
-                .args(chromosomes)
-                .arg("-o")
-                .arg(&final_bam)
-                .arg("-@")
-                .arg(&self.threads)
-                .arg("--write-index")
-                .status()
-                .expect("failed to execute the sorting process")
-        };
 
-        //write the final bam to file
-        println!(
-            "The extractiong of standard chromosomes was exited with: {}",
-            samtools_extract
-        );
+        if self.read_fates {
+            write_read_fates(
+                &read_fates(&file_extracted, &final_bam)?,
+                self.use_pangenome,
+                &parent.join(format!("{}_read_fates.tsv", sample_name)),
+            )?;
+        }
+        if self.coverage_bed {
+            write_coverage_bed(
+                &final_bam,
+                &parent.join(format!("{}_coverage.bed", sample_name)),
+            )?;
+        }
+        let coverage = CoverageSummary::of_bam(&final_bam)?;
 
         //varlociraptor preprocess and call
 
@@ -435,23 +921,24 @@ chr6\t31353872\t31367067";
             varlociraptor_prep_dir.display()
         );
 
-        let varlociraptor_prep = {
-            Command::new("varlociraptor")
-                .arg("preprocess")
-                .arg("variants")
-                .arg("--report-fragment-ids")
-                .arg("--omit-mapq-adjustment")
-                .arg("--atomic-candidate-variants")
-                .arg("--candidates")
-                .arg(&self.haplotype_variants)
-                .arg(&self.genome)
-                .arg("--bam")
-                .arg(&final_bam)
-                .arg("--output")
-                .arg(&varlociraptor_prep_dir)
-                .status()
-                .expect("failed to execute the varlociraptor preprocessing")
-        };
+        let varlociraptor_prep =
+            telemetry.run("varlociraptor_preprocess", "varlociraptor", |command| {
+                command
+                    .arg("preprocess")
+                    .arg("variants")
+                    .arg("--report-fragment-ids")
+                    .arg("--omit-mapq-adjustment")
+                    .arg("--atomic-candidate-variants")
+                    .arg("--candidates")
+                    .arg(&self.haplotype_variants)
+                    .arg(&genome)
+                    .arg("--bam")
+                    .arg(&final_bam)
+                    .arg("--output")
+                    .arg(&varlociraptor_prep_dir)
+                    .status()
+                    .expect("failed to execute the varlociraptor preprocessing")
+            });
         println!(
             "The varlociraptor preprocessing was exited with: {}",
             varlociraptor_prep
@@ -468,23 +955,22 @@ chr6\t31353872\t31367067";
         );
 
         //scenario
-        //read scenario to str and export it back to yaml then use it in scenario
+        //write the scenario (checked above) to the temp dir and use it from there
         //this is required for conda installation
-        let scenario_str = include_str!("../../resources/scenarios/scenario.yaml");
         let scenario_path = temp_dir.path().join("scenario.yaml");
         let mut scenario_file = File::create(&scenario_path)?;
 
         //write the YAML string to the file
-        scenario_file.write_all(scenario_str.as_bytes())?;
+        scenario_file.write_all(scenario.as_bytes())?;
         println!("YAML written to scenario.yaml in temp dir");
 
         println!(
             "{}",
-            format!("sample={}", &varlociraptor_prep_dir.display())
+            format!("{}={}", OBS_SAMPLE, &varlociraptor_prep_dir.display())
         );
 
-        let varlociraptor_call = {
-            Command::new("varlociraptor")
+        let varlociraptor_call = telemetry.run("varlociraptor_call", "varlociraptor", |command| {
+            command
                 .arg("call")
                 .arg("variants")
                 .arg("--omit-strand-bias")
@@ -495,27 +981,44 @@ chr6\t31353872\t31367067";
                 .arg("--omit-alt-locus-bias")
                 .arg("generic")
                 .arg("--obs")
-                .arg(format!("sample={}", varlociraptor_prep_dir.display()))
+                .arg(format!(
+                    "{}={}",
+                    OBS_SAMPLE,
+                    varlociraptor_prep_dir.display()
+                ))
                 .arg("--scenario")
                 .arg(&scenario_path)
                 .stdout(Stdio::piped())
                 .spawn()
                 .expect("failed to execute the varlociraptor calling process")
-        };
+                .wait_with_output()
+                .expect("Varlociraptor: Failed to read stdout")
+        });
         println!(
             "The varlociraptor calling was exited with: {:?}",
-            varlociraptor_call
+            varlociraptor_call.status
         );
 
-        let output = varlociraptor_call
-            .wait_with_output()
-            .expect("Varlociraptor: Failed to read stdout");
+        let output = varlociraptor_call;
         let mut called_file = std::fs::File::create(&varlociraptor_call_dir)?;
         called_file.write_all(&output.stdout)?; //write with bam writer
         called_file.flush()?;
+
+        //sort (if necessary) and index the resulting bcf
+        if varlociraptor_call_dir.extension() == Some(OsStr::new("bcf")) {
+            index_bcf(varlociraptor_call_dir)?;
+        }
+
+        //write the telemetry of the external steps next to the output
+        telemetry.write(&parent.join("preprocess_telemetry.tsv"))?;
+
         // close the file handle of the named temporary files
         temp_dir.close()?;
 
-        Ok(())
+        Ok(PreprocessOutput {
+            calls: outdir.clone(),
+            sample: sample_name.to_string(),
+            coverage,
+        })
     }
 }
@@ -1,2 +1,85 @@
 pub mod hla;
+pub mod telemetry;
 pub mod virus;
+
+use crate::errors::OrthanqError;
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+use yaml_rust::{Yaml, YamlLoader};
+
+//PreprocessOutput is the result of a preprocessing run: the variant calls (BCF) that are the input of the haplotype
+//callers, the name of the sample and a summary of the coverage of its final alignment, such that an orchestrator
+//can pass the calls on without deriving the paths from the output and the reads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreprocessOutput {
+    pub calls: PathBuf,
+    pub sample: String,
+    //None if the final alignment was not written, e.g. if an external step failed.
+    pub coverage: Option<CoverageSummary>,
+}
+
+//CoverageSummary counts the intervals covered by reads of the final alignment and their total length
+//(see hla::covered_intervals).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageSummary {
+    pub intervals: usize,
+    pub covered_bases: u64,
+}
+
+impl CoverageSummary {
+    pub fn of_intervals(intervals: &[(String, i64, i64)]) -> Self {
+        CoverageSummary {
+            intervals: intervals.len(),
+            covered_bases: intervals
+                .iter()
+                .map(|(_, start, end)| (end - start) as u64)
+                .sum(),
+        }
+    }
+
+    //of_bam summarizes the coverage of the given alignment, None if it doesn't exist.
+    pub fn of_bam(bam: &Path) -> Result<Option<Self>> {
+        if bam.exists() {
+            Ok(Some(Self::of_intervals(&hla::covered_intervals(bam)?)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+//OBS_SAMPLE is the name under which the observations are passed to varlociraptor (--obs sample=...), the scenario has
+//to declare a sample of this name.
+pub const OBS_SAMPLE: &str = "sample";
+
+//scenario_samples are the names of the samples declared by a varlociraptor scenario, i.e. the keys of its samples section.
+pub fn scenario_samples(scenario: &str) -> Result<Vec<String>> {
+    let docs = YamlLoader::load_from_str(scenario)?;
+    match docs.first().map(|doc| &doc["samples"]) {
+        Some(Yaml::Hash(samples)) => Ok(samples
+            .keys()
+            .map(|sample| match sample {
+                Yaml::String(sample) => Ok(sample.clone()),
+                other => Err(anyhow!(
+                    "scenario samples must be named by strings, found {:?}",
+                    other
+                )),
+            })
+            .collect::<Result<Vec<String>>>()?),
+        _ => Err(anyhow!("the scenario does not declare any samples")),
+    }
+}
+
+//check_scenario makes sure that the scenario declares the sample the observations are passed as (see OBS_SAMPLE),
+//otherwise varlociraptor fails with an error that doesn't point to the scenario.
+pub fn check_scenario(scenario: &str, sample: &str) -> Result<()> {
+    let declared = scenario_samples(scenario)?;
+    if declared.iter().any(|declared| declared == sample) {
+        Ok(())
+    } else {
+        Err(OrthanqError::ScenarioSampleMismatch {
+            sample: sample.to_string(),
+            declared,
+        }
+        .into())
+    }
+}
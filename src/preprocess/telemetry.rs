@@ -0,0 +1,80 @@
+use anyhow::Result;
+use csv::WriterBuilder;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+
+//Telemetry records the wall time of each external step of the preprocessing and, if GNU time is
+//available at /usr/bin/time, the peak memory (maximum resident set size in kB). Otherwise, only wall time is recorded.
+pub struct Telemetry {
+    scratch: PathBuf,
+    gnu_time: bool,
+    steps: Vec<(String, f64, Option<u64>)>,
+}
+
+impl Telemetry {
+    pub fn new(scratch: &Path) -> Self {
+        Telemetry {
+            scratch: scratch.to_path_buf(),
+            gnu_time: Path::new("/usr/bin/time").exists(),
+            steps: Vec::new(),
+        }
+    }
+
+    //command creates the command for the given program, wrapped with GNU time if available
+    pub fn command(&self, step: &str, program: &str) -> Command {
+        if self.gnu_time {
+            let mut command = Command::new("/usr/bin/time");
+            command
+                .arg("-f")
+                .arg("%M")
+                .arg("-o")
+                .arg(self.rss_path(step))
+                .arg(program);
+            command
+        } else {
+            Command::new(program)
+        }
+    }
+
+    //run executes the given closure with the command for the program and records the telemetry of the step
+    pub fn run<T, F: FnOnce(&mut Command) -> T>(&mut self, step: &str, program: &str, f: F) -> T {
+        let start = Instant::now();
+        let mut command = self.command(step, program);
+        let result = f(&mut command);
+        self.record(step, start);
+        result
+    }
+
+    pub fn record(&mut self, step: &str, start: Instant) {
+        let wall_time = start.elapsed().as_secs_f64();
+        //GNU time writes the exit status before the measurement in case of failure, hence take the last line
+        let peak_rss = fs::read_to_string(self.rss_path(step))
+            .ok()
+            .and_then(|content| content.lines().last()?.trim().parse().ok());
+        self.steps.push((step.to_string(), wall_time, peak_rss));
+    }
+
+    pub fn steps(&self) -> &Vec<(String, f64, Option<u64>)> {
+        &self.steps
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut wtr = WriterBuilder::new().delimiter(b'\t').from_path(path)?;
+        wtr.write_record(&["step", "wall_time_s", "peak_rss_kb"])?;
+        for (step, wall_time, peak_rss) in self.steps.iter() {
+            wtr.write_record(&[
+                step.clone(),
+                format!("{:.2}", wall_time),
+                peak_rss.map_or("NA".to_string(), |rss| rss.to_string()),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    fn rss_path(&self, step: &str) -> PathBuf {
+        self.scratch.join(format!("{}.rss", step))
+    }
+}
@@ -1,12 +1,15 @@
 use anyhow::Result;
 use derive_builder::Builder;
 
+use crate::preprocess::telemetry::Telemetry;
+use crate::preprocess::{CoverageSummary, PreprocessOutput};
+use crate::utils::{index_bcf, prepare_genome, require_tools};
+use std::ffi::OsStr;
 use std::fs;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
-use std::process::Command;
 use std::process::Stdio;
 
 use tempfile::tempdir;
@@ -22,10 +25,14 @@ pub struct Caller {
 }
 
 impl Caller {
-    pub fn call(&self) -> Result<()> {
+    //call runs the preprocessing and returns the produced variant calls, see PreprocessOutput.
+    pub fn call(&self) -> Result<PreprocessOutput> {
         //specify out dir
         let outdir = &self.output;
 
+        //make sure that the external tools are available before starting
+        require_tools(&["bgzip", "tabix", "vg", "samtools", "varlociraptor"])?;
+
         //create the parent dir
         let mut parent = outdir.clone();
         parent.pop();
@@ -34,10 +41,14 @@ impl Caller {
         // Create a directory inside of `std::env::temp_dir()` for temporary files
         let temp_dir = tempdir()?;
 
+        //record wall time and peak memory of the external steps
+        let mut telemetry = Telemetry::new(temp_dir.path());
+
         //1) bgzip and tabix the candidates vcf then, perform vg autoindex (maybe add this part to the candidates virus subcommand.)
 
         // for sarscov2, genome must have been downloaded in the candidate generation step (see reference.fasta):
-        let ref_genome = &self.genome;
+        //vg and varlociraptor need an uncompressed and indexed genome
+        let ref_genome = &prepare_genome(&self.genome, temp_dir.path())?;
 
         //haplotype variantts must have been downloaded in the candidate generation step:
         let haplotype_variants = &self.candidates;
@@ -58,19 +69,19 @@ impl Caller {
         let bgzip_dir = temp_dir.path().join(format!("{}.gz", file_name));
         println!("{}", bgzip_dir.display());
 
-        let bgzip = {
-            Command::new("bgzip")
+        let bgzip = telemetry.run("bgzip", "bgzip", |command| {
+            command
                 .arg("-c")
                 .arg(&haplotype_variants)
                 .stdout(Stdio::piped())
                 .spawn()
                 .expect("failed to execute the zipping process")
-        };
-        println!("Bgzip was exited with: {:?}", bgzip);
+                .wait_with_output()
+                .expect("Bgzip: Failed to read stdout")
+        });
+        println!("Bgzip was exited with: {:?}", bgzip.status);
 
-        let output = bgzip
-            .wait_with_output()
-            .expect("Bgzip: Failed to read stdout");
+        let output = bgzip;
 
         let mut bgzipped_file = std::fs::File::create(&bgzip_dir)?;
         bgzipped_file.write_all(&output.stdout)?; //write with bam writer
@@ -78,20 +89,18 @@ impl Caller {
 
         //tabix
 
-        let tabix = {
-            Command::new("tabix")
+        let tabix = telemetry.run("tabix", "tabix", |command| {
+            command
                 .arg("-p")
                 .arg("vcf")
                 .arg(&bgzip_dir)
                 .stdout(Stdio::piped())
                 .spawn()
                 .expect("failed to execute the tabix process")
-        };
-        println!("Tabix was exited with: {:?}", tabix);
-
-        let _ = tabix
-            .wait_with_output()
-            .expect("Tabix: Failed to read stdout");
+                .wait_with_output()
+                .expect("Tabix: Failed to read stdout")
+        });
+        println!("Tabix was exited with: {:?}", tabix.status);
 
         //vg indexing
 
@@ -99,8 +108,8 @@ impl Caller {
         let idx_name = temp_dir.path().join(&"idx");
         println!("{}", bgzip_dir.display());
 
-        let vg_index = {
-            Command::new("vg")
+        let vg_index = telemetry.run("vg_autoindex", "vg", |command| {
+            command
                 .arg("autoindex")
                 .arg("--workflow")
                 .arg("giraffe")
@@ -114,7 +123,7 @@ impl Caller {
                 .arg(&self.threads)
                 .status()
                 .expect("failed to execute the vg giraffe process")
-        };
+        });
         println!("vg indexing was exited with: {:?}", vg_index);
 
         //2) vg giraffe, sorting, indexing and varlociraptor preprocess-call steps.
@@ -139,8 +148,8 @@ impl Caller {
 
         //"vg giraffe -Z results/vg/autoindex/idx.giraffe.gbz -f {input.reads[0]} -f {input.reads[1]} --output-format BAM -t {threads}  > {output} 2> {log}"
 
-        let align_pangenome = {
-            Command::new("vg")
+        let align_pangenome = telemetry.run("vg_giraffe", "vg", |command| {
+            command
                 .arg("giraffe")
                 .arg("-Z")
                 .arg(format!("{}.giraffe.gbz", &idx_name.display()))
@@ -155,15 +164,15 @@ impl Caller {
                 .stdout(Stdio::piped())
                 .spawn()
                 .expect("failed to execute the vg giraffe process")
-        };
+                .wait_with_output()
+                .expect("Failed to read stdout")
+        });
         println!(
             "Alignment to pangenome was exited with: {:?}",
-            align_pangenome
+            align_pangenome.status
         );
 
-        let output = align_pangenome
-            .wait_with_output()
-            .expect("Failed to read stdout");
+        let output = align_pangenome;
 
         let mut vg_bam = std::fs::File::create(file_aligned_pangenome.clone())?;
         vg_bam.write_all(&output.stdout)?; //write with bam writer
@@ -182,8 +191,8 @@ impl Caller {
             file_vg_aligned_sorted.display()
         );
 
-        let vg_sort = {
-            Command::new("samtools")
+        let vg_sort = telemetry.run("samtools_sort", "samtools", |command| {
+            command
                 .arg("sort")
                 .arg(&file_aligned_pangenome)
                 .arg("-o")
@@ -193,7 +202,7 @@ impl Caller {
                 .arg("--write-index")
                 .status()
                 .expect("failed to execute the sorting process")
-        };
+        });
         println!("The sorting was exited with: {}", vg_sort);
         println!("{}", file_vg_aligned_sorted.display());
 
@@ -207,25 +216,26 @@ impl Caller {
             varlociraptor_prep_dir.display()
         );
 
-        let varlociraptor_prep = {
-            Command::new("varlociraptor")
-                .arg("preprocess")
-                .arg("variants")
-                .arg("--report-fragment-ids")
-                .arg("--omit-mapq-adjustment")
-                .arg("--atomic-candidate-variants")
-                .arg("--candidates")
-                .arg(&haplotype_variants)
-                .arg(&ref_genome)
-                .arg("--bam")
-                .arg(&file_vg_aligned_sorted)
-                .arg("--output")
-                .arg(&varlociraptor_prep_dir)
-                .arg("--max-depth")
-                .arg("3000")
-                .status()
-                .expect("failed to execute the varlociraptor preprocessing")
-        };
+        let varlociraptor_prep =
+            telemetry.run("varlociraptor_preprocess", "varlociraptor", |command| {
+                command
+                    .arg("preprocess")
+                    .arg("variants")
+                    .arg("--report-fragment-ids")
+                    .arg("--omit-mapq-adjustment")
+                    .arg("--atomic-candidate-variants")
+                    .arg("--candidates")
+                    .arg(&haplotype_variants)
+                    .arg(&ref_genome)
+                    .arg("--bam")
+                    .arg(&file_vg_aligned_sorted)
+                    .arg("--output")
+                    .arg(&varlociraptor_prep_dir)
+                    .arg("--max-depth")
+                    .arg("3000")
+                    .status()
+                    .expect("failed to execute the varlociraptor preprocessing")
+            });
         println!(
             "The varlociraptor preprocessing was exited with: {}",
             varlociraptor_prep
@@ -246,8 +256,8 @@ impl Caller {
         println!("YAML written to scenario.yaml in temp dir");
 
         //scenario
-        let varlociraptor_call = {
-            Command::new("varlociraptor")
+        let varlociraptor_call = telemetry.run("varlociraptor_call", "varlociraptor", |command| {
+            command
                 .arg("call")
                 .arg("variants")
                 .arg("--output")
@@ -265,13 +275,25 @@ impl Caller {
                 .arg(&scenario_path)
                 .status()
                 .expect("failed to execute the varlociraptor calling process")
-        };
+        });
         println!(
             "varlociraptor calling finished with exit status: {:?}",
             varlociraptor_call
         );
 
+        //sort (if necessary) and index the resulting bcf
+        if outdir.extension() == Some(OsStr::new("bcf")) {
+            index_bcf(outdir)?;
+        }
+
+        //write the telemetry of the external steps next to the output
+        telemetry.write(&parent.join("preprocess_telemetry.tsv"))?;
+
         //~fin
-        Ok(())
+        Ok(PreprocessOutput {
+            calls: outdir.clone(),
+            sample: sample_name.to_string(),
+            coverage: CoverageSummary::of_bam(&file_vg_aligned_sorted)?,
+        })
     }
 }
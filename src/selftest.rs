@@ -0,0 +1,144 @@
+use crate::benchmark;
+use crate::errors::OrthanqError;
+use crate::utils::{index_bcf, open_bcf, require_tools};
+use anyhow::{anyhow, Result};
+use rust_htslib::bcf::{self, Read};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+//external tools that are required by the preprocessing (see preprocess::hla and preprocess::virus)
+pub const PREPROCESS_TOOLS: [&str; 6] =
+    ["bwa", "samtools", "vg", "varlociraptor", "bgzip", "tabix"];
+
+//StageResult holds the outcome of a stage of the self test, the error message if it failed.
+#[derive(Debug, Clone)]
+pub struct StageResult {
+    pub stage: String,
+    pub error: Option<String>,
+}
+
+impl StageResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+//run executes the stages of the self test on the bundled fixture and returns their results:
+//tools checks that the external tools of the preprocessing are in PATH, htslib converts the fixture calls
+//to an indexed BCF and reads it back, solver solves a minimal linear program in a child process (see probe_solver),
+//calling runs the HLA caller and checks its outputs. All stages are run, even if an earlier one fails.
+pub fn run() -> Vec<StageResult> {
+    let stages: Vec<(&str, fn(&Path) -> Result<()>)> = vec![
+        ("tools", check_tools),
+        ("htslib", check_htslib),
+        ("solver", check_solver),
+        ("calling", check_calling),
+    ];
+    stages
+        .into_iter()
+        .map(|(stage, check)| {
+            let error = tempfile::tempdir()
+                .map_err(|error| anyhow!(error))
+                .and_then(|dir| check(dir.path()))
+                .err()
+                .map(|error| error.to_string());
+            StageResult {
+                stage: stage.to_string(),
+                error,
+            }
+        })
+        .collect()
+}
+
+//summary renders one line per stage, e.g. "PASS calling" or "FAIL tools: vg was not found ...".
+pub fn summary(results: &[StageResult]) -> String {
+    results
+        .iter()
+        .map(|result| match &result.error {
+            None => format!("PASS {}", result.stage),
+            Some(error) => format!("FAIL {}: {}", result.stage, error),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn check_tools(_dir: &Path) -> Result<()> {
+    let missing = PREPROCESS_TOOLS
+        .iter()
+        .filter(|tool| require_tools(&[**tool]).is_err())
+        .cloned()
+        .collect::<Vec<&str>>();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} not found in PATH, the preprocessing won't work",
+            missing.join(", ")
+        ))
+    }
+}
+
+fn check_htslib(dir: &Path) -> Result<()> {
+    let calls = dir.join("calls.vcf");
+    let converted = dir.join("calls.bcf");
+    fs::write(&calls, include_str!("../resources/benchmark/calls.vcf"))?;
+    let mut reader = bcf::Reader::from_path(&calls)?;
+    let header = bcf::Header::from_template(reader.header());
+    let mut expected = 0;
+    {
+        let mut writer = bcf::Writer::from_path(&converted, &header, false, bcf::Format::Bcf)?;
+        for record in reader.records() {
+            writer.write(&record?)?;
+            expected += 1;
+        }
+    }
+    index_bcf(&converted)?;
+    let found = open_bcf(&converted)?.records().count();
+    if found != expected {
+        return Err(anyhow!(
+            "read {} records back from the BCF, expected {}",
+            found,
+            expected
+        ));
+    }
+    Ok(())
+}
+
+//probe_solver runs 'program solver-probe' (see calling::haplotypes::haplotypes::solve_probe) in a child process.
+//An abort within CBC or a CBC library that fails to load kills the process instead of returning an error, hence an
+//abnormal exit of the child is reported as SolverUnavailable, with the last line of its stderr (e.g. of the loader).
+pub fn probe_solver(program: &Path) -> Result<()> {
+    let output = Command::new(program).arg("solver-probe").output()?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let reason = stderr
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .map(|line| format!("{} ({})", line.trim(), output.status))
+        .unwrap_or_else(|| output.status.to_string());
+    Err(OrthanqError::SolverUnavailable { reason }.into())
+}
+
+fn check_solver(_dir: &Path) -> Result<()> {
+    probe_solver(&std::env::current_exe()?)
+}
+
+fn check_calling(dir: &Path) -> Result<()> {
+    let outdir = benchmark::call_fixture(dir)?;
+    for output in ["benchmark.csv", "manifest.json", "final_solution.json"].iter() {
+        if !outdir.join(output).exists() {
+            return Err(anyhow!("the caller did not write {}", output));
+        }
+    }
+    let rows = csv::Reader::from_path(outdir.join("benchmark.csv"))?
+        .records()
+        .count();
+    if rows == 0 {
+        return Err(anyhow!("the caller did not report any solution"));
+    }
+    Ok(())
+}
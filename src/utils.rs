@@ -0,0 +1,151 @@
+use crate::errors::OrthanqError;
+use anyhow::Result;
+use flate2::read::MultiGzDecoder;
+use log::info;
+use quick_xml::reader::Reader as xml_reader;
+use rust_htslib::bcf::{self, Read};
+use std::fs;
+use std::io::{BufRead, BufReader, Read as _};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use url::Url;
+
+//index_bcf makes sure that the given BCF file is coordinate-sorted and accompanied by a CSI index (<path>.csi),
+//so that it can directly be used by e.g. IGV or bcftools. Unsorted files are sorted in place before indexing.
+pub fn index_bcf(path: &Path) -> Result<()> {
+    let mut reader = bcf::Reader::from_path(path)?;
+    let mut records = Vec::new();
+    let mut sorted = true;
+    let mut last = None;
+    for record in reader.records() {
+        let record = record?;
+        let position = (record.rid(), record.pos());
+        if let Some(last) = last {
+            if position < last {
+                sorted = false;
+            }
+        }
+        last = Some(position);
+        records.push(record);
+    }
+    if !sorted {
+        //write the sorted records next to the input and replace it afterwards
+        records.sort_by_key(|record| (record.rid(), record.pos()));
+        let sorted_path = path.with_extension("sorted.bcf");
+        {
+            let header = bcf::Header::from_template(reader.header());
+            let mut writer =
+                bcf::Writer::from_path(&sorted_path, &header, false, bcf::Format::Bcf)?;
+            for record in records.iter_mut() {
+                writer.translate(record);
+                writer.write(record)?;
+            }
+        }
+        fs::rename(&sorted_path, path)?;
+    }
+    bcf::index::build(path, None, 1, bcf::index::Type::Csi(14))?;
+    Ok(())
+}
+
+//open_xml creates an XML reader for the given file. Files ending with .gz (e.g. the hla.xml.gz distributed by IMGT)
+//are decompressed on the fly.
+pub fn open_xml(path: &Path) -> Result<xml_reader<Box<dyn BufRead>>> {
+    let file = fs::File::open(path).map_err(|source| OrthanqError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let input: Box<dyn BufRead> = if path.extension().map_or(false, |ext| ext == "gz") {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    Ok(xml_reader::from_reader(input))
+}
+
+//is_remote returns whether the path is a URL that has to be opened via htslib's remote file support.
+pub fn is_remote(path: &Path) -> bool {
+    path.to_str().map_or(false, |path| {
+        ["http://", "https://", "s3://"]
+            .iter()
+            .any(|prefix| path.starts_with(prefix))
+    })
+}
+
+//open_bcf opens a local BCF/VCF file or, for http(s):// and s3:// URLs, a remote one.
+//Remote files require htslib to be built with libcurl (feature 'remote'), an index next to the file
+//is used by htslib where needed. S3 credentials are taken from the usual AWS environment variables.
+pub fn open_bcf(path: &Path) -> Result<bcf::Reader> {
+    if is_remote(path) {
+        let url = Url::parse(path.to_str().unwrap())?;
+        Ok(bcf::Reader::from_url(&url).map_err(OrthanqError::from)?)
+    } else {
+        Ok(bcf::Reader::from_path(path).map_err(OrthanqError::from)?)
+    }
+}
+
+//prepare_genome makes sure that the genome can be used by bwa, samtools and varlociraptor, which all expect an
+//uncompressed FASTA with a faidx index (<genome>.fai). A gzipped genome is decompressed into the given directory. A
+//genome without index is linked into the given directory and indexed there with samtools faidx, such that nothing is
+//written next to the input. The returned path is the genome to use.
+pub fn prepare_genome(genome: &Path, dir: &Path) -> Result<PathBuf> {
+    let open = |path: &Path| {
+        fs::File::open(path).map_err(|source| OrthanqError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    };
+    //gzip files start with the magic bytes 1f 8b, regardless of their extension
+    let mut magic = [0u8; 2];
+    let gzipped = open(genome)?.read_exact(&mut magic).is_ok() && magic == [0x1f, 0x8b];
+    let genome = if gzipped {
+        let decompressed = dir.join(genome.file_stem().unwrap());
+        info!(
+            "decompressing the gzipped genome {} to {}",
+            genome.display(),
+            decompressed.display()
+        );
+        let mut output = fs::File::create(&decompressed)?;
+        std::io::copy(&mut MultiGzDecoder::new(open(genome)?), &mut output)?;
+        decompressed
+    } else if PathBuf::from(format!("{}.fai", genome.display())).exists() {
+        return Ok(genome.to_path_buf());
+    } else {
+        let linked = dir.join(genome.file_name().unwrap());
+        std::os::unix::fs::symlink(fs::canonicalize(genome)?, &linked)?;
+        linked
+    };
+    info!("creating the faidx index of {}", genome.display());
+    let output = Command::new("samtools")
+        .arg("faidx")
+        .arg(&genome)
+        .output()
+        .map_err(|error| OrthanqError::InvalidGenome {
+            path: genome.clone(),
+            reason: format!("samtools faidx could not be run ({})", error),
+        })?;
+    if !output.status.success() {
+        return Err(OrthanqError::InvalidGenome {
+            path: genome,
+            reason: format!(
+                "samtools faidx failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        }
+        .into());
+    }
+    Ok(genome)
+}
+
+//require_tools makes sure that the given external programs can be found in PATH before any step is started.
+pub fn require_tools(programs: &[&str]) -> Result<()> {
+    let paths = std::env::var_os("PATH").unwrap_or_default();
+    for program in programs {
+        if !std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()) {
+            return Err(OrthanqError::ToolNotFound {
+                program: program.to_string(),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
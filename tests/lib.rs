@@ -1,5 +1,91 @@
 use rust_htslib::bcf;
 
+//PATH is shared by all test threads, hence it is locked while the stubs of stub_tools are in it
+static PATH_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+//StubTools are the external tools replaced by scripts that log each invocation to 'log' instead of executing it.
+//The scripts are first in PATH until the stubs are dropped.
+struct StubTools {
+    log: std::path::PathBuf,
+    path: std::ffi::OsString,
+    _lock: std::sync::MutexGuard<'static, ()>,
+}
+
+impl Drop for StubTools {
+    fn drop(&mut self) {
+        std::env::set_var("PATH", &self.path);
+    }
+}
+
+//stub_tools writes the stubs of bwa, samtools, vg and varlociraptor to 'dir'/bin. 'script' is run by each stub after
+//logging, e.g. to write an output of one of the tools (the tool is given as $0 and its arguments as $1...).
+fn stub_tools(dir: &std::path::Path, script: &str) -> StubTools {
+    use std::os::unix::fs::PermissionsExt;
+
+    let lock = PATH_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let bin = dir.join("bin");
+    let log = dir.join("invocations.txt");
+    std::fs::create_dir_all(&bin).unwrap();
+    for program in ["bwa", "samtools", "vg", "varlociraptor"].iter() {
+        let stub = bin.join(program);
+        std::fs::write(
+            &stub,
+            format!(
+                "#!/bin/sh\necho \"{} $*\" >> {}\n{}",
+                program,
+                log.display(),
+                script
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&stub, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let mut paths = vec![bin];
+    paths.extend(std::env::split_paths(&path));
+    std::env::set_var("PATH", std::env::join_paths(paths).unwrap());
+    StubTools {
+        log,
+        path,
+        _lock: lock,
+    }
+}
+
+//small_fixture loads the small candidate panel of three haplotypes (A*01:01:01, A*02:01:01, A*03:01:01) and the calls
+//of a 0.5/0.3/0.2 mixture of them, with the candidate matrix and the haplotypes in the order of its columns.
+fn small_fixture() -> (
+    orthanq::calling::haplotypes::haplotypes::HaplotypeVariants,
+    orthanq::calling::haplotypes::haplotypes::VariantCalls,
+    orthanq::calling::haplotypes::haplotypes::CandidateMatrix,
+    Vec<orthanq::calling::haplotypes::haplotypes::Haplotype>,
+) {
+    use orthanq::calling::haplotypes::haplotypes::{
+        CandidateMatrix, Haplotype, HaplotypeVariants, VariantCalls,
+    };
+
+    let haplotype_variants =
+        HaplotypeVariants::new(&mut bcf::Reader::from_path("tests/small_candidates.vcf").unwrap())
+            .unwrap();
+    let variant_calls =
+        VariantCalls::new(&mut bcf::Reader::from_path("tests/small_calls.vcf").unwrap()).unwrap();
+    let candidate_matrix = CandidateMatrix::new(&haplotype_variants).unwrap();
+    let haplotypes: Vec<Haplotype> = haplotype_variants
+        .values()
+        .next()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect();
+    (
+        haplotype_variants,
+        variant_calls,
+        candidate_matrix,
+        haplotypes,
+    )
+}
+
 #[test]
 fn check_haplotype_fractions_5050() {
     let mut output = std::path::PathBuf::new();
@@ -51,3 +137,4409 @@ fn check_haplotype_fractions_5050() {
         assert!(check);
     }
 }
+
+#[test]
+fn check_phased_genotypes_are_present() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        Haplotype, HaplotypeVariants, VariantID, VariantStatus,
+    };
+
+    let haplotype_variants =
+        HaplotypeVariants::new(&mut bcf::Reader::from_path("tests/phased_candidates.vcf").unwrap())
+            .unwrap();
+
+    //phased (1|1) and unphased (1/1) genotypes must both be recorded as present
+    let first = &haplotype_variants[&VariantID(1)];
+    assert_eq!(
+        first[&Haplotype("A*01:01:01".to_string())],
+        (VariantStatus::Present, true)
+    );
+    assert_eq!(
+        first[&Haplotype("A*02:01:01".to_string())],
+        (VariantStatus::NotPresent, true)
+    );
+    assert_eq!(
+        first[&Haplotype("A*03:01:01".to_string())],
+        (VariantStatus::Present, true)
+    );
+    let second = &haplotype_variants[&VariantID(2)];
+    assert_eq!(
+        second[&Haplotype("A*02:01:01".to_string())],
+        (VariantStatus::Present, true)
+    );
+    assert_eq!(
+        second[&Haplotype("A*03:01:01".to_string())],
+        (VariantStatus::NotPresent, false)
+    );
+}
+
+#[test]
+fn check_panel_summary_lists_all_samples() {
+    use orthanq::calling::haplotypes::haplotypes::{summarize_panel, Haplotype};
+
+    let mut reader = bcf::Reader::from_path("tests/phased_candidates.vcf").unwrap();
+    let summary = summarize_panel(&mut reader).unwrap();
+    assert_eq!(summary.len(), 3);
+    assert_eq!(summary[&Haplotype("A*01:01:01".to_string())], 2);
+    assert_eq!(summary[&Haplotype("A*02:01:01".to_string())], 2);
+    assert_eq!(summary[&Haplotype("A*03:01:01".to_string())], 1);
+}
+
+#[test]
+fn check_mixture_entropy() {
+    use orthanq::calling::haplotypes::haplotypes::mixture_entropy;
+
+    let even = mixture_entropy(&[0.5, 0.5, 0.0]);
+    let skewed = mixture_entropy(&[0.9, 0.1, 0.0]);
+    assert!((even - 1.0).abs() < 1e-9);
+    assert!(skewed < even);
+    assert_eq!(mixture_entropy(&[1.0, 0.0]), 0.0);
+}
+
+#[test]
+fn check_integer_selection_respects_max_haplotypes() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        linear_program, Haplotype, LinearProgramSettings,
+    };
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let outdir = tempfile::tempdir().unwrap();
+
+    //the continuous linear program explains the calls with all three haplotypes
+    let lp_haplotypes = linear_program(
+        &outdir.path().join("out.csv"),
+        &candidate_matrix,
+        &haplotypes,
+        &variant_calls,
+        &LinearProgramSettings::default(),
+    )
+    .unwrap()
+    .lp_haplotypes;
+    assert_eq!(lp_haplotypes.len(), 3);
+
+    //the mixed integer program is only allowed to select a single haplotype
+    let lp_haplotypes = linear_program(
+        &outdir.path().join("out.csv"),
+        &candidate_matrix,
+        &haplotypes,
+        &variant_calls,
+        &LinearProgramSettings {
+            integer_selection: true,
+            max_haplotypes: Some(1),
+            ..Default::default()
+        },
+    )
+    .unwrap()
+    .lp_haplotypes;
+    assert_eq!(lp_haplotypes, vec![Haplotype("A*01:01:01".to_string())]);
+}
+
+#[test]
+fn check_rejected_variant_counts() {
+    use orthanq::calling::haplotypes::haplotypes::{VariantCalls, VariantCallsOptions, VariantID};
+
+    let (variant_calls, rejected) = VariantCalls::new_with_rejections(
+        &mut bcf::Reader::from_path("tests/rejected_calls.vcf").unwrap(),
+        &VariantCallsOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(variant_calls.len(), 1);
+    assert_eq!(rejected.zero_depth, vec![VariantID(2), VariantID(3)]);
+    assert_eq!(rejected.malformed_afd, vec![VariantID(4)]);
+    assert_eq!(
+        rejected.summary(),
+        "dropped 3: 2 DP0, 1 bad-AFD, 0 missing-AFD, 0 low-quality"
+    );
+}
+
+#[test]
+fn check_rejected_variants_are_written() {
+    let outdir = tempfile::tempdir().unwrap();
+    let rejected = outdir.path().join("rejected.csv");
+    //the model computation is stopped right away, the rejections are written when the calls are parsed
+    orthanq::calling::haplotypes::hla::CallerBuilder::default()
+        .haplotype_variants(bcf::Reader::from_path("tests/small_candidates.vcf").unwrap())
+        .variant_calls(bcf::Reader::from_path("tests/rejected_calls.vcf").unwrap())
+        .xml("tests/hla.xml".into())
+        .common_variants(false)
+        .enable_equivalence_class_constraint(false)
+        .outcsv(outdir.path().join("out.csv"))
+        .prior("diploid".to_string())
+        .lp_cutoff(0.01)
+        .threshold_equivalence_class(1)
+        .extend_haplotypes(Some(true))
+        .num_extend_haplotypes(3)
+        .timeout(Some(0))
+        .rejected_variants(Some(rejected.clone()))
+        .build()
+        .unwrap()
+        .call()
+        .unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(&rejected).unwrap(),
+        "variant,reason\n2,DP0\n3,DP0\n4,bad-AFD\n"
+    );
+}
+
+#[test]
+fn check_extension_by_variant_overlap() {
+    use orthanq::calling::haplotypes::haplotypes::{extend_lp_haplotypes, Haplotype, VariantID};
+    use std::collections::BTreeMap;
+
+    let selected = Haplotype("A*01:01:01".to_string());
+    let similar = Haplotype("A*01:02:01".to_string());
+    let mut haplotype_dict = BTreeMap::new();
+    haplotype_dict.insert(selected.clone(), (1..=5).map(VariantID).collect::<Vec<_>>());
+    haplotype_dict.insert(similar.clone(), (1..=4).map(VariantID).collect::<Vec<_>>());
+
+    //the similar haplotype shares 4 out of 5 variants
+    let extended = extend_lp_haplotypes(&[selected.clone()], &haplotype_dict, 0, Some(0.75));
+    assert!(extended.contains(&selected));
+    assert!(extended.contains(&similar));
+
+    let extended = extend_lp_haplotypes(&[selected.clone()], &haplotype_dict, 0, Some(0.9));
+    assert!(!extended.contains(&similar));
+}
+
+#[test]
+fn check_preprocess_telemetry_has_row_per_step() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut telemetry = orthanq::preprocess::telemetry::Telemetry::new(dir.path());
+    let steps = vec!["first_step", "second_step", "third_step"];
+    for step in steps.iter() {
+        telemetry.run(step, "true", |command| command.status().unwrap());
+    }
+    let path = dir.path().join("preprocess_telemetry.tsv");
+    telemetry.write(&path).unwrap();
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_path(&path)
+        .unwrap();
+    let recorded = rdr
+        .records()
+        .map(|record| record.unwrap()[0].to_string())
+        .collect::<Vec<String>>();
+    assert_eq!(recorded, steps);
+}
+
+#[test]
+fn check_regions_bed_for_requested_genes() {
+    let genes = vec!["HLA-A".to_string(), "HLA-B".to_string()];
+    let bed = orthanq::preprocess::hla::regions_bed(&genes, "ucsc", 0).unwrap();
+    assert_eq!(
+        bed,
+        "chr6\t29941260\t29949572\nchr6\t31353872\t31367067\n".to_string()
+    );
+
+    //all genes are used by default and unknown genes are rejected
+    let all = orthanq::preprocess::hla::regions_bed(&[], "ensembl", 0).unwrap();
+    assert_eq!(all.lines().count(), 10);
+    assert!(orthanq::preprocess::hla::regions_bed(&["HLA-Z".to_string()], "ensembl", 0).is_err());
+}
+
+#[test]
+fn check_results_are_sorted_deterministically_by_density() {
+    use bio::stats::LogProb;
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{write_results, VafDenominator};
+    use orthanq::model::{Data, HaplotypeFractions};
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let data = Data::new(candidate_matrix, variant_calls);
+
+    let fractions = |values: [f64; 3]| {
+        HaplotypeFractions(
+            values
+                .iter()
+                .map(|value| NotNan::new(*value).unwrap())
+                .collect(),
+        )
+    };
+    //the worst event comes first and the two best events have tied densities, the tie is broken by the present
+    //haplotypes (see compare_events)
+    let event_posteriors = vec![
+        (fractions([1.0, 0.0, 0.0]), LogProb(0.2_f64.ln())),
+        (fractions([0.0, 1.0, 0.0]), LogProb(0.4_f64.ln())),
+        (fractions([0.0, 0.0, 1.0]), LogProb(0.4_f64.ln())),
+    ];
+    let outdir = tempfile::tempdir().unwrap();
+    let output = outdir.path().join("results.csv");
+    write_results(
+        &output,
+        &data,
+        &event_posteriors,
+        &haplotypes,
+        "uniform".to_string(),
+        false,
+        None,
+        None,
+        VafDenominator::default(),
+        1e-6,
+    )
+    .unwrap();
+
+    //the same posteriors in any order give the same results table
+    let expected = std::fs::read_to_string(&output).unwrap();
+    for order in &[[0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]] {
+        let permuted = order
+            .iter()
+            .map(|i| event_posteriors[*i].clone())
+            .collect::<Vec<_>>();
+        let permuted_output = outdir.path().join("permuted.csv");
+        write_results(
+            &permuted_output,
+            &data,
+            &permuted,
+            &haplotypes,
+            "uniform".to_string(),
+            false,
+            None,
+            None,
+            VafDenominator::default(),
+            1e-6,
+        )
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&permuted_output).unwrap(), expected);
+    }
+
+    let mut rdr = csv::Reader::from_path(&output).unwrap();
+    let rows = rdr
+        .records()
+        .map(|record| {
+            let record = record.unwrap();
+            (
+                record[1].to_string(),
+                record[3].to_string(),
+                record[4].to_string(),
+            )
+        })
+        .collect::<Vec<(String, String, String)>>();
+    assert_eq!(
+        rows,
+        vec![
+            ("1".to_string(), "1.00".to_string(), "+0.00e0".to_string()),
+            (
+                "1.00".to_string(),
+                "+0.00e0".to_string(),
+                "1.00".to_string()
+            ),
+            (
+                "0.50".to_string(),
+                "+0.00e0".to_string(),
+                "+0.00e0".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn check_lp_vs_posterior_lists_both_fractions() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        linear_program, write_lp_vs_posterior, LinearProgramSettings,
+    };
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let outdir = tempfile::tempdir().unwrap();
+    let outcsv = outdir.path().join("out.csv");
+
+    let solution = linear_program(
+        &outcsv,
+        &candidate_matrix,
+        &haplotypes,
+        &variant_calls,
+        &LinearProgramSettings::default(),
+    )
+    .unwrap();
+    let (lp_haplotypes, lp_fractions) = (solution.lp_haplotypes, solution.fractions);
+    assert_eq!(
+        lp_fractions.keys().cloned().collect::<Vec<_>>(),
+        lp_haplotypes
+    );
+
+    let posterior_fractions = vec![0.5, 0.3, 0.2];
+    write_lp_vs_posterior(&outcsv, &lp_fractions, &haplotypes, &posterior_fractions).unwrap();
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_path(outdir.path().join("lp_vs_posterior.tsv"))
+        .unwrap();
+    assert_eq!(
+        rdr.headers().unwrap().iter().collect::<Vec<_>>(),
+        vec!["haplotype", "lp_fraction", "posterior_fraction"]
+    );
+    let records = rdr.records().map(|r| r.unwrap()).collect::<Vec<_>>();
+    assert_eq!(records.len(), haplotypes.len());
+    for (record, (haplotype, posterior_fraction)) in records
+        .iter()
+        .zip(haplotypes.iter().zip(posterior_fractions.iter()))
+    {
+        assert_eq!(&record[0], haplotype.as_str());
+        assert!(record[1].parse::<f64>().is_ok());
+        assert_eq!(record[2], format!("{:.2}", posterior_fraction));
+    }
+}
+
+#[test]
+fn check_timeout_writes_partial_output() {
+    let outdir = tempfile::tempdir().unwrap();
+    let output = outdir.path().join("timed_out.csv");
+    orthanq::calling::haplotypes::hla::CallerBuilder::default()
+        .haplotype_variants(bcf::Reader::from_path("tests/B.vcf").unwrap())
+        .variant_calls(
+            bcf::Reader::from_path("tests/Sample_HLA00318-0.5_HLA00319-0.5_B.bcf").unwrap(),
+        )
+        .xml("tests/hla.xml".into())
+        .common_variants(false)
+        .enable_equivalence_class_constraint(false)
+        .outcsv(output.clone())
+        .prior("diploid".to_string())
+        .lp_cutoff(0.01)
+        .threshold_equivalence_class(1)
+        .extend_haplotypes(Some(true))
+        .num_extend_haplotypes(3)
+        .timeout(Some(0))
+        .build()
+        .unwrap()
+        .call()
+        .unwrap();
+
+    //the best-so-far posteriors are written
+    let mut rdr = csv::Reader::from_path(&output).unwrap();
+    assert!(rdr.records().count() >= 1);
+
+    //and the run is flagged as timed out
+    let manifest: serde_json::Value =
+        serde_json::from_reader(std::fs::File::open(outdir.path().join("manifest.json")).unwrap())
+            .unwrap();
+    assert_eq!(manifest["timed_out"], serde_json::Value::Bool(true));
+}
+
+#[test]
+fn check_bcf_output_is_sorted_and_indexed() {
+    use rust_htslib::bcf::Read;
+
+    //write the unsorted calls as bcf
+    let outdir = tempfile::tempdir().unwrap();
+    let path = outdir.path().join("calls.bcf");
+    {
+        let mut reader = bcf::Reader::from_path("tests/unsorted_calls.vcf").unwrap();
+        let header = bcf::Header::from_template(reader.header());
+        let mut writer = bcf::Writer::from_path(&path, &header, false, bcf::Format::Bcf).unwrap();
+        for record in reader.records() {
+            let mut record = record.unwrap();
+            writer.translate(&mut record);
+            writer.write(&record).unwrap();
+        }
+    }
+
+    orthanq::utils::index_bcf(&path).unwrap();
+    assert!(outdir.path().join("calls.bcf.csi").exists());
+
+    //the index can be loaded and the records are in coordinate order
+    let mut reader = bcf::IndexedReader::from_path(&path).unwrap();
+    let rid = reader.header().name2rid(b"6").unwrap();
+    reader.fetch(rid, 0, None).unwrap();
+    let positions = reader
+        .records()
+        .map(|record| record.unwrap().pos())
+        .collect::<Vec<i64>>();
+    assert_eq!(positions, vec![99, 199, 299]);
+}
+
+#[test]
+fn check_config_file_with_cli_overrides() {
+    use orthanq::calling::haplotypes::config::CallerConfig;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.yaml");
+    std::fs::write(
+        &path,
+        "prior: diploid\nlp_cutoff: 0.05\nextend_haplotypes: true\nnum_extend_haplotypes: 2\nextension_overlap: 1\n",
+    )
+    .unwrap();
+    let config = CallerConfig::from_path(&path).unwrap();
+    assert_eq!(
+        config,
+        CallerConfig {
+            prior: Some("diploid".to_string()),
+            lp_cutoff: Some(0.05),
+            extend_haplotypes: Some(true),
+            num_extend_haplotypes: Some(2),
+            extension_overlap: Some(1.0),
+            ..Default::default()
+        }
+    );
+
+    //explicitly given parameters take precedence over the config file
+    let merged = config.merge(CallerConfig {
+        prior: Some("uniform".to_string()),
+        integer_selection: Some(true),
+        ..Default::default()
+    });
+    assert_eq!(merged.prior, Some("uniform".to_string()));
+    assert_eq!(merged.lp_cutoff, Some(0.05));
+    assert_eq!(merged.integer_selection, Some(true));
+    assert_eq!(merged.num_extend_haplotypes, Some(2));
+
+    //the builder can be populated from the merged config
+    let caller = merged
+        .apply_hla(
+            orthanq::calling::haplotypes::hla::CallerBuilder::default()
+                .haplotype_variants(bcf::Reader::from_path("tests/B.vcf").unwrap())
+                .variant_calls(
+                    bcf::Reader::from_path("tests/Sample_HLA00318-0.5_HLA00319-0.5_B.bcf").unwrap(),
+                )
+                .xml("tests/hla.xml".into())
+                .outcsv(dir.path().join("out.csv")),
+        )
+        .unwrap()
+        .build();
+    assert!(caller.is_ok());
+
+    //unknown parameters are rejected
+    std::fs::write(&path, "lp_cutof: 0.05\n").unwrap();
+    assert!(CallerConfig::from_path(&path).is_err());
+
+    //negative values of unsigned parameters are rejected instead of wrapping around
+    for parameter in ["timeout", "max_events", "threshold_equivalence_class"] {
+        std::fs::write(&path, format!("{}: -1\n", parameter)).unwrap();
+        assert!(CallerConfig::from_path(&path).is_err());
+    }
+}
+
+#[test]
+fn check_missing_afd_policy() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        MissingAfdPolicy, VariantCalls, VariantCallsOptions, VariantID,
+    };
+
+    //the second record passes all filters but has a "." AFD
+    let (variant_calls, rejected) = VariantCalls::new_with_rejections(
+        &mut bcf::Reader::from_path("tests/missing_afd_calls.vcf").unwrap(),
+        &VariantCallsOptions {
+            missing_afd: MissingAfdPolicy::Uninformative,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(variant_calls.len(), 2);
+    assert!(variant_calls[&VariantID(2)].1.is_empty());
+    assert!(rejected.is_empty());
+
+    let (variant_calls, rejected) = VariantCalls::new_with_rejections(
+        &mut bcf::Reader::from_path("tests/missing_afd_calls.vcf").unwrap(),
+        &VariantCallsOptions {
+            missing_afd: MissingAfdPolicy::Skip,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(variant_calls.len(), 1);
+    assert_eq!(rejected.missing_afd, vec![VariantID(2)]);
+}
+
+#[test]
+fn check_quality_flag_categories() {
+    use orthanq::calling::haplotypes::haplotypes::{QualityFlag, QualityThresholds};
+
+    let thresholds = QualityThresholds::default();
+    assert_eq!(thresholds.flag(25, 0.01, 40), QualityFlag::Pass);
+    //each criterion on its own can lead to a warning
+    assert_eq!(thresholds.flag(5, 0.01, 40), QualityFlag::Warn);
+    assert_eq!(thresholds.flag(25, 0.1, 40), QualityFlag::Warn);
+    assert_eq!(thresholds.flag(25, 0.01, 10), QualityFlag::Warn);
+    //and to a failure, which takes precedence over warnings
+    assert_eq!(thresholds.flag(2, 0.1, 10), QualityFlag::Fail);
+    assert_eq!(thresholds.flag(25, 0.2, 40), QualityFlag::Fail);
+    assert_eq!(thresholds.flag(25, 0.01, 3), QualityFlag::Fail);
+
+    //the thresholds are configurable
+    let lenient = QualityThresholds {
+        warn_coverage: 5,
+        fail_coverage: 1,
+        ..Default::default()
+    };
+    assert_eq!(lenient.flag(25, 0.01, 10), QualityFlag::Pass);
+}
+
+#[test]
+fn check_short_lp_time_limit_yields_solution() {
+    use orthanq::calling::haplotypes::haplotypes::{linear_program, LinearProgramSettings};
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let outdir = tempfile::tempdir().unwrap();
+    let lp = |integer_selection: bool| {
+        linear_program(
+            &outdir.path().join("out.csv"),
+            &candidate_matrix,
+            &haplotypes,
+            &variant_calls,
+            &LinearProgramSettings {
+                integer_selection,
+                max_haplotypes: Some(2),
+                lp_time_limit: Some(0.000001),
+                lp_gap: Some(0.5),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    };
+
+    //with and without integer selection, reaching the limit yields the best solution found so far instead of an error
+    for integer_selection in [true, false].iter() {
+        let solution = lp(*integer_selection);
+        assert!(!solution.lp_haplotypes.is_empty());
+        let total: f64 = solution.fractions.values().sum();
+        assert!((total - 1.0).abs() < 0.05);
+        //the continuous relaxation is only used if the mixed integer program stopped without a solution
+        assert!(!solution.relaxed || (*integer_selection && solution.stopped_at_limit));
+        if *integer_selection && !solution.relaxed {
+            assert!(solution.lp_haplotypes.len() <= 2);
+        }
+    }
+}
+
+#[test]
+fn check_lp_errors_other_than_time_limit_are_returned() {
+    use orthanq::calling::haplotypes::haplotypes::{linear_program, LinearProgramSettings};
+    use orthanq::errors::OrthanqError;
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let outdir = tempfile::tempdir().unwrap();
+
+    //no haplotype may be selected while the fractions have to sum up to 1, the continuous relaxation
+    //would be feasible, but the infeasibility is not hidden behind it
+    let error = linear_program(
+        &outdir.path().join("out.csv"),
+        &candidate_matrix,
+        &haplotypes,
+        &variant_calls,
+        &LinearProgramSettings {
+            integer_selection: true,
+            max_haplotypes: Some(0),
+            lp_time_limit: Some(60.0),
+            ..Default::default()
+        },
+    )
+    .unwrap_err();
+    assert!(
+        matches!(
+            error.downcast_ref::<OrthanqError>(),
+            Some(OrthanqError::InfeasibleLinearProgram { .. })
+        ),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn check_candidate_matrix_column_selection() {
+    use orthanq::calling::haplotypes::haplotypes::CandidateMatrix;
+
+    let (haplotype_variants, _, candidate_matrix, haplotypes) = small_fixture();
+
+    //selecting columns yields the same matrix as rebuilding it from the filtered haplotype variants
+    let selected = vec![haplotypes[2].clone(), haplotypes[0].clone()];
+    assert_eq!(
+        candidate_matrix.select_haplotypes(&haplotypes, &selected),
+        CandidateMatrix::new(&haplotype_variants.filter_for_haplotypes(&selected).unwrap())
+            .unwrap()
+    );
+}
+
+#[test]
+fn check_sample_selection_in_multi_sample_calls() {
+    use orthanq::calling::haplotypes::haplotypes::{VariantCalls, VariantCallsOptions, VariantID};
+
+    //by default, the first sample is used
+    let variant_calls =
+        VariantCalls::new(&mut bcf::Reader::from_path("tests/multi_sample_calls.vcf").unwrap())
+            .unwrap();
+    assert_eq!(variant_calls[&VariantID(1)].0, 0.5);
+
+    //the second sample is selected by name
+    let (variant_calls, rejected) = VariantCalls::new_with_rejections(
+        &mut bcf::Reader::from_path("tests/multi_sample_calls.vcf").unwrap(),
+        &VariantCallsOptions {
+            sample: Some("second".to_string()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(variant_calls[&VariantID(1)].0, 0.25);
+    assert_eq!(variant_calls.min_read_depth(), Some(40));
+    //the second sample has no coverage at the second variant
+    assert_eq!(rejected.zero_depth, vec![VariantID(2)]);
+
+    //unknown samples are rejected
+    assert!(VariantCalls::new_with_rejections(
+        &mut bcf::Reader::from_path("tests/multi_sample_calls.vcf").unwrap(),
+        &VariantCallsOptions {
+            sample: Some("third".to_string()),
+            ..Default::default()
+        },
+    )
+    .is_err());
+}
+
+#[test]
+fn check_evidence_prefers_well_fitting_panel() {
+    use orthanq::calling::haplotypes::haplotypes::PriorTypes;
+    use orthanq::model::{log_evidence, Data};
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+
+    //the calls are explained exactly by a mixture of all three haplotypes (0.5, 0.3, 0.2),
+    //without the third haplotype, the third and fourth variant cannot be explained
+    let full_data = Data::new(candidate_matrix.clone(), variant_calls.clone());
+    let reduced_haplotypes = haplotypes[..2].to_vec();
+    let reduced_data = Data::new(
+        candidate_matrix.select_haplotypes(&haplotypes, &reduced_haplotypes),
+        variant_calls,
+    );
+
+    let full_evidence = log_evidence(&full_data, &haplotypes, PriorTypes::Uniform);
+    let reduced_evidence = log_evidence(&reduced_data, &reduced_haplotypes, PriorTypes::Uniform);
+    assert!(full_evidence.0.is_finite());
+    assert!(full_evidence > reduced_evidence);
+}
+
+#[test]
+fn check_convert_to_g_reads_gzipped_xml() {
+    let outdir = tempfile::tempdir().unwrap();
+    let caller = orthanq::calling::haplotypes::hla::CallerBuilder::default()
+        .haplotype_variants(bcf::Reader::from_path("tests/B.vcf").unwrap())
+        .variant_calls(
+            bcf::Reader::from_path("tests/Sample_HLA00318-0.5_HLA00319-0.5_B.bcf").unwrap(),
+        )
+        .xml("tests/small_hla.xml.gz".into())
+        .common_variants(false)
+        .enable_equivalence_class_constraint(false)
+        .outcsv(outdir.path().join("out.csv"))
+        .prior("diploid".to_string())
+        .lp_cutoff(0.01)
+        .threshold_equivalence_class(1)
+        .extend_haplotypes(Some(true))
+        .num_extend_haplotypes(3)
+        .build()
+        .unwrap();
+
+    //the fixture contains three alleles of the same g group, the second one is unconfirmed
+    let allele_to_g_groups = caller.convert_to_g().unwrap();
+    assert_eq!(
+        allele_to_g_groups.into_iter().collect::<Vec<_>>(),
+        vec![
+            ("A*01:01:01:01".to_string(), "A*01:01:01G".to_string()),
+            ("A*01:01:01:03".to_string(), "A*01:01:01G".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn check_long_format_has_one_row_per_event_and_haplotype() {
+    use bio::stats::LogProb;
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{write_results_long, Haplotype};
+    use orthanq::model::HaplotypeFractions;
+
+    let haplotypes = vec![
+        Haplotype("A*01:01:01".to_string()),
+        Haplotype("A*02:01:01".to_string()),
+        Haplotype("A*03:01:01".to_string()),
+    ];
+    let fractions = |values: [f64; 3]| {
+        HaplotypeFractions(
+            values
+                .iter()
+                .map(|value| NotNan::new(*value).unwrap())
+                .collect(),
+        )
+    };
+    let event_posteriors = vec![
+        (fractions([0.5, 0.5, 0.0]), LogProb(0.2_f64.ln())),
+        (fractions([0.0, 0.5, 0.5]), LogProb(0.7_f64.ln())),
+        (fractions([1.0, 0.0, 0.0]), LogProb(0.1_f64.ln())),
+    ];
+    let outdir = tempfile::tempdir().unwrap();
+    write_results_long(
+        &outdir.path().join("out.csv"),
+        &event_posteriors,
+        &haplotypes,
+        1e-6,
+    )
+    .unwrap();
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_path(outdir.path().join("fractions_long.tsv"))
+        .unwrap();
+    assert_eq!(
+        rdr.headers().unwrap().iter().collect::<Vec<_>>(),
+        vec!["event", "haplotype", "fraction", "density"]
+    );
+    let records = rdr.records().map(|r| r.unwrap()).collect::<Vec<_>>();
+    assert_eq!(records.len(), event_posteriors.len() * haplotypes.len());
+    //events are ordered by density, as in the wide table
+    assert_eq!(&records[0][0], "0");
+    assert_eq!(&records[0][1], "A*01:01:01");
+    assert_eq!(&records[1][2], "0.5");
+    assert_eq!(records[2][3].parse::<f64>().unwrap(), 0.7_f64.ln().exp());
+}
+
+#[test]
+fn check_log_densities_match_results() {
+    use bio::stats::LogProb;
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{
+        write_log_densities, write_results, VafDenominator,
+    };
+    use orthanq::model::{Data, HaplotypeFractions};
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let data = Data::new(candidate_matrix, variant_calls);
+    let fractions = |values: [f64; 3]| {
+        HaplotypeFractions(
+            values
+                .iter()
+                .map(|value| NotNan::new(*value).unwrap())
+                .collect(),
+        )
+    };
+    let event_posteriors = vec![
+        (fractions([0.5, 0.5, 0.0]), LogProb(0.25_f64.ln())),
+        (fractions([0.0, 0.5, 0.5]), LogProb(0.7_f64.ln())),
+        (fractions([1.0, 0.0, 0.0]), LogProb(1e-5_f64.ln())),
+    ];
+    let outdir = tempfile::tempdir().unwrap();
+    let output = outdir.path().join("results.csv");
+    write_results(
+        &output,
+        &data,
+        &event_posteriors,
+        &haplotypes,
+        "uniform".to_string(),
+        false,
+        None,
+        None,
+        VafDenominator::default(),
+        1e-6,
+    )
+    .unwrap();
+    write_log_densities(&output, &event_posteriors, &haplotypes, 1e-6).unwrap();
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_path(outdir.path().join("log_densities.tsv"))
+        .unwrap();
+    assert_eq!(
+        rdr.headers().unwrap().iter().collect::<Vec<_>>(),
+        vec![
+            "log_density",
+            "log_odds",
+            "A*01:01:01",
+            "A*02:01:01",
+            "A*03:01:01"
+        ]
+    );
+    let log_densities = rdr
+        .records()
+        .map(|record| record.unwrap()[0].parse::<f64>().unwrap())
+        .collect::<Vec<_>>();
+    let densities = csv::Reader::from_path(&output)
+        .unwrap()
+        .records()
+        .map(|record| record.unwrap()[0].parse::<f64>().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(log_densities.len(), densities.len());
+    //the rows are in the same order, the formatted densities only lose precision
+    assert_eq!(log_densities[0], 0.7_f64.ln());
+    for (log_density, density) in log_densities.iter().zip(densities.iter()) {
+        assert!((density.ln() - log_density).abs() < 1e-2);
+    }
+}
+
+#[test]
+fn check_panel_comparison() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        compare_panels, Haplotype, HaplotypeVariants, LinearProgramSettings, VariantID,
+    };
+
+    //the updated panel replaces A*03:01:01 by A*03:02:01, which lacks the third variant
+    let (first, variant_calls, _, _) = small_fixture();
+    let second = HaplotypeVariants::new(
+        &mut bcf::Reader::from_path("tests/small_candidates_updated.vcf").unwrap(),
+    )
+    .unwrap();
+
+    let comparison = compare_panels(
+        &first,
+        &second,
+        &variant_calls,
+        &LinearProgramSettings::default(),
+    )
+    .unwrap();
+    let all_variants = vec![VariantID(1), VariantID(2), VariantID(3), VariantID(4)];
+    assert_eq!(comparison.first.covered_variants, all_variants);
+    assert_eq!(comparison.second.covered_variants, all_variants);
+    assert_eq!(
+        comparison.first.unique_haplotypes,
+        vec![Haplotype("A*03:01:01".to_string())]
+    );
+    assert_eq!(
+        comparison.second.unique_haplotypes,
+        vec![Haplotype("A*03:02:01".to_string())]
+    );
+
+    //the calls are explained exactly by the first panel only
+    let first_residual = comparison.first.lp_residual.unwrap();
+    let second_residual = comparison.second.lp_residual.unwrap();
+    assert!(first_residual < 1e-6);
+    assert!(second_residual > first_residual);
+}
+
+#[test]
+fn check_lp_skips_variants_uncharacterized_in_panel() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        linear_program, CandidateMatrix, Haplotype, HaplotypeVariants, LinearProgramSettings,
+        VariantCalls, VariantID,
+    };
+
+    //A*01:01:01 is not characterized at the third variant, although the sample has reads there
+    let haplotype_variants = HaplotypeVariants::new(
+        &mut bcf::Reader::from_path("tests/uncharacterized_candidates.vcf").unwrap(),
+    )
+    .unwrap();
+    let variant_calls =
+        VariantCalls::new(&mut bcf::Reader::from_path("tests/small_calls.vcf").unwrap()).unwrap();
+    let candidate_matrix = CandidateMatrix::new(&haplotype_variants).unwrap();
+    let haplotypes: Vec<Haplotype> = haplotype_variants
+        .values()
+        .next()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect();
+    assert!(variant_calls.is_sample_covered(&VariantID(3), 1));
+
+    let outdir = tempfile::tempdir().unwrap();
+    let solution = linear_program(
+        &outdir.path().join("out.csv"),
+        &candidate_matrix,
+        &haplotypes,
+        &variant_calls,
+        &LinearProgramSettings::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        solution.considered_variants,
+        vec![VariantID(1), VariantID(2), VariantID(4)]
+    );
+}
+
+#[test]
+fn check_lp_skips_variants_uncovered_in_sample() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        linear_program, LinearProgramSettings, VariantCalls, VariantID,
+    };
+
+    //all haplotypes are characterized everywhere, but the sample has only 5 reads at the second variant
+    let (_, _, candidate_matrix, haplotypes) = small_fixture();
+    let variant_calls =
+        VariantCalls::new(&mut bcf::Reader::from_path("tests/low_depth_calls.vcf").unwrap())
+            .unwrap();
+    assert!(!variant_calls.is_sample_covered(&VariantID(2), 10));
+
+    let outdir = tempfile::tempdir().unwrap();
+    let lp = |min_sample_depth| {
+        linear_program(
+            &outdir.path().join("out.csv"),
+            &candidate_matrix,
+            &haplotypes,
+            &variant_calls,
+            &LinearProgramSettings {
+                min_sample_depth,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .considered_variants
+    };
+    assert_eq!(lp(1).len(), 4);
+    assert_eq!(lp(10), vec![VariantID(1), VariantID(3), VariantID(4)]);
+}
+
+#[test]
+fn check_lp_without_usable_variants_is_rejected() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        linear_program, CandidateMatrix, Haplotype, HaplotypeVariants, LinearProgramSettings,
+        VariantCalls,
+    };
+    use orthanq::errors::OrthanqError;
+
+    //at each variant, one of the haplotypes is not characterized, hence no variant passes the coverage gate
+    let haplotype_variants = HaplotypeVariants::new(
+        &mut bcf::Reader::from_path("tests/uncovered_candidates.vcf").unwrap(),
+    )
+    .unwrap();
+    let variant_calls =
+        VariantCalls::new(&mut bcf::Reader::from_path("tests/small_calls.vcf").unwrap()).unwrap();
+    let candidate_matrix = CandidateMatrix::new(&haplotype_variants).unwrap();
+    let haplotypes: Vec<Haplotype> = haplotype_variants
+        .values()
+        .next()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect();
+
+    let outdir = tempfile::tempdir().unwrap();
+    let error = linear_program(
+        &outdir.path().join("out.csv"),
+        &candidate_matrix,
+        &haplotypes,
+        &variant_calls,
+        &LinearProgramSettings::default(),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<OrthanqError>(),
+        Some(OrthanqError::NoUsableVariants {
+            variants: 4,
+            haplotypes: 3
+        })
+    ));
+    assert!(error.to_string().contains("coverage"));
+}
+
+#[test]
+fn check_outputs_are_reproducibly_ordered() {
+    use orthanq::calling::haplotypes::haplotypes::{linear_program, LinearProgramSettings};
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+
+    //extend by all haplotypes within two variants, such that every haplotype is added in the order of the extension
+    let run = || {
+        let outdir = tempfile::tempdir().unwrap();
+        let solution = linear_program(
+            &outdir.path().join("out.csv"),
+            &candidate_matrix,
+            &haplotypes,
+            &variant_calls,
+            &LinearProgramSettings {
+                extend_haplotypes: true,
+                num_variant_distance: 2,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let plot = std::fs::read_to_string(outdir.path().join("lp_solution.json")).unwrap();
+        (solution.extended_haplotypes, plot)
+    };
+    let (first_extended, first_plot) = run();
+    let (second_extended, second_plot) = run();
+    assert_eq!(first_extended, second_extended);
+    assert_eq!(first_plot, second_plot);
+}
+
+#[test]
+fn check_results_below_min_odds_are_omitted() {
+    use bio::stats::LogProb;
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{write_results, VafDenominator};
+    use orthanq::model::{Data, HaplotypeFractions};
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let data = Data::new(candidate_matrix, variant_calls);
+
+    let fractions = |values: [f64; 3]| {
+        HaplotypeFractions(
+            values
+                .iter()
+                .map(|value| NotNan::new(*value).unwrap())
+                .collect(),
+        )
+    };
+    //odds relative to the best event: 1, 0.5 and 0.05
+    let event_posteriors = vec![
+        (fractions([1.0, 0.0, 0.0]), LogProb(0.6_f64.ln())),
+        (fractions([0.0, 1.0, 0.0]), LogProb(0.3_f64.ln())),
+        (fractions([0.0, 0.0, 1.0]), LogProb(0.03_f64.ln())),
+    ];
+    let outdir = tempfile::tempdir().unwrap();
+    let output = outdir.path().join("results.csv");
+    let rows = |min_odds| {
+        write_results(
+            &output,
+            &data,
+            &event_posteriors,
+            &haplotypes,
+            "uniform".to_string(),
+            false,
+            min_odds,
+            None,
+            VafDenominator::default(),
+            1e-6,
+        )
+        .unwrap();
+        csv::Reader::from_path(&output).unwrap().records().count()
+    };
+    assert_eq!(rows(None), 3);
+    assert_eq!(rows(Some(0.1)), 2);
+    //the best record is always written
+    assert_eq!(rows(Some(2.0)), 1);
+}
+
+#[test]
+fn check_open_bcf_distinguishes_remote_paths() {
+    use orthanq::utils::{is_remote, open_bcf};
+    use std::path::Path;
+
+    assert!(is_remote(Path::new("https://example.org/candidates.bcf")));
+    assert!(is_remote(Path::new("s3://bucket/candidates.bcf")));
+    assert!(!is_remote(Path::new("tests/small_calls.vcf")));
+
+    //local files are opened as before
+    let variant_calls = orthanq::calling::haplotypes::haplotypes::VariantCalls::new(
+        &mut open_bcf(Path::new("tests/small_calls.vcf")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(variant_calls.len(), 4);
+}
+
+//requires network access and htslib with libcurl, run with
+//ORTHANQ_REMOTE_BCF=<url of an indexed BCF> cargo test --features remote -- --ignored
+#[test]
+#[ignore]
+fn check_open_remote_bcf() {
+    use rust_htslib::bcf::Read;
+
+    let url = std::env::var("ORTHANQ_REMOTE_BCF").expect("ORTHANQ_REMOTE_BCF has to be set");
+    let mut reader = orthanq::utils::open_bcf(std::path::Path::new(&url)).unwrap();
+    assert!(reader.records().next().is_some());
+}
+
+#[test]
+fn check_lp_posterior_divergence_is_reported() {
+    use orthanq::calling::haplotypes::haplotypes::{check_lp_posterior_consistency, Haplotype};
+    use std::collections::BTreeMap;
+
+    let first = Haplotype("A*01:01:01".to_string());
+    let second = Haplotype("A*02:01:01".to_string());
+    let third = Haplotype("A*03:01:01".to_string());
+    let mut lp_fractions = BTreeMap::new();
+    lp_fractions.insert(first.clone(), 0.5);
+    lp_fractions.insert(second.clone(), 0.5);
+
+    //the posterior agrees on the first haplotype but moves most of the second one to the third,
+    //which was not selected by the linear program
+    let final_haplotypes = vec![first, second.clone(), third];
+    let best_fractions = vec![0.45, 0.05, 0.5];
+    assert_eq!(
+        check_lp_posterior_consistency(&lp_fractions, &final_haplotypes, &best_fractions, 0.25),
+        vec![(second, 0.5, 0.05)]
+    );
+    assert!(
+        check_lp_posterior_consistency(&lp_fractions, &final_haplotypes, &best_fractions, 0.5)
+            .is_empty()
+    );
+}
+
+#[test]
+fn check_regions_bed_is_padded_by_flank() {
+    let bed = orthanq::preprocess::hla::regions_bed(&["HLA-A".to_string()], "ucsc", 1000).unwrap();
+    assert_eq!(bed, "chr6\t29940260\t29950572\n".to_string());
+
+    //starts are clamped at 0
+    let bed =
+        orthanq::preprocess::hla::regions_bed(&["A".to_string()], "ensembl", 30000000).unwrap();
+    assert_eq!(bed, "6\t0\t59949572\n".to_string());
+}
+
+#[test]
+fn check_regions_bed_from_custom_gene_regions() {
+    use orthanq::preprocess::hla::GeneRegions;
+    use std::path::Path;
+
+    //only the regions of the requested build are used, with the chromosome translated to the naming style
+    let gene_regions =
+        GeneRegions::from_path(Path::new("tests/custom_gene_regions.tsv"), "custom").unwrap();
+    assert_eq!(gene_regions.genes(), vec!["A", "MICA"]);
+    assert_eq!(
+        gene_regions.bed(&[], "ucsc", 0).unwrap(),
+        "chr6\t1000\t2000\nchr6\t5000\t6000\n".to_string()
+    );
+    assert_eq!(
+        gene_regions
+            .bed(&["HLA-MICA".to_string()], "ensembl", 100)
+            .unwrap(),
+        "6\t4900\t6100\n".to_string()
+    );
+    assert!(gene_regions.bed(&["B".to_string()], "ensembl", 0).is_err());
+
+    //builds that are not part of the table are rejected
+    assert!(GeneRegions::from_path(Path::new("tests/custom_gene_regions.tsv"), "GRCh37").is_err());
+
+    //the bundled table holds the GRCh38 regions
+    let grch38 =
+        GeneRegions::from_path(Path::new("tests/custom_gene_regions.tsv"), "GRCh38").unwrap();
+    assert_eq!(GeneRegions::bundled().genes().len(), 10);
+    assert_eq!(
+        GeneRegions::bundled()
+            .bed(&["A".to_string()], "ensembl", 0)
+            .unwrap(),
+        grch38.bed(&[], "ensembl", 0).unwrap()
+    );
+}
+
+#[test]
+fn check_haplotype_funnel_is_recorded() {
+    use rust_htslib::bcf::Read;
+
+    let outdir = tempfile::tempdir().unwrap();
+    let output = outdir.path().join("funnel.csv");
+    //the model computation is stopped right away, the funnel is recorded before
+    orthanq::calling::haplotypes::hla::CallerBuilder::default()
+        .haplotype_variants(bcf::Reader::from_path("tests/B.vcf").unwrap())
+        .variant_calls(
+            bcf::Reader::from_path("tests/Sample_HLA00318-0.5_HLA00319-0.5_B.bcf").unwrap(),
+        )
+        .xml("tests/hla.xml".into())
+        .common_variants(false)
+        .enable_equivalence_class_constraint(false)
+        .outcsv(output)
+        .prior("diploid".to_string())
+        .lp_cutoff(0.01)
+        .threshold_equivalence_class(1)
+        .extend_haplotypes(Some(true))
+        .num_extend_haplotypes(3)
+        .timeout(Some(0))
+        .build()
+        .unwrap()
+        .call()
+        .unwrap();
+
+    let manifest: serde_json::Value =
+        serde_json::from_reader(std::fs::File::open(outdir.path().join("manifest.json")).unwrap())
+            .unwrap();
+    let count = |stage: &str| manifest["haplotype_funnel"][stage].as_u64().unwrap();
+    let panel = bcf::Reader::from_path("tests/B.vcf")
+        .unwrap()
+        .header()
+        .sample_count() as u64;
+    assert_eq!(count("panel"), panel);
+    assert!(count("lp_selected") >= 1);
+    assert!(count("lp_selected") <= count("extended"));
+    assert!(count("extended") <= count("panel"));
+    assert_eq!(count("modeled"), count("extended"));
+}
+
+#[test]
+fn check_disjoint_variant_ids_are_rejected() {
+    use orthanq::calling::haplotypes::haplotypes::{HaplotypeVariants, VariantID};
+
+    //the calls have the IDs 11-14, the candidates 1-4
+    let haplotype_variants =
+        HaplotypeVariants::new(&mut bcf::Reader::from_path("tests/small_candidates.vcf").unwrap())
+            .unwrap();
+    let error = haplotype_variants
+        .check_overlap(&(11..=14).map(VariantID).collect::<Vec<_>>())
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("IDs 11-14"));
+    assert!(error.contains("IDs 1-4"));
+    assert!(haplotype_variants
+        .check_overlap(&[VariantID(4), VariantID(11)])
+        .is_ok());
+
+    //the caller fails before any computation
+    let outdir = tempfile::tempdir().unwrap();
+    let result = orthanq::calling::haplotypes::hla::CallerBuilder::default()
+        .haplotype_variants(bcf::Reader::from_path("tests/small_candidates.vcf").unwrap())
+        .variant_calls(bcf::Reader::from_path("tests/disjoint_calls.vcf").unwrap())
+        .xml("tests/hla.xml".into())
+        .common_variants(false)
+        .enable_equivalence_class_constraint(false)
+        .outcsv(outdir.path().join("out.csv"))
+        .prior("diploid".to_string())
+        .lp_cutoff(0.01)
+        .threshold_equivalence_class(1)
+        .extend_haplotypes(Some(true))
+        .num_extend_haplotypes(3)
+        .build()
+        .unwrap()
+        .call();
+    assert!(result.is_err());
+}
+
+#[test]
+fn check_imgt_status_annotation() {
+    use orthanq::calling::haplotypes::haplotypes::Haplotype;
+    use orthanq::calling::haplotypes::hla::write_allele_status;
+
+    let outdir = tempfile::tempdir().unwrap();
+    let caller = orthanq::calling::haplotypes::hla::CallerBuilder::default()
+        .haplotype_variants(bcf::Reader::from_path("tests/B.vcf").unwrap())
+        .variant_calls(
+            bcf::Reader::from_path("tests/Sample_HLA00318-0.5_HLA00319-0.5_B.bcf").unwrap(),
+        )
+        .xml("tests/small_hla.xml.gz".into())
+        .common_variants(false)
+        .enable_equivalence_class_constraint(false)
+        .outcsv(outdir.path().join("out.csv"))
+        .prior("diploid".to_string())
+        .lp_cutoff(0.01)
+        .threshold_equivalence_class(1)
+        .extend_haplotypes(Some(true))
+        .num_extend_haplotypes(3)
+        .build()
+        .unwrap();
+    let allele_status = caller.allele_status().unwrap();
+
+    //A*01:01:01:02N is unconfirmed, A*01:01:01 has confirmed alleles and B*07:02:01 is not in the fixture
+    let haplotypes = vec![
+        Haplotype("A*01:01:01".to_string()),
+        Haplotype("A*01:01:01:02N".to_string()),
+        Haplotype("B*07:02:01".to_string()),
+    ];
+    write_allele_status(
+        &outdir.path().join("out.csv"),
+        &haplotypes,
+        &[0.5, 0.5, 0.0],
+        &allele_status,
+    )
+    .unwrap();
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_path(outdir.path().join("allele_status.tsv"))
+        .unwrap();
+    let statuses = rdr
+        .records()
+        .map(|record| record.unwrap()[2].to_string())
+        .collect::<Vec<String>>();
+    assert_eq!(statuses, vec!["Confirmed", "Unconfirmed", "unknown-status"]);
+}
+
+#[test]
+fn check_benchmark_reports_metrics() {
+    let metrics = orthanq::benchmark::run().unwrap();
+    assert_eq!(metrics["variants"], serde_json::json!(4));
+    assert_eq!(metrics["panel_haplotypes"], serde_json::json!(3));
+    assert!(metrics["lp_time_s"].as_f64().unwrap() >= 0.0);
+    assert!(metrics["evaluated_events"].as_u64().unwrap() > 0);
+    assert!(metrics["total_time_s"].as_f64().unwrap() >= metrics["lp_time_s"].as_f64().unwrap());
+}
+
+#[test]
+fn check_results_join_candidates_and_calls_by_variant() {
+    use bio::stats::LogProb;
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{write_results, VafDenominator, VariantID};
+    use orthanq::model::{Data, HaplotypeFractions};
+
+    //the candidates contain the variants 1-4, the calls only 1, 3 and 4
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let variant_calls = variant_calls
+        .filter_variant_calls(&vec![VariantID(1), VariantID(3), VariantID(4)])
+        .unwrap();
+    let data = Data::new(candidate_matrix, variant_calls);
+
+    //the event explains all calls exactly
+    let event_posteriors = vec![(
+        HaplotypeFractions(
+            [0.5, 0.3, 0.2]
+                .iter()
+                .map(|value| NotNan::new(*value).unwrap())
+                .collect(),
+        ),
+        LogProb::ln_one(),
+    )];
+    let outdir = tempfile::tempdir().unwrap();
+    let output = outdir.path().join("results.csv");
+    write_results(
+        &output,
+        &data,
+        &event_posteriors,
+        &haplotypes,
+        "uniform".to_string(),
+        true,
+        None,
+        None,
+        VafDenominator::default(),
+        1e-6,
+    )
+    .unwrap();
+
+    let mut rdr = csv::Reader::from_path(&output).unwrap();
+    assert_eq!(
+        rdr.headers().unwrap().iter().skip(5).collect::<Vec<_>>(),
+        vec!["VariantID(1)", "VariantID(3)", "VariantID(4)"]
+    );
+    let record = rdr.records().next().unwrap().unwrap();
+    assert_eq!(
+        record.iter().skip(5).collect::<Vec<_>>(),
+        vec!["0.5:1.00", "0.2:1.00", "0.8:1.00"]
+    );
+}
+
+#[test]
+fn check_lp_and_likelihood_join_candidates_and_calls_by_variant() {
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{
+        linear_program, CandidateMatrix, LinearProgramSettings, VariantID,
+    };
+    use orthanq::model::{Data, HaplotypeFractions, ObservationModel, VarlociraptorLikelihood};
+
+    //the candidates contain the variants 1-4, the calls only 1, 3 and 4: the outcome has to be the same as with
+    //candidates restricted to the called variants
+    let (haplotype_variants, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let called = vec![VariantID(1), VariantID(3), VariantID(4)];
+    let variant_calls = variant_calls.filter_variant_calls(&called).unwrap();
+    let restricted_matrix = CandidateMatrix::new(
+        &haplotype_variants
+            .filter_haplotype_variants(&called)
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        haplotype_variants
+            .find_common_variants(&variant_calls, &haplotypes)
+            .unwrap(),
+        called
+    );
+
+    let outdir = tempfile::tempdir().unwrap();
+    let lp = |candidate_matrix: &CandidateMatrix| {
+        linear_program(
+            &outdir.path().join("out.csv"),
+            candidate_matrix,
+            &haplotypes,
+            &variant_calls,
+            &LinearProgramSettings::default(),
+        )
+        .unwrap()
+    };
+    let solution = lp(&candidate_matrix);
+    assert_eq!(solution.considered_variants, called);
+    assert_eq!(solution.fractions, lp(&restricted_matrix).fractions);
+
+    let fractions = HaplotypeFractions(
+        [0.5, 0.3, 0.2]
+            .iter()
+            .map(|value| NotNan::new(*value).unwrap())
+            .collect(),
+    );
+    let likelihood = |candidate_matrix: CandidateMatrix| {
+        VarlociraptorLikelihood::default().compute(
+            &fractions,
+            &Data::new(candidate_matrix, variant_calls.clone()),
+        )
+    };
+    assert_eq!(likelihood(candidate_matrix), likelihood(restricted_matrix));
+}
+
+#[test]
+fn check_plot_data_exported_as_csv() {
+    use orthanq::calling::haplotypes::haplotypes::plot_prediction;
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let outdir = tempfile::tempdir().unwrap();
+    let output = outdir.path().join("out").join("results.csv");
+    plot_prediction(
+        &output,
+        "final",
+        &candidate_matrix,
+        &haplotypes,
+        &variant_calls,
+        &vec![0.5, 0.5, 0.0],
+        true,
+    )
+    .unwrap();
+
+    let read_rows = |name: &str| {
+        let mut rdr = csv::Reader::from_path(outdir.path().join("out").join(name)).unwrap();
+        let header = rdr.headers().unwrap().iter().collect::<Vec<_>>().join(",");
+        let rows = rdr
+            .records()
+            .map(|record| record.unwrap().iter().collect::<Vec<_>>().join(","))
+            .collect::<Vec<String>>();
+        (header, rows)
+    };
+    //one row per variant and haplotype that carries the variant
+    assert_eq!(
+        read_rows("final_plot_variants.csv"),
+        (
+            "variant,vaf".to_string(),
+            vec!["1,0.5", "2,0.3", "3,0.2", "4,0.8", "4,0.8"]
+                .into_iter()
+                .map(String::from)
+                .collect()
+        )
+    );
+    assert_eq!(
+        read_rows("final_plot_haplotype_variants.csv"),
+        (
+            "variant,haplotype".to_string(),
+            vec![
+                "1,A*01:01:01",
+                "2,A*02:01:01",
+                "3,A*03:01:01",
+                "4,A*01:01:01",
+                "4,A*02:01:01"
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect()
+        )
+    );
+    assert_eq!(
+        read_rows("final_plot_haplotype_fractions.csv"),
+        (
+            "haplotype,fraction".to_string(),
+            vec![
+                "A*01:01:01,0.5",
+                "A*02:01:01,0.5",
+                "A*03:01:01,0.0",
+                "A*01:01:01,0.5",
+                "A*02:01:01,0.5"
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect()
+        )
+    );
+    assert!(outdir
+        .path()
+        .join("out")
+        .join("final_solution.json")
+        .exists());
+}
+
+#[test]
+fn check_tied_best_events_are_reported() {
+    use bio::stats::LogProb;
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{rank_events, select_best_event, Haplotype};
+    use orthanq::model::HaplotypeFractions;
+
+    let haplotypes = vec![
+        Haplotype("A*01:01:01".to_string()),
+        Haplotype("A*02:01:01".to_string()),
+        Haplotype("A*03:01:01".to_string()),
+    ];
+    let fractions = |values: [f64; 3]| {
+        HaplotypeFractions(
+            values
+                .iter()
+                .map(|value| NotNan::new(*value).unwrap())
+                .collect(),
+        )
+    };
+    //the first two events are tied, the lexicographically first typing (A*01:01:01/A*03:01:01) is reported
+    let event_posteriors = vec![
+        (fractions([0.0, 0.5, 0.5]), LogProb(0.45_f64.ln())),
+        (fractions([0.5, 0.0, 0.5]), LogProb(0.45_f64.ln())),
+        (fractions([0.5, 0.5, 0.0]), LogProb(0.1_f64.ln())),
+    ];
+    let best_event = select_best_event(&event_posteriors, &haplotypes, 1e-6);
+    assert!(best_event.is_ambiguous());
+    assert_eq!(best_event.tied, vec![0, 1]);
+    assert_eq!(best_event.index, 1);
+
+    //the order of the tied events doesn't change the reported solution
+    let mut reversed = event_posteriors.clone();
+    reversed.swap(0, 1);
+    assert_eq!(select_best_event(&reversed, &haplotypes, 1e-6).index, 0);
+    //and the ranking of the outputs agrees with it
+    for events in &[&event_posteriors, &reversed] {
+        let mut ranked = events.to_vec();
+        rank_events(&mut ranked, &haplotypes, 1e-6);
+        assert_eq!(ranked[0], event_posteriors[1]);
+    }
+
+    //without a tie, the best event is unambiguous
+    let best_event = select_best_event(&event_posteriors[1..], &haplotypes, 1e-6);
+    assert!(!best_event.is_ambiguous());
+    assert_eq!(best_event.index, 0);
+
+    //densities that differ by less than the tolerance are tied, and the ranking of the outputs still starts with the
+    //reported best event, even though its density is slightly lower
+    let near_tie = vec![
+        (fractions([0.0, 0.5, 0.5]), LogProb(0.45_f64.ln())),
+        (fractions([0.5, 0.0, 0.5]), LogProb(0.45_f64.ln() - 1e-7)),
+    ];
+    let best_event = select_best_event(&near_tie, &haplotypes, 1e-6);
+    assert_eq!(best_event.tied, vec![0, 1]);
+    assert_eq!(best_event.index, 1);
+    let mut ranked = near_tie.clone();
+    rank_events(&mut ranked, &haplotypes, 1e-6);
+    assert_eq!(ranked[0], near_tie[best_event.index]);
+
+    //the tolerance is relative, tiny densities are not tied just because their difference is tiny
+    let tiny = vec![
+        (fractions([0.0, 0.5, 0.5]), LogProb(1e-300_f64.ln())),
+        (fractions([0.5, 0.0, 0.5]), LogProb(5e-301_f64.ln())),
+    ];
+    let best_event = select_best_event(&tiny, &haplotypes, 1e-6);
+    assert!(!best_event.is_ambiguous());
+    assert_eq!(best_event.index, 0);
+}
+
+#[test]
+fn check_preprocess_without_pangenome_skips_giraffe() {
+    //stub the external tools such that each invocation is logged instead of executed
+    let dir = tempfile::tempdir().unwrap();
+    let tools = stub_tools(dir.path(), "");
+    let log = &tools.log;
+
+    let genome = dir.path().join("genome.fasta");
+    std::fs::write(&genome, ">6\nACGTACGTAC\n").unwrap();
+    let output = dir.path().join("out").join("sample.vcf");
+    orthanq::preprocess::hla::CallerBuilder::default()
+        .genome(genome)
+        .reads(vec!["sample_1.fastq".into(), "sample_2.fastq".into()])
+        .haplotype_variants("tests/small_candidates.vcf".into())
+        .bwa_index(Some("genome".into()))
+        .output(output.clone())
+        .threads("1".to_string())
+        .use_pangenome(false)
+        .build()
+        .unwrap()
+        .call()
+        .unwrap();
+
+    let invocations = std::fs::read_to_string(&log).unwrap();
+    let programs = invocations
+        .lines()
+        .map(|line| line.split_whitespace().next().unwrap())
+        .collect::<Vec<&str>>();
+    assert!(!programs.contains(&"vg"));
+    assert!(programs.contains(&"varlociraptor"));
+    //varlociraptor is run on the reads extracted from the linear alignment
+    assert!(invocations.contains(&format!(
+        "--bam {}",
+        dir.path()
+            .join("out")
+            .join("sample_processed.bam")
+            .display()
+    )));
+    assert!(output.exists());
+}
+
+#[test]
+fn check_specific_error_variants() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        HaplotypeVariants, VariantCalls, VariantCallsOptions, VariantID,
+    };
+    use orthanq::errors::OrthanqError;
+
+    //calls without read depths
+    let error =
+        VariantCalls::new(&mut bcf::Reader::from_path("tests/missing_dp_calls.vcf").unwrap())
+            .unwrap_err();
+    match error.downcast_ref::<OrthanqError>() {
+        Some(OrthanqError::MissingField { variant, field }) => {
+            assert_eq!((variant.as_str(), field.as_str()), ("1", "DP"))
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+
+    //a sample that is not part of the calls
+    let error = VariantCalls::new_with_rejections(
+        &mut bcf::Reader::from_path("tests/small_calls.vcf").unwrap(),
+        &VariantCallsOptions {
+            sample: Some("unknown".to_string()),
+            ..Default::default()
+        },
+    )
+    .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<OrthanqError>(),
+        Some(OrthanqError::UnknownSample { .. })
+    ));
+
+    //calls that share no variant with the candidates
+    let haplotype_variants =
+        HaplotypeVariants::new(&mut bcf::Reader::from_path("tests/small_candidates.vcf").unwrap())
+            .unwrap();
+    let error = haplotype_variants
+        .check_overlap(&[VariantID(100), VariantID(200)])
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<OrthanqError>(),
+        Some(OrthanqError::DisjointVariants { .. })
+    ));
+
+    //unknown genes and missing tools for the preprocessing
+    let error =
+        orthanq::preprocess::hla::regions_bed(&["HLA-Z".to_string()], "ensembl", 0).unwrap_err();
+    match error.downcast_ref::<OrthanqError>() {
+        Some(OrthanqError::UnknownGenes { genes, .. }) => assert_eq!(genes, &vec!["Z"]),
+        other => panic!("unexpected error: {:?}", other),
+    }
+    let error = orthanq::utils::require_tools(&["orthanq-nonexistent-tool"]).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<OrthanqError>(),
+        Some(OrthanqError::ToolNotFound { .. })
+    ));
+}
+
+#[test]
+fn check_fractions_relative_to_reference_haplotype() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        relative_fractions, write_relative_fractions, Haplotype,
+    };
+
+    let haplotypes = vec![
+        Haplotype("A*01:01:01".to_string()),
+        Haplotype("A*02:01:01".to_string()),
+        Haplotype("A*03:01:01".to_string()),
+    ];
+    let best_fractions = vec![0.25, 0.75, 0.0];
+    let ratios = relative_fractions(&haplotypes, &best_fractions, &haplotypes[0]).unwrap();
+    assert_eq!(
+        ratios,
+        vec![
+            (haplotypes[0].clone(), 1.0),
+            (haplotypes[1].clone(), 3.0),
+            (haplotypes[2].clone(), 0.0),
+        ]
+    );
+
+    //a reference with a fraction of zero yields inf, or NA if both fractions are zero
+    let outdir = tempfile::tempdir().unwrap();
+    write_relative_fractions(
+        &outdir.path().join("out.csv"),
+        &haplotypes,
+        &best_fractions,
+        &haplotypes[2],
+    )
+    .unwrap();
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_path(outdir.path().join("relative_fractions.tsv"))
+        .unwrap();
+    let ratios = rdr
+        .records()
+        .map(|record| record.unwrap()[2].to_string())
+        .collect::<Vec<String>>();
+    assert_eq!(ratios, vec!["inf", "inf", "NA"]);
+
+    //the reference has to be part of the solution
+    assert!(relative_fractions(
+        &haplotypes,
+        &best_fractions,
+        &Haplotype("B*07:02:01".to_string())
+    )
+    .is_err());
+}
+
+#[test]
+fn check_lp_with_alternate_af_field() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        linear_program, Haplotype, LinearProgramSettings, VariantCalls, VariantCallsOptions,
+    };
+
+    let (_, _, candidate_matrix, haplotypes) = small_fixture();
+    let outdir = tempfile::tempdir().unwrap();
+    let lp_haplotypes = |af_field: Option<String>| {
+        let (variant_calls, _) = VariantCalls::new_with_rejections(
+            &mut bcf::Reader::from_path("tests/alternate_af_calls.vcf").unwrap(),
+            &VariantCallsOptions {
+                af_field,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        linear_program(
+            &outdir.path().join("out.csv"),
+            &candidate_matrix,
+            &haplotypes,
+            &variant_calls,
+            &LinearProgramSettings::default(),
+        )
+        .unwrap()
+        .lp_haplotypes
+    };
+
+    //AF is explained by all three haplotypes, the re-estimated VAF only by the second one
+    assert_eq!(lp_haplotypes(None), haplotypes);
+    assert_eq!(
+        lp_haplotypes(Some("VAF".to_string())),
+        vec![Haplotype("A*02:01:01".to_string())]
+    );
+}
+
+#[test]
+fn check_html_report_embeds_plot_and_solutions() {
+    use bio::stats::LogProb;
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{plot_prediction, write_report};
+    use orthanq::model::HaplotypeFractions;
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let fractions = |values: [f64; 3]| {
+        HaplotypeFractions(
+            values
+                .iter()
+                .map(|value| NotNan::new(*value).unwrap())
+                .collect(),
+        )
+    };
+    let event_posteriors = vec![
+        (fractions([0.5, 0.5, 0.0]), LogProb(0.7_f64.ln())),
+        (fractions([0.0, 0.5, 0.5]), LogProb(0.2_f64.ln())),
+        (fractions([1.0, 0.0, 0.0]), LogProb(0.1_f64.ln())),
+    ];
+
+    let outdir = tempfile::tempdir().unwrap();
+    let output = outdir.path().join("out.csv");
+    plot_prediction(
+        &output,
+        "final",
+        &candidate_matrix,
+        &haplotypes,
+        &variant_calls,
+        &vec![0.5, 0.5, 0.0],
+        false,
+    )
+    .unwrap();
+    write_report(&output, &event_posteriors, &haplotypes, 2, 1e-6).unwrap();
+
+    let html = std::fs::read_to_string(outdir.path().join("report.html")).unwrap();
+    for haplotype in haplotypes.iter() {
+        assert!(html.contains(&format!("<th>{}</th>", haplotype.to_string())));
+    }
+    //the plot is rendered without any external script, its spec is embedded as is and only the top 2 solutions are listed
+    assert!(!html.contains("<script src"));
+    assert!(html.contains("<svg"));
+    assert!(html.contains("<title>A*01:01:01: 0.50</title>"));
+    assert!(html.contains("https://vega.github.io/schema/vega-lite/v5.json"));
+    assert!(html.contains("\"haplotype_fractions\":[{"));
+    assert_eq!(html.matches("<tr><td>").count(), 2);
+    assert!(html.contains("<tr><td>0.70</td><td>0.50</td><td>0.50</td><td>0.00</td></tr>"));
+}
+
+#[test]
+fn check_selftest_stages() {
+    use orthanq::selftest::{run, summary, StageResult};
+
+    let results = run();
+    let stages = results
+        .iter()
+        .map(|result| result.stage.as_str())
+        .collect::<Vec<&str>>();
+    assert_eq!(stages, vec!["tools", "htslib", "solver", "calling"]);
+    //the tools and the solver probe (run by the current executable) depend on the environment, htslib and calling
+    //only on the bundled fixture
+    assert!(results[1].passed(), "{:?}", results[1].error);
+    assert!(results[3].passed(), "{:?}", results[3].error);
+
+    let failed = StageResult {
+        stage: "tools".to_string(),
+        error: Some("vg not found in PATH".to_string()),
+    };
+    assert_eq!(
+        summary(&[failed, results[3].clone()]),
+        "FAIL tools: vg not found in PATH\nPASS calling"
+    );
+}
+
+#[test]
+fn check_depth_weighting_prefers_high_depth_variants() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        linear_program, CandidateMatrix, Haplotype, HaplotypeVariants, LinearProgramSettings,
+        VariantCalls,
+    };
+
+    //the first haplotype explains a variant with 500 reads, the second one two conflicting variants with 5 reads each
+    let haplotype_variants =
+        HaplotypeVariants::new(&mut bcf::Reader::from_path("tests/depth_candidates.vcf").unwrap())
+            .unwrap();
+    let variant_calls =
+        VariantCalls::new(&mut bcf::Reader::from_path("tests/depth_calls.vcf").unwrap()).unwrap();
+    let candidate_matrix = CandidateMatrix::new(&haplotype_variants).unwrap();
+    let haplotypes: Vec<Haplotype> = haplotype_variants
+        .values()
+        .next()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect();
+    let outdir = tempfile::tempdir().unwrap();
+    let lp_haplotypes = |depth_weighting: bool| {
+        linear_program(
+            &outdir.path().join("out.csv"),
+            &candidate_matrix,
+            &haplotypes,
+            &variant_calls,
+            &LinearProgramSettings {
+                depth_weighting,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .lp_haplotypes
+    };
+
+    //unweighted, the two low-depth variants outvote the high-depth one
+    assert_eq!(
+        lp_haplotypes(false),
+        vec![Haplotype("A*02:01:01".to_string())]
+    );
+    assert_eq!(
+        lp_haplotypes(true),
+        vec![Haplotype("A*01:01:01".to_string())]
+    );
+}
+
+#[test]
+fn check_candidates_subset_to_gene() {
+    use orthanq::calling::haplotypes::haplotypes::{Haplotype, HaplotypeVariants, VariantID};
+
+    let haplotype_variants = HaplotypeVariants::new_for_gene(
+        &mut bcf::Reader::from_path("tests/two_gene_candidates.vcf").unwrap(),
+        "HLA-B",
+    )
+    .unwrap();
+    assert_eq!(
+        haplotype_variants.keys().cloned().collect::<Vec<_>>(),
+        vec![VariantID(3), VariantID(4)]
+    );
+    for matrix in haplotype_variants.values() {
+        assert_eq!(
+            matrix.keys().cloned().collect::<Vec<_>>(),
+            vec![
+                Haplotype("B*07:02:01".to_string()),
+                Haplotype("B*08:01:01".to_string())
+            ]
+        );
+    }
+
+    //genes without candidates or unknown genes are rejected
+    assert!(HaplotypeVariants::new_for_gene(
+        &mut bcf::Reader::from_path("tests/two_gene_candidates.vcf").unwrap(),
+        "C",
+    )
+    .is_err());
+    assert!(HaplotypeVariants::new_for_gene(
+        &mut bcf::Reader::from_path("tests/two_gene_candidates.vcf").unwrap(),
+        "Z",
+    )
+    .is_err());
+}
+
+#[test]
+fn check_uninformative_haplotypes_are_pruned() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        prune_uninformative_haplotypes, Haplotype, HaplotypeVariants, VariantCalls,
+    };
+
+    //the third haplotype is not covered at any of the variants
+    let haplotype_variants = HaplotypeVariants::new(
+        &mut bcf::Reader::from_path("tests/uncovered_haplotype_candidates.vcf").unwrap(),
+    )
+    .unwrap();
+    let variant_calls =
+        VariantCalls::new(&mut bcf::Reader::from_path("tests/small_calls.vcf").unwrap()).unwrap();
+    let haplotypes: Vec<Haplotype> = haplotype_variants
+        .values()
+        .next()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect();
+
+    assert_eq!(
+        prune_uninformative_haplotypes(&haplotype_variants, &variant_calls, &haplotypes),
+        haplotypes[..2].to_vec()
+    );
+    //if no haplotype is informative, all of them are kept
+    assert_eq!(
+        prune_uninformative_haplotypes(&haplotype_variants, &variant_calls, &haplotypes[2..]),
+        haplotypes[2..].to_vec()
+    );
+}
+
+#[test]
+fn check_merge_of_two_panels() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        Haplotype, HaplotypeVariants, VariantID, VariantStatus,
+    };
+
+    let first =
+        HaplotypeVariants::new(&mut bcf::Reader::from_path("tests/small_candidates.vcf").unwrap())
+            .unwrap();
+    let second = HaplotypeVariants::new(
+        &mut bcf::Reader::from_path("tests/second_panel_candidates.vcf").unwrap(),
+    )
+    .unwrap();
+    let merged = first.merge(&second, "second");
+
+    //union of the variants, the colliding haplotype name of the second panel is namespaced
+    assert_eq!(
+        merged.keys().cloned().collect::<Vec<_>>(),
+        (1..=5).map(VariantID).collect::<Vec<_>>()
+    );
+    let haplotype = |name: &str| Haplotype(name.to_string());
+    for matrix in merged.values() {
+        assert_eq!(
+            matrix.keys().cloned().collect::<Vec<_>>(),
+            vec![
+                haplotype("A*01:01:01"),
+                haplotype("A*02:01:01"),
+                haplotype("A*03:01:01"),
+                haplotype("B*07:02:01"),
+                haplotype("second/A*01:01:01"),
+            ]
+        );
+    }
+    //the shared variant combines both records, the others are absent in the haplotypes of the other panel
+    assert_eq!(
+        merged[&VariantID(4)][&haplotype("B*07:02:01")],
+        (VariantStatus::Present, true)
+    );
+    assert_eq!(
+        merged[&VariantID(4)][&haplotype("A*01:01:01")],
+        (VariantStatus::Present, true)
+    );
+    assert_eq!(
+        merged[&VariantID(1)][&haplotype("B*07:02:01")],
+        (VariantStatus::NotPresent, true)
+    );
+    assert_eq!(
+        merged[&VariantID(5)][&haplotype("A*01:01:01")],
+        (VariantStatus::NotPresent, true)
+    );
+    assert_eq!(
+        merged[&VariantID(5)][&haplotype("second/A*01:01:01")],
+        (VariantStatus::Present, true)
+    );
+}
+
+#[test]
+fn check_solver_failure_is_reported_as_error() {
+    use orthanq::calling::haplotypes::haplotypes::catch_solver_panic;
+    use orthanq::errors::OrthanqError;
+
+    assert_eq!(catch_solver_panic(|| 42).unwrap(), 42);
+
+    //a panic of the solver bindings is turned into an error
+    let error =
+        catch_solver_panic(|| -> usize { panic!("unexpected status of the solver") }).unwrap_err();
+    match error.downcast_ref::<OrthanqError>() {
+        Some(OrthanqError::SolverUnavailable { reason }) => {
+            assert!(reason.contains("unexpected status"))
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    assert!(error.to_string().contains("install the CBC library"));
+}
+
+#[test]
+fn check_solver_probe_reports_abnormal_exit() {
+    use orthanq::errors::OrthanqError;
+    use orthanq::selftest::probe_solver;
+    use std::os::unix::fs::PermissionsExt;
+
+    //the solver of the orthanq binary works
+    probe_solver(std::path::Path::new(env!("CARGO_BIN_EXE_orthanq"))).unwrap();
+
+    //a process that can't load the CBC library is killed, the message of the loader is reported
+    let dir = tempfile::tempdir().unwrap();
+    let program = dir.path().join("orthanq");
+    std::fs::write(
+        &program,
+        "#!/bin/sh\necho 'libCbcSolver.so.3: cannot open shared object file' >&2\nkill -s ABRT $$\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let error = probe_solver(&program).unwrap_err();
+    match error.downcast_ref::<OrthanqError>() {
+        Some(OrthanqError::SolverUnavailable { reason }) => {
+            assert!(reason.contains("libCbcSolver.so.3"));
+            assert!(reason.contains("signal"));
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    assert!(error.to_string().contains("install the CBC library"));
+}
+
+#[test]
+fn check_variant_calls_from_parsed_calls() {
+    use bio::stats::LogProb;
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{AlleleFreqDist, VariantCalls, VariantID};
+    use rust_htslib::bcf;
+    use std::collections::BTreeMap;
+
+    let mut densities = BTreeMap::new();
+    densities.insert(NotNan::new(0.5).unwrap(), LogProb::ln_one());
+    let mut calls = BTreeMap::new();
+    calls.insert(VariantID(1), (0.5, AlleleFreqDist::new(densities)));
+    let mut read_depths = BTreeMap::new();
+    read_depths.insert(VariantID(1), 30);
+    let constructed = VariantCalls::from_calls(calls, read_depths);
+
+    let parsed =
+        VariantCalls::new(&mut bcf::Reader::from_path("tests/small_calls.vcf").unwrap()).unwrap();
+    assert_eq!(constructed.len(), 1);
+    assert_eq!(constructed[&VariantID(1)].0, parsed[&VariantID(1)].0);
+    assert_eq!(
+        constructed.read_depth(&VariantID(1)),
+        parsed.read_depth(&VariantID(1))
+    );
+}
+
+#[test]
+fn check_lp_formulation_export() {
+    use orthanq::calling::haplotypes::haplotypes::{linear_program, LinearProgramSettings};
+    use std::fs;
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let outdir = tempfile::tempdir().unwrap();
+    let outcsv = outdir.path().join("out.csv");
+
+    let solution = linear_program(
+        &outcsv,
+        &candidate_matrix,
+        &haplotypes,
+        &variant_calls,
+        &LinearProgramSettings {
+            export_lp: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let lp = fs::read_to_string(outdir.path().join("lp.lp")).unwrap();
+    assert!(lp.contains("Minimize") && lp.contains("Subject To") && lp.trim_end().ends_with("End"));
+    //one (two-sided) deviation constraint per informative variant
+    for variant in &solution.considered_variants {
+        assert!(lp.contains(&format!(" v{}_over: t{}", variant.0, variant.0)));
+        assert!(lp.contains(&format!(" v{}_under: t{}", variant.0, variant.0)));
+    }
+    assert!(!solution.considered_variants.is_empty());
+    assert_eq!(
+        lp.lines().filter(|line| line.contains("_over:")).count(),
+        solution.considered_variants.len()
+    );
+    for haplotype in &haplotypes {
+        assert!(lp.contains(&format!("= {}", haplotype.as_str())));
+    }
+}
+
+#[test]
+fn check_relaxed_sum_leaves_unexplained_signal_unassigned() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        linear_program, Haplotype, LinearProgramSettings, VariantCalls,
+    };
+
+    let (_, _, candidate_matrix, haplotypes) = small_fixture();
+    //the allele frequencies are explained by fractions summing up to 0.5 only
+    let variant_calls = VariantCalls::new(
+        &mut bcf::Reader::from_path("tests/unexplained_signal_calls.vcf").unwrap(),
+    )
+    .unwrap();
+    let outdir = tempfile::tempdir().unwrap();
+    let outcsv = outdir.path().join("out.csv");
+    let solve = |relax_sum: bool| {
+        linear_program(
+            &outcsv,
+            &candidate_matrix,
+            &haplotypes,
+            &variant_calls,
+            &LinearProgramSettings {
+                relax_sum,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    };
+
+    let strict = solve(false);
+    let relaxed = solve(true);
+    assert_eq!(strict.unassigned_fraction, None);
+    assert!((strict.fractions.values().sum::<f64>() - 1.0).abs() < 1e-6);
+    assert!(relaxed.residual < strict.residual);
+    assert!(relaxed.residual < 1e-6);
+    assert!((relaxed.unassigned_fraction.unwrap() - 0.5).abs() < 1e-6);
+    assert!((relaxed.fractions[&Haplotype("A*01:01:01".to_string())] - 0.3).abs() < 1e-6);
+    assert!((relaxed.fractions[&Haplotype("A*02:01:01".to_string())] - 0.2).abs() < 1e-6);
+}
+
+#[test]
+fn check_panel_fingerprint() {
+    use orthanq::calling::haplotypes::haplotypes::HaplotypeVariants;
+
+    let fingerprint = |path: &str| {
+        HaplotypeVariants::new(&mut bcf::Reader::from_path(path).unwrap())
+            .unwrap()
+            .fingerprint()
+    };
+    let original = fingerprint("tests/small_candidates.vcf");
+    assert_eq!(original.len(), 16);
+    //identical panels produce the same fingerprint, independent of the order of records and samples
+    assert_eq!(original, fingerprint("tests/small_candidates.vcf"));
+    assert_eq!(
+        original,
+        fingerprint("tests/small_candidates_reordered.vcf")
+    );
+    //a modified panel (renamed haplotype, changed genotypes) differs
+    assert_ne!(original, fingerprint("tests/small_candidates_updated.vcf"));
+}
+
+#[test]
+fn check_candidates_without_coverage_field() {
+    use orthanq::calling::haplotypes::haplotypes::{HaplotypeVariants, VariantStatus};
+    use orthanq::errors::OrthanqError;
+
+    //by default, all haplotypes are considered covered
+    let without_coverage = HaplotypeVariants::new(
+        &mut bcf::Reader::from_path("tests/no_coverage_candidates.vcf").unwrap(),
+    )
+    .unwrap();
+    let with_coverage =
+        HaplotypeVariants::new(&mut bcf::Reader::from_path("tests/small_candidates.vcf").unwrap())
+            .unwrap();
+    assert_eq!(without_coverage, with_coverage);
+    assert!(without_coverage
+        .values()
+        .all(|matrix| matrix.values().all(|(_, covered)| *covered)));
+    assert_eq!(
+        without_coverage
+            .values()
+            .next()
+            .unwrap()
+            .values()
+            .next()
+            .unwrap()
+            .0,
+        VariantStatus::Present
+    );
+
+    //if coverage is required, a clear error is returned instead
+    let error = HaplotypeVariants::new_requiring_coverage(
+        &mut bcf::Reader::from_path("tests/no_coverage_candidates.vcf").unwrap(),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<OrthanqError>(),
+        Some(OrthanqError::MissingCoverage)
+    ));
+    assert!(HaplotypeVariants::new_requiring_coverage(
+        &mut bcf::Reader::from_path("tests/small_candidates.vcf").unwrap()
+    )
+    .is_ok());
+}
+
+#[test]
+fn check_final_chromosomes_are_configurable() {
+    use orthanq::preprocess::hla::final_chromosomes;
+
+    //the standard chromosomes by default, named according to the reference
+    let standard = final_chromosomes(&[], &[], "ucsc");
+    assert_eq!(standard.len(), 25);
+    assert_eq!(standard[5], "chr6");
+    assert_eq!(final_chromosomes(&[], &[], "ensembl")[24], "M");
+    assert_eq!(
+        final_chromosomes(
+            &["chr6".to_string()],
+            &["chr6_GL000250v2_alt".to_string()],
+            "ensembl"
+        ),
+        vec!["6", "chr6_GL000250v2_alt"]
+    );
+
+    //stub the external tools such that each invocation is logged instead of executed, the extraction writes the
+    //fixture reads such that they can be converted to fastq for the realignment
+    let dir = tempfile::tempdir().unwrap();
+    let reads = std::fs::canonicalize("tests/paired_reads.sam").unwrap();
+    let tools = stub_tools(
+        dir.path(),
+        &format!(
+            "if [ \"$(basename \"$0\")\" = samtools ] && [ \"$1\" = view ]; then\n\
+             for arg; do\n\
+             if [ \"$previous\" = -o ]; then case \"$arg\" in *_extracted.bam) cp {} \"$arg\";; esac; fi\n\
+             previous=\"$arg\"\n\
+             done\n\
+             fi\n",
+            reads.display()
+        ),
+    );
+    let log = &tools.log;
+
+    let genome = dir.path().join("genome.fasta");
+    std::fs::write(&genome, ">6\nACGTACGTAC\n").unwrap();
+    let output = dir.path().join("out").join("sample.vcf");
+    let preprocess_output = orthanq::preprocess::hla::CallerBuilder::default()
+        .genome(genome)
+        .reads(vec!["sample_1.fastq".into(), "sample_2.fastq".into()])
+        .haplotype_variants("tests/small_candidates.vcf".into())
+        .bwa_index(Some("genome".into()))
+        .vg_index(Some("pangenome.xg".into()))
+        .output(output.clone())
+        .threads("1".to_string())
+        .chromosomes(vec!["6".to_string()])
+        .extra_contigs(vec!["chr6_GL000250v2_alt".to_string()])
+        .build()
+        .unwrap()
+        .call()
+        .unwrap();
+
+    //the produced calls are returned for the haplotype callers, the stubs don't write the final alignment
+    assert_eq!(preprocess_output.calls, output);
+    assert!(preprocess_output.calls.exists());
+    assert_eq!(preprocess_output.sample, "sample");
+    assert_eq!(preprocess_output.coverage, None);
+
+    //the final extraction only keeps the configured contigs
+    let invocations = std::fs::read_to_string(&log).unwrap();
+    let extraction = invocations
+        .lines()
+        .find(|line| line.starts_with("samtools view") && line.contains("_processed.bam"))
+        .unwrap();
+    assert!(extraction.contains("_reheadered.bam 6 chr6_GL000250v2_alt -o"));
+}
+
+#[test]
+fn check_vaf_fit_reports_observed_and_expected_allele_frequencies() {
+    use bio::stats::LogProb;
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{write_results, VafDenominator};
+    use orthanq::model::{Data, HaplotypeFractions};
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let data = Data::new(candidate_matrix, variant_calls);
+
+    let fractions = |values: [f64; 3]| {
+        HaplotypeFractions(
+            values
+                .iter()
+                .map(|value| NotNan::new(*value).unwrap())
+                .collect(),
+        )
+    };
+    //the first event explains the calls exactly, the second one doesn't
+    let event_posteriors = vec![
+        (fractions([0.5, 0.3, 0.2]), LogProb(0.8_f64.ln())),
+        (fractions([1.0, 0.0, 0.0]), LogProb(0.2_f64.ln())),
+    ];
+    let outdir = tempfile::tempdir().unwrap();
+    write_results(
+        &outdir.path().join("results.csv"),
+        &data,
+        &event_posteriors,
+        &haplotypes,
+        "uniform".to_string(),
+        true,
+        None,
+        None,
+        VafDenominator::default(),
+        1e-6,
+    )
+    .unwrap();
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_path(outdir.path().join("vaf_fit.tsv"))
+        .unwrap();
+    assert_eq!(
+        rdr.headers().unwrap().iter().collect::<Vec<_>>(),
+        vec![
+            "event",
+            "variant",
+            "observed_af",
+            "expected_vaf",
+            "deviation",
+            "probability"
+        ]
+    );
+    let rows = rdr
+        .records()
+        .map(|record| {
+            let record = record.unwrap();
+            (
+                record[0].to_string(),
+                record[1].to_string(),
+                record[2].to_string(),
+                record[3].to_string(),
+                record[4].to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let row = |event: &str, variant: &str| {
+        rows.iter()
+            .find(|(e, v, _, _, _)| e == event && v == variant)
+            .map(|(_, _, observed, expected, deviation)| {
+                (observed.as_str(), expected.as_str(), deviation.as_str())
+            })
+    };
+    //one row per variant for the best event
+    assert_eq!(rows.iter().filter(|(event, ..)| event == "0").count(), 4);
+    assert_eq!(row("0", "4"), Some(("0.80", "0.80", "0.00")));
+    assert_eq!(row("0", "2"), Some(("0.30", "0.30", "0.00")));
+    assert_eq!(row("1", "1"), Some(("0.50", "1.00", "0.50")));
+}
+
+#[test]
+fn check_candidate_alleles_within_probability_band() {
+    use bio::stats::LogProb;
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{
+        candidate_alleles, write_candidate_alleles, Haplotype,
+    };
+    use orthanq::model::HaplotypeFractions;
+
+    let haplotypes = vec![
+        Haplotype("A*01:01:01".to_string()),
+        Haplotype("A*02:01:01".to_string()),
+        Haplotype("A*03:01:01".to_string()),
+    ];
+    let fractions = |values: [f64; 3]| {
+        HaplotypeFractions(
+            values
+                .iter()
+                .map(|value| NotNan::new(*value).unwrap())
+                .collect(),
+        )
+    };
+    //A*01:01:01 is not part of the best event, but of two moderate ones
+    let event_posteriors = vec![
+        (fractions([0.0, 1.0, 0.0]), LogProb(0.4_f64.ln())),
+        (fractions([0.5, 0.5, 0.0]), LogProb(0.3_f64.ln())),
+        (fractions([0.5, 0.0, 0.5]), LogProb(0.3_f64.ln())),
+    ];
+
+    let candidates = candidate_alleles(&event_posteriors, &haplotypes, 0.5);
+    assert_eq!(
+        candidates
+            .iter()
+            .map(|(haplotype, _)| haplotype.clone())
+            .collect::<Vec<_>>(),
+        vec![haplotypes[1].clone(), haplotypes[0].clone()]
+    );
+    assert!((candidates[0].1 - 0.7).abs() < 1e-9);
+    assert!((candidates[1].1 - 0.6).abs() < 1e-9);
+
+    let outdir = tempfile::tempdir().unwrap();
+    write_candidate_alleles(
+        &outdir.path().join("out.csv"),
+        &event_posteriors,
+        &haplotypes,
+        0.5,
+    )
+    .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(outdir.path().join("candidate_alleles.tsv")).unwrap(),
+        "haplotype\tpresence_probability\nA*02:01:01\t0.700\nA*01:01:01\t0.600\n"
+    );
+}
+
+#[test]
+fn check_samples_are_combined_into_one_table() {
+    use orthanq::calling::haplotypes::haplotypes::{combine_sample_results, sample_outputs};
+
+    //two related samples, called against the same panel
+    let outdir = tempfile::tempdir().unwrap();
+    let variant_calls = ["mother.bcf", "child.bcf"]
+        .iter()
+        .map(|name| {
+            let path = outdir.path().join(name);
+            std::fs::copy("tests/Sample_HLA00318-0.5_HLA00319-0.5_B.bcf", &path).unwrap();
+            path
+        })
+        .collect::<Vec<_>>();
+    let output = outdir.path().join("out").join("combined.csv");
+    let outputs = sample_outputs(&variant_calls, &output).unwrap();
+    assert_eq!(
+        outputs,
+        vec![
+            (
+                "mother".to_string(),
+                outdir
+                    .path()
+                    .join("out")
+                    .join("mother")
+                    .join("combined.csv")
+            ),
+            (
+                "child".to_string(),
+                outdir.path().join("out").join("child").join("combined.csv")
+            ),
+        ]
+    );
+    for (calls, (_, outcsv)) in variant_calls.iter().zip(outputs.iter()) {
+        orthanq::calling::haplotypes::hla::CallerBuilder::default()
+            .haplotype_variants(bcf::Reader::from_path("tests/B.vcf").unwrap())
+            .variant_calls(bcf::Reader::from_path(calls).unwrap())
+            .xml("tests/hla.xml".into())
+            .common_variants(false)
+            .enable_equivalence_class_constraint(false)
+            .outcsv(outcsv.clone())
+            .prior("diploid".to_string())
+            .lp_cutoff(0.01)
+            .threshold_equivalence_class(1)
+            .extend_haplotypes(Some(true))
+            .num_extend_haplotypes(3)
+            .build()
+            .unwrap()
+            .call()
+            .unwrap();
+    }
+    combine_sample_results(&outputs, &output).unwrap();
+
+    let mut rdr = csv::Reader::from_path(&output).unwrap();
+    let headers = rdr.headers().unwrap().clone();
+    assert_eq!(&headers[0], "sample");
+    assert_eq!(&headers[1], "density");
+    assert_eq!(&headers[2], "odds");
+    let samples = rdr
+        .records()
+        .map(|record| record.unwrap()[0].to_string())
+        .collect::<Vec<_>>();
+    //all rows of both samples, the mother first
+    let rows_of =
+        |outcsv: &std::path::PathBuf| csv::Reader::from_path(outcsv).unwrap().records().count();
+    assert_eq!(
+        samples.len(),
+        rows_of(&outputs[0].1) + rows_of(&outputs[1].1)
+    );
+    assert_eq!(samples.first().map(String::as_str), Some("mother"));
+    assert_eq!(samples.last().map(String::as_str), Some("child"));
+
+    //samples with the same name can't be told apart
+    assert!(sample_outputs(
+        &[variant_calls[0].clone(), variant_calls[0].clone()],
+        &output
+    )
+    .is_err());
+}
+
+#[test]
+fn check_panel_validation_reports_all_issues() {
+    use orthanq::calling::haplotypes::haplotypes::{HaplotypeVariants, PanelIssue, VariantID};
+
+    let validate = |path: &str| {
+        HaplotypeVariants::validate(&mut bcf::Reader::from_path(path).unwrap()).unwrap()
+    };
+
+    //a valid panel
+    assert!(validate("tests/small_candidates.vcf").is_empty());
+
+    //several problems of one panel are reported at once
+    assert_eq!(
+        validate("tests/invalid_panel_candidates.vcf"),
+        vec![
+            PanelIssue::InvalidId {
+                id: "rs2".to_string()
+            },
+            PanelIssue::NoPresentGenotype {
+                variant: VariantID(3)
+            },
+            PanelIssue::DuplicateId {
+                variant: VariantID(1)
+            },
+        ]
+    );
+
+    //missing coverage field
+    assert_eq!(
+        validate("tests/no_coverage_candidates.vcf"),
+        vec![PanelIssue::MissingCoverage]
+    );
+
+    //no haplotypes
+    assert!(validate("tests/no_samples_candidates.vcf").contains(&PanelIssue::NoSamples));
+
+    //reading the panel for calling fails on the invalid ID instead of panicking
+    assert!(HaplotypeVariants::new(
+        &mut bcf::Reader::from_path("tests/invalid_panel_candidates.vcf").unwrap()
+    )
+    .is_err());
+    assert_eq!(
+        PanelIssue::DuplicateId {
+            variant: VariantID(1)
+        }
+        .to_string(),
+        "variant ID 1 occurs in several records"
+    );
+}
+
+#[test]
+fn check_fractions_are_snapped_to_grid() {
+    use bio::stats::LogProb;
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{snap_fraction, write_results, VafDenominator};
+    use orthanq::model::{Data, HaplotypeFractions};
+
+    let snap = |fraction: f64, grid: Option<f64>| {
+        NotNan::into_inner(snap_fraction(NotNan::new(fraction).unwrap(), grid))
+    };
+    assert!((snap(0.48, Some(0.05)) - 0.5).abs() < 1e-9);
+    assert!((snap(0.52, Some(0.05)) - 0.5).abs() < 1e-9);
+    assert_eq!(snap(0.48, None), 0.48);
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let data = Data::new(candidate_matrix, variant_calls);
+    let event_posteriors = vec![(
+        HaplotypeFractions(
+            [0.48, 0.52, 0.0]
+                .iter()
+                .map(|value| NotNan::new(*value).unwrap())
+                .collect(),
+        ),
+        LogProb(0.9_f64.ln()),
+    )];
+
+    let outdir = tempfile::tempdir().unwrap();
+    let output = outdir.path().join("results.csv");
+    write_results(
+        &output,
+        &data,
+        &event_posteriors,
+        &haplotypes,
+        "uniform".to_string(),
+        false,
+        None,
+        Some(0.05),
+        VafDenominator::default(),
+        1e-6,
+    )
+    .unwrap();
+    let mut rdr = csv::Reader::from_path(&output).unwrap();
+    let record = rdr.records().next().unwrap().unwrap();
+    assert_eq!((&record[2], &record[3]), ("0.50", "0.50"));
+}
+
+#[test]
+fn check_second_run_loads_inputs_from_cache() {
+    use orthanq::calling::haplotypes::haplotypes::{CacheOptions, HaplotypeVariants, InputCache};
+
+    let dir = tempfile::tempdir().unwrap();
+    let candidates = dir.path().join("B.vcf");
+    let calls = dir.path().join("calls.bcf");
+    std::fs::copy("tests/B.vcf", &candidates).unwrap();
+    std::fs::copy("tests/Sample_HLA00318-0.5_HLA00319-0.5_B.bcf", &calls).unwrap();
+    let cache_path = dir.path().join("cache.json");
+    let input_cache =
+        InputCache::new(cache_path.clone(), &[candidates.clone(), calls.clone()]).unwrap();
+
+    let run = |panel: &str, name: &str| {
+        let output = dir.path().join(name).join("results.csv");
+        orthanq::calling::haplotypes::hla::CallerBuilder::default()
+            .haplotype_variants(bcf::Reader::from_path(panel).unwrap())
+            .variant_calls(bcf::Reader::from_path(&calls).unwrap())
+            .xml("tests/hla.xml".into())
+            .common_variants(false)
+            .enable_equivalence_class_constraint(false)
+            .outcsv(output.clone())
+            .prior("diploid".to_string())
+            .lp_cutoff(0.01)
+            .threshold_equivalence_class(1)
+            .extend_haplotypes(Some(true))
+            .num_extend_haplotypes(3)
+            .input_cache(Some(input_cache.clone()))
+            .build()
+            .unwrap()
+            .call()
+            .unwrap();
+        std::fs::read_to_string(output).unwrap()
+    };
+
+    let first = run(candidates.to_str().unwrap(), "first");
+    assert!(cache_path.exists());
+    //the second run is given another panel, which is not parsed as the inputs are taken from the cache
+    let second = run("tests/small_candidates.vcf", "second");
+    assert_eq!(first, second);
+
+    //the cache is only used with the same options
+    let options = CacheOptions {
+        caller: "hla".to_string(),
+        ..Default::default()
+    };
+    let (cached_calls, cached_panel, panel) = input_cache.load(&options).unwrap().unwrap();
+    //only the called variants of the panel are cached, along with the summary of the whole panel
+    assert!(cached_panel
+        .keys()
+        .all(|variant| cached_calls.contains_key(variant)));
+    let whole_panel =
+        HaplotypeVariants::new(&mut bcf::Reader::from_path(&candidates).unwrap()).unwrap();
+    assert_eq!(panel.fingerprint, whole_panel.fingerprint());
+    assert_eq!(panel.variants, whole_panel.len());
+    let other_options = CacheOptions {
+        require_coverage: true,
+        ..options.clone()
+    };
+    assert!(input_cache.load(&other_options).unwrap().is_none());
+
+    //a changed input invalidates the cache
+    std::fs::write(&candidates, "changed").unwrap();
+    let changed = InputCache::new(cache_path, &[candidates, calls]).unwrap();
+    assert!(changed.load(&options).unwrap().is_none());
+}
+
+#[test]
+fn check_cache_round_trip_keeps_non_finite_densities() {
+    use bio::stats::LogProb;
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{
+        AlleleFreqDist, CacheOptions, HaplotypeVariants, InputCache, PanelSummary, VariantCalls,
+        VariantID,
+    };
+    use std::collections::BTreeMap;
+
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("calls.vcf");
+    std::fs::copy("tests/small_candidates.vcf", &input).unwrap();
+    let cache = InputCache::new(dir.path().join("cache.json"), &[input]).unwrap();
+    let options = CacheOptions {
+        caller: "virus".to_string(),
+        ..Default::default()
+    };
+
+    let mut afd = BTreeMap::new();
+    afd.insert(NotNan::new(0.0).unwrap(), LogProb::ln_zero());
+    afd.insert(NotNan::new(0.5).unwrap(), LogProb(-0.1));
+    afd.insert(NotNan::new(1.0).unwrap(), LogProb(-2.5));
+    let mut calls = BTreeMap::new();
+    calls.insert(VariantID(1), (0.5, AlleleFreqDist::new(afd)));
+    let variant_calls = VariantCalls::from_calls(calls, BTreeMap::new());
+    cache
+        .store(
+            &options,
+            &variant_calls,
+            &HaplotypeVariants(BTreeMap::new()),
+            &PanelSummary::default(),
+        )
+        .unwrap();
+
+    let (loaded, _) = cache.load(&options).unwrap().unwrap();
+    let (af, afd) = loaded.get(&VariantID(1)).unwrap();
+    assert_eq!(*af, 0.5);
+    let densities: Vec<_> = afd.values().map(|density| density.0).collect();
+    assert_eq!(densities, vec![f64::NEG_INFINITY, -0.1, -2.5]);
+}
+
+#[test]
+fn check_variant_attribution_sums_to_expected_vaf() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        variant_attribution, write_variant_attribution, CandidateMatrix, Haplotype,
+        HaplotypeVariants, VafDenominator, VariantCalls, VariantID,
+    };
+
+    let haplotype_variants = HaplotypeVariants::new(
+        &mut bcf::Reader::from_path("tests/uncharacterized_candidates.vcf").unwrap(),
+    )
+    .unwrap();
+    let variant_calls =
+        VariantCalls::new(&mut bcf::Reader::from_path("tests/small_calls.vcf").unwrap()).unwrap();
+    let candidate_matrix = CandidateMatrix::new(&haplotype_variants).unwrap();
+    let haplotypes: Vec<Haplotype> = haplotype_variants
+        .values()
+        .next()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect();
+    let fractions = vec![0.5, 0.3, 0.2];
+
+    let attributions = variant_attribution(
+        &candidate_matrix,
+        &variant_calls,
+        &haplotypes,
+        &fractions,
+        VafDenominator::default(),
+    );
+    assert_eq!(attributions.len(), 4);
+    for attribution in attributions.iter() {
+        let sum: f64 = attribution
+            .contributions
+            .iter()
+            .map(|(_, contribution)| contribution)
+            .sum();
+        assert!((sum - attribution.expected_vaf).abs() < 1e-9);
+    }
+    //variant 4 is carried by the first two haplotypes
+    let fourth = attributions
+        .iter()
+        .find(|attribution| attribution.variant == VariantID(4))
+        .unwrap();
+    assert_eq!(
+        fourth.contributions,
+        vec![(haplotypes[0].clone(), 0.5), (haplotypes[1].clone(), 0.3)]
+    );
+    assert!((fourth.expected_vaf - 0.8).abs() < 1e-9);
+    //the first haplotype is not characterized at variant 3, hence the contribution of the third one is renormalized
+    let third = attributions
+        .iter()
+        .find(|attribution| attribution.variant == VariantID(3))
+        .unwrap();
+    assert_eq!(third.contributions.len(), 1);
+    assert!((third.expected_vaf - 0.4).abs() < 1e-9);
+
+    let outdir = tempfile::tempdir().unwrap();
+    write_variant_attribution(
+        &outdir.path().join("out.csv"),
+        &candidate_matrix,
+        &variant_calls,
+        &haplotypes,
+        &fractions,
+        VafDenominator::default(),
+    )
+    .unwrap();
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_path(outdir.path().join("variant_attribution.tsv"))
+        .unwrap();
+    let rows = rdr
+        .records()
+        .map(|record| record.unwrap())
+        .filter(|record| &record[0] == "4")
+        .map(|record| {
+            (
+                record[2].to_string(),
+                record[3].to_string(),
+                record[4].to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rows,
+        vec![
+            (
+                "0.8000".to_string(),
+                "A*01:01:01".to_string(),
+                "0.5000".to_string()
+            ),
+            (
+                "0.8000".to_string(),
+                "A*02:01:01".to_string(),
+                "0.3000".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn check_variant_attribution_follows_vaf_denominator() {
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{
+        expected_vafs, variant_attribution, CandidateMatrix, Haplotype, HaplotypeVariants,
+        VafDenominator, VariantCalls, VariantID,
+    };
+    use orthanq::model::HaplotypeFractions;
+
+    let haplotype_variants = HaplotypeVariants::new(
+        &mut bcf::Reader::from_path("tests/uncharacterized_candidates.vcf").unwrap(),
+    )
+    .unwrap();
+    let variant_calls =
+        VariantCalls::new(&mut bcf::Reader::from_path("tests/small_calls.vcf").unwrap()).unwrap();
+    let candidate_matrix = CandidateMatrix::new(&haplotype_variants).unwrap();
+    let haplotypes: Vec<Haplotype> = haplotype_variants
+        .values()
+        .next()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect();
+    let fractions = vec![0.5, 0.3, 0.2];
+
+    //if all haplotypes are included, the contribution of the third haplotype to variant 3 is not renormalized
+    let attributions = variant_attribution(
+        &candidate_matrix,
+        &variant_calls,
+        &haplotypes,
+        &fractions,
+        VafDenominator::IncludeAll,
+    );
+    let third = attributions
+        .iter()
+        .find(|attribution| attribution.variant == VariantID(3))
+        .unwrap();
+    assert_eq!(third.contributions, vec![(haplotypes[2].clone(), 0.2)]);
+    assert!((third.expected_vaf - 0.2).abs() < 1e-9);
+
+    //the attribution expects the same allele frequencies as the model for both denominators
+    let haplotype_fractions = HaplotypeFractions(
+        fractions
+            .iter()
+            .map(|fraction| NotNan::new(*fraction).unwrap())
+            .collect(),
+    );
+    for denominator in [VafDenominator::ExcludeUncovered, VafDenominator::IncludeAll].iter() {
+        let vafs = expected_vafs(&haplotype_fractions, &candidate_matrix, *denominator);
+        for attribution in variant_attribution(
+            &candidate_matrix,
+            &variant_calls,
+            &haplotypes,
+            &fractions,
+            *denominator,
+        ) {
+            assert!((attribution.expected_vaf - vafs[&attribution.variant]).abs() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn check_panel_matrix_equals_haplotype_variants() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        CandidateMatrix, HaplotypeVariants, PanelMatrix, VariantID,
+    };
+
+    for path in &[
+        "tests/small_candidates.vcf",
+        "tests/uncharacterized_candidates.vcf",
+        "tests/no_coverage_candidates.vcf",
+    ] {
+        let haplotype_variants =
+            HaplotypeVariants::new(&mut bcf::Reader::from_path(path).unwrap()).unwrap();
+        let panel = PanelMatrix::new(&mut bcf::Reader::from_path(path).unwrap()).unwrap();
+        assert_eq!(panel.to_haplotype_variants(), haplotype_variants);
+        assert_eq!(
+            CandidateMatrix::from_panel(&panel),
+            CandidateMatrix::new(&haplotype_variants).unwrap()
+        );
+        assert_eq!(panel.fingerprint(), haplotype_variants.fingerprint());
+        //expanding only some of the variants equals filtering the expanded panel
+        let variants = vec![VariantID(2), VariantID(4)];
+        assert_eq!(
+            panel.haplotype_variants_for(&variants),
+            haplotype_variants.filter_for_variants(&variants).unwrap()
+        );
+    }
+
+    //merging the panels equals merging the expanded panels
+    let read = |path: &str| {
+        (
+            HaplotypeVariants::new(&mut bcf::Reader::from_path(path).unwrap()).unwrap(),
+            PanelMatrix::new(&mut bcf::Reader::from_path(path).unwrap()).unwrap(),
+        )
+    };
+    let (first, first_panel) = read("tests/small_candidates.vcf");
+    let (second, second_panel) = read("tests/second_panel_candidates.vcf");
+    let merged = first_panel.merge(&second_panel, "second");
+    assert_eq!(
+        merged.to_haplotype_variants(),
+        first.merge(&second, "second")
+    );
+    assert_eq!(
+        merged.fingerprint(),
+        first.merge(&second, "second").fingerprint()
+    );
+}
+
+#[test]
+fn check_solutions_with_same_haplotype_set_are_collapsed() {
+    use bio::stats::LogProb;
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{
+        distinct_haplotype_sets, write_distinct_solutions, Haplotype,
+    };
+    use orthanq::model::HaplotypeFractions;
+
+    let haplotypes = vec![
+        Haplotype("A*01:01".to_string()),
+        Haplotype("A*02:01".to_string()),
+        Haplotype("A*03:01".to_string()),
+    ];
+    let fractions = |values: [f64; 3]| {
+        HaplotypeFractions(
+            values
+                .iter()
+                .map(|value| NotNan::new(*value).unwrap())
+                .collect(),
+        )
+    };
+    let event_posteriors = vec![
+        (fractions([0.6, 0.4, 0.0]), LogProb(0.3_f64.ln())),
+        (fractions([0.5, 0.5, 0.0]), LogProb(0.4_f64.ln())),
+        (fractions([0.4, 0.6, 0.0]), LogProb(0.1_f64.ln())),
+        (fractions([0.0, 0.5, 0.5]), LogProb(0.15_f64.ln())),
+        (fractions([1.0, 0.0, 0.0]), LogProb(0.05_f64.ln())),
+    ];
+
+    //the three events of {A*01:01, A*02:01} collapse into the best of them
+    let distinct = distinct_haplotype_sets(&event_posteriors, &haplotypes, 10, 1e-6);
+    assert_eq!(
+        distinct
+            .iter()
+            .map(|(fractions, _)| fractions.clone())
+            .collect::<Vec<_>>(),
+        vec![
+            fractions([0.5, 0.5, 0.0]),
+            fractions([0.0, 0.5, 0.5]),
+            fractions([1.0, 0.0, 0.0])
+        ]
+    );
+    assert_eq!(
+        distinct_haplotype_sets(&event_posteriors, &haplotypes, 2, 1e-6).len(),
+        2
+    );
+
+    let outdir = tempfile::tempdir().unwrap();
+    write_distinct_solutions(
+        &outdir.path().join("out.csv"),
+        &event_posteriors,
+        &haplotypes,
+        2,
+        1e-6,
+    )
+    .unwrap();
+    let tsv = std::fs::read_to_string(outdir.path().join("distinct_solutions.tsv")).unwrap();
+    assert_eq!(
+        tsv,
+        "density\thaplotypes\tA*01:01\tA*02:01\tA*03:01\n\
+         0.4000\tA*01:01,A*02:01\t0.50\t0.50\t0.00\n\
+         0.1500\tA*02:01,A*03:01\t0.00\t0.50\t0.50\n"
+    );
+}
+
+#[test]
+fn check_afd_scales() {
+    use assert_approx_eq::assert_approx_eq;
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{
+        AfdScale, VariantCalls, VariantCallsOptions, VariantID,
+    };
+
+    //all three files encode a density of 0.01 at the allele frequencies 0 and 1 and of 0.98 (or 1 as PHRED 0) at 0.5
+    for (path, afd_scale, at_half) in &[
+        ("tests/small_calls.vcf", AfdScale::Phred, 1.0),
+        ("tests/prob_afd_calls.vcf", AfdScale::Prob, 0.98),
+        ("tests/logprob_afd_calls.vcf", AfdScale::LogProb, 0.98),
+    ] {
+        let (variant_calls, _) = VariantCalls::new_with_rejections(
+            &mut bcf::Reader::from_path(path).unwrap(),
+            &VariantCallsOptions {
+                afd_scale: *afd_scale,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let (_, afd) = &variant_calls[&VariantID(1)];
+        let query = |vaf: f64| afd.vaf_query(&NotNan::new(vaf).unwrap()).unwrap().exp();
+        assert_approx_eq!(query(0.0), 0.01, 1e-9);
+        assert_approx_eq!(query(0.5), *at_half, 1e-9);
+        assert_approx_eq!(query(1.0), 0.01, 1e-9);
+    }
+    assert_eq!("logprob".parse::<AfdScale>(), Ok(AfdScale::LogProb));
+    assert_eq!(AfdScale::default(), AfdScale::Phred);
+}
+
+#[test]
+fn check_max_haplotypes_above_panel_size_is_clamped() {
+    use orthanq::calling::haplotypes::config::CallerConfig;
+    use orthanq::calling::haplotypes::haplotypes::{
+        check_max_haplotypes, linear_program, LinearProgramSettings,
+    };
+    use orthanq::errors::OrthanqError;
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+
+    //the panel has three haplotypes
+    let max_haplotypes = check_max_haplotypes(Some(10), haplotypes.len()).unwrap();
+    assert_eq!(max_haplotypes, Some(3));
+    assert_eq!(check_max_haplotypes(Some(2), 3).unwrap(), Some(2));
+    assert_eq!(check_max_haplotypes(None, 3).unwrap(), None);
+    let error = check_max_haplotypes(Some(0), 3).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<OrthanqError>(),
+        Some(OrthanqError::InvalidMaxHaplotypes { value: 0 })
+    ));
+
+    //the clamped value behaves as no limit
+    let outdir = tempfile::tempdir().unwrap();
+    let lp_haplotypes = linear_program(
+        &outdir.path().join("out.csv"),
+        &candidate_matrix,
+        &haplotypes,
+        &variant_calls,
+        &LinearProgramSettings {
+            integer_selection: true,
+            max_haplotypes,
+            ..Default::default()
+        },
+    )
+    .unwrap()
+    .lp_haplotypes;
+    assert_eq!(lp_haplotypes.len(), 3);
+
+    //negative values are rejected in the config file
+    let path = outdir.path().join("config.yaml");
+    std::fs::write(&path, "max_haplotypes: -1\n").unwrap();
+    assert!(CallerConfig::from_path(&path).is_err());
+}
+
+#[test]
+fn check_custom_observation_model() {
+    use bio::stats::{LogProb, Prob};
+    use orthanq::calling::haplotypes::haplotypes::{Haplotype, PriorTypes};
+    use orthanq::model::{event_posteriors, Data, HaplotypeFractions, ObservationModel};
+    use std::sync::Arc;
+
+    //the likelihood of an event is the fraction of the first haplotype, independent of the data
+    #[derive(Debug)]
+    struct FirstFraction;
+
+    impl ObservationModel for FirstFraction {
+        fn compute(&self, event: &HaplotypeFractions, _data: &Data) -> LogProb {
+            LogProb::from(Prob(*event[0]))
+        }
+    }
+
+    let (_, variant_calls, candidate_matrix, _) = small_fixture();
+    let data = Data::new(candidate_matrix, variant_calls);
+    let haplotypes = vec![
+        Haplotype("A*01:01:01".to_string()),
+        Haplotype("A*02:01:01".to_string()),
+    ];
+
+    //with the diploid prior, the events are 0/1, 0.5/0.5 and 1/0 with equal prior probability,
+    //hence the posteriors are proportional to 0, 0.5 and 1
+    let posteriors = event_posteriors(
+        &data,
+        &haplotypes,
+        PriorTypes::Diploid,
+        Arc::new(FirstFraction),
+    );
+    let posterior = |first: f64| {
+        posteriors
+            .iter()
+            .find(|(fractions, _)| *fractions[0] == first)
+            .map_or(0.0, |(_, posterior)| posterior.exp())
+    };
+    assert!(posterior(0.0) < 1e-9);
+    assert!((posterior(0.5) - 1.0 / 3.0).abs() < 1e-9);
+    assert!((posterior(1.0) - 2.0 / 3.0).abs() < 1e-9);
+}
+
+#[test]
+fn check_prior_weights_prefer_common_allele_under_tie() {
+    use bio::stats::LogProb;
+    use orthanq::calling::haplotypes::haplotypes::{
+        prior_weights_for, read_prior_weights, Haplotype, PriorTypes,
+    };
+    use orthanq::model::{weighted_event_posteriors, Data, HaplotypeFractions, ObservationModel};
+    use std::io::Write;
+    use std::sync::Arc;
+
+    //the data is explained equally well by either haplotype alone, but not by both
+    #[derive(Debug)]
+    struct EitherHaplotype;
+
+    impl ObservationModel for EitherHaplotype {
+        fn compute(&self, event: &HaplotypeFractions, _data: &Data) -> LogProb {
+            if event.iter().any(|fraction| **fraction == 1.0) {
+                LogProb::ln_one()
+            } else {
+                LogProb::ln_zero()
+            }
+        }
+    }
+
+    let (_, variant_calls, candidate_matrix, _) = small_fixture();
+    let data = Data::new(candidate_matrix, variant_calls);
+    let haplotypes = vec![
+        Haplotype("A*01:01:01".to_string()),
+        Haplotype("A*02:01:01".to_string()),
+    ];
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("prior_weights.tsv");
+    let mut file = std::fs::File::create(&path).unwrap();
+    writeln!(file, "haplotype\tprior_weight").unwrap();
+    writeln!(file, "A*01:01:01\t0.001").unwrap();
+    writeln!(file, "A*02:01:01\t0.3").unwrap();
+    let weights = prior_weights_for(&read_prior_weights(&path).unwrap(), &haplotypes);
+    assert_eq!(weights, vec![0.001, 0.3]);
+
+    let posterior = |weights: &[f64], first: f64| {
+        weighted_event_posteriors(
+            &data,
+            &haplotypes,
+            PriorTypes::Diploid,
+            weights,
+            Arc::new(EitherHaplotype),
+        )
+        .iter()
+        .find(|(fractions, _)| *fractions[0] == first)
+        .map_or(0.0, |(_, posterior)| posterior.exp())
+    };
+
+    //without weights, both haplotypes are equally probable
+    assert!((posterior(&[], 1.0) - 0.5).abs() < 1e-9);
+    assert!((posterior(&[], 0.0) - 0.5).abs() < 1e-9);
+
+    //with weights, the common allele A*02:01:01 is preferred by the ratio of the weights
+    assert!((posterior(&weights, 0.0) - 0.3 / 0.301).abs() < 1e-9);
+    assert!((posterior(&weights, 1.0) - 0.001 / 0.301).abs() < 1e-9);
+
+    //haplotypes without a weight get the weight of the rarest haplotype
+    let unknown = vec![Haplotype("A*03:01:01".to_string())];
+    assert_eq!(
+        prior_weights_for(&read_prior_weights(&path).unwrap(), &unknown),
+        vec![0.001]
+    );
+}
+
+#[test]
+fn check_fit_divergence_of_well_and_poorly_fit_variants() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        fit_divergences, write_fit_divergences, VafDenominator, VariantID,
+    };
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+
+    //the true mixture explains all variants perfectly
+    let fits = fit_divergences(
+        &candidate_matrix,
+        &variant_calls,
+        &haplotypes,
+        &[0.5, 0.3, 0.2],
+        VafDenominator::default(),
+    );
+    assert_eq!(fits.len(), 4);
+    assert!(fits.iter().all(|fit| fit.divergence < 1e-9));
+
+    //without A*03:01:01, variant 3 (observed at 0.2) is expected at 0.0, where the AFD has a density of 0.01 (PHRED 20)
+    let fits = fit_divergences(
+        &candidate_matrix,
+        &variant_calls,
+        &haplotypes,
+        &[0.5, 0.5, 0.0],
+        VafDenominator::default(),
+    );
+    let divergence = |variant: i32| {
+        fits.iter()
+            .find(|fit| fit.variant == VariantID(variant))
+            .unwrap()
+            .divergence
+    };
+    assert!(divergence(1) < 1e-9);
+    assert!((divergence(3) - 100.0_f64.ln()).abs() < 1e-6);
+    assert!(divergence(2) > divergence(1) && divergence(2) < divergence(3));
+
+    let outdir = tempfile::tempdir().unwrap();
+    write_fit_divergences(
+        &outdir.path().join("out.csv"),
+        &candidate_matrix,
+        &variant_calls,
+        &haplotypes,
+        &[0.5, 0.5, 0.0],
+        VafDenominator::default(),
+    )
+    .unwrap();
+    let tsv = std::fs::read_to_string(outdir.path().join("fit_divergence.tsv")).unwrap();
+    assert!(tsv.starts_with("variant\texpected_vaf\tdivergence\n1\t0.50\t0.0000\n"));
+    assert!(tsv.contains("\n3\t0.00\t4.6052\n"));
+}
+
+#[test]
+fn check_coverage_bed_of_final_alignment() {
+    use orthanq::preprocess::hla::{covered_intervals, write_coverage_bed};
+    use std::path::Path;
+
+    //overlapping reads are merged, deletions are part of the covered interval and unmapped reads are skipped
+    let intervals = covered_intervals(Path::new("tests/small_reads.sam")).unwrap();
+    assert_eq!(
+        intervals,
+        vec![("6".to_string(), 99, 114), ("6".to_string(), 199, 211)]
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let bed = dir.path().join("sample_coverage.bed");
+    write_coverage_bed(Path::new("tests/small_reads.sam"), &bed).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&bed).unwrap(),
+        "6\t99\t114\n6\t199\t211\n"
+    );
+}
+
+#[test]
+fn check_read_names_survive_fastq_round_trip() {
+    use orthanq::preprocess::hla::{read_fates, write_paired_fastq, write_read_fates};
+    use std::path::Path;
+
+    //the mates are not adjacent in the coordinate sorted bam, the secondary alignment and the read
+    //without mate are skipped
+    let dir = tempfile::tempdir().unwrap();
+    let (fastq_1, fastq_2) = (dir.path().join("reads_1.fq"), dir.path().join("reads_2.fq"));
+    let pairs =
+        write_paired_fastq(Path::new("tests/paired_reads.sam"), &fastq_1, &fastq_2).unwrap();
+    assert_eq!(pairs, 2);
+    assert_eq!(
+        std::fs::read_to_string(&fastq_1).unwrap(),
+        "@M01:12:FC1:1:1101:15589:1331\nACGTACGTAC\n+\nIIIIIIIIII\n\
+         @M01:12:FC1:1:1101:15589:1332\nCCCCAAAAGG\n+\nIIIIIIIIII\n"
+    );
+    //reverse strand mates are written in sequencing orientation, the names carry no /1 or /2 suffix
+    assert_eq!(
+        std::fs::read_to_string(&fastq_2).unwrap(),
+        "@M01:12:FC1:1:1101:15589:1331\nCCGGGGTTTT\n+\nJIHGFEDCBA\n\
+         @M01:12:FC1:1:1101:15589:1332\nTTAAAACCCC\n+\nIIIIIIIIII\n"
+    );
+    //reads without qualities get the default quality of samtools fastq
+    write_paired_fastq(
+        Path::new("tests/missing_quality_reads.sam"),
+        &fastq_1,
+        &fastq_2,
+    )
+    .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&fastq_1).unwrap(),
+        "@M01:12:FC1:1:1101:15589:1334\nACGTACGTAC\n+\n\"\"\"\"\"\"\"\"\"\"\n"
+    );
+
+    //the names of the realigned reads are those of the extracted ones
+    let fates = read_fates(
+        Path::new("tests/paired_reads.sam"),
+        Path::new("tests/realigned_reads.sam"),
+    )
+    .unwrap();
+    assert_eq!(
+        fates,
+        vec![
+            (
+                "M01:12:FC1:1:1101:15589:1331".to_string(),
+                Some("6:500".to_string())
+            ),
+            ("M01:12:FC1:1:1101:15589:1332".to_string(), None),
+            ("M01:12:FC1:1:1101:15589:1333".to_string(), None),
+        ]
+    );
+    let output = dir.path().join("sample_read_fates.tsv");
+    write_read_fates(&fates, true, &output).unwrap();
+    let content = std::fs::read_to_string(&output).unwrap();
+    assert_eq!(
+        content.lines().take(2).collect::<Vec<_>>(),
+        vec![
+            "read\textracted\trealigned\tfinal_position",
+            "M01:12:FC1:1:1101:15589:1331\ttrue\ttrue\t6:500"
+        ]
+    );
+}
+
+#[test]
+fn check_typing_report_of_heterozygous_locus() {
+    use orthanq::calling::haplotypes::haplotypes::Haplotype;
+    use orthanq::calling::haplotypes::hla::{
+        typing_report, write_typing_report, ReportResolution, TypingReportRow,
+    };
+    use std::collections::BTreeMap;
+
+    let haplotypes = vec![
+        Haplotype("A*01:01:01".to_string()),
+        Haplotype("A*02:01:01".to_string()),
+        Haplotype("A*03:01:01".to_string()),
+        Haplotype("B*07:02:01".to_string()),
+    ];
+    let fractions = [0.25, 0.25, 0.0, 0.5];
+    let g_groups: BTreeMap<String, String> =
+        vec![("A*01:01:01:01".to_string(), "A*01:01:01G".to_string())]
+            .into_iter()
+            .collect();
+
+    let rows = typing_report(
+        &haplotypes,
+        &fractions,
+        ReportResolution::TwoField,
+        0.9,
+        &g_groups,
+    );
+    assert_eq!(
+        rows,
+        vec![
+            TypingReportRow {
+                locus: "A".to_string(),
+                allele1: "A*01:01".to_string(),
+                allele2: "A*02:01".to_string(),
+                resolution: ReportResolution::TwoField,
+                confidence: 0.9,
+            },
+            //a single allele is reported as homozygous
+            TypingReportRow {
+                locus: "B".to_string(),
+                allele1: "B*07:02".to_string(),
+                allele2: "B*07:02".to_string(),
+                resolution: ReportResolution::TwoField,
+                confidence: 0.9,
+            },
+        ]
+    );
+
+    //alleles without G group are kept as they are
+    let rows = typing_report(
+        &haplotypes,
+        &fractions,
+        "G".parse().unwrap(),
+        0.9,
+        &g_groups,
+    );
+    assert_eq!(rows[0].allele1, "A*01:01:01G");
+    assert_eq!(rows[0].allele2, "A*02:01:01");
+
+    let outdir = tempfile::tempdir().unwrap();
+    write_typing_report(&outdir.path().join("out.csv"), &rows[..1]).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(outdir.path().join("typing_report.tsv")).unwrap(),
+        "locus\tallele1\tallele2\tresolution\tconfidence\nA\tA*01:01:01G\tA*02:01:01\tG\t0.9000\n"
+    );
+}
+
+#[test]
+fn check_results_are_converted_to_g_groups() {
+    use orthanq::calling::haplotypes::hla::convert_results_to_g;
+
+    let outdir = tempfile::tempdir().unwrap();
+    let output = outdir.path().join("G_groups.csv");
+    convert_results_to_g(
+        &"tests/three_field_results.csv".into(),
+        &"tests/hla.xml".into(),
+        &output,
+    )
+    .unwrap();
+
+    //haplotypes without G group are kept, the values are unchanged
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        "density,odds,A*01:01:01G,A*02:01:01G,X*99:99:99\n\
+         0.90,1,0.50,0.50,0.00\n\
+         0.10,0.11,1.00,0.00,0.00\n"
+    );
+}
+
+#[test]
+fn check_duplicate_variant_ids_are_rejected() {
+    use orthanq::calling::haplotypes::haplotypes::{HaplotypeVariants, PanelIssue, VariantID};
+    use orthanq::errors::OrthanqError;
+
+    //variant 2 occurs twice, reading the panel for calling fails with the offending ID
+    let error = HaplotypeVariants::new(
+        &mut bcf::Reader::from_path("tests/duplicate_id_candidates.vcf").unwrap(),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<OrthanqError>(),
+        Some(OrthanqError::DuplicateVariantId { variant: 2 })
+    ));
+    assert!(error.to_string().contains("variant ID 2"));
+
+    //the validation still reports it among the other issues
+    let issues = HaplotypeVariants::validate(
+        &mut bcf::Reader::from_path("tests/duplicate_id_candidates.vcf").unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        issues,
+        vec![PanelIssue::DuplicateId {
+            variant: VariantID(2)
+        }]
+    );
+}
+
+#[test]
+fn check_coverage_summary_of_alignment() {
+    use orthanq::preprocess::CoverageSummary;
+    use std::path::Path;
+
+    assert_eq!(
+        CoverageSummary::of_bam(Path::new("tests/small_reads.sam")).unwrap(),
+        Some(CoverageSummary {
+            intervals: 2,
+            covered_bases: 27
+        })
+    );
+    assert_eq!(
+        CoverageSummary::of_bam(Path::new("tests/missing.bam")).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn check_gzipped_genome_without_index_is_prepared() {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use orthanq::errors::OrthanqError;
+    use orthanq::utils::prepare_genome;
+    use std::io::Write;
+
+    //the index is created with the real samtools, stubs of other tests must not be in PATH
+    let _path = PATH_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let fasta = ">chr1 first\nACGT\nACGT\nAC\n>chr2\nGGGG\nGG\n";
+    let expected_index = "chr1\t10\t12\t4\t5\nchr2\t6\t31\t4\t5\n";
+    let dir = tempfile::tempdir().unwrap();
+
+    //an uncompressed genome without index is linked into the given directory and indexed there, nothing is written
+    //next to it
+    let genome = dir.path().join("genome.fa");
+    std::fs::write(&genome, fasta).unwrap();
+    let work_dir = tempfile::tempdir().unwrap();
+    let prepared = prepare_genome(&genome, work_dir.path()).unwrap();
+    assert_eq!(prepared, work_dir.path().join("genome.fa"));
+    assert_eq!(std::fs::read_to_string(&prepared).unwrap(), fasta);
+    assert_eq!(
+        std::fs::read_to_string(work_dir.path().join("genome.fa.fai")).unwrap(),
+        expected_index
+    );
+    assert!(!dir.path().join("genome.fa.fai").exists());
+
+    //a genome with index is used as it is
+    std::fs::copy(
+        work_dir.path().join("genome.fa.fai"),
+        dir.path().join("genome.fa.fai"),
+    )
+    .unwrap();
+    assert_eq!(prepare_genome(&genome, work_dir.path()).unwrap(), genome);
+
+    //a gzipped genome is decompressed into the given directory and indexed there
+    let gzipped = dir.path().join("gzipped.fa.gz");
+    let mut encoder = GzEncoder::new(
+        std::fs::File::create(&gzipped).unwrap(),
+        Compression::default(),
+    );
+    encoder.write_all(fasta.as_bytes()).unwrap();
+    encoder.finish().unwrap();
+    let work_dir = tempfile::tempdir().unwrap();
+    let prepared = prepare_genome(&gzipped, work_dir.path()).unwrap();
+    assert_eq!(prepared, work_dir.path().join("gzipped.fa"));
+    assert_eq!(std::fs::read_to_string(&prepared).unwrap(), fasta);
+    assert_eq!(
+        std::fs::read_to_string(work_dir.path().join("gzipped.fa.fai")).unwrap(),
+        expected_index
+    );
+
+    //a genome that can't be indexed is rejected with a clear error
+    let malformed = dir.path().join("malformed.fa");
+    std::fs::write(&malformed, ">chr1\nAC\nACGT\n").unwrap();
+    let error = prepare_genome(&malformed, tempfile::tempdir().unwrap().path()).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<OrthanqError>(),
+        Some(OrthanqError::InvalidGenome { .. })
+    ));
+}
+
+#[test]
+fn check_min_mapq_is_applied_to_extraction() {
+    use orthanq::preprocess::hla::extraction_args;
+    use std::ffi::OsString;
+    use std::path::Path;
+
+    let args = |min_mapq| -> Vec<String> {
+        extraction_args(
+            Path::new("sorted.bam"),
+            Path::new("regions.bed"),
+            Path::new("extracted.bam"),
+            min_mapq,
+        )
+        .into_iter()
+        .map(|arg: OsString| arg.into_string().unwrap())
+        .collect()
+    };
+
+    //by default, no reads are filtered by their mapping quality
+    assert!(!args(None).contains(&"-q".to_string()));
+
+    let filtered = args(Some(20));
+    let q = filtered.iter().position(|arg| arg == "-q").unwrap();
+    assert_eq!(filtered[q + 1], "20");
+    assert_eq!(&filtered[..4], &["view", "sorted.bam", "-L", "regions.bed"]);
+}
+
+#[test]
+fn check_results_diff_reports_changed_call() {
+    use orthanq::calling::haplotypes::haplotypes::{diff_results, write_results_diff, Haplotype};
+    use std::path::PathBuf;
+
+    let results = |dir: &str| -> Vec<PathBuf> {
+        ["sample1", "sample2"]
+            .iter()
+            .map(|sample| PathBuf::from(format!("tests/{}/{}.csv", dir, sample)))
+            .collect()
+    };
+    let diffs = diff_results(&results("results_before"), &results("results_after"), 0.05).unwrap();
+    assert_eq!(diffs.len(), 2);
+
+    //sample1 only differs in the fractions, within the tolerance
+    assert_eq!(diffs[0].sample, "sample1");
+    assert!(!diffs[0].changed);
+    assert!((diffs[0].max_fraction_change - 0.02).abs() < 1e-9);
+
+    //sample2 changed from homozygous A*01:01:01 to A*01:01:01/A*03:01:01
+    assert_eq!(diffs[1].sample, "sample2");
+    assert!(diffs[1].changed);
+    assert_eq!(
+        diffs[1]
+            .after
+            .as_ref()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>(),
+        vec![
+            Haplotype("A*01:01:01".to_string()),
+            Haplotype("A*03:01:01".to_string())
+        ]
+    );
+
+    //a sample missing on one side is reported as changed
+    let diffs = diff_results(
+        &results("results_before"),
+        &results("results_after")[..1],
+        0.05,
+    )
+    .unwrap();
+    assert!(diffs[1].changed);
+    assert!(diffs[1].after.is_none());
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("diff.tsv");
+    write_results_diff(&output, &diffs).unwrap();
+    let written = std::fs::read_to_string(&output).unwrap();
+    assert!(written.contains("sample2\ttrue\tA*01:01:01=1.00\tNA\t1.00"));
+}
+
+#[test]
+fn check_error_rates_weight_the_lp_objective() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        linear_program, LinearProgramSettings, VariantCalls, VariantCallsOptions, VariantID,
+    };
+
+    let read_calls = |error_field: Option<&str>| {
+        VariantCalls::new_with_rejections(
+            &mut bcf::Reader::from_path("tests/error_rate_calls.vcf").unwrap(),
+            &VariantCallsOptions {
+                error_field: error_field.map(|field| field.to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .0
+    };
+
+    //without an error field, all calls are considered reliable
+    assert_eq!(read_calls(None).error_rate(&VariantID(1)), None);
+
+    let variant_calls = read_calls(Some("ERR"));
+    assert_eq!(variant_calls.error_rate(&VariantID(1)), Some(0.5));
+    assert_eq!(variant_calls.error_rate(&VariantID(2)), None);
+    assert_eq!(variant_calls.error_rate(&VariantID(3)), Some(0.0));
+    assert_eq!(variant_calls.error_rate(&VariantID(4)), Some(0.75));
+
+    let (_, _, candidate_matrix, haplotypes) = small_fixture();
+    let outdir = tempfile::tempdir().unwrap();
+    linear_program(
+        &outdir.path().join("out.csv"),
+        &candidate_matrix,
+        &haplotypes,
+        &variant_calls,
+        &LinearProgramSettings {
+            export_lp: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    //the deviations are weighted by 1 - error rate in the objective
+    let lp = std::fs::read_to_string(outdir.path().join("lp.lp")).unwrap();
+    let objective = lp.lines().find(|line| line.starts_with(" obj:")).unwrap();
+    assert!(objective.contains(" 0.5 t1 "));
+    assert!(objective.contains(" 1 t2 "));
+    assert!(objective.contains(" 1 t3 "));
+    assert!(objective.ends_with(" 0.25 t4"));
+}
+
+#[test]
+fn check_max_events_caps_the_event_search() {
+    use orthanq::calling::haplotypes::haplotypes::{Haplotype, PriorTypes};
+    use orthanq::model::{
+        event_posteriors_with_options, Data, ModelOptions, VarlociraptorLikelihood,
+    };
+    use std::sync::Arc;
+
+    let (_, variant_calls, candidate_matrix, _) = small_fixture();
+    let data = Data::new(candidate_matrix, variant_calls);
+    let haplotypes = vec![
+        Haplotype("A*01:01:01".to_string()),
+        Haplotype("A*02:01:01".to_string()),
+        Haplotype("A*03:01:01".to_string()),
+    ];
+    let posteriors = |max_events| {
+        event_posteriors_with_options(
+            &data,
+            &haplotypes,
+            PriorTypes::Diploid,
+            &ModelOptions {
+                max_events,
+                ..Default::default()
+            },
+            Arc::new(VarlociraptorLikelihood::default()),
+        )
+    };
+
+    //with the diploid prior, there are 6 events of three haplotypes (three homozygous, three heterozygous)
+    let full = posteriors(None);
+    assert_eq!(full.len(), 6);
+
+    //the capped search completes with fewer events, whose posteriors are normalized among themselves
+    let capped = posteriors(Some(3));
+    assert!(!capped.is_empty() && capped.len() <= 3);
+    let total: f64 = capped.iter().map(|(_, posterior)| posterior.exp()).sum();
+    assert!((total - 1.0).abs() < 1e-6);
+
+    //a cap above the number of events doesn't leave any out
+    assert_eq!(posteriors(Some(100)).len(), 6);
+}
+
+#[test]
+fn check_read_support_of_best_solution() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        read_fragment_ids, write_read_support, CandidateMatrix, Haplotype, HaplotypeVariants,
+        VafDenominator, VariantCalls, VariantID,
+    };
+
+    let fragment_ids = read_fragment_ids(
+        &mut bcf::Reader::from_path("tests/fragment_calls.vcf").unwrap(),
+        "FRAGMENT_IDS",
+        None,
+    )
+    .unwrap();
+    assert_eq!(fragment_ids[&VariantID(1)], vec!["r1", "r2"]);
+    assert_eq!(fragment_ids[&VariantID(4)], vec!["r1", "r4"]);
+    assert!(!fragment_ids.contains_key(&VariantID(3)));
+
+    let haplotype_variants =
+        HaplotypeVariants::new(&mut bcf::Reader::from_path("tests/small_candidates.vcf").unwrap())
+            .unwrap();
+    let variant_calls =
+        VariantCalls::new(&mut bcf::Reader::from_path("tests/fragment_calls.vcf").unwrap())
+            .unwrap();
+    let haplotypes = vec![
+        Haplotype("A*01:01:01".to_string()),
+        Haplotype("A*02:01:01".to_string()),
+        Haplotype("A*03:01:01".to_string()),
+    ];
+    let dir = tempfile::tempdir().unwrap();
+    write_read_support(
+        &dir.path().join("out.csv"),
+        &CandidateMatrix::new(&haplotype_variants).unwrap(),
+        &variant_calls,
+        &haplotypes,
+        &[0.5, 0.5, 0.0],
+        &fragment_ids,
+        VafDenominator::default(),
+    )
+    .unwrap();
+
+    //the fragments of each variant are listed for the haplotypes of the solution that carry it,
+    //variant 3 is only carried by the absent A*03:01:01
+    let written = std::fs::read_to_string(dir.path().join("read_support.tsv")).unwrap();
+    assert_eq!(
+        written.lines().collect::<Vec<&str>>(),
+        vec![
+            "variant\thaplotype\tcontribution\tfragment_ids",
+            "1\tA*01:01:01\t0.5000\tr1,r2",
+            "2\tA*02:01:01\t0.5000\tr3",
+            "4\tA*01:01:01\t0.5000\tr1,r4",
+            "4\tA*02:01:01\t0.5000\tr1,r4",
+        ]
+    );
+}
+
+#[test]
+fn check_presence_concordance_flags_discordant_variant() {
+    use orthanq::calling::haplotypes::haplotypes::{
+        presence_concordance, read_prob_absent, write_presence_concordance, CandidateMatrix,
+        Haplotype, HaplotypeVariants, VafDenominator, VariantCalls, VariantID,
+    };
+
+    let prob_absent =
+        read_prob_absent(&mut bcf::Reader::from_path("tests/discordant_calls.vcf").unwrap())
+            .unwrap();
+    assert!((prob_absent[&VariantID(1)] - 0.01).abs() < 1e-6);
+    assert!((prob_absent[&VariantID(4)] - 0.001).abs() < 1e-6);
+
+    let haplotype_variants =
+        HaplotypeVariants::new(&mut bcf::Reader::from_path("tests/small_candidates.vcf").unwrap())
+            .unwrap();
+    let variant_calls =
+        VariantCalls::new(&mut bcf::Reader::from_path("tests/discordant_calls.vcf").unwrap())
+            .unwrap();
+    let haplotypes = vec![
+        Haplotype("A*01:01:01".to_string()),
+        Haplotype("A*02:01:01".to_string()),
+        Haplotype("A*03:01:01".to_string()),
+    ];
+    let concordances = presence_concordance(
+        &CandidateMatrix::new(&haplotype_variants).unwrap(),
+        &variant_calls,
+        &haplotypes,
+        &[0.5, 0.5, 0.0],
+        &prob_absent,
+        VafDenominator::default(),
+    );
+
+    //variant 3 is confidently called but only carried by the absent A*03:01:01,
+    //variant 2 is ambiguous and hence never discordant
+    let discordant: Vec<VariantID> = concordances
+        .iter()
+        .filter(|concordance| concordance.is_discordant())
+        .map(|concordance| concordance.variant)
+        .collect();
+    assert_eq!(discordant, vec![VariantID(3)]);
+
+    let dir = tempfile::tempdir().unwrap();
+    write_presence_concordance(&dir.path().join("out.csv"), &concordances).unwrap();
+    let written = std::fs::read_to_string(dir.path().join("presence_concordance.tsv")).unwrap();
+    assert_eq!(
+        written.lines().collect::<Vec<&str>>(),
+        vec![
+            "variant\tprob_absent\tobserved\timplied\tconcordant",
+            "1\t0.0100\tpresent\tpresent\ttrue",
+            "2\t0.5012\tambiguous\tpresent\tNA",
+            "3\t0.0100\tpresent\tabsent\tfalse",
+            "4\t0.0010\tpresent\tpresent\ttrue",
+        ]
+    );
+}
+
+#[test]
+fn check_boundary_fractions_are_flagged() {
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{boundary_fractions, Haplotype, PriorTypes};
+    use orthanq::model::HaplotypeFractions;
+
+    let haplotypes = vec![
+        Haplotype("A*01:01:01".to_string()),
+        Haplotype("A*02:01:01".to_string()),
+        Haplotype("A*03:01:01".to_string()),
+    ];
+    let fractions = |fractions: &[f64]| {
+        HaplotypeFractions(
+            fractions
+                .iter()
+                .map(|fraction| NotNan::new(*fraction).unwrap())
+                .collect(),
+        )
+    };
+
+    //a fraction pinned at the upper bound is flagged, absent haplotypes are not
+    assert_eq!(
+        boundary_fractions(
+            &fractions(&[1.0, 0.0, 0.0]),
+            &haplotypes,
+            &PriorTypes::Uniform
+        ),
+        vec![(Haplotype("A*01:01:01".to_string()), 1.0)]
+    );
+    assert_eq!(
+        boundary_fractions(
+            &fractions(&[0.95, 0.05, 0.0]),
+            &haplotypes,
+            &PriorTypes::Uniform
+        ),
+        vec![
+            (Haplotype("A*01:01:01".to_string()), 0.95),
+            (Haplotype("A*02:01:01".to_string()), 0.05)
+        ]
+    );
+    assert!(boundary_fractions(
+        &fractions(&[0.5, 0.3, 0.2]),
+        &haplotypes,
+        &PriorTypes::Uniform
+    )
+    .is_empty());
+    //the diploid grid points are exact
+    assert!(boundary_fractions(
+        &fractions(&[1.0, 0.0, 0.0]),
+        &haplotypes,
+        &PriorTypes::Diploid
+    )
+    .is_empty());
+}
+
+#[test]
+fn check_vaf_denominator_policies() {
+    use bio::stats::LogProb;
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{
+        expected_vafs, write_results, CandidateMatrix, Haplotype, HaplotypeVariants,
+        VafDenominator, VariantCalls, VariantID,
+    };
+    use orthanq::model::{Data, HaplotypeFractions, ObservationModel, VarlociraptorLikelihood};
+
+    //A*01:01:01 is not covered at variant 3, which is carried only by A*03:01:01
+    let haplotype_variants = HaplotypeVariants::new(
+        &mut bcf::Reader::from_path("tests/uncharacterized_candidates.vcf").unwrap(),
+    )
+    .unwrap();
+    let candidate_matrix = CandidateMatrix::new(&haplotype_variants).unwrap();
+    let haplotypes: Vec<Haplotype> = haplotype_variants
+        .values()
+        .next()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect();
+    let fractions = HaplotypeFractions(
+        [0.5, 0.25, 0.25]
+            .iter()
+            .map(|value| NotNan::new(*value).unwrap())
+            .collect(),
+    );
+
+    //excluding the uncovered haplotype, A*03:01:01 makes up half of the informative fractions
+    let vafs = expected_vafs(
+        &fractions,
+        &candidate_matrix,
+        VafDenominator::ExcludeUncovered,
+    );
+    assert!((vafs[&VariantID(3)] - 0.5).abs() < 1e-9);
+    let vafs = expected_vafs(&fractions, &candidate_matrix, VafDenominator::IncludeAll);
+    assert!((vafs[&VariantID(3)] - 0.25).abs() < 1e-9);
+    //variants covered in all haplotypes are not affected by the policy
+    for denominator in [VafDenominator::ExcludeUncovered, VafDenominator::IncludeAll].iter() {
+        let vafs = expected_vafs(&fractions, &candidate_matrix, *denominator);
+        assert!((vafs[&VariantID(1)] - 0.5).abs() < 1e-9);
+        assert!((vafs[&VariantID(4)] - 0.75).abs() < 1e-9);
+    }
+
+    let variant_calls =
+        VariantCalls::new(&mut bcf::Reader::from_path("tests/small_calls.vcf").unwrap()).unwrap();
+    let data = Data::new(candidate_matrix, variant_calls);
+    let event_posteriors = vec![(fractions, LogProb(0.9_f64.ln()))];
+    let outdir = tempfile::tempdir().unwrap();
+    let query_of_variant_3 = |denominator: VafDenominator| {
+        let output = outdir.path().join("results.csv");
+        write_results(
+            &output,
+            &data,
+            &event_posteriors,
+            &haplotypes,
+            "uniform".to_string(),
+            true,
+            None,
+            None,
+            denominator,
+            1e-6,
+        )
+        .unwrap();
+        let mut rdr = csv::Reader::from_path(&output).unwrap();
+        let column = rdr
+            .headers()
+            .unwrap()
+            .iter()
+            .position(|header| header == "VariantID(3)")
+            .unwrap();
+        let record = rdr.records().next().unwrap().unwrap();
+        record[column].split(':').next().unwrap().to_string()
+    };
+    assert_eq!(query_of_variant_3(VafDenominator::ExcludeUncovered), "0.5");
+    assert_eq!(query_of_variant_3(VafDenominator::IncludeAll), "0.25");
+
+    //the model uses the same policy, the call of variant 3 (AF 0.2) supports 0.25 better than 0.5
+    let (fractions, _) = &event_posteriors[0];
+    let likelihood = |model: VarlociraptorLikelihood| model.compute(fractions, &data);
+    assert_eq!(
+        likelihood(VarlociraptorLikelihood::default()),
+        likelihood(VarlociraptorLikelihood::new(
+            VafDenominator::ExcludeUncovered
+        ))
+    );
+    assert!(
+        likelihood(VarlociraptorLikelihood::new(VafDenominator::IncludeAll))
+            > likelihood(VarlociraptorLikelihood::new(
+                VafDenominator::ExcludeUncovered
+            ))
+    );
+}
+
+#[test]
+fn check_variant_quality_percentile_keeps_top_variants() {
+    use orthanq::calling::haplotypes::haplotypes::{VariantCalls, VariantCallsOptions, VariantID};
+
+    let read = |variant_quality_percentile: Option<f64>| {
+        VariantCalls::new_with_rejections(
+            &mut bcf::Reader::from_path("tests/quality_calls.vcf").unwrap(),
+            &VariantCallsOptions {
+                variant_quality_percentile,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    };
+    let (variant_calls, rejected) = read(None);
+    assert_eq!(variant_calls.len(), 8);
+    assert!(rejected.is_empty());
+
+    //at the 75th percentile, only the top quartile is kept: the two calls with PROB_ABSENT 0 (farthest from 0.5)
+    let (variant_calls, rejected) = read(Some(75.0));
+    assert_eq!(
+        variant_calls.keys().cloned().collect::<Vec<_>>(),
+        vec![VariantID(2), VariantID(5)]
+    );
+    assert_eq!(
+        rejected.low_quality,
+        vec![1, 3, 4, 6, 7, 8]
+            .into_iter()
+            .map(VariantID)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(variant_calls.read_depth(&VariantID(1)), None);
+
+    //at the median, the next best calls (PROB_ABSENT 10 and 1) are kept as well
+    let (variant_calls, _) = read(Some(50.0));
+    assert_eq!(
+        variant_calls.keys().cloned().collect::<Vec<_>>(),
+        vec![VariantID(2), VariantID(3), VariantID(5), VariantID(6)]
+    );
+}
+
+#[test]
+fn check_output_prefix_is_applied_to_all_generated_files() {
+    let outdir = tempfile::tempdir().unwrap();
+    orthanq::calling::haplotypes::hla::CallerBuilder::default()
+        .haplotype_variants(bcf::Reader::from_path("tests/B.vcf").unwrap())
+        .variant_calls(
+            bcf::Reader::from_path("tests/Sample_HLA00318-0.5_HLA00319-0.5_B.bcf").unwrap(),
+        )
+        .xml("tests/hla.xml".into())
+        .common_variants(false)
+        .enable_equivalence_class_constraint(false)
+        .outcsv(outdir.path().join("predictions.csv"))
+        .prior("diploid".to_string())
+        .lp_cutoff(0.01)
+        .threshold_equivalence_class(1)
+        .extend_haplotypes(Some(true))
+        .num_extend_haplotypes(3)
+        .timeout(Some(0))
+        .output_prefix(Some("sample1_".to_string()))
+        .long_format(true)
+        .build()
+        .unwrap()
+        .call()
+        .unwrap();
+
+    let names: Vec<String> = std::fs::read_dir(outdir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    assert!(names.contains(&"sample1_predictions.csv".to_string()));
+    assert!(names.contains(&"sample1_manifest.json".to_string()));
+    assert!(names.contains(&"sample1_fractions_long.tsv".to_string()));
+    //no file is left without the prefix, including the scratch directory
+    assert!(
+        names.iter().all(|name| name.starts_with("sample1_")),
+        "{:?}",
+        names
+    );
+}
+
+#[test]
+fn check_haploid_prior_reports_single_haplotype() {
+    use orthanq::calling::haplotypes::haplotypes::PriorTypes;
+    use orthanq::model::{event_posteriors, Data, VarlociraptorLikelihood};
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let data = Data::new(candidate_matrix, variant_calls);
+
+    assert_eq!(PriorTypes::from_str("haploid"), Ok(PriorTypes::Haploid));
+    let posteriors = event_posteriors(
+        &data,
+        &haplotypes,
+        PriorTypes::Haploid,
+        Arc::new(VarlociraptorLikelihood::default()),
+    );
+    //one event per haplotype, each with exactly that haplotype at 1.0
+    assert_eq!(posteriors.len(), haplotypes.len());
+    for (fractions, _) in posteriors.iter() {
+        assert_eq!(fractions.iter().filter(|f| ***f == 1.0).count(), 1);
+        assert_eq!(fractions.iter().filter(|f| ***f == 0.0).count(), 2);
+    }
+    let total: f64 = posteriors.iter().map(|(_, p)| p.exp()).sum();
+    assert!((total - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn check_pipeline_types_gene_from_aligned_sample() {
+    use orthanq::calling::haplotypes::hla::ReportResolution;
+
+    //stub the external tools such that each invocation is logged instead of executed, varlociraptor reports
+    //the fixture calls of the sample
+    let dir = tempfile::tempdir().unwrap();
+    let calls = std::fs::canonicalize("tests/Sample_HLA00318-0.5_HLA00319-0.5_B.bcf").unwrap();
+    let tools = stub_tools(
+        dir.path(),
+        &format!(
+            "if [ \"$(basename \"$0\")\" = varlociraptor ] && [ \"$1\" = call ]; then cat {}; fi\n",
+            calls.display()
+        ),
+    );
+    let log = &tools.log;
+    let genome = dir.path().join("genome.fasta");
+    std::fs::write(&genome, ">6\nACGTACGTAC\n").unwrap();
+
+    let outdir = dir.path().join("out");
+    let result = orthanq::pipeline::PipelineBuilder::default()
+        .alignment(dir.path().join("NA12878.cram"))
+        .gene("B".to_string())
+        .genome(genome)
+        .haplotype_variants("tests/B.vcf".into())
+        .xml("tests/hla.xml".into())
+        .outdir(outdir.clone())
+        .build()
+        .unwrap()
+        .call()
+        .unwrap();
+
+    assert_eq!(result.sample, "NA12878");
+    assert_eq!(result.calls, outdir.join("NA12878.bcf"));
+    assert!(result.results.exists());
+    assert!(outdir.join("G_groups.csv").exists());
+    assert_eq!(result.typing.len(), 1);
+    assert_eq!(result.typing[0].locus, "B");
+    assert_eq!(result.typing[0].resolution, ReportResolution::GGroup);
+
+    //the aligned reads are extracted directly, decoded with the genome, and not realigned
+    let invocations = std::fs::read_to_string(&log).unwrap();
+    let programs = invocations
+        .lines()
+        .map(|line| line.split_whitespace().next().unwrap())
+        .collect::<Vec<&str>>();
+    assert!(!programs.contains(&"bwa"));
+    assert!(!programs.contains(&"vg"));
+    assert!(invocations.contains("--reference"));
+}
+
+#[test]
+fn check_scenario_with_mismatched_sample_is_rejected() {
+    use orthanq::errors::OrthanqError;
+    use orthanq::preprocess::{check_scenario, scenario_samples, OBS_SAMPLE};
+
+    let bundled = std::fs::read_to_string("resources/scenarios/scenario.yaml").unwrap();
+    assert_eq!(scenario_samples(&bundled).unwrap(), vec!["sample"]);
+    check_scenario(&bundled, OBS_SAMPLE).unwrap();
+
+    //the scenario names the sample differently than the observations are passed to varlociraptor
+    let dir = tempfile::tempdir().unwrap();
+    let scenario = dir.path().join("scenario.yaml");
+    std::fs::write(
+        &scenario,
+        bundled
+            .replace("  sample:", "  tumor:")
+            .replace("sample:]", "tumor:]"),
+    )
+    .unwrap();
+    let error = orthanq::preprocess::hla::CallerBuilder::default()
+        .genome("genome.fasta".into())
+        .reads(vec!["sample_1.fastq".into(), "sample_2.fastq".into()])
+        .haplotype_variants("tests/small_candidates.vcf".into())
+        .bwa_index(None)
+        .output(dir.path().join("out").join("sample.bcf"))
+        .threads("1".to_string())
+        .use_pangenome(false)
+        .scenario(Some(scenario))
+        .build()
+        .unwrap()
+        .call()
+        .unwrap_err();
+    match error.downcast_ref::<OrthanqError>() {
+        Some(OrthanqError::ScenarioSampleMismatch { sample, declared }) => {
+            assert_eq!(sample, "sample");
+            assert_eq!(declared, &vec!["tumor".to_string()]);
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[test]
+fn check_unconfirmed_alleles_are_excluded() {
+    use orthanq::calling::haplotypes::haplotypes::Haplotype;
+    use orthanq::calling::haplotypes::hla::unconfirmed_haplotypes;
+
+    let outdir = tempfile::tempdir().unwrap();
+    let outcsv = outdir.path().join("out.csv");
+    let mut caller = orthanq::calling::haplotypes::hla::CallerBuilder::default()
+        .haplotype_variants(bcf::Reader::from_path("tests/small_candidates.vcf").unwrap())
+        .variant_calls(bcf::Reader::from_path("tests/small_calls.vcf").unwrap())
+        .xml("tests/unconfirmed_hla.xml".into())
+        .common_variants(false)
+        .enable_equivalence_class_constraint(false)
+        .outcsv(outcsv.clone())
+        .prior("diploid".to_string())
+        .lp_cutoff(0.01)
+        .threshold_equivalence_class(1)
+        .extend_haplotypes(Some(false))
+        .num_extend_haplotypes(0)
+        .confirmed_only(true)
+        .build()
+        .unwrap();
+
+    //A*03:01:01 is only known as the unconfirmed A*03:01:01:01
+    let haplotypes = vec![
+        Haplotype("A*01:01:01".to_string()),
+        Haplotype("A*02:01:01".to_string()),
+        Haplotype("A*03:01:01".to_string()),
+    ];
+    assert_eq!(
+        unconfirmed_haplotypes(&haplotypes, &caller.allele_status().unwrap()),
+        vec![Haplotype("A*03:01:01".to_string())]
+    );
+
+    caller.call().unwrap();
+    let headers = csv::Reader::from_path(&outcsv)
+        .unwrap()
+        .headers()
+        .unwrap()
+        .iter()
+        .map(|header| header.to_string())
+        .collect::<Vec<String>>();
+    assert!(headers.len() > 2);
+    assert!(!headers.contains(&"A*03:01:01".to_string()));
+}
+
+#[test]
+fn check_qc_json_contains_core_metrics() {
+    let outdir = tempfile::tempdir().unwrap();
+    orthanq::calling::haplotypes::hla::CallerBuilder::default()
+        .haplotype_variants(bcf::Reader::from_path("tests/B.vcf").unwrap())
+        .variant_calls(
+            bcf::Reader::from_path("tests/Sample_HLA00318-0.5_HLA00319-0.5_B.bcf").unwrap(),
+        )
+        .xml("tests/hla.xml".into())
+        .common_variants(false)
+        .enable_equivalence_class_constraint(false)
+        .outcsv(outdir.path().join("predictions.csv"))
+        .prior("diploid".to_string())
+        .lp_cutoff(0.01)
+        .threshold_equivalence_class(1)
+        .extend_haplotypes(Some(true))
+        .num_extend_haplotypes(3)
+        .timeout(Some(0))
+        .fit_divergence(true)
+        .build()
+        .unwrap()
+        .call()
+        .unwrap();
+
+    let qc: serde_json::Value =
+        serde_json::from_reader(std::fs::File::open(outdir.path().join("qc.json")).unwrap())
+            .unwrap();
+    assert_eq!(qc["schema_version"], 1);
+    assert!(qc["quality"].is_string());
+    assert!(qc["coverage"]["variants"].as_u64().unwrap() > 0);
+    assert!(qc["coverage"]["informative_variants"].is_u64());
+    assert!(qc["coverage"]["min_coverage"].is_i64());
+    assert!(qc["solution"]["lp_residual"].is_f64());
+    assert!(qc["solution"]["mixture_entropy"].is_f64());
+    assert!(qc["lp_posterior_divergent"].is_array());
+    //only the computed diagnostics are included
+    assert!(qc["fit_divergence"]["variants"].as_u64().unwrap() > 0);
+    assert!(qc["fit_divergence"]["poorly_fit_variants"].is_array());
+    assert!(qc.get("presence_concordance").is_none());
+}
+
+#[test]
+fn check_warm_started_lp_matches_cold_solve() {
+    use orthanq::calling::haplotypes::haplotypes::{linear_program, LinearProgramSettings};
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let outdir = tempfile::tempdir().unwrap();
+    let solve = |warm_start: Option<Vec<f64>>| {
+        let solution = linear_program(
+            &outdir.path().join("out.csv"),
+            &candidate_matrix,
+            &haplotypes,
+            &variant_calls,
+            &LinearProgramSettings {
+                integer_selection: true,
+                warm_start,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        haplotypes
+            .iter()
+            .map(|haplotype| solution.fractions.get(haplotype).cloned().unwrap_or(0.0))
+            .collect::<Vec<f64>>()
+    };
+
+    let cold = solve(None);
+    //seeded with the previous solution, and with a start that doesn't fit the haplotypes and is ignored
+    for warm_start in vec![cold.clone(), vec![1.0]] {
+        let warm = solve(Some(warm_start));
+        for (cold, warm) in cold.iter().zip(warm.iter()) {
+            assert!((cold - warm).abs() < 1e-6, "{:?} vs {:?}", cold, warm);
+        }
+    }
+}
+
+#[test]
+fn check_near_homozygous_sample_surfaces_het_alternative() {
+    use bio::stats::{LogProb, Prob};
+    use ordered_float::NotNan;
+    use orthanq::calling::haplotypes::haplotypes::{
+        homozygous_ambiguity, write_homozygous_ambiguity, Haplotype, HomozygousAmbiguity,
+    };
+    use orthanq::model::HaplotypeFractions;
+
+    let haplotypes = vec![
+        Haplotype("A*01:01:01".to_string()),
+        Haplotype("A*02:01:01".to_string()),
+        Haplotype("A*03:01:01".to_string()),
+    ];
+    let event = |fractions: &[f64], density: f64| {
+        (
+            HaplotypeFractions(
+                fractions
+                    .iter()
+                    .map(|fraction| NotNan::new(*fraction).unwrap())
+                    .collect(),
+            ),
+            LogProb::from(Prob(density)),
+        )
+    };
+    //the homozygous A*01:01:01 is best, the heterozygous pair with A*02:01:01 is only slightly worse
+    let event_posteriors = vec![
+        event(&[0.5, 0.5, 0.0], 0.3),
+        event(&[1.0, 0.0, 0.0], 0.5),
+        event(&[0.0, 1.0, 0.0], 0.2),
+    ];
+
+    let ambiguity = homozygous_ambiguity(&event_posteriors, &haplotypes, 0.5, 1e-6).unwrap();
+    assert_eq!((ambiguity.homozygous, ambiguity.heterozygous), (1, 0));
+    assert!((ambiguity.odds - 0.6).abs() < 1e-9);
+    //outside of the band, the homozygous call stands
+    assert_eq!(
+        homozygous_ambiguity(&event_posteriors, &haplotypes, 0.7, 1e-6),
+        None
+    );
+    //a heterozygous best solution is never ambiguous in this sense
+    assert_eq!(
+        homozygous_ambiguity(&event_posteriors[..1], &haplotypes, 0.0, 1e-6),
+        None::<HomozygousAmbiguity>
+    );
+
+    let outdir = tempfile::tempdir().unwrap();
+    write_homozygous_ambiguity(
+        &outdir.path().join("out.csv"),
+        &event_posteriors,
+        &haplotypes,
+        &ambiguity,
+    )
+    .unwrap();
+    let written = std::fs::read_to_string(outdir.path().join("homozygous_ambiguity.tsv")).unwrap();
+    assert_eq!(
+        written.lines().collect::<Vec<&str>>(),
+        vec![
+            "solution\tdensity\todds\thaplotypes\tA*01:01:01\tA*02:01:01\tA*03:01:01",
+            "homozygous\t0.5000\t1.0000\tA*01:01:01\t1.00\t0.00\t0.00",
+            "heterozygous\t0.3000\t0.6000\tA*01:01:01,A*02:01:01\t0.50\t0.50\t0.00",
+        ]
+    );
+}
+
+#[test]
+fn check_combined_plot_contains_both_stages() {
+    use orthanq::calling::haplotypes::haplotypes::{plot_prediction, write_combined_plot};
+
+    let (_, variant_calls, candidate_matrix, haplotypes) = small_fixture();
+    let outdir = tempfile::tempdir().unwrap();
+    let output = outdir.path().join("results.csv");
+    for (stage, fractions) in &[("lp", vec![0.7, 0.3, 0.0]), ("final", vec![0.5, 0.5, 0.0])] {
+        plot_prediction(
+            &output,
+            stage,
+            &candidate_matrix,
+            &haplotypes,
+            &variant_calls,
+            fractions,
+            false,
+        )
+        .unwrap();
+    }
+    write_combined_plot(&output).unwrap();
+
+    let combined: serde_json::Value = serde_json::from_reader(
+        std::fs::File::open(outdir.path().join("combined_solution.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(combined["facet"]["row"]["field"], "stage");
+    let solutions = combined["datasets"]["solutions"].as_array().unwrap();
+    for stage in &["lp", "final"] {
+        let rows = solutions
+            .iter()
+            .filter(|row| row["stage"] == *stage)
+            .collect::<Vec<_>>();
+        //every stage has the observed vafs and the fractions of the haplotypes having the variants
+        assert!(rows.iter().any(|row| row["vaf"].is_number()), "{}", stage);
+        assert!(
+            rows.iter().any(|row| row["fraction"].is_number()),
+            "{}",
+            stage
+        );
+    }
+    //the stages keep their own fractions
+    let fractions_of = |stage: &str| {
+        solutions
+            .iter()
+            .filter(|row| row["stage"] == stage && row["fraction"].is_number())
+            .map(|row| row["fraction"].as_f64().unwrap())
+            .collect::<Vec<f64>>()
+    };
+    assert!(fractions_of("lp")
+        .iter()
+        .all(|fraction| [0.7, 0.3, 0.0].contains(fraction)));
+    assert!(fractions_of("final")
+        .iter()
+        .all(|fraction| [0.5, 0.0].contains(fraction)));
+}
+
+#[test]
+fn check_preprocess_tempdir_is_created_under_configured_path() {
+    //stub the external tools such that each invocation is logged instead of executed
+    let dir = tempfile::tempdir().unwrap();
+    let tools = stub_tools(dir.path(), "");
+    let log = &tools.log;
+
+    let genome = dir.path().join("genome.fasta");
+    std::fs::write(&genome, ">6\nACGTACGTAC\n").unwrap();
+    let scratch = dir.path().join("scratch");
+    orthanq::preprocess::hla::CallerBuilder::default()
+        .genome(genome)
+        .reads(vec!["sample_1.fastq".into(), "sample_2.fastq".into()])
+        .haplotype_variants("tests/small_candidates.vcf".into())
+        .bwa_index(Some("genome".into()))
+        .output(dir.path().join("out").join("sample.vcf"))
+        .threads("1".to_string())
+        .use_pangenome(false)
+        .tempdir(Some(scratch.clone()))
+        .build()
+        .unwrap()
+        .call()
+        .unwrap();
+
+    //the intermediate alignment is written into a working directory under the configured path
+    let invocations = std::fs::read_to_string(&log).unwrap();
+    let alignment = invocations
+        .lines()
+        .find(|line| line.starts_with("bwa mem"))
+        .and_then(|line| {
+            line.split_whitespace()
+                .skip_while(|arg| *arg != "-o")
+                .nth(1)
+        })
+        .map(std::path::PathBuf::from)
+        .unwrap();
+    assert_eq!(alignment.parent().unwrap().parent().unwrap(), scratch);
+    //and removed once the preprocessing is done
+    assert!(scratch.exists());
+    assert_eq!(std::fs::read_dir(&scratch).unwrap().count(), 0);
+}